@@ -0,0 +1,27 @@
+//! Text command throughput: the borrowed `execute_args` fast path against
+//! parsing into a `Command` first.
+//!
+//! Run with `cargo bench --bench commands`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rustdis::cache::RustdisCache;
+use rustdis::protocol::RustdisProtocol;
+
+fn text_commands(c: &mut Criterion) {
+    let protocol = RustdisProtocol::new(RustdisCache::new());
+    protocol.execute_args(&["SET", "user:1", "ana"]);
+
+    let mut group = c.benchmark_group("text_commands");
+    for args in [&["GET", "user:1"][..], &["SET", "user:1", "ana"][..]] {
+        group.bench_function(format!("{} execute_args", args[0]), |b| {
+            b.iter(|| protocol.execute_args(black_box(args)))
+        });
+        group.bench_function(format!("{} parse_args + execute", args[0]), |b| {
+            b.iter(|| protocol.execute(RustdisProtocol::parse_args(black_box(args)).unwrap()))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, text_commands);
+criterion_main!(benches);
@@ -0,0 +1,60 @@
+//! Embedded cache throughput under a mixed read/write load from several
+//! threads, to compare the shard locks.
+//!
+//! Run with `cargo bench --bench contention`, then again with
+//! `--features dashmap`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rustdis::cache::RustdisCache;
+use std::sync::Barrier;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const THREADS: usize = 8;
+const KEYS: usize = 1024;
+
+/// Runs `iters` operations on each of `THREADS` threads, one in `writes_per`
+/// a SET and the rest GETs, and returns the time until the last one is done
+fn mixed_load(cache: &RustdisCache, iters: u64, writes_per: u64) -> Duration {
+    let barrier = Barrier::new(THREADS + 1);
+    thread::scope(|scope| {
+        for thread in 0..THREADS {
+            let barrier = &barrier;
+            scope.spawn(move || {
+                barrier.wait();
+                for i in 0..iters {
+                    let key = format!("key:{}", (i as usize * 31 + thread * 97) % KEYS);
+                    if i % writes_per == 0 {
+                        cache.set(key, "value".to_string()).unwrap();
+                    } else {
+                        black_box(cache.get(&key).unwrap());
+                    }
+                }
+                barrier.wait();
+            });
+        }
+        barrier.wait();
+        let started = Instant::now();
+        barrier.wait();
+        started.elapsed()
+    })
+}
+
+fn shard_locks(c: &mut Criterion) {
+    let mut group = c.benchmark_group(format!("contention ({} locks)", rustdis::backend::NAME));
+    for shards in [1, 16] {
+        let cache = RustdisCache::new().sharded(shards);
+        for i in 0..KEYS {
+            cache.set(format!("key:{}", i), "value".to_string()).unwrap();
+        }
+        for (label, writes_per) in [("90% reads", 10), ("50% reads", 2)] {
+            group.bench_function(format!("{} shards, {}", shards, label), |b| {
+                b.iter_custom(|iters| mixed_load(&cache, iters, writes_per))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, shard_locks);
+criterion_main!(benches);
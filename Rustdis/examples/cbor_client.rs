@@ -0,0 +1,88 @@
+//! Minimal CBOR client for constrained devices.
+//!
+//! The encoder/decoder below only uses `core` and a caller-provided buffer,
+//! so it can be copied into a `no_std` firmware as-is. Here it talks to an
+//! in-process Rustdis instead of a socket.
+
+use rustdis::api::RustdisApi;
+use rustdis::cache::RustdisCache;
+
+/// Writes a CBOR major type header, returning the number of bytes used
+fn write_header(buf: &mut [u8], major: u8, len: usize) -> usize {
+    let len = len as u64;
+    // Additional info 24-27: the length follows in 1, 2, 4 or 8 bytes
+    let (info, width) = match len {
+        0..=23 => (len as u8, 0),
+        24..=0xff => (24, 1),
+        0x100..=0xffff => (25, 2),
+        0x1_0000..=0xffff_ffff => (26, 4),
+        _ => (27, 8),
+    };
+    buf[0] = (major << 5) | info;
+    buf[1..1 + width].copy_from_slice(&len.to_be_bytes()[8 - width..]);
+    1 + width
+}
+
+/// Writes a CBOR text string
+fn write_text(buf: &mut [u8], text: &str) -> usize {
+    let used = write_header(buf, 3, text.len());
+    buf[used..used + text.len()].copy_from_slice(text.as_bytes());
+    used + text.len()
+}
+
+/// Encodes `{"command": name, "args": {k: v, ...}}` into `buf`
+fn encode_command(buf: &mut [u8], name: &str, args: &[(&str, &str)]) -> usize {
+    let mut at = write_header(buf, 5, 2);
+    at += write_text(&mut buf[at..], "command");
+    at += write_text(&mut buf[at..], name);
+    at += write_text(&mut buf[at..], "args");
+    at += write_header(&mut buf[at..], 5, args.len());
+    for (key, value) in args {
+        at += write_text(&mut buf[at..], key);
+        at += write_text(&mut buf[at..], value);
+    }
+    at
+}
+
+/// Decodes a reply that is a CBOR text string (e.g. "OK" or a value)
+fn decode_text(reply: &[u8]) -> Option<&str> {
+    let (major, info) = (reply.first()? >> 5, reply[0] & 0x1f);
+    let width = match info {
+        0..=23 => 0,
+        24..=27 => 1 << (info - 24),
+        _ => return None,
+    };
+    if major != 3 {
+        return None;
+    }
+    let len = match width {
+        0 => info as usize,
+        _ => reply.get(1..1 + width)?.iter().fold(0usize, |len, byte| (len << 8) | *byte as usize),
+    };
+    let start = 1 + width;
+    core::str::from_utf8(reply.get(start..start.checked_add(len)?)?).ok()
+}
+
+fn main() -> anyhow::Result<()> {
+    let api = RustdisApi::new(RustdisCache::new());
+    let mut buf = [0u8; 128];
+
+    let len = encode_command(&mut buf, "SET", &[("key", "sensor:1"), ("value", "21.5")]);
+    let reply = api.api_execute_command_cbor(&buf[..len])?;
+    println!("SET -> {:?}", decode_text(&reply));
+
+    let len = encode_command(&mut buf, "GET", &[("key", "sensor:1")]);
+    let reply = api.api_execute_command_cbor(&buf[..len])?;
+    println!("GET -> {:?}", decode_text(&reply));
+
+    // Values of 256 bytes or more need the two-byte length form
+    let reading = "21.5,".repeat(60);
+    let mut buf = [0u8; 512];
+    let len = encode_command(&mut buf, "SET", &[("key", "sensor:log"), ("value", &reading)]);
+    api.api_execute_command_cbor(&buf[..len])?;
+    let len = encode_command(&mut buf, "GET", &[("key", "sensor:log")]);
+    let reply = api.api_execute_command_cbor(&buf[..len])?;
+    println!("GET sensor:log -> {} bytes", decode_text(&reply).map_or(0, str::len));
+
+    Ok(())
+}
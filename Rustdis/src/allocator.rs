@@ -0,0 +1,117 @@
+use anyhow::Result;
+use serde::Serialize;
+
+#[cfg(all(feature = "jemalloc", feature = "mimalloc"))]
+compile_error!("features `jemalloc` and `mimalloc` are mutually exclusive");
+
+/// Name of the global allocator the binary was built with
+pub const NAME: &str = if cfg!(feature = "jemalloc") {
+    "jemalloc"
+} else if cfg!(feature = "mimalloc") {
+    "mimalloc"
+} else {
+    "system"
+};
+
+/// Allocator-level memory statistics, in bytes
+#[derive(Debug, Clone, Serialize)]
+pub struct AllocatorStats {
+    pub allocator: &'static str,
+    /// Bytes handed out to the application; mimalloc doesn't track this
+    /// outside of debug builds
+    pub allocated: Option<usize>,
+    /// Bytes in pages the allocator has in use (committed, for mimalloc)
+    pub active: usize,
+    /// Bytes physically resident in memory
+    pub resident: usize,
+    /// `active / allocated`; values well above 1.0 mean fragmentation
+    pub fragmentation_ratio: Option<f64>,
+}
+
+/// Reads jemalloc's `stats.allocated`, `stats.active` and `stats.resident`,
+/// refreshed by advancing the epoch first
+#[cfg(feature = "jemalloc")]
+pub fn stats() -> Result<Option<AllocatorStats>> {
+    use tikv_jemalloc_ctl::{epoch, stats};
+
+    // Stats are cached by jemalloc until the epoch is advanced
+    epoch::advance().map_err(|e| anyhow::anyhow!("Failed to refresh allocator stats: {}", e))?;
+    let read = |value: tikv_jemalloc_ctl::Result<usize>| value.map_err(|e| anyhow::anyhow!("Failed to read allocator stats: {}", e));
+    let allocated = read(stats::allocated::read())?;
+    let active = read(stats::active::read())?;
+    let resident = read(stats::resident::read())?;
+
+    Ok(Some(AllocatorStats {
+        allocator: NAME,
+        allocated: Some(allocated),
+        active,
+        resident,
+        fragmentation_ratio: Some(if allocated == 0 { 1.0 } else { active as f64 / allocated as f64 }),
+    }))
+}
+
+/// Reads the current RSS and committed bytes from `mi_process_info`;
+/// mimalloc keeps no count of allocated bytes, so that and the
+/// fragmentation ratio are left out
+#[cfg(feature = "mimalloc")]
+pub fn stats() -> Result<Option<AllocatorStats>> {
+    use libmimalloc_sys::mi_process_info;
+    use std::ptr::null_mut;
+
+    let (mut resident, mut committed) = (0, 0);
+    // SAFETY: every out-param is either null, which mimalloc skips, or a
+    // valid pointer to a usize
+    unsafe {
+        mi_process_info(null_mut(), null_mut(), null_mut(), &mut resident, null_mut(), &mut committed, null_mut(), null_mut());
+    }
+
+    Ok(Some(AllocatorStats {
+        allocator: NAME,
+        allocated: None,
+        active: committed,
+        resident,
+        fragmentation_ratio: None,
+    }))
+}
+
+/// Reads current allocator statistics
+///
+/// Returns `None` with the system allocator, which doesn't expose any.
+#[cfg(not(any(feature = "jemalloc", feature = "mimalloc")))]
+pub fn stats() -> Result<Option<AllocatorStats>> {
+    Ok(None)
+}
+
+/// Total bytes ever allocated by the calling thread
+///
+/// Only jemalloc tracks this; returns `None` for other allocators.
+#[cfg(feature = "jemalloc")]
+pub fn thread_allocated() -> Option<u64> {
+    tikv_jemalloc_ctl::thread::allocatedp::read().ok().map(|allocated| allocated.get())
+}
+
+/// Total bytes ever allocated by the calling thread
+///
+/// Only jemalloc tracks this; returns `None` for other allocators.
+#[cfg(not(feature = "jemalloc"))]
+pub fn thread_allocated() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_match_allocator() {
+        let stats = stats().unwrap();
+        assert_eq!(stats.is_some(), NAME != "system");
+        if let Some(stats) = stats {
+            assert!(stats.resident > 0);
+            assert_eq!(stats.allocated.is_some(), NAME == "jemalloc");
+            if let Some(allocated) = stats.allocated {
+                assert!(stats.resident >= allocated);
+            }
+        }
+    }
+}
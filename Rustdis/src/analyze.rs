@@ -0,0 +1,224 @@
+use crate::cache::{KeyMetadata, RustdisCache};
+use anyhow::Result;
+use std::io::Write;
+use std::str::FromStr;
+
+/// Keys read from the cache per batch; memory use is bounded by one batch
+pub const EXPORT_BATCH: usize = 1000;
+
+/// Columns of an export, in file order
+pub const EXPORT_COLUMNS: &[&str] = &["key", "type", "bytes", "ttl_ms", "idle_ms", "namespace"];
+
+/// File format written by `rustdis analyze`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    /// Requires the `parquet` feature
+    Parquet,
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            "parquet" => Ok(ExportFormat::Parquet),
+            _ => Err(anyhow::anyhow!("Unknown export format: {} (expected csv or parquet)", s)),
+        }
+    }
+}
+
+/// Conventional namespace of a key: the part before its first `:`
+pub fn namespace_of(key: &str) -> Option<&str> {
+    key.split_once(':').map(|(namespace, _)| namespace)
+}
+
+/// Streams key metadata from `cache` to `out`, returning the number of keys
+/// written
+///
+/// Keys are read in batches of `EXPORT_BATCH` (see
+/// `RustdisCache::scan_metadata`), so the keyspace is never materialized.
+pub fn export(cache: &RustdisCache, format: ExportFormat, out: impl Write + Send) -> Result<usize> {
+    match format {
+        ExportFormat::Csv => export_csv(cache, out),
+        #[cfg(feature = "parquet")]
+        ExportFormat::Parquet => parquet_export::export(cache, out),
+        #[cfg(not(feature = "parquet"))]
+        ExportFormat::Parquet => Err(anyhow::anyhow!("Parquet export requires building with --features parquet")),
+    }
+}
+
+/// Calls `write` with each batch of key metadata until the scan completes
+fn for_each_batch(cache: &RustdisCache, mut write: impl FnMut(&[KeyMetadata]) -> Result<()>) -> Result<usize> {
+    let (mut cursor, mut written) = (0, 0);
+    loop {
+        let (next, batch) = cache.scan_metadata(cursor, EXPORT_BATCH)?;
+        if !batch.is_empty() {
+            write(&batch)?;
+            written += batch.len();
+        }
+        if next == 0 {
+            return Ok(written);
+        }
+        cursor = next;
+    }
+}
+
+fn export_csv(cache: &RustdisCache, mut out: impl Write) -> Result<usize> {
+    writeln!(out, "{}", EXPORT_COLUMNS.join(","))?;
+    let written = for_each_batch(cache, |batch| {
+        for meta in batch {
+            writeln!(
+                out,
+                "{},{},{},{},{},{}",
+                csv_field(&meta.key),
+                meta.value_type,
+                meta.bytes,
+                meta.ttl.map_or(String::new(), |ttl| ttl.as_millis().to_string()),
+                meta.idle.as_millis(),
+                namespace_of(&meta.key).map_or(String::new(), csv_field),
+            )?;
+        }
+        Ok(())
+    })?;
+    out.flush()?;
+    Ok(written)
+}
+
+/// Quotes a field if it contains a delimiter, quote or line break (RFC 4180)
+pub(crate) fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_export {
+    use super::{for_each_batch, namespace_of};
+    use crate::cache::RustdisCache;
+    use anyhow::Result;
+    use parquet::data_type::{ByteArray, ByteArrayType, Int64Type};
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::{SerializedColumnWriter, SerializedFileWriter};
+    use parquet::schema::parser::parse_message_type;
+    use std::io::Write;
+    use std::sync::Arc;
+
+    const SCHEMA: &str = "
+        message keys {
+            required binary key (UTF8);
+            required binary type (UTF8);
+            required int64 bytes;
+            optional int64 ttl_ms;
+            required int64 idle_ms;
+            optional binary namespace (UTF8);
+        }
+    ";
+
+    /// Writes one row group per batch
+    pub fn export(cache: &RustdisCache, out: impl Write + Send) -> Result<usize> {
+        let schema = Arc::new(parse_message_type(SCHEMA)?);
+        let mut writer = SerializedFileWriter::new(out, schema, Arc::new(WriterProperties::builder().build()))?;
+
+        let written = for_each_batch(cache, |batch| {
+            let mut group = writer.next_row_group()?;
+            let keys: Vec<ByteArray> = batch.iter().map(|meta| meta.key.as_str().into()).collect();
+            let types: Vec<ByteArray> = batch.iter().map(|meta| meta.value_type.into()).collect();
+            let bytes: Vec<i64> = batch.iter().map(|meta| meta.bytes as i64).collect();
+            let ttls: Vec<Option<i64>> = batch.iter().map(|meta| meta.ttl.map(|ttl| ttl.as_millis() as i64)).collect();
+            let idle: Vec<i64> = batch.iter().map(|meta| meta.idle.as_millis() as i64).collect();
+            let namespaces: Vec<Option<ByteArray>> = batch.iter().map(|meta| namespace_of(&meta.key).map(ByteArray::from)).collect();
+
+            write_column(group.next_column()?, |column| column.typed::<ByteArrayType>().write_batch(&keys, None, None))?;
+            write_column(group.next_column()?, |column| column.typed::<ByteArrayType>().write_batch(&types, None, None))?;
+            write_column(group.next_column()?, |column| column.typed::<Int64Type>().write_batch(&bytes, None, None))?;
+            write_column(group.next_column()?, |column| write_optional::<Int64Type>(column, &ttls))?;
+            write_column(group.next_column()?, |column| column.typed::<Int64Type>().write_batch(&idle, None, None))?;
+            write_column(group.next_column()?, |column| write_optional::<ByteArrayType>(column, &namespaces))?;
+            group.close()?;
+            Ok(())
+        })?;
+        writer.close()?;
+        Ok(written)
+    }
+
+    fn write_column(
+        column: Option<SerializedColumnWriter<'_>>,
+        write: impl FnOnce(&mut SerializedColumnWriter<'_>) -> parquet::errors::Result<usize>,
+    ) -> Result<()> {
+        let mut column = column.ok_or_else(|| anyhow::anyhow!("Parquet schema has fewer columns than the export"))?;
+        write(&mut column)?;
+        column.close()?;
+        Ok(())
+    }
+
+    /// Writes a nullable column: present values plus a definition level per row
+    fn write_optional<T: parquet::data_type::DataType>(
+        column: &mut SerializedColumnWriter<'_>,
+        values: &[Option<T::T>],
+    ) -> parquet::errors::Result<usize> {
+        let levels: Vec<i16> = values.iter().map(|value| i16::from(value.is_some())).collect();
+        let present: Vec<T::T> = values.iter().flatten().cloned().collect();
+        column.typed::<T>().write_batch(&present, Some(&levels), None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_csv_export() {
+        let clock = Arc::new(ManualClock::new());
+        let cache = RustdisCache::with_clock(clock.clone());
+        for i in 0..EXPORT_BATCH + 5 {
+            cache.set(format!("user:{}", i), "x".to_string()).unwrap();
+        }
+        cache.set("odd,\"key\"".to_string(), "value".to_string()).unwrap();
+        cache.expire("user:0", Duration::from_secs(60)).unwrap();
+        cache.hset("cart:9".to_string(), vec![("sku".to_string(), "1".to_string())]).unwrap();
+        clock.advance(Duration::from_secs(1));
+
+        let mut out = Vec::new();
+        assert_eq!(export(&cache, ExportFormat::Csv, &mut out).unwrap(), EXPORT_BATCH + 7);
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "key,type,bytes,ttl_ms,idle_ms,namespace");
+        assert_eq!(lines[1], "user:0,string,7,59000,1000,user");
+        assert_eq!(lines[2], "user:1,string,7,,1000,user");
+        assert!(lines.contains(&"\"odd,\"\"key\"\"\",string,14,,1000,"));
+        assert!(lines.contains(&"cart:9,hash,10,,1000,cart"));
+        assert_eq!(lines.len(), EXPORT_BATCH + 8);
+
+        assert!("xlsx".parse::<ExportFormat>().is_err());
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_parquet_export() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let cache = RustdisCache::new();
+        for i in 0..EXPORT_BATCH + 1 {
+            cache.set(format!("k{}", i), "v".to_string()).unwrap();
+        }
+        cache.expire("k0", Duration::from_secs(60)).unwrap();
+
+        let path = std::env::temp_dir().join(format!("rustdis-analyze-{}.parquet", std::process::id()));
+        export(&cache, ExportFormat::Parquet, std::fs::File::create(&path).unwrap()).unwrap();
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let metadata = reader.metadata();
+        assert_eq!(metadata.file_metadata().num_rows(), EXPORT_BATCH as i64 + 1);
+        assert_eq!(metadata.num_row_groups(), 2);
+        let columns: Vec<&str> = metadata.file_metadata().schema_descr().columns().iter().map(|column| column.name()).collect();
+        assert_eq!(columns, EXPORT_COLUMNS);
+    }
+}
@@ -1,9 +1,10 @@
-use crate::cache::RustdisCache;
-use crate::protocol::{RustdisProtocol, Response};
+use crate::auth::AclUser;
+use crate::cache::{RustdisCache, SetCondition, SetOptions, TypedValue};
+use crate::glob::glob_match;
+use crate::idgen::IdKind;
+use crate::protocol::{Command, RustdisProtocol, Response, ValueEncoding};
+use crate::pubsub::EventFlags;
 use anyhow::Result;
-use serde_json;
-use std::collections::HashMap;
-use std::sync::Arc;
 
 /// HTTP-like API interface for Rustdis
 pub struct RustdisApi {
@@ -17,20 +18,61 @@ impl RustdisApi {
         }
     }
 
-    /// GET /api/get?key=<key>
-    /// Get value by key
-    pub fn api_get(&self, key: &str) -> Result<String> {
-        let command = crate::protocol::Command::Get { key: key.to_string() };
+    /// The cache behind this API
+    pub fn cache(&self) -> &RustdisCache {
+        self.protocol.cache()
+    }
+
+    /// GET /api/get?key=<key>[&encoding=base64]
+    /// Get value by key; binary values need `encoding=base64`
+    pub fn api_get(&self, key: &str, encoding: ValueEncoding) -> Result<String> {
+        let command = crate::protocol::Command::Get { key: key.to_string(), encoding };
         let response = self.protocol.execute(command);
-        Ok(RustdisProtocol::response_to_json(&response)?)
+        RustdisProtocol::response_to_json(&response)
+    }
+
+    /// GET /api/get?key=<key> for a key with a `content-type` metadata field:
+    /// its bytes, to serve as that content type; `None` for any other key,
+    /// which is answered with `api_get`
+    pub fn api_get_typed(&self, key: &str) -> Result<Option<TypedValue>> {
+        let typed = self.cache().get_typed(key)?;
+        if typed.is_some() {
+            // Only a poisoned lock fails, and the value can still be served
+            let _ = self.cache().record_heat([key]);
+        }
+        Ok(typed)
     }
 
     /// POST /api/set
-    /// Body: {"key": "mykey", "value": "myvalue"}
-    pub fn api_set(&self, key: String, value: String) -> Result<String> {
-        let command = crate::protocol::Command::Set { key, value };
+    /// Body: {"key": "mykey", "value": "myvalue"}, plus `"encoding": "base64"`
+    /// for binary values
+    pub fn api_set(&self, key: String, value: String, encoding: ValueEncoding) -> Result<String> {
+        let command = crate::protocol::Command::Set {
+            key,
+            value,
+            encoding,
+            nx: false,
+            xx: false,
+            ex: None,
+            px: None,
+            keepttl: false,
+            get: false,
+        };
         let response = self.protocol.execute(command);
-        Ok(RustdisProtocol::response_to_json(&response)?)
+        RustdisProtocol::response_to_json(&response)
+    }
+
+    /// PUT /api/keys/<key>
+    /// Body: the raw value. Stores it if `condition` holds, clearing any TTL
+    /// as SET does; returns whether it was written
+    pub fn api_put(&self, key: String, value: Vec<u8>, condition: SetCondition) -> Result<bool> {
+        // Only a poisoned lock fails, and the write can still go ahead
+        let _ = self.cache().record_heat([key.as_str()]);
+        let written = self.cache().set_bytes_with(key.clone(), value, SetOptions { condition, ..SetOptions::default() })?.written;
+        if written {
+            self.cache().keyspace_events().notify(EventFlags::STRING, "set", &key);
+        }
+        Ok(written)
     }
 
     /// DELETE /api/del?key=<key>
@@ -38,7 +80,7 @@ impl RustdisApi {
     pub fn api_del(&self, key: &str) -> Result<String> {
         let command = crate::protocol::Command::Del { key: key.to_string() };
         let response = self.protocol.execute(command);
-        Ok(RustdisProtocol::response_to_json(&response)?)
+        RustdisProtocol::response_to_json(&response)
     }
 
     /// GET /api/exists?key=<key>
@@ -46,7 +88,7 @@ impl RustdisApi {
     pub fn api_exists(&self, key: &str) -> Result<String> {
         let command = crate::protocol::Command::Exists { key: key.to_string() };
         let response = self.protocol.execute(command);
-        Ok(RustdisProtocol::response_to_json(&response)?)
+        RustdisProtocol::response_to_json(&response)
     }
 
     /// GET /api/keys
@@ -54,7 +96,46 @@ impl RustdisApi {
     pub fn api_keys(&self) -> Result<String> {
         let command = crate::protocol::Command::Keys;
         let response = self.protocol.execute(command);
-        Ok(RustdisProtocol::response_to_json(&response)?)
+        RustdisProtocol::response_to_json(&response)
+    }
+
+    /// GET /api/keys?pattern=<pattern>
+    /// Keys matching a glob pattern
+    pub fn api_keys_matching(&self, pattern: &str) -> Result<String> {
+        let keys = self.cache().keys()?.into_iter().filter(|key| glob_match(pattern, key)).collect();
+        RustdisProtocol::response_to_json(&Response::StringArray(keys))
+    }
+
+    /// GET /api/keys/<key>/fields
+    /// Every field of a hash
+    pub fn api_hgetall(&self, key: &str) -> Result<String> {
+        let command = crate::protocol::Command::HGetAll { key: key.to_string() };
+        let response = self.protocol.execute(command);
+        RustdisProtocol::response_to_json(&response)
+    }
+
+    /// GET /api/keys/<key>/fields/<field>
+    /// One field of a hash
+    pub fn api_hget(&self, key: &str, field: &str) -> Result<String> {
+        let command = crate::protocol::Command::HGet { key: key.to_string(), field: field.to_string() };
+        let response = self.protocol.execute(command);
+        RustdisProtocol::response_to_json(&response)
+    }
+
+    /// PUT /api/keys/<key>/fields/<field>
+    /// Body: the raw field value. Sets one field of a hash, creating it
+    pub fn api_hset(&self, key: String, field: String, value: String) -> Result<String> {
+        let command = crate::protocol::Command::HSet { key, fields: [(field, value)].into_iter().collect() };
+        let response = self.protocol.execute(command);
+        RustdisProtocol::response_to_json(&response)
+    }
+
+    /// DELETE /api/keys/<key>/fields/<field>
+    /// Remove one field of a hash
+    pub fn api_hdel(&self, key: &str, field: &str) -> Result<String> {
+        let command = crate::protocol::Command::HDel { key: key.to_string(), fields: vec![field.to_string()] };
+        let response = self.protocol.execute(command);
+        RustdisProtocol::response_to_json(&response)
     }
 
     /// DELETE /api/flush
@@ -62,7 +143,7 @@ impl RustdisApi {
     pub fn api_flush(&self) -> Result<String> {
         let command = crate::protocol::Command::Flush;
         let response = self.protocol.execute(command);
-        Ok(RustdisProtocol::response_to_json(&response)?)
+        RustdisProtocol::response_to_json(&response)
     }
 
     /// GET /api/size
@@ -70,7 +151,7 @@ impl RustdisApi {
     pub fn api_size(&self) -> Result<String> {
         let command = crate::protocol::Command::Size;
         let response = self.protocol.execute(command);
-        Ok(RustdisProtocol::response_to_json(&response)?)
+        RustdisProtocol::response_to_json(&response)
     }
 
     /// GET /api/ping
@@ -78,7 +159,32 @@ impl RustdisApi {
     pub fn api_ping(&self) -> Result<String> {
         let command = crate::protocol::Command::Ping;
         let response = self.protocol.execute(command);
-        Ok(RustdisProtocol::response_to_json(&response)?)
+        RustdisProtocol::response_to_json(&response)
+    }
+
+    /// GET /api/genid?kind=<uuid|ulid|snowflake>
+    /// Generate a unique ID
+    pub fn api_genid(&self, kind: IdKind) -> Result<String> {
+        let command = crate::protocol::Command::GenId { kind };
+        let response = self.protocol.execute(command);
+        RustdisProtocol::response_to_json(&response)
+    }
+
+    /// GET /api/heatmap?prefix=<prefix>&window=<seconds>
+    /// Accesses per key prefix over the last day, one row per window
+    pub fn api_heatmap(&self, prefix: String, window: u64) -> Result<String> {
+        let command = crate::protocol::Command::Heatmap { prefix, window };
+        let response = self.protocol.execute(command);
+        RustdisProtocol::response_to_json(&response)
+    }
+
+    /// GET /api/heatmap?format=csv
+    /// The heatmap as CSV, for spreadsheets and plotting tools
+    pub fn api_heatmap_csv(&self, prefix: &str, window: u64) -> Result<String> {
+        let matrix = self.cache().heatmap(prefix, std::time::Duration::from_secs(window))?;
+        let mut out = Vec::new();
+        matrix.write_csv(&mut out)?;
+        Ok(String::from_utf8(out)?)
     }
 
     /// POST /api/command
@@ -87,15 +193,93 @@ impl RustdisApi {
         match RustdisProtocol::parse_command(json_command) {
             Ok(command) => {
                 let response = self.protocol.execute(command);
-                Ok(RustdisProtocol::response_to_json(&response)?)
+                RustdisProtocol::response_to_json(&response)
             }
             Err(e) => {
-                let error_response = Response::Error { error: e.to_string() };
-                Ok(RustdisProtocol::response_to_json(&error_response)?)
+                let error_response = Response::from(e);
+                RustdisProtocol::response_to_json(&error_response)
             }
         }
     }
 
+    /// POST /api/command with an already parsed command
+    pub fn api_execute(&self, command: crate::protocol::Command) -> Result<String> {
+        let response = self.protocol.execute(command);
+        RustdisProtocol::response_to_json(&response)
+    }
+
+    /// POST /api/command (Content-Type: application/cbor) with an already
+    /// parsed command, replying in CBOR
+    pub fn api_execute_cbor(&self, command: crate::protocol::Command) -> Result<Vec<u8>> {
+        let response = self.protocol.execute(command);
+        RustdisProtocol::response_to_cbor(&response)
+    }
+
+    /// POST /api/command (Content-Type: application/cbor)
+    /// Execute raw CBOR command, replying in CBOR
+    pub fn api_execute_command_cbor(&self, cbor_command: &[u8]) -> Result<Vec<u8>> {
+        let response = match RustdisProtocol::parse_command_cbor(cbor_command) {
+            Ok(command) => self.protocol.execute(command),
+            Err(e) => Response::from(e),
+        };
+        RustdisProtocol::response_to_cbor(&response)
+    }
+
+    /// POST /api/batch
+    /// Run command envelopes in order, reporting each one's outcome; a failed
+    /// operation doesn't stop or undo the others. Unless `allow_admin` is set,
+    /// admin commands (see `Command::is_admin`) fail with `ADMIN_ONLY`;
+    /// commands `user`'s role doesn't allow fail with `NOPERM`
+    ///
+    /// With `atomic` the operations run like a MULTI/EXEC transaction, with
+    /// no other command in between; if any of them can't be parsed or isn't
+    /// allowed, none runs and the others fail with `EXECABORT`.
+    pub fn api_batch(&self, operations: Vec<serde_json::Value>, atomic: bool, allow_admin: bool, user: Option<&AclUser>) -> Result<String> {
+        let total = operations.len();
+        let checked: Vec<Result<Command, Response>> = operations
+            .into_iter()
+            .map(|envelope| match RustdisProtocol::parse_envelope(envelope) {
+                Ok(command) if command.is_admin() && !allow_admin => {
+                    Err(Response::coded_error("ADMIN_ONLY", "Admin commands are only accepted on the admin listener"))
+                }
+                Ok(command) => match user.and_then(|user| Some((user, user.role.denied(&command)?))) {
+                    Some((user, denied)) => Err(Response::coded_error("NOPERM", user.no_permission(denied))),
+                    None => Ok(command),
+                },
+                Err(e) => Err(Response::from(e)),
+            })
+            .collect();
+
+        let responses: Vec<Response> = if !atomic {
+            checked.into_iter().map(|command| command.map_or_else(|e| e, |command| self.protocol.execute(command))).collect()
+        } else if checked.iter().any(Result::is_err) {
+            let aborted = || Response::coded_error("EXECABORT", "Transaction discarded because of previous errors");
+            checked.into_iter().map(|command| command.err().unwrap_or_else(aborted)).collect()
+        } else {
+            let commands = checked.into_iter().flatten().collect();
+            match self.protocol.exec(commands, &[]) {
+                Response::Array(replies) => replies,
+                // Refused as a whole, e.g. by an injected fault
+                refused => vec![refused; total],
+            }
+        };
+
+        let mut failed = 0;
+        let results: Vec<serde_json::Value> = responses
+            .into_iter()
+            .map(|response| match response {
+                Response::Error { error, code } => {
+                    failed += 1;
+                    serde_json::json!({ "status": "error", "error": error, "code": code })
+                }
+                response => serde_json::json!({ "status": "ok", "result": response }),
+            })
+            .collect();
+
+        let body = serde_json::json!({ "results": results, "succeeded": total - failed, "failed": failed });
+        Ok(body.to_string())
+    }
+
     /// Generate API documentation
     pub fn api_docs(&self) -> String {
         r#"
@@ -106,13 +290,47 @@ impl RustdisApi {
 ### GET /api/get?key=<key>
 Get value by key
 - **Query Parameter**: `key` - The key to retrieve
-- **Response**: JSON with the value or null if not found
+- **Query Parameter**: `encoding` (optional) - `base64` to read binary values
+- **Response**: JSON with the value or null if not found; `NOT_UTF8` for a binary value without `encoding=base64`
+- **Typed values**: without `encoding`, a string whose metadata has a `content-type` field (see META.SET) is sent as its raw bytes with that `Content-Type`, a strong `ETag` (weak when the response is compressed) and `Cache-Control: max-age=<TTL>` (`no-cache` without a TTL); `If-None-Match` gets a 304
 
 ### POST /api/set
 Set key-value pair
-- **Body**: `{"key": "mykey", "value": "myvalue"}`
+- **Body**: `{"key": "mykey", "value": "myvalue"}`, plus `"encoding": "base64"` to store binary values
 - **Response**: `"OK"` on success
 
+### GET /api/keys/<key>
+Same as `GET /api/get?key=<key>`, typed values included; takes `encoding` too
+
+### PUT /api/keys/<key>
+Store the request body as the value of a key, clearing any TTL
+- **Header**: `If-Match` (optional) - an ETag of the current value: the write only happens if the key still holds it; `*` for any existing key
+- **Header**: `If-None-Match: *` (optional) - only create the key, never overwrite it
+- **Response**: 204 with the `ETag` of the new value; 412 `PRECONDITION_FAILED` when the condition doesn't hold
+
+ETags are the strong ones sent for typed values by `GET /api/get`; as RFC 9110 asks, `If-Match` compares strongly, so a weak `W/"..."` ETag never matches.
+
+### DELETE /api/keys/<key>
+Same as `DELETE /api/del?key=<key>`
+
+### GET /api/keys/<key>/fields
+Every field of a hash
+- **Response**: JSON object of fields and values, `{}` if the key doesn't exist
+
+### GET /api/keys/<key>/fields/<field>
+One field of a hash
+- **Response**: The value, or null if the key or field doesn't exist
+
+### PUT /api/keys/<key>/fields/<field>
+Set one field of a hash to the request body (UTF-8), creating the hash if needed
+- **Response**: `1` if the field is new, `0` if it was updated
+
+### DELETE /api/keys/<key>/fields/<field>
+Remove one field of a hash
+- **Response**: `1` if removed, `0` if it didn't exist
+
+Keys and fields in these paths are percent-decoded, so `/` in a key is sent as `%2F`.
+
 ### DELETE /api/del?key=<key>
 Delete key
 - **Query Parameter**: `key` - The key to delete
@@ -123,9 +341,10 @@ Check if key exists
 - **Query Parameter**: `key` - The key to check
 - **Response**: `true` if exists, `false` otherwise
 
-### GET /api/keys
+### GET /api/keys?pattern=<pattern>
 Get all keys
-- **Response**: Array of all keys
+- **Query Parameter**: `pattern` (optional) - glob pattern (`*`, `?`, `[abc]`) the keys must match
+- **Response**: Array of all keys, or of the matching ones
 
 ### DELETE /api/flush
 Clear all data
@@ -139,14 +358,126 @@ Get number of keys
 Test connection
 - **Response**: `"PONG"`
 
+### GET /api/genid?kind=<kind>
+Generate a unique ID
+- **Query Parameter**: `kind` - `uuid` (default), `ulid` or `snowflake`
+- **Response**: The generated ID as a string
+
+### GET /api/heatmap?prefix=<prefix>&window=<seconds>
+Access counts per key prefix (the part before the first `:`) over the last day
+- **Query Parameter**: `prefix` (optional) - Only prefixes starting with this
+- **Query Parameter**: `window` (optional) - Seconds per row, default 3600
+- **Query Parameter**: `format` (optional) - `json` (default) or `csv`
+- **Response**: `{"window", "prefixes", "rows"}`, each row `[start_ms, count...]`; CSV has a `start_ms` column then one per prefix
+
 ### POST /api/command
 Execute raw JSON command
-- **Body**: JSON command object
+- **Body**: JSON command object, optionally with a `version` field
 - **Response**: JSON response from command execution
 
+With `Content-Type: application/cbor` the body is the same envelope encoded
+as a CBOR map and the response is CBOR-encoded too.
+
+### POST /api/batch
+Execute several commands in order
+- **Body**: `{"operations": [<command envelope>, ...], "atomic": false}` (up to 1000 operations)
+- **Response**: `{"results": [...], "succeeded": 1, "failed": 1}` with one
+  `{"status": "ok", "result": ...}` or `{"status": "error", "error": "...", "code": "..."}`
+  per operation, in request order
+
+Operations are not all-or-nothing: a failing operation is reported and the
+rest still run. With `"atomic": true` the batch runs like `MULTI`/`EXEC`: no
+command from another client runs in between, and if any operation can't be
+parsed or isn't allowed, none runs and the others fail with `EXECABORT`. As in
+Redis, an operation failing while it runs doesn't undo the ones before it.
+
+### POST /api/graphql
+GraphQL endpoint (only with the `graphql` feature)
+- **Body**: `{"query": "...", "variables": {...}}`
+- **Queries**: `key(key)`, `keys(pattern, limit)`, `exists(key)`, `size`
+- **Mutations**: `set(key, value)`, `delete(key)`
+- **Response**: standard GraphQL `{"data": ..., "errors": [...]}`
+
+## Admin Listener
+
+Started with `--admin-addr`, the server splits its API in two. The main
+address keeps the data-plane endpoints and refuses admin operations: `DELETE
+/api/flush` is not routed, and FLUSH, NS.LIMIT, CONFIG GET/SET, EVICTION DEBUG
+and DEBUG commands sent to `/api/command` or `/api/batch` fail with 403
+`ADMIN_ONLY`. The admin address serves the full API. Without `--admin-addr`
+everything is served on the main address.
+
+## Metrics and Access Log
+
+`GET /metrics` serves Prometheus counters (`rustdis_http_requests_total`) and
+latency histograms (`rustdis_http_request_duration_seconds`) labelled by method,
+route and status. Each listener reports its own requests. Every request is also
+logged as `client "METHOD /path" status latency`; pass `--no-access-log` to
+turn that off.
+
+## Authentication
+
+When the server is started with `--user` or OIDC options, every request needs
+`Authorization: Bearer <secret>`, where the secret is the `default` user's
+password or an OIDC token. Missing or rejected credentials get a 401 with
+`{"code": "UNAUTHORIZED"}`.
+
+Each user has a role naming the commands it may run, the same roles RESP
+clients are checked against. `admin` runs everything, `readwrite` (the
+default) everything but admin commands, and `--role NAME=CMD[,CMD...]`
+defines allow-lists. Fixed endpoints need the commands they run (e.g. `GET
+/api/get` needs GET, `/api/graphql` needs GET, KEYS, SET and DEL); refused
+requests, and refused operations in `/api/batch`, get `NOPERM` with a 403.
+
+## Protocol Versioning
+
+Command envelopes look like `{"version": 1, "command": "GET", "args": {"key": "mykey"}}`.
+`version` is optional and defaults to the current version (1).
+
+- New commands, optional arguments and response fields are added without
+  bumping the version; clients must ignore fields they don't understand.
+- Removing or changing the meaning of a command or field bumps the version.
+- `{"command": "CAPABILITIES"}` returns the supported versions, commands and
+  protocol features so clients can adapt.
+
+Parse failures return `{"error": "...", "code": "..."}` where `code` is one of
+`INVALID_JSON`, `UNSUPPORTED_VERSION`, `UNKNOWN_COMMAND`, `UNKNOWN_FIELD`,
+`MISSING_FIELD` or `INVALID_COMMAND`.
+
+## Request Validation
+
+Over HTTP, invalid requests are rejected with a 4xx status and the same
+envelope plus a `field` naming the offending body field or query parameter:
+
+```json
+{"error": "`key` must be a string, got a number", "code": "INVALID_TYPE", "field": "key"}
+```
+
+Besides the codes above, `INVALID_TYPE`, `INVALID_ARGUMENT` and
+`VALUE_TOO_LARGE` (413; keys up to 64 KiB, values up to 16 MiB) can be returned.
+
+## Idempotency Keys
+
+POST and DELETE requests may send an `Idempotency-Key` header (up to 255
+characters). The first response is stored for 24 hours
+(`--idempotency-window-secs`) and replayed, with `Idempotent-Replayed: true`,
+for retries with the same key, method, path and body. Reusing a key for a
+different request returns 422 `IDEMPOTENCY_KEY_REUSED`; retrying while the
+first request is still running returns 409 `IDEMPOTENCY_IN_PROGRESS`. 5xx
+responses are not stored, so those requests can be retried.
+
+## Compression
+
+Responses larger than the compression threshold (1024 bytes by default,
+`--compress-min-bytes`) are gzip- or deflate-encoded when the request sends a
+matching `Accept-Encoding` header.
+
 ## Example Usage
 
 ```bash
+# Start the server
+rustdis http --addr 127.0.0.1:8080
+
 # Get a value
 curl "http://localhost:8080/api/get?key=mykey"
 
@@ -165,6 +496,11 @@ curl "http://localhost:8080/api/keys"
 curl -X POST "http://localhost:8080/api/command" \
      -H "Content-Type: application/json" \
      -d '{"command": "GET", "args": {"key": "mykey"}}'
+
+# Execute a batch
+curl -X POST "http://localhost:8080/api/batch" \
+     -H "Content-Type: application/json" \
+     -d '{"operations": [{"command": "SET", "args": {"key": "a", "value": "1"}}, {"command": "GET", "args": {"key": "a"}}]}'
 ```
         "#.trim().to_string()
     }
@@ -180,11 +516,11 @@ mod tests {
         let api = RustdisApi::new(cache);
 
         // Test SET
-        let result = api.api_set("test_key".to_string(), "test_value".to_string()).unwrap();
+        let result = api.api_set("test_key".to_string(), "test_value".to_string(), ValueEncoding::Utf8).unwrap();
         assert!(result.contains("OK") || result == "\"OK\"");
 
         // Test GET
-        let result = api.api_get("test_key").unwrap();
+        let result = api.api_get("test_key", ValueEncoding::Utf8).unwrap();
         assert!(result.contains("test_value"));
 
         // Test EXISTS
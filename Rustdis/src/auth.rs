@@ -0,0 +1,413 @@
+use crate::protocol::{Command, ADMIN_COMMANDS, SUPPORTED_COMMANDS};
+use anyhow::Result;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::sync::Arc;
+
+/// User checked when a client authenticates with a password but no username,
+/// as with Redis' `AUTH <password>`
+pub const DEFAULT_USER: &str = "default";
+
+/// Role of users that aren't given one
+pub const DEFAULT_ROLE: &str = "readwrite";
+
+/// Commands a role may run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandAccess {
+    /// Every command, admin commands included
+    All,
+    /// Every command except admin commands (see `Command::is_admin`)
+    Data,
+    /// Only these commands, by wire name (e.g. `GET`, `CONFIG.GET`)
+    Only(BTreeSet<&'static str>),
+}
+
+/// Named set of commands, shared by every front end: RESP connections and
+/// HTTP requests are checked against the same roles
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Role {
+    pub name: String,
+    pub access: CommandAccess,
+}
+
+impl Role {
+    /// Whether every command is allowed, so requests needn't be parsed to
+    /// be checked
+    pub fn is_unrestricted(&self) -> bool {
+        self.access == CommandAccess::All
+    }
+
+    pub fn allows_name(&self, name: &str) -> bool {
+        match &self.access {
+            CommandAccess::All => true,
+            CommandAccess::Data => !ADMIN_COMMANDS.contains(&name),
+            CommandAccess::Only(commands) => commands.contains(name),
+        }
+    }
+
+    /// First command in `command` the role may not run, if any; PROFILE
+    /// needs both itself and the profiled command allowed
+    pub fn denied(&self, command: &Command) -> Option<&'static str> {
+        if !self.allows_name(command.name()) {
+            return Some(command.name());
+        }
+        match command {
+            Command::Profile { command } => self.denied(command),
+            _ => None,
+        }
+    }
+}
+
+/// Roles users can be given, by name
+///
+/// `admin` (every command) and `readwrite` (every non-admin command) are
+/// built in; others are defined as allow-lists.
+#[derive(Debug, Clone)]
+pub struct Roles {
+    roles: HashMap<String, Role>,
+}
+
+impl Default for Roles {
+    fn default() -> Self {
+        let builtin = [("admin", CommandAccess::All), (DEFAULT_ROLE, CommandAccess::Data)];
+        Self {
+            roles: builtin
+                .into_iter()
+                .map(|(name, access)| (name.to_string(), Role { name: name.to_string(), access }))
+                .collect(),
+        }
+    }
+}
+
+impl Roles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defines a role from `name=COMMAND[,COMMAND...]`, replacing any role
+    /// with the same name
+    ///
+    /// Commands are wire names, case-insensitive (e.g. `get,mget,config.get`).
+    pub fn define(&mut self, spec: &str) -> Result<()> {
+        let (name, commands) = spec
+            .split_once('=')
+            .filter(|(name, _)| !name.is_empty() && !name.contains(':'))
+            .ok_or_else(|| anyhow::anyhow!("Invalid role: expected name=COMMAND[,COMMAND...]"))?;
+        let commands = commands
+            .split(',')
+            .map(|command| {
+                let command = command.trim().to_uppercase();
+                SUPPORTED_COMMANDS
+                    .iter()
+                    .find(|name| **name == command)
+                    .copied()
+                    .ok_or_else(|| anyhow::anyhow!("Unknown command in role {}: {}", name, command))
+            })
+            .collect::<Result<BTreeSet<_>>>()?;
+        let role = Role { name: name.to_string(), access: CommandAccess::Only(commands) };
+        self.roles.insert(name.to_string(), role);
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Role> {
+        self.roles.get(name)
+    }
+
+    /// Parses `name` or `name:role` into a user
+    pub fn user(&self, spec: &str) -> Result<AclUser> {
+        let (name, role) = spec.split_once(':').unwrap_or((spec, DEFAULT_ROLE));
+        if name.is_empty() {
+            return Err(anyhow::anyhow!("Invalid user: expected name[:role]"));
+        }
+        let role = self.get(role).ok_or_else(|| anyhow::anyhow!("Unknown role: {}", role))?;
+        Ok(AclUser { name: name.to_string(), role: role.clone() })
+    }
+}
+
+/// Identity a client authenticated as, and what it may run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AclUser {
+    pub name: String,
+    pub role: Role,
+}
+
+impl AclUser {
+    /// Message for a refused command, worded the same on every front end
+    pub fn no_permission(&self, command: &str) -> String {
+        format!("User {} has no permissions to run the '{}' command", self.name, command)
+    }
+}
+
+/// Checks the credentials a client presents
+///
+/// `secret` is a password or a bearer token, depending on the provider.
+/// Providers return `None` for credentials they don't accept, so the next
+/// one can try.
+pub trait AuthProvider: Send + Sync {
+    /// Short name for logs and debug output, e.g. `static`
+    fn name(&self) -> &'static str;
+
+    fn authenticate(&self, username: Option<&str>, secret: &str) -> Option<AclUser>;
+}
+
+/// Users with fixed passwords, configured at startup
+#[derive(Default)]
+pub struct StaticPasswords {
+    users: HashMap<String, (AclUser, String)>,
+}
+
+impl StaticPasswords {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `user`, replacing any user with the same name
+    pub fn insert(&mut self, user: AclUser, password: String) {
+        self.users.insert(user.name.clone(), (user, password));
+    }
+
+    /// Parses `name:password` or `name:password:role`
+    ///
+    /// The name ends at the first `:`. The last `:`-separated part is taken
+    /// as the role only if `roles` defines it, so a password that itself
+    /// ends in `:<role>` needs the role spelled out.
+    pub fn parse_user(spec: &str, roles: &Roles) -> Result<(AclUser, String)> {
+        let invalid = || anyhow::anyhow!("Invalid user: expected name:password[:role]");
+        let (name, password) = spec.split_once(':').ok_or_else(invalid)?;
+        let (password, role) = match password.rsplit_once(':') {
+            Some((password, role)) if roles.get(role).is_some() => (password, role),
+            _ => (password, DEFAULT_ROLE),
+        };
+        if name.is_empty() || password.is_empty() {
+            return Err(invalid());
+        }
+        let role = roles.get(role).ok_or_else(|| anyhow::anyhow!("Unknown role: {}", role))?.clone();
+        Ok((AclUser { name: name.to_string(), role }, password.to_string()))
+    }
+}
+
+impl AuthProvider for StaticPasswords {
+    fn name(&self) -> &'static str {
+        "static"
+    }
+
+    fn authenticate(&self, username: Option<&str>, secret: &str) -> Option<AclUser> {
+        let (user, password) = self.users.get(username.unwrap_or(DEFAULT_USER))?;
+        constant_time_eq(password.as_bytes(), secret.as_bytes()).then(|| user.clone())
+    }
+}
+
+/// Compares without returning early, so timing doesn't reveal how much of a
+/// password matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Providers tried in order until one accepts the credentials
+///
+/// With no providers, authentication is off and every client is let in.
+#[derive(Clone, Default)]
+pub struct Authenticator {
+    providers: Vec<Arc<dyn AuthProvider>>,
+}
+
+impl Authenticator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_provider(mut self, provider: impl AuthProvider + 'static) -> Self {
+        self.providers.push(Arc::new(provider));
+        self
+    }
+
+    /// Whether clients must authenticate before running commands
+    pub fn is_required(&self) -> bool {
+        !self.providers.is_empty()
+    }
+
+    pub fn authenticate(&self, username: Option<&str>, secret: &str) -> Option<AclUser> {
+        self.providers.iter().find_map(|provider| provider.authenticate(username, secret))
+    }
+}
+
+impl fmt::Debug for Authenticator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.providers.iter().map(|provider| provider.name())).finish()
+    }
+}
+
+#[cfg(feature = "oidc")]
+pub use oidc::{OidcConfig, OidcValidator, DEFAULT_ROLES_CLAIM};
+
+#[cfg(feature = "oidc")]
+mod oidc {
+    use super::{AclUser, AuthProvider};
+    use anyhow::Result;
+    use jsonwebtoken::{DecodingKey, Validation};
+    use serde_json::Value;
+
+    /// Claim read for roles unless configured otherwise
+    pub const DEFAULT_ROLES_CLAIM: &str = "roles";
+
+    /// What an OIDC token must carry to be accepted
+    #[derive(Debug, Clone)]
+    pub struct OidcConfig {
+        /// Expected `iss` claim
+        pub issuer: String,
+        /// Expected `aud` claim, usually the client ID Rustdis is registered as
+        pub audience: String,
+        /// Claim holding the roles, as a dotted path (e.g. `realm_access.roles`);
+        /// either an array of strings or a space-separated string
+        pub roles_claim: String,
+        /// Roles mapped to ACL users; the first role the token carries, in
+        /// this order, decides the user
+        pub roles: Vec<(String, AclUser)>,
+    }
+
+    /// Validates OIDC ID or access tokens (JWTs) passed as the password
+    ///
+    /// The signature, `exp`, `iss` and `aud` are checked. Keys aren't fetched
+    /// from the provider's JWKS endpoint: the public key is configured up
+    /// front. Usernames are ignored, since the token alone says who the
+    /// client is.
+    #[derive(Debug)]
+    pub struct OidcValidator {
+        config: OidcConfig,
+        key: DecodingKey,
+        validation: Validation,
+    }
+
+    impl OidcValidator {
+        /// Validates tokens signed by the provider's RSA, EC or Ed25519 key
+        pub fn from_pem(config: OidcConfig, pem: &[u8]) -> Result<Self> {
+            let key = DecodingKey::from_rsa_pem(pem)
+                .or_else(|_| DecodingKey::from_ec_pem(pem))
+                .or_else(|_| DecodingKey::from_ed_pem(pem))
+                .map_err(|_| anyhow::anyhow!("OIDC key must be an RSA, EC or Ed25519 public key in PEM format"))?;
+            Ok(Self::with_key(config, key))
+        }
+
+        /// Validates tokens signed with a shared HMAC secret
+        pub fn from_secret(config: OidcConfig, secret: &[u8]) -> Self {
+            Self::with_key(config, DecodingKey::from_secret(secret))
+        }
+
+        fn with_key(config: OidcConfig, key: DecodingKey) -> Self {
+            let mut validation = Validation::new_for_family(key.family());
+            validation.set_issuer(&[&config.issuer]);
+            validation.set_audience(&[&config.audience]);
+            validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+            Self { config, key, validation }
+        }
+
+        fn roles<'a>(&self, claims: &'a Value) -> Vec<&'a str> {
+            let claim = self.config.roles_claim.split('.').try_fold(claims, |value, part| value.get(part));
+            match claim {
+                Some(Value::Array(roles)) => roles.iter().filter_map(Value::as_str).collect(),
+                Some(Value::String(roles)) => roles.split_whitespace().collect(),
+                _ => Vec::new(),
+            }
+        }
+    }
+
+    impl AuthProvider for OidcValidator {
+        fn name(&self) -> &'static str {
+            "oidc"
+        }
+
+        fn authenticate(&self, _username: Option<&str>, secret: &str) -> Option<AclUser> {
+            let claims = jsonwebtoken::decode::<Value>(secret, &self.key, &self.validation).ok()?.claims;
+            let roles = self.roles(&claims);
+            self.config
+                .roles
+                .iter()
+                .find(|(role, _)| roles.contains(&role.as_str()))
+                .map(|(_, user)| user.clone())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_static_passwords() {
+        let roles = Roles::new();
+        let mut passwords = StaticPasswords::new();
+        for spec in ["default:hunter2", "ops:s3cret:admin", "odd:pass:word"] {
+            let (user, password) = StaticPasswords::parse_user(spec, &roles).unwrap();
+            passwords.insert(user, password);
+        }
+        assert!(StaticPasswords::parse_user("nopassword", &roles).is_err());
+        assert!(StaticPasswords::parse_user(":x", &roles).is_err());
+
+        let auth = Authenticator::new().with_provider(passwords);
+        assert!(auth.is_required());
+        let default = auth.authenticate(None, "hunter2").unwrap();
+        assert_eq!((default.name.as_str(), default.role.name.as_str()), ("default", DEFAULT_ROLE));
+        assert!(auth.authenticate(Some("ops"), "s3cret").unwrap().role.is_unrestricted());
+        assert_eq!(auth.authenticate(Some("odd"), "pass:word").unwrap().role.name, DEFAULT_ROLE);
+        assert_eq!(auth.authenticate(Some("ops"), "hunter2"), None);
+        assert_eq!(auth.authenticate(Some("nobody"), "s3cret"), None);
+        assert!(!Authenticator::new().is_required());
+    }
+
+    #[test]
+    fn test_role_allow_lists() {
+        let mut roles = Roles::new();
+        roles.define("reader=get, mget,Exists").unwrap();
+        roles.define("profiler=PROFILE,GET").unwrap();
+        assert!(roles.define("bad=GET,TELEPORT").is_err());
+        assert!(roles.define("noequals").is_err());
+        assert!(roles.user("app:nope").is_err());
+
+        let parse = |args: &[&str]| crate::protocol::RustdisProtocol::parse_args(args).unwrap();
+        let reader = roles.user("app:reader").unwrap().role;
+        assert_eq!(reader.denied(&parse(&["GET", "k"])), None);
+        assert_eq!(reader.denied(&parse(&["SET", "k", "v"])), Some("SET"));
+        let profiler = roles.get("profiler").unwrap();
+        assert_eq!(profiler.denied(&parse(&["PROFILE", "GET", "k"])), None);
+        assert_eq!(profiler.denied(&parse(&["PROFILE", "DEL", "k"])), Some("DEL"));
+
+        let readwrite = roles.user("app").unwrap().role;
+        assert_eq!(readwrite.denied(&parse(&["SET", "k", "v"])), None);
+        assert_eq!(readwrite.denied(&parse(&["PROFILE", "FLUSH"])), Some("FLUSH"));
+        assert_eq!(roles.get("admin").unwrap().denied(&parse(&["FLUSH"])), None);
+    }
+
+    #[cfg(feature = "oidc")]
+    #[test]
+    fn test_oidc_roles() {
+        use jsonwebtoken::{encode, EncodingKey, Header};
+
+        let roles = Roles::new();
+        let config = OidcConfig {
+            issuer: "https://sso.example.com".to_string(),
+            audience: "rustdis".to_string(),
+            roles_claim: "realm_access.roles".to_string(),
+            roles: vec![
+                ("cache-admin".to_string(), roles.user("ops:admin").unwrap()),
+                ("cache-user".to_string(), roles.user("app").unwrap()),
+            ],
+        };
+        let auth = Authenticator::new().with_provider(OidcValidator::from_secret(config, b"shared"));
+        let token = |iss: &str, roles: &[&str]| {
+            let claims = serde_json::json!({
+                "iss": iss,
+                "aud": "rustdis",
+                "exp": jsonwebtoken::get_current_timestamp() + 300,
+                "realm_access": { "roles": roles },
+            });
+            encode(&Header::default(), &claims, &EncodingKey::from_secret(b"shared")).unwrap()
+        };
+
+        assert_eq!(auth.authenticate(None, &token("https://sso.example.com", &["cache-user"])).unwrap().name, "app");
+        let both = auth.authenticate(Some("ignored"), &token("https://sso.example.com", &["cache-user", "cache-admin"]));
+        assert_eq!(both, roles.user("ops:admin").ok());
+        assert_eq!(auth.authenticate(None, &token("https://sso.example.com", &["viewer"])), None);
+        assert_eq!(auth.authenticate(None, &token("https://evil.example.com", &["cache-admin"])), None);
+        assert_eq!(auth.authenticate(None, "not-a-jwt"), None);
+    }
+}
@@ -0,0 +1,67 @@
+use anyhow::Result;
+
+/// Name of the lock the keyspace shards were built with
+pub const NAME: &str = if cfg!(feature = "dashmap") { "dashmap" } else { "std" };
+
+/// Reader-writer lock guarding one keyspace shard
+///
+/// With the `dashmap` feature, DashMap's own shard lock, which spins briefly
+/// before parking and has no poisoning. Only the lock changes: a shard's keys
+/// stay in an `IndexMap` either way, since SCAN cursors and eviction sampling
+/// index into it.
+#[cfg(feature = "dashmap")]
+pub(crate) type ShardLock<T> = dashmap::RwLock<T>;
+#[cfg(feature = "dashmap")]
+pub(crate) type ShardReadGuard<'a, T> = dashmap::RwLockReadGuard<'a, T>;
+#[cfg(feature = "dashmap")]
+pub(crate) type ShardWriteGuard<'a, T> = dashmap::RwLockWriteGuard<'a, T>;
+
+/// Reader-writer lock guarding one keyspace shard; std's unless the
+/// `dashmap` feature is on
+#[cfg(not(feature = "dashmap"))]
+pub(crate) type ShardLock<T> = std::sync::RwLock<T>;
+#[cfg(not(feature = "dashmap"))]
+pub(crate) type ShardReadGuard<'a, T> = std::sync::RwLockReadGuard<'a, T>;
+#[cfg(not(feature = "dashmap"))]
+pub(crate) type ShardWriteGuard<'a, T> = std::sync::RwLockWriteGuard<'a, T>;
+
+/// Takes `lock` shared
+#[cfg(feature = "dashmap")]
+pub(crate) fn read<T>(lock: &ShardLock<T>) -> Result<ShardReadGuard<'_, T>> {
+    Ok(lock.read())
+}
+
+/// Takes `lock` shared
+#[cfg(not(feature = "dashmap"))]
+pub(crate) fn read<T>(lock: &ShardLock<T>) -> Result<ShardReadGuard<'_, T>> {
+    lock.read().map_err(|_| anyhow::anyhow!("Failed to acquire read lock"))
+}
+
+/// Takes `lock` exclusively
+#[cfg(feature = "dashmap")]
+pub(crate) fn write<T>(lock: &ShardLock<T>) -> Result<ShardWriteGuard<'_, T>> {
+    Ok(lock.write())
+}
+
+/// Takes `lock` exclusively
+#[cfg(not(feature = "dashmap"))]
+pub(crate) fn write<T>(lock: &ShardLock<T>) -> Result<ShardWriteGuard<'_, T>> {
+    lock.write().map_err(|_| anyhow::anyhow!("Failed to acquire write lock"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shard_lock_shares_reads() {
+        let lock = ShardLock::new(0);
+        {
+            let first = read(&lock).unwrap();
+            let second = read(&lock).unwrap();
+            assert_eq!(*first + *second, 0);
+        }
+        *write(&lock).unwrap() += 1;
+        assert_eq!(*read(&lock).unwrap(), 1);
+    }
+}
@@ -0,0 +1,80 @@
+use std::fmt;
+
+/// Append-only byte buffer with a fixed maximum size
+///
+/// Appends extend the buffer in place and reads copy out a byte range, so
+/// log fragments or partially downloaded files can grow without the whole
+/// value being rewritten on every write.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Blob {
+    data: Vec<u8>,
+    max_len: usize,
+}
+
+/// Append that would grow a blob past its maximum size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlobFull {
+    pub max_len: usize,
+}
+
+impl fmt::Display for BlobFull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Blob would exceed its maximum size of {} bytes", self.max_len)
+    }
+}
+
+impl std::error::Error for BlobFull {}
+
+impl Blob {
+    pub fn new(max_len: usize) -> Self {
+        Self { data: Vec::new(), max_len }
+    }
+
+    /// Appends `bytes` at the end, returning the new length; nothing is
+    /// written if the blob would outgrow its maximum size
+    pub fn append(&mut self, bytes: &[u8]) -> Result<usize, BlobFull> {
+        if bytes.len() > self.max_len - self.data.len() {
+            return Err(BlobFull { max_len: self.max_len });
+        }
+        self.data.extend_from_slice(bytes);
+        Ok(self.data.len())
+    }
+
+    /// Up to `len` bytes starting at `offset`; empty past the end
+    pub fn read(&self, offset: usize, len: usize) -> &[u8] {
+        let start = offset.min(self.data.len());
+        let end = start.saturating_add(len).min(self.data.len());
+        &self.data[start..end]
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blob_append_and_read() {
+        let mut blob = Blob::new(8);
+        assert_eq!(blob.append(b"abc").unwrap(), 3);
+        assert_eq!(blob.append(b"defgh").unwrap(), 8);
+        assert_eq!(blob.append(b"i"), Err(BlobFull { max_len: 8 }));
+        assert_eq!(blob.len(), 8);
+
+        assert_eq!(blob.read(2, 3), b"cde");
+        assert_eq!(blob.read(6, 100), b"gh");
+        assert!(blob.read(20, 1).is_empty());
+        assert_eq!(blob.read(0, usize::MAX), b"abcdefgh");
+    }
+}
@@ -1,63 +1,3400 @@
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use anyhow::Result;
+use indexmap::{IndexMap, IndexSet};
+use rand::Rng;
+#[cfg(feature = "chaos")]
+use crate::chaos::FaultInjector;
+#[cfg(feature = "functions")]
+use crate::functions::FunctionRegistry;
+#[cfg(feature = "scripting")]
+use crate::scripting::ScriptRegistry;
+use crate::backend::{self, ShardLock, ShardReadGuard, ShardWriteGuard};
+use crate::blob::{Blob, BlobFull};
+use crate::clock::{Clock, SystemClock};
+use crate::compat::CompatMode;
+use crate::eviction::{self, EvictionPolicy, EvictionPool};
+use crate::glob::glob_match;
+use crate::heatmap::{Heatmap, HeatmapMatrix};
+use crate::snapshot::{self, SnapshotEntry, SnapshotState};
+use crate::warm::WarmState;
+use crate::idgen::{IdGenerator, IdKind};
+use crate::profile;
+use crate::pubsub::{EventFlags, KeyspaceEvents, PubSub};
+use crate::seen::SeenFilter;
+use crate::zset::SortedSet;
+
+/// A value stored under a key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    /// Raw bytes; the string APIs (`get`, `set`, ...) read and write UTF-8
+    /// text, `get_bytes` and `set_bytes` any bytes
+    String(Vec<u8>),
+    /// Field-value pairs in insertion order
+    Hash(IndexMap<String, String>),
+    /// Members ordered by score
+    SortedSet(SortedSet),
+    /// Append-only bytes with a maximum size
+    Blob(Blob),
+}
+
+impl Value {
+    /// Redis type name: `string`, `hash` or `zset`, or `blob`
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Hash(_) => "hash",
+            Value::SortedSet(_) => "zset",
+            Value::Blob(_) => "blob",
+        }
+    }
+
+    /// Approximate bytes charged against maxmemory
+    fn size(&self) -> usize {
+        match self {
+            Value::String(value) => value.len(),
+            Value::Hash(fields) => fields.iter().map(|(field, value)| field.len() + value.len()).sum(),
+            Value::SortedSet(set) => set.size(),
+            Value::Blob(blob) => blob.len(),
+        }
+    }
+}
+
+/// When a SET is allowed to write
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SetCondition {
+    #[default]
+    Always,
+    /// Only if the key doesn't exist (NX)
+    IfMissing,
+    /// Only if the key already exists (XX)
+    IfExists,
+    /// Only if the key holds a string whose `value_tag` is this, as HTTP
+    /// `If-Match` asks
+    IfTag(u64),
+}
+
+/// What a write does to a key's TTL (SET, GETEX)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SetExpiry {
+    /// Drop any TTL, as a plain SET or GETEX PERSIST does
+    #[default]
+    Clear,
+    /// Keep the current TTL (KEEPTTL, or GETEX without options)
+    Keep,
+    /// Expire the key after this long (EX/PX)
+    After(Duration),
+}
+
+/// Options of a SET, as in Redis
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SetOptions {
+    pub condition: SetCondition,
+    pub expiry: SetExpiry,
+    /// Return the previous value (GET); fails with `WrongType` if the key
+    /// holds a non-string value
+    pub get: bool,
+}
+
+/// Result of `set_with` (text) or `set_bytes_with` (bytes)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetOutcome<V = String> {
+    /// False when the condition wasn't met and nothing was written
+    pub written: bool,
+    /// Value before the write; only read when `SetOptions::get` is set
+    pub previous: Option<V>,
+}
+
+/// Metadata about one key, for keyspace analysis
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyMetadata {
+    pub key: String,
+    /// Redis type name (see `Value::type_name`)
+    pub value_type: &'static str,
+    /// Approximate bytes charged against maxmemory, key included
+    pub bytes: usize,
+    /// Time left before the key expires; `None` if it has no TTL
+    pub ttl: Option<Duration>,
+    /// Time since the key was last read or written
+    pub idle: Duration,
+}
+
+impl KeyMetadata {
+    fn new(key: &str, entry: &Entry, now: u64) -> Self {
+        Self {
+            key: key.to_string(),
+            value_type: entry.value.type_name(),
+            bytes: entry.size(key),
+            ttl: entry.expires_at.map(|deadline| Duration::from_millis(deadline - now)),
+            idle: Duration::from_millis(now.saturating_sub(entry.access.last())),
+        }
+    }
+}
+
+/// Result of SAMPLEKEYS
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeySample {
+    /// Keys held when sampling ended, including expired ones not yet removed
+    pub keyspace: usize,
+    /// Distinct live keys drawn, whether or not they matched
+    pub examined: usize,
+    /// Drawn keys that matched
+    pub keys: Vec<KeyMetadata>,
+}
+
+impl KeySample {
+    /// Keys in the keyspace the pattern would match, extrapolated from the
+    /// share of examined keys that matched
+    pub fn estimated_matches(&self) -> usize {
+        if self.examined == 0 {
+            return 0;
+        }
+        (self.keys.len() as f64 / self.examined as f64 * self.keyspace as f64).round() as usize
+    }
+}
+
+/// Random draws per read lock acquisition while sampling keys
+const SAMPLE_BATCH: usize = 64;
+
+/// Random draws per requested key before SAMPLEKEYS settles for a smaller
+/// sample, so a pattern matching almost nothing can't keep it drawing
+const SAMPLE_DRAWS_PER_KEY: usize = 32;
+
+/// Which keys a SCAN returns; `None` filters accept every key
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanFilter {
+    /// Glob the key must match (MATCH)
+    pub pattern: Option<String>,
+    /// Metadata field and a glob its value must match (META)
+    pub meta: Option<(String, String)>,
+}
+
+/// Most metadata fields one key can carry
+pub const MAX_KEY_META_FIELDS: usize = 16;
+
+/// Metadata field (matched ignoring ASCII case) naming the media type a
+/// string value is served as over HTTP
+pub const CONTENT_TYPE_META: &str = "content-type";
+
+/// A string value stored with a `CONTENT_TYPE_META` field (see `get_typed`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypedValue {
+    pub value: Vec<u8>,
+    pub content_type: String,
+    /// Time left before the key expires; `None` if it has no TTL
+    pub ttl: Option<Duration>,
+}
+
+/// Tag of a string value, from which HTTP ETags are made
+///
+/// FNV-1a, so tags stay the same across restarts and Rust releases and a
+/// client's ETag outlives a server upgrade.
+pub fn value_tag(value: &[u8]) -> u64 {
+    value.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3))
+}
+
+/// Operation against a key holding a different kind of value, e.g. HGET on
+/// a string
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WrongType;
+
+impl fmt::Display for WrongType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Operation against a key holding the wrong kind of value")
+    }
+}
+
+impl std::error::Error for WrongType {}
+
+/// Write that refuses to replace an existing key, e.g. RESTORE without
+/// REPLACE
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusyKey;
+
+impl fmt::Display for BusyKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Target key name already exists")
+    }
+}
+
+impl std::error::Error for BusyKey {}
+
+/// Operation that needs an existing key, e.g. RENAME of a missing key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoSuchKey;
+
+impl fmt::Display for NoSuchKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "No such key")
+    }
+}
+
+impl std::error::Error for NoSuchKey {}
+
+/// Write to a cache serving a snapshot read-only (see
+/// `RustdisCache::set_read_only`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadOnly;
+
+impl fmt::Display for ReadOnly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "You can't write against a read-only snapshot server")
+    }
+}
+
+impl std::error::Error for ReadOnly {}
+
+/// Text read of a string value holding bytes that aren't UTF-8; such values
+/// are only readable with `get_bytes`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotUtf8;
+
+impl fmt::Display for NotUtf8 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Value is binary, not UTF-8 text")
+    }
+}
+
+impl std::error::Error for NotUtf8 {}
+
+/// SAVE or BGSAVE while another save is still writing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaveInProgress;
+
+impl fmt::Display for SaveInProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "A save is already in progress")
+    }
+}
+
+impl std::error::Error for SaveInProgress {}
+
+/// Wall-clock time in milliseconds since the Unix epoch, for timestamps that
+/// outlive the process (snapshots, heatmap rows)
+pub(crate) fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+/// Converts a stored value for the text APIs
+fn utf8(value: Vec<u8>) -> Result<String> {
+    String::from_utf8(value).map_err(|_| NotUtf8.into())
+}
+
+/// Fails with `NotUtf8` before a text API changes a key it couldn't return
+fn check_utf8(value: &[u8]) -> Result<()> {
+    std::str::from_utf8(value).map(|_| ()).map_err(|_| NotUtf8.into())
+}
+
+/// Arithmetic on a value that isn't a number, or whose result doesn't fit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumberError {
+    NotInteger,
+    NotFloat,
+    Overflow,
+}
+
+impl fmt::Display for NumberError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NumberError::NotInteger => write!(f, "Value is not an integer or out of range"),
+            NumberError::NotFloat => write!(f, "Value is not a valid float"),
+            NumberError::Overflow => write!(f, "Increment or decrement would overflow"),
+        }
+    }
+}
+
+impl std::error::Error for NumberError {}
+
+/// A stored value with its access metadata
+#[derive(Debug)]
+struct Entry {
+    value: Value,
+    access: Access,
+    /// Milliseconds since the cache epoch of the last write
+    written_at: u64,
+    /// Pinned entries are never evicted by maxmemory
+    pinned: bool,
+    /// Milliseconds since the cache epoch after which the key is gone
+    expires_at: Option<u64>,
+    /// User metadata (META.SET) in the order fields were first set; a Vec
+    /// since keys carry at most `MAX_KEY_META_FIELDS`
+    meta: Vec<(String, String)>,
+    /// Changes with every write to the value or TTL, for WATCH
+    version: u64,
+}
+
+impl Entry {
+    fn new(value: Value, now: u64) -> Self {
+        Self {
+            value,
+            access: Access::new(now),
+            written_at: now,
+            pinned: false,
+            expires_at: None,
+            meta: Vec::new(),
+            version: 0,
+        }
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|deadline| now >= deadline)
+    }
+
+    /// Approximate bytes charged against maxmemory for this entry
+    fn size(&self, key: &str) -> usize {
+        key.len() + self.value.size() + meta_size(&self.meta)
+    }
+}
+
+/// When and how often an entry was read or written, for eviction
+///
+/// Atomics so reads can record an access under the shared data lock.
+#[derive(Debug)]
+struct Access {
+    /// Milliseconds since the cache epoch of the last read or write
+    last: AtomicU64,
+    /// Logarithmic LFU counter as of `last` (see `eviction::lfu_increment`)
+    frequency: AtomicU8,
+}
+
+impl Access {
+    fn new(now: u64) -> Self {
+        Self {
+            last: AtomicU64::new(now),
+            frequency: AtomicU8::new(eviction::LFU_INIT_VAL),
+        }
+    }
+
+    /// Records a read or write at `now`
+    fn touch(&self, now: u64) {
+        let frequency = eviction::lfu_increment(self.frequency(now));
+        self.frequency.store(frequency, Ordering::Relaxed);
+        self.last.store(now, Ordering::Relaxed);
+    }
+
+    fn last(&self) -> u64 {
+        self.last.load(Ordering::Relaxed)
+    }
+
+    /// LFU counter at `now`, decayed for the time since the last access
+    fn frequency(&self, now: u64) -> u8 {
+        eviction::lfu_decay(self.frequency.load(Ordering::Relaxed), now.saturating_sub(self.last()))
+    }
+}
+
+/// Bookkeeping bytes per key that `Entry::size` doesn't charge: the key's
+/// `String` and its `Entry` in the index map, and the stored hash
+const KEY_OVERHEAD: usize = std::mem::size_of::<String>() + std::mem::size_of::<Entry>() + std::mem::size_of::<u64>();
+
+/// Bookkeeping bytes per key with a TTL, for its copy in the expires index
+/// (plus the key's length)
+const VOLATILE_KEY_OVERHEAD: usize = std::mem::size_of::<String>() + std::mem::size_of::<u64>();
+
+/// Bytes of key metadata charged against maxmemory
+fn meta_size(meta: &[(String, String)]) -> usize {
+    meta.iter().map(|(field, value)| field.len() + value.len()).sum()
+}
+
+/// Keyspace and memory accounting, guarded by the data lock
+#[derive(Debug)]
+struct Store {
+    /// IndexMap gives O(1) random access for eviction sampling
+    entries: IndexMap<String, Entry>,
+    /// Approximate bytes used by keys and values
+    used_bytes: usize,
+    /// Highest `used_bytes` since startup
+    peak_bytes: usize,
+    /// Memory limit in bytes; 0 means unlimited
+    maxmemory: usize,
+    /// Keys sampled per eviction
+    samples: usize,
+    /// Which keys are sampled and how they are scored
+    policy: EvictionPolicy,
+    pool: EvictionPool,
+    evictions: u64,
+    /// Keys removed because their TTL ran out
+    expired: u64,
+    /// Keys that have a TTL, sampled by the active expire cycle
+    expires: IndexSet<String>,
+    /// Glob patterns whose keys are pinned without an explicit PIN
+    pin_patterns: Vec<String>,
+    /// Glob patterns and the percentage by which TTLs set on their keys are
+    /// randomly spread; the first match applies
+    ttl_jitter: Vec<(String, u64)>,
+    /// Milliseconds after a write during which a key is only evicted if
+    /// every other unpinned key was written as recently; 0 disables
+    protect_recent: u64,
+    /// Publishes `expired` and `evicted` notifications
+    events: Arc<KeyspaceEvents>,
+    /// Last entry version handed out
+    version: u64,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Self {
+            entries: IndexMap::new(),
+            used_bytes: 0,
+            peak_bytes: 0,
+            maxmemory: 0,
+            samples: eviction::DEFAULT_SAMPLES,
+            policy: EvictionPolicy::default(),
+            pool: EvictionPool::default(),
+            evictions: 0,
+            expired: 0,
+            expires: IndexSet::new(),
+            pin_patterns: Vec::new(),
+            ttl_jitter: Vec::new(),
+            protect_recent: 0,
+            events: Arc::new(KeyspaceEvents::default()),
+            version: 0,
+        }
+    }
+}
+
+impl Store {
+    fn insert(&mut self, key: String, mut entry: Entry) {
+        entry.version = self.next_version();
+        self.used_bytes += entry.size(&key);
+        if let Some(old) = self.entries.get(&key) {
+            self.used_bytes -= old.size(&key);
+            // An overwrite is one more access to the key, not a new key, so
+            // LFU keeps counting from the old value's frequency
+            let now = entry.access.last();
+            entry.access.frequency.store(old.access.frequency(now), Ordering::Relaxed);
+            entry.access.touch(now);
+        }
+        if entry.expires_at.is_some() {
+            self.expires.insert(key.clone());
+        } else {
+            self.expires.swap_remove(&key);
+        }
+        self.entries.insert(key, entry);
+        self.peak_bytes = self.peak_bytes.max(self.used_bytes);
+    }
+
+    /// Accounts for a value changed in place
+    fn resize(&mut self, grown: usize, freed: usize) {
+        self.used_bytes = self.used_bytes + grown - freed;
+        self.peak_bytes = self.peak_bytes.max(self.used_bytes);
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.entries.swap_remove(key)?;
+        self.used_bytes -= entry.size(key);
+        if entry.expires_at.is_some() {
+            self.expires.swap_remove(key);
+        }
+        Some(entry)
+    }
+
+    /// A version no entry has had yet, for one changed in place
+    fn next_version(&mut self) -> u64 {
+        self.version += 1;
+        self.version
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.expires.clear();
+        self.pool.clear();
+        self.used_bytes = 0;
+    }
+
+    fn is_pinned(&self, key: &str, entry: &Entry) -> bool {
+        entry.pinned || self.pin_patterns.iter().any(|pattern| glob_match(pattern, key))
+    }
+
+    /// `ttl` moved by a random amount within the first `ttl_jitter` pattern
+    /// matching `key`, so keys written together don't all expire together
+    fn jittered(&self, key: &str, ttl: Duration) -> Duration {
+        let Some((_, percent)) = self.ttl_jitter.iter().find(|(pattern, _)| glob_match(pattern, key)) else {
+            return ttl;
+        };
+        let ttl = ttl.as_millis() as u64;
+        let spread = ttl.saturating_mul(*percent) / 100;
+        Duration::from_millis(rand::thread_rng().gen_range(ttl - spread..=ttl.saturating_add(spread)))
+    }
+
+    /// Whether `entry` was written within the `protect_recent` window
+    fn is_recent(&self, entry: &Entry, now: u64) -> bool {
+        self.protect_recent > 0 && now.saturating_sub(entry.written_at) < self.protect_recent
+    }
+
+    /// Number of keys the policy may evict from: those with a TTL for the
+    /// volatile policies, every key otherwise
+    fn population(&self) -> usize {
+        if self.policy.is_volatile() {
+            self.expires.len()
+        } else {
+            self.entries.len()
+        }
+    }
+
+    /// The `index`th key eviction samples from (see `population`)
+    fn candidate(&self, index: usize) -> Option<(&String, &Entry)> {
+        if self.policy.is_volatile() {
+            self.expires.get_index(index).and_then(|key| self.entries.get_key_value(key))
+        } else {
+            self.entries.get_index(index)
+        }
+    }
+
+    /// How good a victim `entry` is under the policy: idle time for LRU,
+    /// the inverse of the decayed access frequency for LFU, nearness of the
+    /// deadline for volatile-ttl; random policies score every key the same,
+    /// the sample being random already
+    fn eviction_score(&self, entry: &Entry, now: u64) -> u64 {
+        match self.policy {
+            policy if policy.is_lfu() => (u8::MAX - entry.access.frequency(now)) as u64,
+            policy if policy.is_random() => 0,
+            EvictionPolicy::VolatileTtl => u64::MAX - entry.expires_at.unwrap_or(u64::MAX),
+            _ => now.saturating_sub(entry.access.last()),
+        }
+    }
+
+    /// Samples random keys into the pool, scored with `eviction_score`
+    ///
+    /// When the population is no larger than the sample size every key is
+    /// offered. Pinned keys and `excluded` are never offered, nor recently
+    /// written keys while `protect` is set.
+    fn sample_into_pool(&mut self, now: u64, protect: bool, excluded: &str) {
+        let len = self.population();
+        let mut rng = rand::thread_rng();
+        let indexes: Vec<usize> = if self.samples >= len {
+            (0..len).collect()
+        } else {
+            (0..self.samples).map(|_| rng.gen_range(0..len)).collect()
+        };
+        // Out of `self` while sampling, which borrows the keys it offers
+        let mut pool = std::mem::take(&mut self.pool);
+        for index in indexes {
+            let Some((key, entry)) = self.candidate(index) else {
+                continue;
+            };
+            if key == excluded || self.is_pinned(key, entry) || (protect && self.is_recent(entry, now)) {
+                continue;
+            }
+            pool.offer(self.eviction_score(entry, now), key);
+        }
+        self.pool = pool;
+    }
+
+    /// Picks the next key to evict by sampling, per the policy
+    ///
+    /// `excluded` (the key being written) is never picked. Recently written
+    /// keys (see `protect_recent`) are passed over, unless nothing else can
+    /// be evicted. Under noeviction there is never a victim.
+    fn next_victim(&mut self, now: u64, excluded: &str) -> Option<String> {
+        if self.policy == EvictionPolicy::NoEviction {
+            return None;
+        }
+        let victim = self.next_victim_with(now, self.protect_recent > 0, excluded);
+        if victim.is_none() && self.protect_recent > 0 {
+            return self.next_victim_with(now, false, excluded);
+        }
+        victim
+    }
+
+    fn next_victim_with(&mut self, now: u64, protect: bool, excluded: &str) -> Option<String> {
+        loop {
+            if self.pool.is_empty() {
+                self.sample_into_pool(now, protect, excluded);
+            }
+            if self.pool.is_empty() && self.samples < self.population() {
+                // The sample hit only pinned, protected or excluded keys; fall
+                // back to a full scan so OOM is only reported when nothing
+                // else can go
+                let samples = std::mem::replace(&mut self.samples, usize::MAX);
+                self.sample_into_pool(now, protect, excluded);
+                self.samples = samples;
+            }
+            let (_, key) = self.pool.pop()?;
+            // Pool entries may refer to keys deleted or rewritten since they
+            // were sampled, or be a key sampled for another write
+            if key == excluded {
+                continue;
+            }
+            match self.entries.get(&key) {
+                Some(entry) if protect && self.is_recent(entry, now) => continue,
+                Some(entry) if self.policy.is_volatile() && entry.expires_at.is_none() => continue,
+                Some(_) => return Some(key),
+                None => continue,
+            }
+        }
+    }
+}
+
+/// Key cap for a prefix namespace (e.g. `recent:*`), trimmed oldest-first
+#[derive(Debug, Default)]
+struct Namespace {
+    max_keys: usize,
+    /// Write sequence counter used to order members
+    seq: u64,
+    /// Live members of the namespace and the sequence of their last write
+    members: HashMap<String, u64>,
+    /// Write order; entries whose sequence no longer matches `members` are stale
+    order: VecDeque<(u64, String)>,
+}
+
+impl Namespace {
+    fn touch(&mut self, key: &str) {
+        self.seq += 1;
+        self.members.insert(key.to_string(), self.seq);
+        self.order.push_back((self.seq, key.to_string()));
+    }
+
+    fn forget(&mut self, key: &str) {
+        self.members.remove(key);
+        if self.order.len() > 2 * self.members.len() + 16 {
+            let members = &self.members;
+            self.order.retain(|(seq, key)| members.get(key) == Some(seq));
+        }
+    }
+
+    /// Removes the oldest members until the namespace is within its cap
+    fn trim(&mut self) -> Vec<String> {
+        let mut evicted = Vec::new();
+        while self.members.len() > self.max_keys {
+            let Some((seq, key)) = self.order.pop_front() else { break };
+            if self.members.get(&key) == Some(&seq) {
+                self.members.remove(&key);
+                evicted.push(key);
+            }
+        }
+        evicted
+    }
+}
+
+/// Namespace caps, locked only by operations holding every shard (see
+/// `RustdisCache::lock_namespaces`); `None` while no NS.LIMIT is set
+type NamespacesGuard<'a> = Option<MutexGuard<'a, HashMap<String, Namespace>>>;
+
+/// Most shards a cache can be split into (see `RustdisCache::sharded`)
+pub const MAX_SHARDS: usize = 256;
+
+/// Low bits of a SCAN cursor, holding the position within a shard; the bits
+/// above hold the shard, so a single shard's cursors are plain positions
+const SHARD_CURSOR_BITS: u32 = usize::BITS - MAX_SHARDS.trailing_zeros();
+
+/// Shard holding `key` in a keyspace split into `shards`
+fn shard_of(key: &str, shards: usize) -> usize {
+    if shards == 1 {
+        return 0;
+    }
+    // Fixed hasher keys, so SCAN cursors stay valid across restarts
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    (hasher.finish() % shards as u64) as usize
+}
+
+/// Index drawn at random in proportion to `weights`, whose sum must be
+/// positive
+fn weighted_index(rng: &mut impl Rng, weights: &[usize]) -> usize {
+    let mut slot = rng.gen_range(0..weights.iter().sum::<usize>());
+    let mut index = 0;
+    while slot >= weights[index] {
+        slot -= weights[index];
+        index += 1;
+    }
+    index
+}
+
+/// Share of `maxmemory` the `index`th of `shards` shards may use; the
+/// shares add up to `maxmemory`
+fn shard_budget(maxmemory: usize, shards: usize, index: usize) -> usize {
+    maxmemory / shards + usize::from(index < maxmemory % shards)
+}
+
+/// Locks on the shards one operation touches, taken in shard order so
+/// operations locking several never deadlock
+///
+/// Derefs to the shard of the key the operation was locked for.
+struct Shards<G> {
+    guards: Vec<(usize, G)>,
+    count: usize,
+    primary: usize,
+}
+
+impl<G: Deref<Target = Store>> Shards<G> {
+    /// Whether every shard is locked, as for writes while namespaces exist
+    fn holds_all(&self) -> bool {
+        self.guards.len() == self.count
+    }
+
+    /// Shard holding `key`, which must be one of those locked
+    fn shard(&self, key: &str) -> &Store {
+        let index = shard_of(key, self.count);
+        let (_, guard) = self.guards.iter().find(|(locked, _)| *locked == index).expect("shard was not locked");
+        guard
+    }
+
+    fn stores(&self) -> impl Iterator<Item = &Store> {
+        self.guards.iter().map(|(_, guard)| &**guard)
+    }
+}
+
+impl<G: DerefMut<Target = Store>> Shards<G> {
+    fn shard_mut(&mut self, key: &str) -> &mut Store {
+        let index = shard_of(key, self.count);
+        let (_, guard) = self.guards.iter_mut().find(|(locked, _)| *locked == index).expect("shard was not locked");
+        guard
+    }
+
+    fn stores_mut(&mut self) -> impl Iterator<Item = &mut Store> {
+        self.guards.iter_mut().map(|(_, guard)| &mut **guard)
+    }
+}
+
+impl<G: Deref<Target = Store>> Deref for Shards<G> {
+    type Target = Store;
+
+    fn deref(&self) -> &Store {
+        let (_, guard) = self.guards.iter().find(|(index, _)| *index == self.primary).expect("shard was not locked");
+        guard
+    }
+}
+
+impl<G: DerefMut<Target = Store>> DerefMut for Shards<G> {
+    fn deref_mut(&mut self) -> &mut Store {
+        let primary = self.primary;
+        let (_, guard) = self.guards.iter_mut().find(|(index, _)| *index == primary).expect("shard was not locked");
+        guard
+    }
+}
+
+/// Acquisition counts and cumulative wait times for the shard locks, each
+/// operation counted once however many shards it locks
+#[derive(Debug, Default)]
+struct LockStats {
+    reads: AtomicU64,
+    writes: AtomicU64,
+    read_wait_ns: AtomicU64,
+    write_wait_ns: AtomicU64,
+    max_wait_ns: AtomicU64,
+}
+
+impl LockStats {
+    fn record(&self, write: bool, waited: Duration) {
+        let waited = waited.as_nanos() as u64;
+        let (count, total) = if write {
+            (&self.writes, &self.write_wait_ns)
+        } else {
+            (&self.reads, &self.read_wait_ns)
+        };
+        count.fetch_add(1, Ordering::Relaxed);
+        total.fetch_add(waited, Ordering::Relaxed);
+        self.max_wait_ns.fetch_max(waited, Ordering::Relaxed);
+    }
+}
+
+/// Memory section of INFO
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryInfo {
+    pub keys: usize,
+    pub used_bytes: usize,
+    pub maxmemory: usize,
+    pub evictions: u64,
+    pub pinned_keys: usize,
+    pub pinned_bytes: usize,
+}
+
+/// Result of MEMORY STATS
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryStats {
+    pub keys: usize,
+    /// Keys with a TTL
+    pub volatile_keys: usize,
+    /// Bytes of keys, values and metadata, as charged against maxmemory
+    pub dataset_bytes: usize,
+    /// Estimated bytes of per-key bookkeeping on top of `dataset_bytes`
+    pub overhead_bytes: usize,
+    /// Highest `dataset_bytes` since startup; with several shards, the sum
+    /// of each shard's peak, so an upper bound
+    pub peak_bytes: usize,
+    pub maxmemory: usize,
+}
+
+impl MemoryStats {
+    pub fn total_bytes(&self) -> usize {
+        self.dataset_bytes + self.overhead_bytes
+    }
+}
+
+/// Most sampling rounds in one active expire cycle
+const MAX_EXPIRE_ROUNDS: usize = 16;
+
+/// Longest the pause between cycles grows to under load, in intervals
+const MAX_EXPIRE_BACKOFF: u32 = 16;
+
+/// Settings for the background active expire cycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ActiveExpireConfig {
+    /// Pause between cycles
+    pub interval: Duration,
+    /// Keys with a TTL sampled per round
+    pub samples: usize,
+    /// Share of `interval`, in percent, one cycle may run for
+    pub max_effort: u8,
+    /// Store lock acquisitions per second above which the pause between
+    /// cycles doubles, up to `MAX_EXPIRE_BACKOFF` intervals (0 never backs off)
+    pub busy_ops: u64,
+}
+
+impl Default for ActiveExpireConfig {
+    /// Redis' defaults: 10 cycles a second, 20 keys per sample, at most 25%
+    /// of the time spent expiring
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(100),
+            samples: 20,
+            max_effort: 25,
+            busy_ops: 100_000,
+        }
+    }
+}
+
+impl ActiveExpireConfig {
+    /// Longest one cycle may run for
+    pub fn budget(&self) -> Duration {
+        self.interval * u32::from(self.max_effort.min(100)) / 100
+    }
+}
+
+/// Counters kept by active expire cycles, and where the next one starts
+#[derive(Debug, Default)]
+struct ExpireStats {
+    /// Shard the next cycle starts from: the one a spent budget cut short,
+    /// so cycles take turns over every shard instead of favouring the first
+    next_shard: AtomicUsize,
+    cycles: AtomicU64,
+    /// Keys removed by cycles, as opposed to on access
+    expired: AtomicU64,
+    last_expired: AtomicU64,
+    /// Cycles stopped by their time budget with expired keys likely left
+    timed_out: AtomicU64,
+    /// Cycles that ran after a lengthened pause
+    backed_off: AtomicU64,
+    /// Current pause between cycles, in intervals
+    backoff: AtomicU64,
+}
+
+/// Running background expiry thread; stopped and joined on drop
+#[derive(Debug)]
+pub struct ActiveExpire {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for ActiveExpire {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+/// How often the auto-save thread checks the save points
+pub const AUTO_SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Running auto-save thread; stopped and joined on drop
+#[derive(Debug)]
+pub struct AutoSave {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for AutoSave {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Remaining lifetime of a key, as reported by TTL and PTTL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ttl {
+    Missing,
+    /// The key exists and never expires
+    Persistent,
+    Expires(Duration),
+}
+
+/// Result of DEBUG STATE
+///
+/// Only covers state that exists in this build: there is no replication or
+/// AOF state to report. Counters are summed across shards.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugState {
+    pub shards: usize,
+    pub keys: usize,
+    pub used_bytes: usize,
+    pub maxmemory: usize,
+    pub evictions: u64,
+    /// Keys removed because their TTL ran out
+    pub expired: u64,
+    /// Keys that currently have a TTL
+    pub volatile_keys: usize,
+    /// Active expire cycles run so far
+    pub expire_cycles: u64,
+    /// Keys removed by active expire cycles
+    pub expired_by_cycles: u64,
+    /// Keys removed by the most recent cycle
+    pub expired_last_cycle: u64,
+    /// Cycles cut short by their time budget
+    pub expire_timed_out: u64,
+    /// Cycles run after backing off under load
+    pub expire_backed_off: u64,
+    /// Current pause between cycles, in intervals (1 when not backing off)
+    pub expire_backoff: u64,
+    /// Candidates waiting in the eviction pool
+    pub pending_evictions: usize,
+    pub namespaces: usize,
+    pub seen_filters: usize,
+    pub lock_reads: u64,
+    pub lock_writes: u64,
+    pub lock_read_wait_us: u64,
+    pub lock_write_wait_us: u64,
+    pub lock_max_wait_us: u64,
+}
+
+/// Result of EVICTION DEBUG
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvictionReport {
+    pub keys: usize,
+    pub samples: usize,
+    pub pool_size: usize,
+    pub evictions: u64,
+    pub used_bytes: usize,
+    pub maxmemory: usize,
+    pub policy: EvictionPolicy,
+    pub trials: usize,
+    /// Mean fraction of keys that were better victims than the sampled pick
+    /// (0.0 = exact LRU or LFU)
+    pub mean_rank: f64,
+    /// Worst fraction observed across trials
+    pub worst_rank: f64,
+}
 
 /// Core cache structure using HashMap
+///
+/// The keyspace is split into shards, each behind its own lock, so writes
+/// to keys in different shards don't wait for each other (see `sharded`).
+/// Lock order: shards are always acquired in index order, and before
+/// `namespaces` and `seen`.
 #[derive(Debug, Clone)]
 pub struct RustdisCache {
-    data: Arc<RwLock<HashMap<String, String>>>,
+    shards: Arc<[ShardLock<Store>]>,
+    namespaces: Arc<Mutex<HashMap<String, Namespace>>>,
+    /// Whether any NS.LIMIT is set; only changed with every shard locked
+    namespaced: Arc<AtomicBool>,
+    seen: Arc<Mutex<HashMap<String, SeenFilter>>>,
+    ids: Arc<IdGenerator>,
+    lock_stats: Arc<LockStats>,
+    expire_stats: Arc<ExpireStats>,
+    heatmap: Arc<Mutex<Heatmap>>,
+    snapshots: Arc<SnapshotState>,
+    warmers: Arc<WarmState>,
+    pubsub: Arc<PubSub>,
+    events: Arc<KeyspaceEvents>,
+    /// Held shared by every protocol command and exclusively by EXEC
+    transactions: Arc<RwLock<()>>,
+    /// Whether new RESP connections start in `CompatMode::Strict`
+    redis_compat_strict: Arc<AtomicBool>,
+    /// Refuses every data write (see `set_read_only`)
+    read_only: Arc<AtomicBool>,
+    #[cfg(feature = "chaos")]
+    faults: Arc<FaultInjector>,
+    #[cfg(feature = "functions")]
+    functions: Arc<FunctionRegistry>,
+    #[cfg(feature = "scripting")]
+    scripts: Arc<ScriptRegistry>,
+    clock: Arc<dyn Clock>,
+    /// Reference point for access times
+    epoch: Instant,
 }
 
-impl RustdisCache {
-    /// Creates a new empty cache
-    pub fn new() -> Self {
-        Self {
-            data: Arc::new(RwLock::new(HashMap::new())),
+impl RustdisCache {
+    /// Creates a new empty cache
+    pub fn new() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+
+    /// Creates a new empty cache driven by the given clock
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        let pubsub = Arc::new(PubSub::default());
+        let events = Arc::new(KeyspaceEvents::new(pubsub.clone()));
+        Self {
+            shards: Arc::new([ShardLock::new(Store { events: events.clone(), ..Store::default() })]),
+            namespaces: Arc::new(Mutex::new(HashMap::new())),
+            namespaced: Arc::new(AtomicBool::new(false)),
+            seen: Arc::new(Mutex::new(HashMap::new())),
+            ids: Arc::new(IdGenerator::default()),
+            lock_stats: Arc::new(LockStats::default()),
+            expire_stats: Arc::new(ExpireStats::default()),
+            heatmap: Arc::new(Mutex::new(Heatmap::new())),
+            snapshots: Arc::new(SnapshotState::default()),
+            warmers: Arc::new(WarmState::default()),
+            pubsub,
+            events,
+            transactions: Arc::new(RwLock::new(())),
+            redis_compat_strict: Arc::new(AtomicBool::new(false)),
+            read_only: Arc::new(AtomicBool::new(false)),
+            #[cfg(feature = "chaos")]
+            faults: Arc::new(FaultInjector::default()),
+            #[cfg(feature = "functions")]
+            functions: Arc::new(FunctionRegistry::default()),
+            #[cfg(feature = "scripting")]
+            scripts: Arc::new(ScriptRegistry::default()),
+            epoch: clock.now(),
+            clock,
+        }
+    }
+
+    /// Creates a new empty cache whose snowflake IDs carry the given node ID
+    pub fn with_node_id(node_id: u16) -> Result<Self> {
+        Ok(Self {
+            ids: Arc::new(IdGenerator::new(node_id)?),
+            ..Self::new()
+        })
+    }
+
+    /// Splits the keyspace of a new cache into `shards` lock-partitioned
+    /// shards (1 to `MAX_SHARDS`), for write throughput across many
+    /// connections
+    ///
+    /// A key's hash picks its shard. Each shard gets an equal share of
+    /// maxmemory and evicts from its own keys, so a skewed keyspace may
+    /// evict from one shard while others still have room. While any NS.LIMIT
+    /// is set, writes lock every shard, since trimming a namespace can
+    /// remove keys from any of them. Keys already stored are dropped.
+    pub fn sharded(mut self, shards: usize) -> Self {
+        self.shards = (0..shards.clamp(1, MAX_SHARDS)).map(|_| ShardLock::new(Store { events: self.events.clone(), ..Store::default() })).collect();
+        self
+    }
+
+    /// Number of lock-partitioned shards (see `sharded`)
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Read lock on the shard holding `key`
+    fn read_shard(&self, key: &str) -> Result<ShardReadGuard<'_, Store>> {
+        self.read_shard_at(shard_of(key, self.shards.len()))
+    }
+
+    fn read_shard_at(&self, index: usize) -> Result<ShardReadGuard<'_, Store>> {
+        let started = Instant::now();
+        let guard = backend::read(&self.shards[index])?;
+        self.record_wait(false, started.elapsed());
+        Ok(guard)
+    }
+
+    /// Read locks on the shards holding `keys`, or on every shard if `keys`
+    /// is empty
+    fn read_shards(&self, keys: &[&str]) -> Result<Shards<ShardReadGuard<'_, Store>>> {
+        let started = Instant::now();
+        let indexes = if keys.is_empty() { (0..self.shards.len()).collect() } else { self.shard_indexes(keys) };
+        let mut guards = Vec::with_capacity(indexes.len());
+        for index in indexes {
+            guards.push((index, backend::read(&self.shards[index])?));
+        }
+        self.record_wait(false, started.elapsed());
+        Ok(Shards { guards, count: self.shards.len(), primary: self.primary_shard(keys) })
+    }
+
+    /// Write locks for changing data at `keys`, or every shard if `keys` is
+    /// empty; fails with `ReadOnly` on a read-only cache
+    ///
+    /// Every shard is locked while namespaces exist (see `lock_namespaces`).
+    fn write_shards(&self, keys: &[&str]) -> Result<Shards<ShardWriteGuard<'_, Store>>> {
+        self.check_writable()?;
+        self.lock_shards((!keys.is_empty()).then(|| self.shard_indexes(keys)), self.primary_shard(keys))
+    }
+
+    /// `write_shards` for the shard holding `key`
+    fn write_shard(&self, key: &str) -> Result<Shards<ShardWriteGuard<'_, Store>>> {
+        self.write_shards(&[key])
+    }
+
+    /// `write_shards` for the `index`th shard
+    fn write_shard_at(&self, index: usize) -> Result<Shards<ShardWriteGuard<'_, Store>>> {
+        self.check_writable()?;
+        self.lock_shards(Some(vec![index]), index)
+    }
+
+    /// Write locks on every shard for changing settings, allowed on a
+    /// read-only cache
+    fn settings_shards(&self) -> Result<Shards<ShardWriteGuard<'_, Store>>> {
+        self.lock_shards(None, 0)
+    }
+
+    /// Write locks on the `wanted` shards, or on every shard if `None` or
+    /// while namespaces exist
+    fn lock_shards(&self, wanted: Option<Vec<usize>>, primary: usize) -> Result<Shards<ShardWriteGuard<'_, Store>>> {
+        let started = Instant::now();
+        loop {
+            let indexes = match &wanted {
+                Some(indexes) if !self.namespaced.load(Ordering::Acquire) => indexes.clone(),
+                _ => (0..self.shards.len()).collect(),
+            };
+            let all = indexes.len() == self.shards.len();
+            let mut guards = Vec::with_capacity(indexes.len());
+            for index in indexes {
+                guards.push((index, backend::write(&self.shards[index])?));
+            }
+            // NS.LIMIT may have set the first namespace while we waited
+            if all || !self.namespaced.load(Ordering::Acquire) {
+                self.record_wait(true, started.elapsed());
+                return Ok(Shards { guards, count: self.shards.len(), primary });
+            }
+        }
+    }
+
+    /// Sorted, distinct shards holding `keys`
+    fn shard_indexes(&self, keys: &[&str]) -> Vec<usize> {
+        let mut indexes: Vec<usize> = keys.iter().map(|key| shard_of(key, self.shards.len())).collect();
+        indexes.sort_unstable();
+        indexes.dedup();
+        indexes
+    }
+
+    fn primary_shard(&self, keys: &[&str]) -> usize {
+        keys.first().map_or(0, |key| shard_of(key, self.shards.len()))
+    }
+
+    fn record_wait(&self, write: bool, waited: Duration) {
+        self.lock_stats.record(write, waited);
+        profile::record_lock_wait(waited);
+    }
+
+    /// The namespace caps for a write holding `data`: only locked when the
+    /// write holds every shard, since otherwise no namespace exists (see
+    /// `write_shards`), and the lock would serialize writes to different
+    /// shards
+    fn lock_namespaces<G: Deref<Target = Store>>(&self, data: &Shards<G>) -> Result<NamespacesGuard<'_>> {
+        if !data.holds_all() {
+            return Ok(None);
+        }
+        Ok(Some(self.namespaces.lock().map_err(|_| anyhow::anyhow!("Failed to acquire namespace lock"))?))
+    }
+
+    /// GET operation - retrieves value by key
+    ///
+    /// A key past its expiry reads as missing and is removed on the spot.
+    /// Fails with `WrongType` if the key doesn't hold a string, and with
+    /// `NotUtf8` if it holds binary data.
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        self.get_bytes(key)?.map(utf8).transpose()
+    }
+
+    /// GET operation for binary values - the stored bytes, whatever they are
+    pub fn get_bytes(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let now = self.now();
+        let (value, expired) = {
+            let data = self.read_shard(key)?;
+            match data.entries.get(key) {
+                Some(entry) if entry.is_expired(now) => (None, true),
+                Some(Entry { value: Value::String(value), access, .. }) => {
+                    access.touch(now);
+                    (Some(value.clone()), false)
+                }
+                Some(_) => return Err(WrongType.into()),
+                None => (None, false),
+            }
+        };
+        if expired {
+            self.remove_expired(key)?;
+        }
+        profile::record_access(1, value.as_ref().map_or(0, Vec::len), 0);
+        Ok(value)
+    }
+
+    /// The value at `key` with its content type, if it's a string carrying a
+    /// `CONTENT_TYPE_META` metadata field; `None` for any other key
+    ///
+    /// Only a hit counts as an access, so callers can fall back to `get`
+    /// without a key being read twice.
+    pub fn get_typed(&self, key: &str) -> Result<Option<TypedValue>> {
+        let now = self.now();
+        let data = self.read_shard(key)?;
+        let Some(Entry { value: Value::String(value), meta, expires_at, access, .. }) = data.entries.get(key) else {
+            return Ok(None);
+        };
+        let content_type = meta.iter().find(|(field, _)| field.eq_ignore_ascii_case(CONTENT_TYPE_META));
+        let (Some((_, content_type)), false) = (content_type, expires_at.is_some_and(|deadline| now >= deadline)) else {
+            return Ok(None);
+        };
+        access.touch(now);
+        profile::record_access(1, value.len(), 0);
+        Ok(Some(TypedValue {
+            value: value.clone(),
+            content_type: content_type.clone(),
+            ttl: expires_at.map(|deadline| Duration::from_millis(deadline - now)),
+        }))
+    }
+
+    /// SET operation - stores key-value pair
+    ///
+    /// Like Redis, overwriting a key clears its TTL. When maxmemory is set, keys are evicted per maxmemory-policy until the new
+    /// value fits; if it can't fit at all the write fails with an OOM error.
+    pub fn set(&self, key: String, value: String) -> Result<()> {
+        self.set_bytes(key, value.into_bytes())
+    }
+
+    /// SET operation for binary values - stores any bytes, e.g. serialized
+    /// or compressed payloads
+    pub fn set_bytes(&self, key: String, value: Vec<u8>) -> Result<()> {
+        let mut data = self.write_shard(&key)?;
+        let mut namespaces = self.lock_namespaces(&data)?;
+        self.set_entry(&mut data, &mut namespaces, key, value, None)
+    }
+
+    /// SET with options - conditional write, TTL handling and reading the
+    /// previous value in one atomic step
+    ///
+    /// The condition is checked and the value written under the same write
+    /// lock, so `IfMissing` with a TTL works as a lock acquisition.
+    pub fn set_with(&self, key: String, value: String, options: SetOptions) -> Result<SetOutcome> {
+        let outcome = self.write_with(key, value.into_bytes(), options, true)?;
+        Ok(SetOutcome { written: outcome.written, previous: outcome.previous.map(utf8).transpose()? })
+    }
+
+    /// `set_with` for binary values; the previous value comes back as bytes
+    pub fn set_bytes_with(&self, key: String, value: Vec<u8>, options: SetOptions) -> Result<SetOutcome<Vec<u8>>> {
+        self.write_with(key, value, options, false)
+    }
+
+    /// With `text`, a previous value asked for but not UTF-8 fails the
+    /// write before anything changes
+    fn write_with(&self, key: String, value: Vec<u8>, options: SetOptions, text: bool) -> Result<SetOutcome<Vec<u8>>> {
+        let now = self.now();
+        let mut data = self.write_shard(&key)?;
+        let mut namespaces = self.lock_namespaces(&data)?;
+        if data.entries.get(&key).is_some_and(|entry| entry.is_expired(now)) {
+            Self::drop_expired(&mut data, &mut namespaces, &key);
+        }
+
+        let existing = data.entries.get(&key);
+        let previous = match existing {
+            Some(Entry { value: Value::String(previous), .. }) if options.get => Some(previous.clone()),
+            Some(_) if options.get => return Err(WrongType.into()),
+            _ => None,
+        };
+        if text {
+            previous.as_deref().map(check_utf8).transpose()?;
+        }
+        let allowed = match options.condition {
+            SetCondition::Always => true,
+            SetCondition::IfMissing => existing.is_none(),
+            SetCondition::IfExists => existing.is_some(),
+            SetCondition::IfTag(tag) => matches!(existing, Some(Entry { value: Value::String(current), .. }) if value_tag(current) == tag),
+        };
+        if !allowed {
+            profile::record_access(1, previous.as_ref().map_or(0, Vec::len), 0);
+            return Ok(SetOutcome { written: false, previous });
+        }
+
+        let expires_at = match options.expiry {
+            SetExpiry::Clear => None,
+            SetExpiry::Keep => existing.and_then(|entry| entry.expires_at),
+            SetExpiry::After(ttl) => Some(now.saturating_add(data.jittered(&key, ttl).as_millis() as u64)),
+        };
+        self.set_entry(&mut data, &mut namespaces, key, value, expires_at)?;
+        Ok(SetOutcome { written: true, previous })
+    }
+
+    /// GETSET operation - stores `value` and returns the previous one
+    ///
+    /// Same as SET with `get`: the TTL is cleared and non-string keys fail
+    /// with `WrongType`.
+    pub fn getset(&self, key: String, value: String) -> Result<Option<String>> {
+        let options = SetOptions { get: true, ..SetOptions::default() };
+        Ok(self.set_with(key, value, options)?.previous)
+    }
+
+    /// GETDEL operation - returns the string at `key` and deletes it in one
+    /// step, so two callers never both read the value
+    pub fn getdel(&self, key: &str) -> Result<Option<String>> {
+        let now = self.now();
+        let mut data = self.write_shard(key)?;
+        let mut namespaces = self.lock_namespaces(&data)?;
+        let expired = match data.entries.get(key) {
+            Some(entry) if entry.is_expired(now) => true,
+            Some(Entry { value: Value::String(value), .. }) => {
+                check_utf8(value)?;
+                false
+            }
+            Some(_) => return Err(WrongType.into()),
+            None => {
+                profile::record_access(1, 0, 0);
+                return Ok(None);
+            }
+        };
+        if expired {
+            Self::drop_expired(&mut data, &mut namespaces, key);
+            profile::record_access(1, 0, 0);
+            return Ok(None);
+        }
+
+        if let Some(namespace) = Self::namespace_for(&mut namespaces, key) {
+            namespace.forget(key);
+        }
+        let value = match data.remove(key) {
+            Some(Entry { value: Value::String(value), .. }) => Some(value),
+            _ => None,
+        };
+        profile::record_access(1, value.as_ref().map_or(0, Vec::len), 0);
+        value.map(utf8).transpose()
+    }
+
+    /// GETEX operation - returns the string at `key` and updates its TTL in
+    /// one step
+    ///
+    /// `SetExpiry::Keep` leaves the TTL alone and `Clear` removes it
+    /// (PERSIST). Missing keys are left missing.
+    pub fn getex(&self, key: &str, expiry: SetExpiry) -> Result<Option<String>> {
+        let now = self.now();
+        let mut data = self.write_shard(key)?;
+        let expired = match data.entries.get(key) {
+            Some(entry) if entry.is_expired(now) => true,
+            Some(Entry { value: Value::String(value), .. }) => {
+                check_utf8(value)?;
+                false
+            }
+            Some(_) => return Err(WrongType.into()),
+            None => {
+                profile::record_access(1, 0, 0);
+                return Ok(None);
+            }
+        };
+        if expired {
+            let mut namespaces = self.lock_namespaces(&data)?;
+            Self::drop_expired(&mut data, &mut namespaces, key);
+            profile::record_access(1, 0, 0);
+            return Ok(None);
+        }
+
+        let expiry = match expiry {
+            SetExpiry::After(ttl) => SetExpiry::After(data.jittered(key, ttl)),
+            expiry => expiry,
+        };
+        let version = data.next_version();
+        let Some(Entry { value: Value::String(value), expires_at, access, version: key_version, .. }) = data.entries.get_mut(key) else {
+            return Ok(None);
+        };
+        match expiry {
+            SetExpiry::Keep => {}
+            SetExpiry::Clear => *expires_at = None,
+            SetExpiry::After(ttl) => *expires_at = Some(now.saturating_add(ttl.as_millis() as u64)),
+        }
+        if expiry != SetExpiry::Keep {
+            *key_version = version;
+        }
+        access.touch(now);
+        let (value, has_ttl) = (value.clone(), expires_at.is_some());
+        if has_ttl {
+            data.expires.insert(key.to_string());
+        } else {
+            data.expires.swap_remove(key);
+        }
+        profile::record_access(1, value.len(), 0);
+        Ok(Some(utf8(value)?))
+    }
+
+    /// RENAME / RENAMENX operation - moves the value at `from` to `to` in
+    /// one step
+    ///
+    /// The value keeps its TTL and pin. With `replace` whatever `to` held is
+    /// overwritten (RENAME); without it nothing moves if `to` exists
+    /// (RENAMENX). Returns whether the value moved; fails with `NoSuchKey`
+    /// if `from` is missing.
+    pub fn rename(&self, from: &str, to: &str, replace: bool) -> Result<bool> {
+        self.transfer(from, to, replace, false)
+    }
+
+    /// COPY operation - copies the value at `from`, any type, to `to` with
+    /// its TTL in one step
+    ///
+    /// Returns false when `from` is missing or, without `replace`, `to`
+    /// exists. Copying a key onto itself is an error.
+    pub fn copy(&self, from: &str, to: &str, replace: bool) -> Result<bool> {
+        self.transfer(from, to, replace, true)
+    }
+
+    /// Shared by RENAME and COPY: both keys are checked and written with
+    /// their shards locked, so no other command sees a half-done move
+    fn transfer(&self, from: &str, to: &str, replace: bool, keep_source: bool) -> Result<bool> {
+        let now = self.now();
+        let mut data = self.write_shards(&[from, to])?;
+        let mut namespaces = self.lock_namespaces(&data)?;
+        for key in [from, to] {
+            let store = data.shard_mut(key);
+            if store.entries.get(key).is_some_and(|entry| entry.is_expired(now)) {
+                Self::drop_expired(store, &mut namespaces, key);
+            }
+        }
+        let Some(source) = data.shard(from).entries.get(from) else {
+            profile::record_access(1, 0, 0);
+            return if keep_source { Ok(false) } else { Err(NoSuchKey.into()) };
+        };
+        if from == to {
+            // As in Redis: RENAME onto itself is a no-op, RENAMENX reports
+            // the destination as taken, and COPY refuses
+            return if keep_source {
+                Err(anyhow::anyhow!("Source and destination are the same key"))
+            } else {
+                Ok(replace)
+            };
+        }
+        let existing = data.shard(to).entries.get(to);
+        if existing.is_some() && !replace {
+            profile::record_access(2, 0, 0);
+            return Ok(false);
+        }
+
+        let size = source.value.size();
+        let replaced = existing.map_or(0, |old| old.size(to));
+        // A rename frees the source as it fills the destination, if both
+        // share a shard and so a memory budget
+        let same_shard = shard_of(from, self.shards.len()) == shard_of(to, self.shards.len());
+        let freed = replaced + if keep_source || !same_shard { 0 } else { source.size(from) };
+        let mut entry = Entry::new(source.value.clone(), now);
+        entry.expires_at = source.expires_at;
+        entry.meta = source.meta.clone();
+        // Overwriting a pinned key keeps it pinned, as with SET
+        entry.pinned = (!keep_source && source.pinned) || existing.is_some_and(|old| old.pinned);
+        self.evict_for(data.shard_mut(to), &mut namespaces, from, (to.len() + size).saturating_sub(freed))?;
+
+        if !keep_source {
+            if let Some(namespace) = Self::namespace_for(&mut namespaces, from) {
+                namespace.forget(from);
+            }
+            data.shard_mut(from).remove(from);
+        }
+        if let Some(namespace) = Self::namespace_for(&mut namespaces, to) {
+            namespace.touch(to);
+            for evicted in namespace.trim() {
+                data.shard_mut(&evicted).remove(&evicted);
+            }
+        }
+        data.shard_mut(to).insert(to.to_string(), entry);
+        profile::record_access(2, size, size);
+        Ok(true)
+    }
+
+    /// MSET operation - stores several key-value pairs under one lock
+    /// acquisition, so readers see either none or all of them
+    ///
+    /// With maxmemory set, the write fails up front if the pairs can't fit
+    /// even in an otherwise empty cache; later pairs may still evict earlier
+    /// ones.
+    pub fn mset(&self, pairs: Vec<(String, String)>) -> Result<()> {
+        let keys: Vec<&str> = pairs.iter().map(|(key, _)| key.as_str()).collect();
+        let mut data = self.write_shards(&keys)?;
+        let mut namespaces = self.lock_namespaces(&data)?;
+        for (index, store) in &data.guards {
+            let incoming: usize = pairs
+                .iter()
+                .filter(|(key, _)| shard_of(key, self.shards.len()) == *index)
+                .map(|(key, value)| key.len() + value.len())
+                .sum();
+            if store.maxmemory > 0 && incoming > store.maxmemory {
+                return Err(anyhow::anyhow!("OOM command not allowed when used memory > 'maxmemory'"));
+            }
+        }
+        for (key, value) in pairs {
+            self.set_entry(&mut data, &mut namespaces, key, value.into_bytes(), None)?;
+        }
+        Ok(())
+    }
+
+    /// MGET operation - reads several string keys under one lock acquisition
+    ///
+    /// Values come back in request order; missing keys and keys holding
+    /// other types read as `None`, as in Redis. Fails with `NotUtf8` if any
+    /// value is binary.
+    pub fn mget(&self, keys: &[String]) -> Result<Vec<Option<String>>> {
+        let now = self.now();
+        let mut expired = Vec::new();
+        let values: Vec<Option<Vec<u8>>> = {
+            let data = self.read_shards(&keys.iter().map(String::as_str).collect::<Vec<_>>())?;
+            keys.iter()
+                .map(|key| match data.shard(key).entries.get(key) {
+                    Some(entry) if entry.is_expired(now) => {
+                        expired.push(key);
+                        None
+                    }
+                    Some(Entry { value: Value::String(value), access, .. }) => {
+                        access.touch(now);
+                        Some(value.clone())
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+        for key in expired {
+            self.remove_expired(key)?;
+        }
+        profile::record_access(keys.len(), values.iter().flatten().map(Vec::len).sum(), 0);
+        values.into_iter().map(|value| value.map(utf8).transpose()).collect()
+    }
+
+    /// MGETEX operation - reads several string keys and updates the TTL of
+    /// each one found, under one lock acquisition
+    ///
+    /// As with MGET, missing keys and keys holding other types read as
+    /// `None`; their TTLs are left alone. Fails with `NotUtf8`, before any
+    /// TTL changes, if any value is binary.
+    pub fn mgetex(&self, keys: &[String], expiry: SetExpiry) -> Result<Vec<Option<String>>> {
+        let now = self.now();
+        let mut data = self.write_shards(&keys.iter().map(String::as_str).collect::<Vec<_>>())?;
+        let mut namespaces = self.lock_namespaces(&data)?;
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            let store = data.shard_mut(key);
+            let value = match store.entries.get(key) {
+                Some(entry) if entry.is_expired(now) => {
+                    Self::drop_expired(store, &mut namespaces, key);
+                    None
+                }
+                Some(Entry { value: Value::String(value), .. }) => {
+                    check_utf8(value)?;
+                    Some(value.clone())
+                }
+                _ => None,
+            };
+            values.push(value);
+        }
+
+        for (key, _) in keys.iter().zip(&values).filter(|(_, value)| value.is_some()) {
+            let data = data.shard_mut(key);
+            let expiry = match expiry {
+                SetExpiry::After(ttl) => SetExpiry::After(data.jittered(key, ttl)),
+                expiry => expiry,
+            };
+            let version = data.next_version();
+            let Some(Entry { expires_at, access, version: key_version, .. }) = data.entries.get_mut(key) else {
+                continue;
+            };
+            match expiry {
+                SetExpiry::Keep => {}
+                SetExpiry::Clear => *expires_at = None,
+                SetExpiry::After(ttl) => *expires_at = Some(now.saturating_add(ttl.as_millis() as u64)),
+            }
+            if expiry != SetExpiry::Keep {
+                *key_version = version;
+            }
+            access.touch(now);
+            if expires_at.is_some() {
+                data.expires.insert(key.clone());
+            } else {
+                data.expires.swap_remove(key);
+            }
+        }
+        profile::record_access(keys.len(), values.iter().flatten().map(Vec::len).sum(), 0);
+        values.into_iter().map(|value| value.map(utf8).transpose()).collect()
+    }
+
+    /// Writes one string entry with the store and namespace locks held
+    fn set_entry(
+        &self,
+        data: &mut Shards<ShardWriteGuard<'_, Store>>,
+        namespaces: &mut NamespacesGuard,
+        key: String,
+        value: Vec<u8>,
+        expires_at: Option<u64>,
+    ) -> Result<()> {
+        profile::record_access(1, 0, key.len() + value.len());
+        let mut entry = Entry::new(Value::String(value), self.now());
+        entry.expires_at = expires_at;
+        let store = data.shard_mut(&key);
+        let replaced = store.entries.get(&key).map_or(0, |old| old.size(&key));
+        // Overwriting a pinned key keeps it pinned, and its metadata
+        if let Some(old) = store.entries.get(&key) {
+            entry.pinned = old.pinned;
+            entry.meta = old.meta.clone();
+        }
+        self.evict_for(store, namespaces, &key, entry.size(&key).saturating_sub(replaced))?;
+
+        if let Some(namespace) = Self::namespace_for(namespaces, &key) {
+            namespace.touch(&key);
+            for evicted in namespace.trim() {
+                data.shard_mut(&evicted).remove(&evicted);
+            }
+        }
+        data.shard_mut(&key).insert(key, entry);
+        Ok(())
+    }
+
+    /// INCRBY operation - adds `delta` to the integer stored at `key`
+    ///
+    /// A missing key counts as 0. The update happens under the write lock,
+    /// so concurrent increments never lose updates, and the key keeps its
+    /// TTL. Fails with `NumberError` if the value isn't a 64-bit integer or
+    /// the result overflows.
+    pub fn incr_by(&self, key: &str, delta: i64) -> Result<i64> {
+        self.update_string(key, |current| {
+            let current = match current {
+                Some(value) => value.parse::<i64>().map_err(|_| NumberError::NotInteger)?,
+                None => 0,
+            };
+            let updated = current.checked_add(delta).ok_or(NumberError::Overflow)?;
+            Ok((updated.to_string(), updated))
+        })
+    }
+
+    /// INCRBYFLOAT operation - adds `delta` to the number stored at `key`
+    ///
+    /// Same guarantees as `incr_by`; results that would be infinite or NaN
+    /// are rejected.
+    pub fn incr_by_float(&self, key: &str, delta: f64) -> Result<f64> {
+        self.update_string(key, |current| {
+            let current = match current {
+                Some(value) => value.parse::<f64>().ok().filter(|n| n.is_finite()).ok_or(NumberError::NotFloat)?,
+                None => 0.0,
+            };
+            let updated = current + delta;
+            if !updated.is_finite() {
+                return Err(NumberError::Overflow.into());
+            }
+            Ok((updated.to_string(), updated))
+        })
+    }
+
+    /// Replaces the string at `key` with what `update` derives from its
+    /// current value (`None` if missing), keeping the key's TTL and pin
+    fn update_string<T>(&self, key: &str, update: impl FnOnce(Option<&str>) -> Result<(String, T)>) -> Result<T> {
+        let now = self.now();
+        let mut data = self.write_shard(key)?;
+        let mut namespaces = self.lock_namespaces(&data)?;
+        let expired = match data.entries.get(key) {
+            Some(entry) if entry.is_expired(now) => true,
+            Some(Entry { value: Value::String(_), .. }) | None => false,
+            Some(_) => return Err(WrongType.into()),
+        };
+        if expired {
+            Self::drop_expired(&mut data, &mut namespaces, key);
+        }
+
+        // Binary values can't parse as numbers anyway, so lossy text is enough
+        let current = match data.entries.get(key) {
+            Some(Entry { value: Value::String(value), .. }) => Some(String::from_utf8_lossy(value)),
+            _ => None,
+        };
+        let replaced = current.as_ref().map_or(0, |value| key.len() + value.len());
+        let (value, result) = update(current.as_deref())?;
+        let value = value.into_bytes();
+        profile::record_access(1, replaced.saturating_sub(key.len()), value.len());
+        self.evict_for(&mut data, &mut namespaces, key, (key.len() + value.len()).saturating_sub(replaced))?;
+
+        if let Some(namespace) = Self::namespace_for(&mut namespaces, key) {
+            namespace.touch(key);
+            for evicted in namespace.trim() {
+                data.shard_mut(&evicted).remove(&evicted);
+            }
+        }
+        let version = data.next_version();
+        let (new_len, old_len) = match data.entries.get_mut(key) {
+            Some(Entry { value: Value::String(current), access, written_at, version: key_version, .. }) => {
+                access.touch(now);
+                *written_at = now;
+                *key_version = version;
+                (value.len(), std::mem::replace(current, value).len())
+            }
+            _ => {
+                data.insert(key.to_string(), Entry::new(Value::String(value), now));
+                (0, 0)
+            }
+        };
+        data.resize(new_len, old_len);
+        Ok(result)
+    }
+
+    /// DEL operation - deletes a key
+    pub fn del(&self, key: &str) -> Result<bool> {
+        let mut data = self.write_shard(key)?;
+        let mut namespaces = self.lock_namespaces(&data)?;
+        if let Some(namespace) = Self::namespace_for(&mut namespaces, key) {
+            namespace.forget(key);
+        }
+        // Dropping the seen filter resets it, but filters aren't keys: like
+        // KEYS and EXISTS, the reply only counts the keyspace
+        self.seen.lock().map_err(|_| anyhow::anyhow!("Failed to acquire seen lock"))?.remove(key);
+        profile::record_access(1, 0, 0);
+        let now = self.now();
+        // An expired key was already gone, so removing it doesn't count
+        Ok(data.remove(key).is_some_and(|entry| !entry.is_expired(now)))
+    }
+
+    /// EXISTS operation - checks if key exists
+    pub fn exists(&self, key: &str) -> Result<bool> {
+        let now = self.now();
+        let expired = {
+            let data = self.read_shard(key)?;
+            profile::record_access(1, 0, 0);
+            match data.entries.get(key) {
+                Some(entry) => entry.is_expired(now),
+                None => return Ok(false),
+            }
+        };
+        if expired {
+            self.remove_expired(key)?;
+        }
+        Ok(!expired)
+    }
+
+    /// MEMORY USAGE operation - approximate bytes `key` uses: its name,
+    /// value (every field and member of a hash or sorted set) and metadata,
+    /// as charged against maxmemory; `None` if the key is missing
+    ///
+    /// Like TYPE, doesn't count as an access.
+    pub fn memory_usage(&self, key: &str) -> Result<Option<usize>> {
+        let now = self.now();
+        let expired = {
+            let data = self.read_shard(key)?;
+            profile::record_access(1, 0, 0);
+            match data.entries.get(key) {
+                Some(entry) if entry.is_expired(now) => true,
+                Some(entry) => return Ok(Some(entry.size(key))),
+                None => return Ok(None),
+            }
+        };
+        if expired {
+            self.remove_expired(key)?;
+        }
+        Ok(None)
+    }
+
+    /// TYPE operation - Redis type name of the value at `key` (see
+    /// `Value::type_name`), `None` if the key is missing
+    ///
+    /// Like EXISTS, doesn't count as an access.
+    pub fn type_of(&self, key: &str) -> Result<Option<&'static str>> {
+        let now = self.now();
+        let expired = {
+            let data = self.read_shard(key)?;
+            profile::record_access(1, 0, 0);
+            match data.entries.get(key) {
+                Some(entry) if entry.is_expired(now) => true,
+                Some(entry) => return Ok(Some(entry.value.type_name())),
+                None => return Ok(None),
+            }
+        };
+        if expired {
+            self.remove_expired(key)?;
+        }
+        Ok(None)
+    }
+
+    /// Metadata for up to `count` keys starting at `cursor`, for streaming
+    /// the keyspace in bounded batches
+    ///
+    /// Returns the cursor for the next batch, 0 once the scan is complete.
+    /// Each batch holds the read lock only while it is collected. Keys
+    /// removed between batches can make a later key move into an already
+    /// scanned slot, so a scan running alongside writes may miss or repeat
+    /// keys. Expired keys are skipped, and scanning doesn't count as an
+    /// access, so idle times are left as they were.
+    pub fn scan_metadata(&self, cursor: usize, count: usize) -> Result<(usize, Vec<KeyMetadata>)> {
+        let now = self.now();
+        let mut batch = Vec::new();
+        let next = self.scan_shard(cursor, count, |key, entry| {
+            if !entry.is_expired(now) {
+                batch.push(KeyMetadata::new(key, entry, now));
+            }
+        })?;
+        profile::record_access(batch.len(), batch.iter().map(|meta| meta.key.len()).sum(), 0);
+        Ok((next, batch))
+    }
+
+    /// SAMPLEKEYS operation - up to `count` distinct live keys drawn
+    /// uniformly at random, keeping those `pattern` matches, with their
+    /// metadata
+    ///
+    /// Keys are drawn `SAMPLE_BATCH` at a time, each batch under its own
+    /// read locks, so sampling a huge keyspace never holds writers back for
+    /// long. A rare pattern may leave the sample short of `count` once
+    /// `SAMPLE_DRAWS_PER_KEY` draws per requested key are used up. A
+    /// keyspace no larger than `count` is walked in full (see
+    /// `scan_metadata`). Sampling doesn't count as an access.
+    pub fn sample_keys(&self, count: usize, pattern: Option<&str>) -> Result<KeySample> {
+        let matches = |key: &str| pattern.is_none_or(|pattern| glob_match(pattern, key));
+        let mut sample = KeySample { keyspace: self.size()?, ..KeySample::default() };
+        if count >= sample.keyspace {
+            let mut cursor = 0;
+            loop {
+                let (next, batch) = self.scan_metadata(cursor, SAMPLE_BATCH)?;
+                sample.examined += batch.len();
+                sample.keys.extend(batch.into_iter().filter(|meta| matches(&meta.key)));
+                if next == 0 {
+                    break;
+                }
+                cursor = next;
+            }
+            sample.keys.truncate(count);
+            return Ok(sample);
+        }
+
+        let now = self.now();
+        let mut rng = rand::thread_rng();
+        let mut drawn: HashSet<String> = HashSet::new();
+        let mut draws = count.saturating_mul(SAMPLE_DRAWS_PER_KEY);
+        while sample.keys.len() < count && draws > 0 {
+            let data = self.read_shards(&[])?;
+            let stores: Vec<&Store> = data.stores().collect();
+            let lens: Vec<usize> = stores.iter().map(|store| store.entries.len()).collect();
+            sample.keyspace = lens.iter().sum();
+            if sample.keyspace == 0 {
+                break;
+            }
+            let batch = draws.min(SAMPLE_BATCH);
+            draws -= batch;
+            for _ in 0..batch {
+                // Each draw picks its shard by its share of the keyspace, so
+                // every key is equally likely
+                let store = stores[weighted_index(&mut rng, &lens)];
+                let Some((key, entry)) = store.entries.get_index(rng.gen_range(0..store.entries.len())) else {
+                    continue;
+                };
+                if entry.is_expired(now) || drawn.contains(key) {
+                    continue;
+                }
+                drawn.insert(key.clone());
+                sample.examined += 1;
+                if matches(key) {
+                    sample.keys.push(KeyMetadata::new(key, entry, now));
+                    if sample.keys.len() == count {
+                        break;
+                    }
+                }
+            }
+        }
+        profile::record_access(sample.keys.len(), sample.keys.iter().map(|meta| meta.key.len()).sum(), 0);
+        Ok(sample)
+    }
+
+    /// SCAN operation - walks up to `count` keyspace slots from `cursor`,
+    /// returning the live keys `filter` accepts
+    ///
+    /// Returns the cursor for the next call, 0 once the scan is complete. As
+    /// in Redis, a filtered batch may hold fewer than `count` keys, or none,
+    /// before the scan is over. Same guarantees as `scan_metadata`.
+    pub fn scan(&self, cursor: usize, count: usize, filter: &ScanFilter) -> Result<(usize, Vec<String>)> {
+        let now = self.now();
+        let mut keys = Vec::new();
+        let next = self.scan_shard(cursor, count, |key, entry| {
+            let matches = !entry.is_expired(now)
+                && filter.pattern.as_deref().is_none_or(|pattern| glob_match(pattern, key))
+                && filter.meta.as_ref().is_none_or(|(field, pattern)| {
+                    entry.meta.iter().any(|(name, value)| name == field && glob_match(pattern, value))
+                });
+            if matches {
+                keys.push(key.clone());
+            }
+        })?;
+        profile::record_access(keys.len(), keys.iter().map(String::len).sum(), 0);
+        Ok((next, keys))
+    }
+
+    /// Runs `visit` on up to `count` slots of the shard `cursor` points
+    /// into, under that shard's read lock, returning the cursor of the next
+    /// batch, 0 once the last shard is done
+    ///
+    /// A batch never crosses into the next shard, so it may visit fewer
+    /// slots than `count` before the scan is over.
+    fn scan_shard(&self, cursor: usize, count: usize, mut visit: impl FnMut(&String, &Entry)) -> Result<usize> {
+        let (index, position) = (cursor >> SHARD_CURSOR_BITS, cursor & ((1 << SHARD_CURSOR_BITS) - 1));
+        if index >= self.shards.len() {
+            return Ok(0);
+        }
+        let data = self.read_shard_at(index)?;
+        let end = position.saturating_add(count.max(1)).min(data.entries.len());
+        for (key, entry) in (position..end).filter_map(|slot| data.entries.get_index(slot)) {
+            visit(key, entry);
+        }
+        Ok(if end < data.entries.len() {
+            index << SHARD_CURSOR_BITS | end
+        } else if index + 1 < self.shards.len() {
+            (index + 1) << SHARD_CURSOR_BITS
+        } else {
+            0
+        })
+    }
+
+    /// KEYS operation - returns all keys (be careful with large datasets)
+    pub fn keys(&self) -> Result<Vec<String>> {
+        let now = self.now();
+        let data = self.read_shards(&[])?;
+        let keys: Vec<String> = data
+            .stores()
+            .flat_map(|store| &store.entries)
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .map(|(key, _)| key.clone())
+            .collect();
+        profile::record_access(keys.len(), keys.iter().map(String::len).sum(), 0);
+        Ok(keys)
+    }
+
+    /// FLUSH operation - clears all data
+    pub fn flush(&self) -> Result<()> {
+        let mut data = self.write_shards(&[])?;
+        let mut namespaces = self.namespaces.lock().map_err(|_| anyhow::anyhow!("Failed to acquire namespace lock"))?;
+        for namespace in namespaces.values_mut() {
+            namespace.members.clear();
+            namespace.order.clear();
+        }
+        self.seen.lock().map_err(|_| anyhow::anyhow!("Failed to acquire seen lock"))?.clear();
+        data.stores_mut().for_each(Store::clear);
+        Ok(())
+    }
+
+    /// SIZE operation - returns number of keys
+    ///
+    /// As with Redis' DBSIZE, expired keys that haven't been removed yet are
+    /// still counted.
+    pub fn size(&self) -> Result<usize> {
+        let data = self.read_shards(&[])?;
+        Ok(data.stores().map(|store| store.entries.len()).sum())
+    }
+
+    /// NS.LIMIT operation - caps the number of keys under a prefix pattern
+    ///
+    /// `pattern` is a prefix optionally followed by `*` (e.g. `recent:*`).
+    /// Once the cap is exceeded, the oldest written keys in the namespace are
+    /// evicted at SET time. A `max_keys` of 0 removes the limit.
+    /// Returns the keys evicted to bring the namespace within its new cap.
+    pub fn set_namespace_limit(&self, pattern: &str, max_keys: usize) -> Result<Vec<String>> {
+        let prefix = pattern.strip_suffix('*').unwrap_or(pattern).to_string();
+        let mut data = self.write_shards(&[])?;
+        let mut namespaces = self.namespaces.lock().map_err(|_| anyhow::anyhow!("Failed to acquire namespace lock"))?;
+
+        if max_keys == 0 {
+            namespaces.remove(&prefix);
+            self.namespaced.store(!namespaces.is_empty(), Ordering::Release);
+            return Ok(Vec::new());
+        }
+
+        if let Some(namespace) = namespaces.get_mut(&prefix) {
+            namespace.max_keys = max_keys;
+        } else {
+            // Existing keys have no write history, so they are ordered arbitrarily
+            let mut namespace = Namespace { max_keys, ..Default::default() };
+            for key in data.stores().flat_map(|store| store.entries.keys()).filter(|key| key.starts_with(&prefix)) {
+                namespace.touch(key);
+            }
+            namespaces.insert(prefix.clone(), namespace);
+            self.namespaced.store(true, Ordering::Release);
+        }
+
+        let evicted = namespaces.get_mut(&prefix).map(Namespace::trim).unwrap_or_default();
+        for key in &evicted {
+            data.shard_mut(key).remove(key);
+        }
+        Ok(evicted)
+    }
+
+    /// NS.LIMITS operation - lists configured namespace caps as (pattern, max_keys)
+    pub fn namespace_limits(&self) -> Result<Vec<(String, usize)>> {
+        let namespaces = self.namespaces.lock().map_err(|_| anyhow::anyhow!("Failed to acquire namespace lock"))?;
+        let mut limits: Vec<(String, usize)> = namespaces
+            .iter()
+            .map(|(prefix, namespace)| (format!("{}*", prefix), namespace.max_keys))
+            .collect();
+        limits.sort();
+        Ok(limits)
+    }
+
+    /// SEEN.ADD operation - records an item in a recently-seen set
+    ///
+    /// Returns true if the item was not seen under `key` within the last
+    /// `ttl`. Answers are approximate (see `SeenFilter`): false positives
+    /// are possible, false negatives within the TTL are not.
+    pub fn seen_add(&self, key: &str, item: &str, ttl: Duration) -> Result<bool> {
+        self.check_writable()?;
+        let mut seen = self.seen.lock().map_err(|_| anyhow::anyhow!("Failed to acquire seen lock"))?;
+        let filter = seen.entry(key.to_string()).or_insert_with(|| SeenFilter::new(ttl));
+        Ok(filter.add(item, ttl, self.clock.now()))
+    }
+
+    /// GENID operation - mints a server-generated unique ID
+    pub fn generate_id(&self, kind: IdKind) -> Result<String> {
+        self.ids.generate(kind)
+    }
+
+    /// CONFIG GET operation - reads cache parameters matching a name or `*`
+    pub fn config_get(&self, pattern: &str) -> Result<BTreeMap<String, String>> {
+        // Every shard has the same settings but its own share of maxmemory
+        let data = self.read_shards(&[])?;
+        let maxmemory: usize = data.stores().map(|store| store.maxmemory).sum();
+        let mut config = BTreeMap::new();
+        config.insert("maxmemory".to_string(), maxmemory.to_string());
+        config.insert("maxmemory-samples".to_string(), data.samples.to_string());
+        config.insert("maxmemory-policy".to_string(), data.policy.to_string());
+        config.insert("maxmemory-protect-recent".to_string(), (data.protect_recent / 1000).to_string());
+        config.insert("pin-patterns".to_string(), data.pin_patterns.join(","));
+        let jitter: Vec<String> = data.ttl_jitter.iter().map(|(pattern, percent)| format!("{} {}", pattern, percent)).collect();
+        config.insert("ttl-jitter".to_string(), jitter.join(" "));
+        config.insert("notify-keyspace-events".to_string(), self.events.flags().to_string());
+        let strict = self.redis_compat_strict.load(Ordering::Relaxed);
+        config.insert("redis-compat-strict".to_string(), if strict { "yes" } else { "no" }.to_string());
+        config.insert("snapshot-path".to_string(), self.snapshots.path()?.display().to_string());
+        config.insert("save".to_string(), snapshot::format_save_points(&self.snapshots.save_points()?));
+        #[cfg(feature = "chaos")]
+        config.extend(self.faults.parameters()?);
+        #[cfg(feature = "functions")]
+        config.extend(self.functions.parameters()?);
+        #[cfg(feature = "scripting")]
+        config.extend(self.scripts.parameters()?);
+        config.retain(|name, _| pattern == "*" || name.eq_ignore_ascii_case(pattern));
+        Ok(config)
+    }
+
+    /// CONFIG SET operation - updates a cache parameter at runtime
+    ///
+    /// Lowering maxmemory evicts immediately until usage fits, except under
+    /// noeviction, where only writes are refused until it does.
+    pub fn config_set(&self, parameter: &str, value: &str) -> Result<()> {
+        let parameter = parameter.to_lowercase();
+        #[cfg(feature = "chaos")]
+        if self.faults.set_parameter(&parameter, value)? {
+            return Ok(());
+        }
+        #[cfg(feature = "functions")]
+        if self.functions.set_parameter(&parameter, value)? {
+            return Ok(());
+        }
+        #[cfg(feature = "scripting")]
+        if self.scripts.set_parameter(&parameter, value)? {
+            return Ok(());
+        }
+
+        if parameter == "snapshot-path" {
+            return self.snapshots.set_path(PathBuf::from(value));
+        }
+        if parameter == "save" {
+            return self.snapshots.set_save_points(snapshot::parse_save_points(value)?);
+        }
+        if parameter == "notify-keyspace-events" {
+            self.events.set_flags(value.parse()?);
+            return Ok(());
+        }
+        if parameter == "redis-compat-strict" {
+            let strict = match value.to_lowercase().as_str() {
+                "yes" => true,
+                "no" => false,
+                _ => return Err(anyhow::anyhow!("redis-compat-strict must be yes or no")),
+            };
+            self.redis_compat_strict.store(strict, Ordering::Relaxed);
+            return Ok(());
+        }
+
+        let mut data = self.settings_shards()?;
+        match parameter.as_str() {
+            "maxmemory" => {
+                let maxmemory = eviction::parse_memory(value)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid maxmemory value: {}", value))?;
+                for (index, store) in &mut data.guards {
+                    store.maxmemory = shard_budget(maxmemory, self.shards.len(), *index);
+                }
+                if data.policy == EvictionPolicy::NoEviction || self.is_read_only() {
+                    return Ok(());
+                }
+                let mut namespaces = self.lock_namespaces(&data)?;
+                for store in data.stores_mut() {
+                    self.evict_for(store, &mut namespaces, "", 0)?;
+                }
+            }
+            "maxmemory-samples" => {
+                let samples = value
+                    .parse()
+                    .ok()
+                    .filter(|samples| *samples > 0)
+                    .ok_or_else(|| anyhow::anyhow!("maxmemory-samples must be a positive integer"))?;
+                data.stores_mut().for_each(|store| store.samples = samples);
+            }
+            "maxmemory-policy" => {
+                let policy: EvictionPolicy = value.parse()?;
+                for store in data.stores_mut() {
+                    store.policy = policy;
+                    // Pool scores were computed under the old policy
+                    store.pool.clear();
+                }
+            }
+            "maxmemory-protect-recent" => {
+                let secs: u64 = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("maxmemory-protect-recent must be a number of seconds"))?;
+                for store in data.stores_mut() {
+                    store.protect_recent = secs.saturating_mul(1000);
+                    // Drop pool candidates that may have become protected
+                    store.pool.clear();
+                }
+            }
+            "pin-patterns" => {
+                let patterns: Vec<String> = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|pattern| !pattern.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                for store in data.stores_mut() {
+                    store.pin_patterns = patterns.clone();
+                    // Drop pool candidates that may have become pinned
+                    store.pool.clear();
+                }
+            }
+            "ttl-jitter" => {
+                let words: Vec<&str> = value.split_whitespace().collect();
+                if !words.len().is_multiple_of(2) {
+                    return Err(anyhow::anyhow!("ttl-jitter takes pattern and percent pairs"));
+                }
+                let jitter = words
+                    .chunks(2)
+                    .map(|pair| {
+                        let percent = pair[1]
+                            .parse()
+                            .ok()
+                            .filter(|percent| *percent < 100)
+                            .ok_or_else(|| anyhow::anyhow!("ttl-jitter percent must be an integer from 0 to 99"))?;
+                        Ok((pair[0].to_string(), percent))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                data.stores_mut().for_each(|store| store.ttl_jitter = jitter.clone());
+            }
+            _ => return Err(anyhow::anyhow!("Unknown config parameter: {}", parameter)),
+        }
+        Ok(())
+    }
+
+    /// Fault injector shared by every handle to this cache
+    #[cfg(feature = "chaos")]
+    pub fn faults(&self) -> &FaultInjector {
+        &self.faults
+    }
+
+    /// Function libraries shared by every handle to this cache
+    #[cfg(feature = "functions")]
+    pub fn functions(&self) -> &FunctionRegistry {
+        &self.functions
+    }
+
+    /// Lua scripts cached by every handle to this cache
+    #[cfg(feature = "scripting")]
+    pub fn scripts(&self) -> &ScriptRegistry {
+        &self.scripts
+    }
+
+    /// DUMP operation - the value and metadata of `key` as an opaque payload
+    /// for `restore_key`; `None` if the key doesn't exist
+    ///
+    /// The TTL and pin aren't included: RESTORE sets its own TTL.
+    pub fn dump(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let now = self.now();
+        let entry = {
+            let data = self.read_shard(key)?;
+            profile::record_access(1, 0, 0);
+            match data.entries.get(key) {
+                Some(entry) if entry.is_expired(now) => None,
+                Some(entry) => Some(SnapshotEntry {
+                    key: key.to_string(),
+                    value: entry.value.clone(),
+                    expires_at: None,
+                    pinned: false,
+                    meta: entry.meta.clone(),
+                }),
+                None => return Ok(None),
+            }
+        };
+        match entry {
+            Some(entry) => Ok(Some(snapshot::dump_entry(&entry)?)),
+            None => {
+                self.remove_expired(key)?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// RESTORE operation - recreates a DUMP payload under `key`, expiring
+    /// after `ttl` if set
+    ///
+    /// Fails with `BusyKey` if the key exists and `replace` isn't set; a
+    /// replaced key keeps its pin, as with SET.
+    pub fn restore_key(&self, key: String, payload: &[u8], ttl: Option<Duration>, replace: bool) -> Result<()> {
+        let restored = snapshot::restore_entry(payload)?;
+        let now = self.now();
+        let mut data = self.write_shard(&key)?;
+        let mut namespaces = self.lock_namespaces(&data)?;
+        match data.entries.get(&key) {
+            Some(entry) if entry.is_expired(now) => Self::drop_expired(&mut data, &mut namespaces, &key),
+            Some(_) if !replace => return Err(BusyKey.into()),
+            _ => {}
+        }
+
+        let mut entry = Entry { meta: restored.meta, ..Entry::new(restored.value, now) };
+        entry.expires_at = ttl.map(|ttl| now.saturating_add(ttl.as_millis() as u64));
+        entry.pinned = data.entries.get(&key).is_some_and(|old| old.pinned);
+        let replaced = data.entries.get(&key).map_or(0, |old| old.size(&key));
+        profile::record_access(1, 0, entry.size(&key));
+        self.evict_for(&mut data, &mut namespaces, &key, entry.size(&key).saturating_sub(replaced))?;
+
+        if let Some(namespace) = Self::namespace_for(&mut namespaces, &key) {
+            namespace.touch(&key);
+            for evicted in namespace.trim() {
+                data.shard_mut(&evicted).remove(&evicted);
+            }
+        }
+        data.insert(key, entry);
+        Ok(())
+    }
+
+    /// META.SET operation - attaches metadata fields to `key`, returning how
+    /// many were added
+    ///
+    /// Metadata is charged against maxmemory. Like a pin it survives
+    /// overwrites and is dropped with the key; RENAME and COPY carry it to
+    /// the new key, and snapshots keep it. Fails with `NoSuchKey` if the key
+    /// doesn't exist, and without changes if the key would end up with more
+    /// than `MAX_KEY_META_FIELDS` fields.
+    pub fn meta_set(&self, key: &str, fields: Vec<(String, String)>) -> Result<usize> {
+        let now = self.now();
+        let mut data = self.write_shard(key)?;
+        let mut namespaces = self.lock_namespaces(&data)?;
+        let mut meta = match data.entries.get(key) {
+            Some(entry) if entry.is_expired(now) => {
+                Self::drop_expired(&mut data, &mut namespaces, key);
+                return Err(NoSuchKey.into());
+            }
+            Some(entry) => entry.meta.clone(),
+            None => return Err(NoSuchKey.into()),
+        };
+
+        let (old_size, mut added) = (meta_size(&meta), 0);
+        for (field, value) in fields {
+            match meta.iter_mut().find(|(name, _)| *name == field) {
+                Some((_, old)) => *old = value,
+                None => {
+                    meta.push((field, value));
+                    added += 1;
+                }
+            }
+        }
+        if meta.len() > MAX_KEY_META_FIELDS {
+            return Err(anyhow::anyhow!("A key can carry at most {} metadata fields", MAX_KEY_META_FIELDS));
+        }
+        let new_size = meta_size(&meta);
+        profile::record_access(1, 0, new_size);
+        self.evict_for(&mut data, &mut namespaces, key, new_size.saturating_sub(old_size))?;
+        let Some(entry) = data.entries.get_mut(key) else {
+            return Err(NoSuchKey.into());
+        };
+        entry.meta = meta;
+        data.resize(new_size, old_size);
+        Ok(added)
+    }
+
+    /// META.GET operation - every metadata field of `key`, in the order they
+    /// were first set; empty if the key doesn't exist
+    pub fn meta_get(&self, key: &str) -> Result<Vec<(String, String)>> {
+        let now = self.now();
+        let expired = {
+            let data = self.read_shard(key)?;
+            profile::record_access(1, 0, 0);
+            match data.entries.get(key) {
+                Some(entry) if entry.is_expired(now) => true,
+                Some(entry) => return Ok(entry.meta.clone()),
+                None => return Ok(Vec::new()),
+            }
+        };
+        if expired {
+            self.remove_expired(key)?;
+        }
+        Ok(Vec::new())
+    }
+
+    /// META.DEL operation - removes metadata fields from `key`, returning
+    /// how many existed
+    pub fn meta_del(&self, key: &str, fields: &[String]) -> Result<usize> {
+        let now = self.now();
+        let mut data = self.write_shard(key)?;
+        profile::record_access(1, 0, 0);
+        let Some(entry) = data.entries.get_mut(key).filter(|entry| !entry.is_expired(now)) else {
+            return Ok(0);
+        };
+        let (before, old_size) = (entry.meta.len(), meta_size(&entry.meta));
+        entry.meta.retain(|(name, _)| !fields.contains(name));
+        let (removed, freed) = (before - entry.meta.len(), old_size - meta_size(&entry.meta));
+        data.used_bytes -= freed;
+        Ok(removed)
+    }
+
+    /// PIN operation - exempts a key from maxmemory eviction
+    ///
+    /// Returns false if the key doesn't exist. The pin survives overwrites
+    /// and is dropped when the key is deleted.
+    pub fn pin(&self, key: &str) -> Result<bool> {
+        self.set_pinned(key, true)
+    }
+
+    /// UNPIN operation - makes an explicitly pinned key evictable again
+    pub fn unpin(&self, key: &str) -> Result<bool> {
+        self.set_pinned(key, false)
+    }
+
+    fn set_pinned(&self, key: &str, pinned: bool) -> Result<bool> {
+        let now = self.now();
+        let mut data = self.write_shard(key)?;
+        let Some(entry) = data.entries.get_mut(key).filter(|entry| !entry.is_expired(now)) else {
+            return Ok(false);
+        };
+        entry.pinned = pinned;
+        if pinned {
+            data.pool.clear();
+        }
+        Ok(true)
+    }
+
+    /// Value of any type stored under `key`, following the same expiry
+    /// rules as GET
+    pub fn get_value(&self, key: &str) -> Result<Option<Value>> {
+        let now = self.now();
+        let (value, expired) = {
+            let data = self.read_shard(key)?;
+            match data.entries.get(key) {
+                Some(entry) if entry.is_expired(now) => (None, true),
+                Some(entry) => {
+                    entry.access.touch(now);
+                    (Some(entry.value.clone()), false)
+                }
+                None => (None, false),
+            }
+        };
+        if expired {
+            self.remove_expired(key)?;
+        }
+        profile::record_access(1, value.as_ref().map_or(0, Value::size), 0);
+        Ok(value)
+    }
+
+    /// HSET operation - sets fields in the hash at `key`, creating it if needed
+    ///
+    /// Returns the number of fields that were added rather than updated.
+    /// Fails with `WrongType` if the key holds a non-hash value.
+    pub fn hset(&self, key: String, fields: Vec<(String, String)>) -> Result<usize> {
+        let now = self.now();
+        let mut data = self.write_shard(&key)?;
+        let mut namespaces = self.lock_namespaces(&data)?;
+        let expired = match data.entries.get(&key) {
+            Some(entry) if entry.is_expired(now) => true,
+            Some(Entry { value: Value::Hash(_), .. }) | None => false,
+            Some(_) => return Err(WrongType.into()),
+        };
+        if expired {
+            Self::drop_expired(&mut data, &mut namespaces, &key);
+        }
+
+        let incoming = match data.entries.get(&key) {
+            Some(Entry { value: Value::Hash(hash), .. }) => fields
+                .iter()
+                .map(|(field, value)| match hash.get(field) {
+                    Some(old) => value.len().saturating_sub(old.len()),
+                    None => field.len() + value.len(),
+                })
+                .sum(),
+            _ => key.len() + fields.iter().map(|(field, value)| field.len() + value.len()).sum::<usize>(),
+        };
+        profile::record_access(1, 0, incoming);
+        self.evict_for(&mut data, &mut namespaces, &key, incoming)?;
+
+        if let Some(namespace) = Self::namespace_for(&mut namespaces, &key) {
+            namespace.touch(&key);
+            for evicted in namespace.trim() {
+                data.shard_mut(&evicted).remove(&evicted);
+            }
+        }
+        if !data.entries.contains_key(&key) {
+            data.insert(key.clone(), Entry::new(Value::Hash(IndexMap::new()), now));
+        }
+
+        let (mut added, mut grown, mut freed) = (0, 0, 0);
+        let version = data.next_version();
+        if let Some(Entry { value: Value::Hash(hash), access, written_at, version: key_version, .. }) = data.entries.get_mut(&key) {
+            access.touch(now);
+            *written_at = now;
+            *key_version = version;
+            for (field, value) in fields {
+                grown += field.len() + value.len();
+                let field_len = field.len();
+                match hash.insert(field, value) {
+                    Some(old) => freed += field_len + old.len(),
+                    None => added += 1,
+                }
+            }
+        }
+        data.resize(grown, freed);
+        Ok(added)
+    }
+
+    /// HGET operation - reads one field of a hash
+    pub fn hget(&self, key: &str, field: &str) -> Result<Option<String>> {
+        Ok(self.read_hash(key, |hash| hash.get(field).cloned())?.flatten())
+    }
+
+    /// HMGET operation - reads several fields of a hash, in request order
+    pub fn hmget(&self, key: &str, fields: &[String]) -> Result<Vec<Option<String>>> {
+        let values = self.read_hash(key, |hash| fields.iter().map(|field| hash.get(field).cloned()).collect())?;
+        Ok(values.unwrap_or_else(|| vec![None; fields.len()]))
+    }
+
+    /// HGETALL operation - every field-value pair of a hash, in insertion order
+    pub fn hgetall(&self, key: &str) -> Result<Vec<(String, String)>> {
+        let pairs = self.read_hash(key, |hash| hash.iter().map(|(field, value)| (field.clone(), value.clone())).collect())?;
+        Ok(pairs.unwrap_or_default())
+    }
+
+    /// HLEN operation - number of fields in a hash
+    pub fn hlen(&self, key: &str) -> Result<usize> {
+        Ok(self.read_hash(key, IndexMap::len)?.unwrap_or(0))
+    }
+
+    /// HDEL operation - removes fields from a hash, returning how many existed
+    ///
+    /// The key is deleted once its last field is removed.
+    pub fn hdel(&self, key: &str, fields: &[String]) -> Result<usize> {
+        let now = self.now();
+        let mut data = self.write_shard(key)?;
+        let mut namespaces = self.lock_namespaces(&data)?;
+        profile::record_access(1, 0, 0);
+        let (mut removed, mut freed) = (0, 0);
+        let version = data.next_version();
+        let emptied = match data.entries.get_mut(key) {
+            Some(entry) if entry.is_expired(now) => {
+                Self::drop_expired(&mut data, &mut namespaces, key);
+                return Ok(0);
+            }
+            Some(Entry { value: Value::Hash(hash), version: key_version, .. }) => {
+                for field in fields {
+                    if let Some(value) = hash.shift_remove(field) {
+                        removed += 1;
+                        freed += field.len() + value.len();
+                    }
+                }
+                if removed > 0 {
+                    *key_version = version;
+                }
+                hash.is_empty()
+            }
+            Some(_) => return Err(WrongType.into()),
+            None => return Ok(0),
+        };
+        data.used_bytes -= freed;
+        if emptied {
+            if let Some(namespace) = Self::namespace_for(&mut namespaces, key) {
+                namespace.forget(key);
+            }
+            data.remove(key);
+        }
+        Ok(removed)
+    }
+
+    /// Runs `read` against the hash at `key`; `None` if the key doesn't exist
+    fn read_hash<T>(&self, key: &str, read: impl FnOnce(&IndexMap<String, String>) -> T) -> Result<Option<T>> {
+        let now = self.now();
+        let (result, expired) = {
+            let data = self.read_shard(key)?;
+            match data.entries.get(key) {
+                Some(entry) if entry.is_expired(now) => (None, true),
+                Some(Entry { value: Value::Hash(hash), access, .. }) => {
+                    access.touch(now);
+                    (Some(read(hash)), false)
+                }
+                Some(_) => return Err(WrongType.into()),
+                None => (None, false),
+            }
+        };
+        if expired {
+            self.remove_expired(key)?;
+        }
+        profile::record_access(1, 0, 0);
+        Ok(result)
+    }
+
+    /// ZADD operation - sets member scores in the sorted set at `key`,
+    /// creating it if needed
+    ///
+    /// Returns the number of members that were added rather than updated.
+    /// Fails with `WrongType` if the key holds a non-sorted-set value.
+    pub fn zadd(&self, key: String, members: Vec<(String, f64)>) -> Result<usize> {
+        let now = self.now();
+        let mut data = self.write_shard(&key)?;
+        let mut namespaces = self.lock_namespaces(&data)?;
+        let expired = match data.entries.get(&key) {
+            Some(entry) if entry.is_expired(now) => true,
+            Some(Entry { value: Value::SortedSet(_), .. }) | None => false,
+            Some(_) => return Err(WrongType.into()),
+        };
+        if expired {
+            Self::drop_expired(&mut data, &mut namespaces, &key);
+        }
+
+        let member_size = |member: &String| member.len() + std::mem::size_of::<f64>();
+        let incoming = match data.entries.get(&key) {
+            Some(Entry { value: Value::SortedSet(set), .. }) => {
+                members.iter().filter(|(member, _)| set.score(member).is_none()).map(|(member, _)| member_size(member)).sum()
+            }
+            _ => key.len() + members.iter().map(|(member, _)| member_size(member)).sum::<usize>(),
+        };
+        profile::record_access(1, 0, incoming);
+        self.evict_for(&mut data, &mut namespaces, &key, incoming)?;
+
+        if let Some(namespace) = Self::namespace_for(&mut namespaces, &key) {
+            namespace.touch(&key);
+            for evicted in namespace.trim() {
+                data.shard_mut(&evicted).remove(&evicted);
+            }
+        }
+        if !data.entries.contains_key(&key) {
+            data.insert(key.clone(), Entry::new(Value::SortedSet(SortedSet::new()), now));
+        }
+
+        let (mut added, mut grown) = (0, 0);
+        let version = data.next_version();
+        if let Some(Entry { value: Value::SortedSet(set), access, written_at, version: key_version, .. }) = data.entries.get_mut(&key) {
+            access.touch(now);
+            *written_at = now;
+            *key_version = version;
+            for (member, score) in members {
+                let size = member_size(&member);
+                if set.insert(member, score).is_none() {
+                    added += 1;
+                    grown += size;
+                }
+            }
+        }
+        data.resize(grown, 0);
+        Ok(added)
+    }
+
+    /// ZREM operation - removes members from a sorted set, returning how many
+    /// existed
+    ///
+    /// The key is deleted once its last member is removed.
+    pub fn zrem(&self, key: &str, members: &[String]) -> Result<usize> {
+        let now = self.now();
+        let mut data = self.write_shard(key)?;
+        let mut namespaces = self.lock_namespaces(&data)?;
+        profile::record_access(1, 0, 0);
+        let (mut removed, mut freed) = (0, 0);
+        let version = data.next_version();
+        let emptied = match data.entries.get_mut(key) {
+            Some(entry) if entry.is_expired(now) => {
+                Self::drop_expired(&mut data, &mut namespaces, key);
+                return Ok(0);
+            }
+            Some(Entry { value: Value::SortedSet(set), version: key_version, .. }) => {
+                for member in members {
+                    if set.remove(member).is_some() {
+                        removed += 1;
+                        freed += member.len() + std::mem::size_of::<f64>();
+                    }
+                }
+                if removed > 0 {
+                    *key_version = version;
+                }
+                set.is_empty()
+            }
+            Some(_) => return Err(WrongType.into()),
+            None => return Ok(0),
+        };
+        data.used_bytes -= freed;
+        if emptied {
+            if let Some(namespace) = Self::namespace_for(&mut namespaces, key) {
+                namespace.forget(key);
+            }
+            data.remove(key);
+        }
+        Ok(removed)
+    }
+
+    /// ZSCORE operation - score of one member
+    pub fn zscore(&self, key: &str, member: &str) -> Result<Option<f64>> {
+        Ok(self.read_zset(key, |set| set.score(member))?.flatten())
+    }
+
+    /// ZRANK operation - zero-based position of a member in ascending order
+    pub fn zrank(&self, key: &str, member: &str) -> Result<Option<usize>> {
+        Ok(self.read_zset(key, |set| set.rank(member))?.flatten())
+    }
+
+    /// ZRANGE / ZREVRANGE operation - members between two ranks, inclusive,
+    /// with their scores
+    pub fn zrange(&self, key: &str, start: i64, stop: i64, rev: bool) -> Result<Vec<(String, f64)>> {
+        Ok(self.read_zset(key, |set| set.range(start, stop, rev))?.unwrap_or_default())
+    }
+
+    /// ZCARD operation - number of members in a sorted set
+    pub fn zcard(&self, key: &str) -> Result<usize> {
+        Ok(self.read_zset(key, SortedSet::len)?.unwrap_or(0))
+    }
+
+    /// Runs `read` against the sorted set at `key`; `None` if the key doesn't exist
+    fn read_zset<T>(&self, key: &str, read: impl FnOnce(&SortedSet) -> T) -> Result<Option<T>> {
+        let now = self.now();
+        let (result, expired) = {
+            let data = self.read_shard(key)?;
+            match data.entries.get(key) {
+                Some(entry) if entry.is_expired(now) => (None, true),
+                Some(Entry { value: Value::SortedSet(set), access, .. }) => {
+                    access.touch(now);
+                    (Some(read(set)), false)
+                }
+                Some(_) => return Err(WrongType.into()),
+                None => (None, false),
+            }
+        };
+        if expired {
+            self.remove_expired(key)?;
+        }
+        profile::record_access(1, 0, 0);
+        Ok(result)
+    }
+
+    /// BLOB.CREATE operation - creates an empty blob at `key` that can grow
+    /// to `max_len` bytes
+    ///
+    /// Returns false, leaving the key alone, if it already exists.
+    pub fn blob_create(&self, key: String, max_len: usize) -> Result<bool> {
+        let now = self.now();
+        let mut data = self.write_shard(&key)?;
+        let mut namespaces = self.lock_namespaces(&data)?;
+        match data.entries.get(&key) {
+            Some(entry) if entry.is_expired(now) => Self::drop_expired(&mut data, &mut namespaces, &key),
+            Some(_) => {
+                profile::record_access(1, 0, 0);
+                return Ok(false);
+            }
+            None => {}
+        }
+        profile::record_access(1, 0, key.len());
+        self.evict_for(&mut data, &mut namespaces, &key, key.len())?;
+        if let Some(namespace) = Self::namespace_for(&mut namespaces, &key) {
+            namespace.touch(&key);
+            for evicted in namespace.trim() {
+                data.shard_mut(&evicted).remove(&evicted);
+            }
+        }
+        data.insert(key, Entry::new(Value::Blob(Blob::new(max_len)), now));
+        Ok(true)
+    }
+
+    /// BLOB.APPEND operation - appends to the blob at `key` in place,
+    /// returning its new length
+    ///
+    /// Fails with `NoSuchKey` if the blob doesn't exist, `WrongType` if the
+    /// key holds another type and `BlobFull` if it would outgrow its maximum
+    /// size.
+    pub fn blob_append(&self, key: &str, bytes: &[u8]) -> Result<usize> {
+        let now = self.now();
+        let mut data = self.write_shard(key)?;
+        let mut namespaces = self.lock_namespaces(&data)?;
+        match data.entries.get(key) {
+            Some(entry) if entry.is_expired(now) => {
+                Self::drop_expired(&mut data, &mut namespaces, key);
+                return Err(NoSuchKey.into());
+            }
+            Some(Entry { value: Value::Blob(blob), .. }) => {
+                if bytes.len() > blob.max_len() - blob.len() {
+                    return Err(BlobFull { max_len: blob.max_len() }.into());
+                }
+            }
+            Some(_) => return Err(WrongType.into()),
+            None => return Err(NoSuchKey.into()),
+        }
+        profile::record_access(1, 0, bytes.len());
+        self.evict_for(&mut data, &mut namespaces, key, bytes.len())?;
+        let version = data.next_version();
+        let Some(Entry { value: Value::Blob(blob), access, written_at, version: key_version, .. }) = data.entries.get_mut(key) else {
+            return Err(NoSuchKey.into());
+        };
+        access.touch(now);
+        *written_at = now;
+        *key_version = version;
+        let len = blob.append(bytes)?;
+        data.resize(bytes.len(), 0);
+        Ok(len)
+    }
+
+    /// BLOB.READ operation - up to `len` bytes of the blob at `key` starting
+    /// at `offset`; `None` if the key doesn't exist
+    pub fn blob_read(&self, key: &str, offset: usize, len: usize) -> Result<Option<Vec<u8>>> {
+        let value = self.read_blob(key, |blob| blob.read(offset, len).to_vec())?;
+        profile::record_access(1, value.as_ref().map_or(0, Vec::len), 0);
+        Ok(value)
+    }
+
+    /// BLOB.LEN operation - current length of the blob at `key`
+    pub fn blob_len(&self, key: &str) -> Result<usize> {
+        let len = self.read_blob(key, Blob::len)?.unwrap_or(0);
+        profile::record_access(1, 0, 0);
+        Ok(len)
+    }
+
+    /// Runs `read` against the blob at `key`; `None` if the key doesn't exist
+    fn read_blob<T>(&self, key: &str, read: impl FnOnce(&Blob) -> T) -> Result<Option<T>> {
+        let now = self.now();
+        let (result, expired) = {
+            let data = self.read_shard(key)?;
+            match data.entries.get(key) {
+                Some(entry) if entry.is_expired(now) => (None, true),
+                Some(Entry { value: Value::Blob(blob), access, .. }) => {
+                    access.touch(now);
+                    (Some(read(blob)), false)
+                }
+                Some(_) => return Err(WrongType.into()),
+                None => (None, false),
+            }
+        };
+        if expired {
+            self.remove_expired(key)?;
+        }
+        Ok(result)
+    }
+
+    /// EXPIRE / PEXPIRE operation - removes a key once `ttl` has passed
+    ///
+    /// Returns false if the key doesn't exist. A zero TTL expires the key
+    /// immediately.
+    pub fn expire(&self, key: &str, ttl: Duration) -> Result<bool> {
+        let now = self.now();
+        let mut data = self.write_shard(key)?;
+        profile::record_access(1, 0, 0);
+        let version = data.next_version();
+        let ttl = data.jittered(key, ttl);
+        match data.entries.get_mut(key) {
+            Some(entry) if !entry.is_expired(now) => {
+                entry.expires_at = Some(now.saturating_add(ttl.as_millis() as u64));
+                entry.version = version;
+                data.expires.insert(key.to_string());
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// TTL / PTTL operation - time left before a key expires
+    pub fn ttl(&self, key: &str) -> Result<Ttl> {
+        let now = self.now();
+        let data = self.read_shard(key)?;
+        profile::record_access(1, 0, 0);
+        Ok(match data.entries.get(key) {
+            Some(entry) if entry.is_expired(now) => Ttl::Missing,
+            Some(Entry { expires_at: Some(deadline), .. }) => Ttl::Expires(Duration::from_millis(deadline - now)),
+            Some(_) => Ttl::Persistent,
+            None => Ttl::Missing,
+        })
+    }
+
+    /// Version of `key`'s value and TTL, which changes with every write to
+    /// them; `None` if the key doesn't exist (WATCH)
+    pub fn key_version(&self, key: &str) -> Result<Option<u64>> {
+        let now = self.now();
+        let data = self.read_shard(key)?;
+        Ok(data.entries.get(key).filter(|entry| !entry.is_expired(now)).map(|entry| entry.version))
+    }
+
+    /// Deletes `key` if it has expired, returning whether it did
+    ///
+    /// A read-only cache keeps the key, which reads as missing all the same.
+    fn remove_expired(&self, key: &str) -> Result<bool> {
+        if self.is_read_only() {
+            return Ok(false);
+        }
+        let mut data = self.write_shard(key)?;
+        // The key may have been rewritten or deleted since it was checked
+        if !data.entries.get(key).is_some_and(|entry| entry.is_expired(self.now())) {
+            return Ok(false);
+        }
+        let mut namespaces = self.lock_namespaces(&data)?;
+        Self::drop_expired(&mut data, &mut namespaces, key);
+        Ok(true)
+    }
+
+    fn drop_expired(data: &mut Store, namespaces: &mut NamespacesGuard, key: &str) {
+        if let Some(namespace) = Self::namespace_for(namespaces, key) {
+            namespace.forget(key);
+        }
+        data.remove(key);
+        data.expired += 1;
+        data.events.notify(EventFlags::EXPIRED, "expired", key);
+    }
+
+    /// One active expire cycle: removes expired keys that are never read
+    ///
+    /// Like Redis, samples `samples` keys with a TTL in each shard and
+    /// removes the expired ones, repeating while more than a quarter of a
+    /// sample had expired (up to `MAX_EXPIRE_ROUNDS` rounds per shard). The
+    /// shard lock is released between rounds. Returns the number of keys
+    /// removed.
+    pub fn active_expire_cycle(&self, samples: usize) -> Result<usize> {
+        self.active_expire_cycle_within(samples, Duration::MAX)
+    }
+
+    /// `active_expire_cycle`, but no further rounds start once `budget` has
+    /// passed, so a keyspace full of expired keys can't hold the store lock
+    /// for long; what's left waits for later cycles or a read. The next
+    /// cycle starts from the shard this one didn't finish.
+    ///
+    /// Seen filters whose whole window has passed are reclaimed too. Removes
+    /// nothing from a read-only cache.
+    pub fn active_expire_cycle_within(&self, samples: usize, budget: Duration) -> Result<usize> {
+        if self.is_read_only() {
+            return Ok(0);
+        }
+        let started = Instant::now();
+        let stats = &self.expire_stats;
+        let mut removed = 0;
+        let mut timed_out = false;
+        let first = stats.next_shard.load(Ordering::Relaxed) % self.shards.len();
+        'shards: for visited in 0..self.shards.len() {
+            let shard = (first + visited) % self.shards.len();
+            for round in 0..MAX_EXPIRE_ROUNDS {
+                if (round > 0 || visited > 0) && started.elapsed() >= budget {
+                    timed_out = true;
+                    stats.next_shard.store(shard, Ordering::Relaxed);
+                    break 'shards;
+                }
+                let now = self.now();
+                let mut data = self.write_shard_at(shard)?;
+                let mut namespaces = self.lock_namespaces(&data)?;
+
+                let len = data.expires.len();
+                let sampled = samples.min(len);
+                let mut rng = rand::thread_rng();
+                let expired: Vec<String> = (0..sampled)
+                    .map(|i| if sampled == len { i } else { rng.gen_range(0..len) })
+                    .filter_map(|index| data.expires.get_index(index))
+                    .filter(|key| data.entries.get(key.as_str()).is_some_and(|entry| entry.is_expired(now)))
+                    .cloned()
+                    .collect();
+                for key in &expired {
+                    // Random samples may repeat a key
+                    if data.entries.contains_key(key) {
+                        Self::drop_expired(&mut data, &mut namespaces, key);
+                        removed += 1;
+                    }
+                }
+                if expired.len() * 4 <= sampled || sampled == 0 {
+                    break;
+                }
+            }
+        }
+        let now = self.clock.now();
+        self.seen
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Failed to acquire seen lock"))?
+            .retain(|_, filter| !filter.is_idle(now));
+        stats.cycles.fetch_add(1, Ordering::Relaxed);
+        stats.expired.fetch_add(removed as u64, Ordering::Relaxed);
+        stats.last_expired.store(removed as u64, Ordering::Relaxed);
+        if timed_out {
+            stats.timed_out.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(removed)
+    }
+
+    /// Starts a background thread running `active_expire_cycle_within`
+    /// every `config.interval`, each cycle limited to `config.budget()`
+    ///
+    /// Store lock acquisitions during each pause measure command load; above
+    /// `config.busy_ops` a second the pause doubles, so expiry yields to
+    /// foreground commands at peak traffic, and it drops back to one
+    /// interval once load does. The thread stops when the returned handle is
+    /// dropped.
+    pub fn spawn_active_expire(&self, config: ActiveExpireConfig) -> ActiveExpire {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let cache = self.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let mut backoff = 1;
+                while !stop.load(Ordering::Acquire) {
+                    // Only a poisoned lock fails, and every later cycle would too
+                    if cache.active_expire_cycle_within(config.samples, config.budget()).is_err() {
+                        break;
+                    }
+                    let (ops, paused) = (cache.lock_acquisitions(), Instant::now());
+                    thread::park_timeout(config.interval * backoff);
+                    let rate = (cache.lock_acquisitions() - ops) as f64 / paused.elapsed().as_secs_f64().max(1e-6);
+                    backoff = if config.busy_ops > 0 && rate > config.busy_ops as f64 {
+                        (backoff * 2).min(MAX_EXPIRE_BACKOFF)
+                    } else {
+                        1
+                    };
+                    let stats = &cache.expire_stats;
+                    stats.backoff.store(u64::from(backoff), Ordering::Relaxed);
+                    if backoff > 1 {
+                        stats.backed_off.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            })
+        };
+        ActiveExpire { stop, thread: Some(thread) }
+    }
+
+    /// Counts an access to each of `keys` in the heatmap
+    pub fn record_heat<'a>(&self, keys: impl IntoIterator<Item = &'a str>) -> Result<()> {
+        let now = self.now();
+        let mut heatmap = self.heatmap.lock().map_err(|_| anyhow::anyhow!("Failed to acquire heatmap lock"))?;
+        heatmap.record(now, keys);
+        Ok(())
+    }
+
+    /// HEATMAP operation - access counts per key prefix over the last day,
+    /// summed into `window`s
+    ///
+    /// Only prefixes starting with `prefix` are included. Row start times are
+    /// Unix timestamps in milliseconds.
+    pub fn heatmap(&self, prefix: &str, window: Duration) -> Result<HeatmapMatrix> {
+        let now = self.now();
+        let mut matrix = {
+            let heatmap = self.heatmap.lock().map_err(|_| anyhow::anyhow!("Failed to acquire heatmap lock"))?;
+            heatmap.matrix(now, prefix, window)
+        };
+        let unix_now = unix_millis();
+        for (start, _) in &mut matrix.rows {
+            *start = unix_now.saturating_sub(now - *start);
+        }
+        Ok(matrix)
+    }
+
+    /// Point-in-time copy of every live key, for writing a snapshot
+    ///
+    /// Values are cloned with every shard read-locked, so writers wait for
+    /// the copy but not for the snapshot to reach disk.
+    pub fn snapshot(&self) -> Result<Vec<SnapshotEntry>> {
+        let now = self.now();
+        let unix_now = unix_millis();
+        let data = self.read_shards(&[])?;
+        Ok(data
+            .stores()
+            .flat_map(|store| &store.entries)
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .map(|(key, entry)| SnapshotEntry {
+                key: key.clone(),
+                value: entry.value.clone(),
+                expires_at: entry.expires_at.map(|deadline| unix_now + (deadline - now)),
+                pinned: entry.pinned,
+                meta: entry.meta.clone(),
+            })
+            .collect())
+    }
+
+    /// Replaces the keyspace with `entries`, returning how many were loaded
+    ///
+    /// Keys whose expiry has passed since the snapshot was taken are skipped.
+    /// maxmemory isn't enforced while loading.
+    pub fn restore(&self, entries: Vec<SnapshotEntry>) -> Result<usize> {
+        let now = self.now();
+        let unix_now = unix_millis();
+        let mut data = self.write_shards(&[])?;
+        let mut namespaces = self.lock_namespaces(&data)?;
+        for namespace in namespaces.iter_mut().flat_map(|namespaces| namespaces.values_mut()) {
+            namespace.members.clear();
+            namespace.order.clear();
+        }
+        data.stores_mut().for_each(Store::clear);
+        let mut loaded = 0;
+        for snapshot in entries {
+            let expires_at = match snapshot.expires_at {
+                Some(deadline) if deadline <= unix_now => continue,
+                Some(deadline) => Some(now + (deadline - unix_now)),
+                None => None,
+            };
+            if let Some(namespace) = Self::namespace_for(&mut namespaces, &snapshot.key) {
+                namespace.touch(&snapshot.key);
+            }
+            let entry = Entry { pinned: snapshot.pinned, expires_at, meta: snapshot.meta, ..Entry::new(snapshot.value, now) };
+            data.shard_mut(&snapshot.key).insert(snapshot.key, entry);
+            loaded += 1;
+        }
+        Ok(loaded)
+    }
+
+    /// SAVE operation - writes a snapshot to the configured path, returning
+    /// the number of keys saved
+    pub fn save(&self) -> Result<usize> {
+        self.save_to(&self.snapshots.path()?)
+    }
+
+    /// Writes a snapshot to `path`
+    ///
+    /// Fails with `SaveInProgress` while another save is writing.
+    pub fn save_to(&self, path: &Path) -> Result<usize> {
+        if !self.snapshots.begin() {
+            return Err(SaveInProgress.into());
+        }
+        let writes = self.writes();
+        let saved = self.snapshot().and_then(|entries| {
+            snapshot::write_file(&entries, Some(self.ids.snowflake_state()?), path)?;
+            Ok(entries.len())
+        });
+        self.snapshots.finish(saved.is_ok().then_some(writes));
+        saved
+    }
+
+    /// BGSAVE operation - copies the keyspace, then writes it to the
+    /// configured path on a background thread
+    ///
+    /// Fails with `SaveInProgress` while another save is writing.
+    pub fn bgsave(&self) -> Result<JoinHandle<()>> {
+        if !self.snapshots.begin() {
+            return Err(SaveInProgress.into());
+        }
+        let writes = self.writes();
+        let snapshot = self
+            .snapshots
+            .path()
+            .and_then(|path| Ok((path, self.snapshot()?, self.ids.snowflake_state()?)));
+        let (path, entries, snowflake) = match snapshot {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                self.snapshots.finish(None);
+                return Err(e);
+            }
+        };
+        let state = self.snapshots.clone();
+        Ok(thread::spawn(move || {
+            let saved = snapshot::write_file(&entries, Some(snowflake), &path).is_ok();
+            state.record_background(saved);
+            state.finish(saved.then_some(writes));
+        }))
+    }
+
+    /// Loads the snapshot at `path`, replacing the keyspace and resuming
+    /// the snowflake generator after the saved state
+    ///
+    /// The loaded keys count as saved, so they don't trigger an automatic
+    /// save on their own.
+    pub fn load(&self, path: &Path) -> Result<usize> {
+        let (entries, snowflake) = snapshot::read_file(path)?;
+        let loaded = self.restore(entries)?;
+        if let Some(state) = snowflake {
+            self.ids.restore_snowflake(state)?;
+        }
+        self.snapshots.mark_saved(self.writes());
+        Ok(loaded)
+    }
+
+    /// Makes `path` the snapshot path and loads it if it exists, returning
+    /// the number of keys loaded
+    ///
+    /// Startup recovery: later SAVE and BGSAVE calls write back to `path`.
+    pub fn load_from(&self, path: &Path) -> Result<usize> {
+        self.snapshots.set_path(path.to_path_buf())?;
+        if !path.exists() {
+            return Ok(0);
+        }
+        self.load(path)
+    }
+
+    /// Writes since the last successful snapshot
+    ///
+    /// Counted as write-lock acquisitions, so a write that changed nothing
+    /// (e.g. DEL of a missing key) still counts.
+    pub fn changes_since_save(&self) -> u64 {
+        self.snapshots.changes_since_save(self.writes())
+    }
+
+    /// Starts a thread that runs BGSAVE whenever a save point is reached
+    /// (see `SnapshotState::save_due`); save points can change at runtime
+    /// with CONFIG SET save
+    pub fn spawn_auto_save(&self) -> AutoSave {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let cache = self.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                while !stop.load(Ordering::Acquire) {
+                    match cache.snapshots.save_due(cache.writes()) {
+                        Ok(true) => {
+                            // Only fails if another save started in between
+                            let _ = cache.bgsave();
+                        }
+                        Ok(false) => {}
+                        Err(_) => break,
+                    }
+                    thread::park_timeout(AUTO_SAVE_INTERVAL);
+                }
+            })
+        };
+        AutoSave { stop, thread: Some(thread) }
+    }
+
+    fn writes(&self) -> u64 {
+        self.lock_stats.writes.load(Ordering::Relaxed)
+    }
+
+    /// Snapshot path and save history
+    pub fn snapshots(&self) -> &SnapshotState {
+        &self.snapshots
+    }
+
+    /// Status of the warm rules started with `warm::spawn`
+    pub fn warmers(&self) -> &WarmState {
+        &self.warmers
+    }
+
+    /// Pub/sub channels, shared by every connection to this cache
+    pub fn pubsub(&self) -> &Arc<PubSub> {
+        &self.pubsub
+    }
+
+    /// Runs `run` alongside other commands, but never while a transaction
+    /// runs (see `exclusive`)
+    pub(crate) fn shared<T>(&self, run: impl FnOnce() -> T) -> T {
+        // The lock guards no data, so a panic while it was held left
+        // nothing half-done
+        let _guard = self.transactions.read().unwrap_or_else(PoisonError::into_inner);
+        run()
+    }
+
+    /// Runs `run` with every other command sent through `RustdisProtocol`
+    /// waiting, so a transaction's commands apply as one step
+    pub(crate) fn exclusive<T>(&self, run: impl FnOnce() -> T) -> T {
+        let _guard = self.transactions.write().unwrap_or_else(PoisonError::into_inner);
+        run()
+    }
+
+    /// Reply mode new RESP connections start in (`redis-compat-strict`);
+    /// COMPAT switches a connection's own
+    pub fn compat_mode(&self) -> CompatMode {
+        if self.redis_compat_strict.load(Ordering::Relaxed) {
+            CompatMode::Strict
+        } else {
+            CompatMode::Native
         }
     }
 
-    /// GET operation - retrieves value by key
-    pub fn get(&self, key: &str) -> Result<Option<String>> {
-        let data = self.data.read().map_err(|_| anyhow::anyhow!("Failed to acquire read lock"))?;
-        Ok(data.get(key).cloned())
+    /// Makes every data write fail with `ReadOnly`, for serving a loaded
+    /// snapshot without changing it; expired keys are no longer removed and
+    /// lowering maxmemory evicts nothing, but CONFIG SET and SAVE still work
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::Relaxed);
     }
 
-    /// SET operation - stores key-value pair
-    pub fn set(&self, key: String, value: String) -> Result<()> {
-        let mut data = self.data.write().map_err(|_| anyhow::anyhow!("Failed to acquire write lock"))?;
-        data.insert(key, value);
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Relaxed)
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.is_read_only() {
+            return Err(ReadOnly.into());
+        }
         Ok(())
     }
 
-    /// DEL operation - deletes a key
-    pub fn del(&self, key: &str) -> Result<bool> {
-        let mut data = self.data.write().map_err(|_| anyhow::anyhow!("Failed to acquire write lock"))?;
-        Ok(data.remove(key).is_some())
+    /// Keyspace notifier publishing on this cache's channels
+    pub fn keyspace_events(&self) -> &Arc<KeyspaceEvents> {
+        &self.events
     }
 
-    /// EXISTS operation - checks if key exists
-    pub fn exists(&self, key: &str) -> Result<bool> {
-        let data = self.data.read().map_err(|_| anyhow::anyhow!("Failed to acquire read lock"))?;
-        Ok(data.contains_key(key))
+    /// INFO memory section - memory usage, limits and pinned-key accounting
+    pub fn memory_info(&self) -> Result<MemoryInfo> {
+        let data = self.read_shards(&[])?;
+        let mut info = MemoryInfo { keys: 0, used_bytes: 0, maxmemory: 0, evictions: 0, pinned_keys: 0, pinned_bytes: 0 };
+        for store in data.stores() {
+            for (key, entry) in &store.entries {
+                if store.is_pinned(key, entry) {
+                    info.pinned_keys += 1;
+                    info.pinned_bytes += entry.size(key);
+                }
+            }
+            info.keys += store.entries.len();
+            info.used_bytes += store.used_bytes;
+            info.maxmemory += store.maxmemory;
+            info.evictions += store.evictions;
+        }
+        Ok(info)
     }
 
-    /// KEYS operation - returns all keys (be careful with large datasets)
-    pub fn keys(&self) -> Result<Vec<String>> {
-        let data = self.data.read().map_err(|_| anyhow::anyhow!("Failed to acquire read lock"))?;
-        Ok(data.keys().cloned().collect())
+    /// MEMORY STATS operation - global memory accounting
+    pub fn memory_stats(&self) -> Result<MemoryStats> {
+        let data = self.read_shards(&[])?;
+        let mut stats = MemoryStats { keys: 0, volatile_keys: 0, dataset_bytes: 0, overhead_bytes: 0, peak_bytes: 0, maxmemory: 0 };
+        for store in data.stores() {
+            let volatile_key_bytes: usize = store.expires.iter().map(|key| key.len()).sum();
+            stats.keys += store.entries.len();
+            stats.volatile_keys += store.expires.len();
+            stats.dataset_bytes += store.used_bytes;
+            stats.overhead_bytes += store.entries.len() * KEY_OVERHEAD + store.expires.len() * VOLATILE_KEY_OVERHEAD + volatile_key_bytes;
+            stats.peak_bytes += store.peak_bytes;
+            stats.maxmemory += store.maxmemory;
+        }
+        Ok(stats)
     }
 
-    /// FLUSH operation - clears all data
-    pub fn flush(&self) -> Result<()> {
-        let mut data = self.data.write().map_err(|_| anyhow::anyhow!("Failed to acquire write lock"))?;
-        data.clear();
+    fn lock_acquisitions(&self) -> u64 {
+        self.lock_stats.reads.load(Ordering::Relaxed) + self.lock_stats.writes.load(Ordering::Relaxed)
+    }
+
+    /// DEBUG STATE operation - snapshot of internal state for bug reports
+    pub fn debug_state(&self) -> Result<DebugState> {
+        let data = self.read_shards(&[])?;
+        let namespaces = self.namespaces.lock().map_err(|_| anyhow::anyhow!("Failed to acquire namespace lock"))?.len();
+        let seen_filters = self.seen.lock().map_err(|_| anyhow::anyhow!("Failed to acquire seen lock"))?.len();
+        let stats = &self.lock_stats;
+        let expire = &self.expire_stats;
+        Ok(DebugState {
+            shards: self.shards.len(),
+            keys: data.stores().map(|store| store.entries.len()).sum(),
+            used_bytes: data.stores().map(|store| store.used_bytes).sum(),
+            maxmemory: data.stores().map(|store| store.maxmemory).sum(),
+            evictions: data.stores().map(|store| store.evictions).sum(),
+            expired: data.stores().map(|store| store.expired).sum(),
+            volatile_keys: data.stores().map(|store| store.expires.len()).sum(),
+            expire_cycles: expire.cycles.load(Ordering::Relaxed),
+            expired_by_cycles: expire.expired.load(Ordering::Relaxed),
+            expired_last_cycle: expire.last_expired.load(Ordering::Relaxed),
+            expire_timed_out: expire.timed_out.load(Ordering::Relaxed),
+            expire_backed_off: expire.backed_off.load(Ordering::Relaxed),
+            expire_backoff: expire.backoff.load(Ordering::Relaxed).max(1),
+            pending_evictions: data.stores().map(|store| store.pool.len()).sum(),
+            namespaces,
+            seen_filters,
+            lock_reads: stats.reads.load(Ordering::Relaxed),
+            lock_writes: stats.writes.load(Ordering::Relaxed),
+            lock_read_wait_us: stats.read_wait_ns.load(Ordering::Relaxed) / 1_000,
+            lock_write_wait_us: stats.write_wait_ns.load(Ordering::Relaxed) / 1_000,
+            lock_max_wait_us: stats.max_wait_ns.load(Ordering::Relaxed) / 1_000,
+        })
+    }
+
+    /// EVICTION DEBUG operation - measures how close sampled eviction is to
+    /// exact LRU or LFU
+    ///
+    /// Runs `trials` simulated picks with the current sample size and policy
+    /// and ranks each picked key's score (idle time, or rarity for LFU)
+    /// against every key the policy may evict: a rank of 0.0 means it was the
+    /// key the exact policy would pick, 1.0 the one it would keep longest.
+    /// With several shards, each trial samples one shard, picked by its
+    /// share of those keys, as evictions do, but ranks against them all.
+    pub fn eviction_debug(&self, trials: usize) -> Result<EvictionReport> {
+        let data = self.read_shards(&[])?;
+        let now = self.now();
+        let mut scores: Vec<u64> = data
+            .stores()
+            .flat_map(|store| {
+                store
+                    .entries
+                    .values()
+                    .filter(|entry| !store.policy.is_volatile() || entry.expires_at.is_some())
+                    .map(move |entry| store.eviction_score(entry, now))
+            })
+            .collect();
+        scores.sort_unstable();
+
+        let mut report = EvictionReport {
+            keys: scores.len(),
+            samples: data.samples,
+            pool_size: data.stores().map(|store| store.pool.len()).sum(),
+            evictions: data.stores().map(|store| store.evictions).sum(),
+            used_bytes: data.stores().map(|store| store.used_bytes).sum(),
+            maxmemory: data.stores().map(|store| store.maxmemory).sum(),
+            policy: data.policy,
+            trials: 0,
+            mean_rank: 0.0,
+            worst_rank: 0.0,
+        };
+        if scores.is_empty() {
+            return Ok(report);
+        }
+
+        let mut rng = rand::thread_rng();
+        let mut total = 0.0;
+        let populations: Vec<usize> = data.stores().map(Store::population).collect();
+        for _ in 0..trials {
+            let (_, store) = &data.guards[weighted_index(&mut rng, &populations)];
+            let picked = (0..store.samples)
+                .filter_map(|_| store.candidate(rng.gen_range(0..store.population())))
+                .map(|(_, entry)| store.eviction_score(entry, now))
+                .max()
+                .unwrap_or(0);
+            // Fraction of keys that were better victims than the pick
+            let better = scores.len() - scores.partition_point(|&score| score <= picked);
+            let rank = better as f64 / scores.len() as f64;
+            total += rank;
+            report.worst_rank = report.worst_rank.max(rank);
+        }
+        report.trials = trials;
+        if trials > 0 {
+            report.mean_rank = total / trials as f64;
+        }
+        Ok(report)
+    }
+
+    /// DEBUG SET-TIME operation - moves a manual clock to `since_origin`
+    ///
+    /// Only caches built with a `ManualClock` (e.g. by `testing::TestServer`)
+    /// support this; the system clock can't be set. Compiled only with the
+    /// `testing` feature, so production builds can't move time at all.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn set_time(&self, since_origin: Duration) -> Result<()> {
+        let clock = self
+            .clock
+            .as_manual()
+            .ok_or_else(|| anyhow::anyhow!("DEBUG SET-TIME requires a cache built with a manual clock"))?;
+        clock.set(since_origin);
         Ok(())
     }
 
-    /// SIZE operation - returns number of keys
-    pub fn size(&self) -> Result<usize> {
-        let data = self.data.read().map_err(|_| anyhow::anyhow!("Failed to acquire read lock"))?;
-        Ok(data.len())
+    /// Milliseconds since the cache epoch
+    fn now(&self) -> u64 {
+        self.clock.now().saturating_duration_since(self.epoch).as_millis() as u64
+    }
+
+    /// Evicts keys until `incoming` more bytes fit under maxmemory
+    ///
+    /// `protected` (the key being written) is never chosen as a victim.
+    fn evict_for(&self, data: &mut Store, namespaces: &mut NamespacesGuard, protected: &str, incoming: usize) -> Result<()> {
+        if data.maxmemory == 0 {
+            return Ok(());
+        }
+        let now = self.now();
+        while data.used_bytes + incoming > data.maxmemory {
+            let Some(victim) = data.next_victim(now, protected) else {
+                return Err(anyhow::anyhow!("OOM command not allowed when used memory > 'maxmemory'"));
+            };
+            data.remove(&victim);
+            data.evictions += 1;
+            data.events.notify(EventFlags::EVICTED, "evicted", &victim);
+            if let Some(namespace) = Self::namespace_for(namespaces, &victim) {
+                namespace.forget(&victim);
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds the namespace owning a key, preferring the longest matching prefix
+    fn namespace_for<'a>(namespaces: &'a mut NamespacesGuard, key: &str) -> Option<&'a mut Namespace> {
+        let namespaces = namespaces.as_deref_mut()?;
+        let prefix = namespaces
+            .keys()
+            .filter(|prefix| key.starts_with(prefix.as_str()))
+            .max_by_key(|prefix| prefix.len())?
+            .clone();
+        namespaces.get_mut(&prefix)
     }
 }
 
@@ -70,6 +3407,7 @@ impl Default for RustdisCache {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::ManualClock;
 
     #[test]
     fn test_basic_operations() {
@@ -110,4 +3448,877 @@ mod tests {
         cache.flush().unwrap();
         assert_eq!(cache.size().unwrap(), 0);
     }
+
+    #[test]
+    fn test_namespace_limit_trims_oldest() {
+        let cache = RustdisCache::new();
+        cache.set("other".to_string(), "x".to_string()).unwrap();
+        cache.set_namespace_limit("recent:*", 2).unwrap();
+
+        cache.set("recent:1".to_string(), "a".to_string()).unwrap();
+        cache.set("recent:2".to_string(), "b".to_string()).unwrap();
+        cache.set("recent:3".to_string(), "c".to_string()).unwrap();
+
+        assert_eq!(cache.get("recent:1").unwrap(), None);
+        assert!(cache.exists("recent:2").unwrap());
+        assert!(cache.exists("recent:3").unwrap());
+        assert!(cache.exists("other").unwrap());
+
+        // Rewriting a key makes it the newest in its namespace
+        cache.set("recent:2".to_string(), "b2".to_string()).unwrap();
+        cache.set("recent:4".to_string(), "d".to_string()).unwrap();
+        assert_eq!(cache.get("recent:3").unwrap(), None);
+        assert_eq!(cache.get("recent:2").unwrap(), Some("b2".to_string()));
+
+        // Lowering the cap trims immediately
+        let evicted = cache.set_namespace_limit("recent:*", 1).unwrap();
+        assert_eq!(evicted, vec!["recent:2".to_string()]);
+        assert_eq!(cache.namespace_limits().unwrap(), vec![("recent:*".to_string(), 1)]);
+    }
+
+    #[test]
+    fn test_maxmemory_evicts_least_recently_used() {
+        let clock = Arc::new(ManualClock::new());
+        let cache = RustdisCache::with_clock(clock.clone());
+        cache.config_set("maxmemory-samples", "10").unwrap();
+        for i in 0..10 {
+            cache.set(format!("key{}", i), "0123456789".to_string()).unwrap();
+        }
+        clock.advance(Duration::from_millis(5));
+        // Touch everything except key0 so it is clearly the LRU key
+        for i in 1..10 {
+            cache.get(&format!("key{}", i)).unwrap();
+        }
+
+        // Each entry is 14 bytes; leave room for 9 of them
+        cache.config_set("maxmemory", "126").unwrap();
+        assert_eq!(cache.size().unwrap(), 9);
+        assert!(!cache.exists("key0").unwrap());
+
+        let report = cache.eviction_debug(20).unwrap();
+        assert_eq!(report.keys, 9);
+        assert_eq!(report.evictions, 1);
+        assert!(report.mean_rank <= report.worst_rank);
+        assert_eq!(cache.config_get("maxmemory").unwrap()["maxmemory"], "126");
+
+        // A value larger than maxmemory can never fit
+        assert!(cache.set("huge".to_string(), "x".repeat(200)).is_err());
+    }
+
+    #[test]
+    fn test_debug_state() {
+        let cache = RustdisCache::new();
+        cache.set("a".to_string(), "1".to_string()).unwrap();
+        cache.get("a").unwrap();
+        cache.seen_add("webhooks", "evt_1", Duration::from_secs(60)).unwrap();
+
+        let state = cache.debug_state().unwrap();
+        assert_eq!((state.shards, state.keys, state.used_bytes), (1, 1, 2));
+        assert_eq!(state.seen_filters, 1);
+        // The GET plus the snapshot's own read
+        assert_eq!((state.lock_reads, state.lock_writes), (2, 1));
+    }
+
+    #[test]
+    fn test_keys_expire_lazily() {
+        let clock = Arc::new(ManualClock::new());
+        let cache = RustdisCache::with_clock(clock.clone());
+        cache.set("session".to_string(), "abc".to_string()).unwrap();
+        cache.set("user".to_string(), "ana".to_string()).unwrap();
+        assert_eq!(cache.ttl("session").unwrap(), Ttl::Persistent);
+        assert_eq!(cache.ttl("missing").unwrap(), Ttl::Missing);
+        assert!(!cache.expire("missing", Duration::from_secs(1)).unwrap());
+
+        assert!(cache.expire("session", Duration::from_secs(10)).unwrap());
+        clock.advance(Duration::from_secs(4));
+        assert_eq!(cache.ttl("session").unwrap(), Ttl::Expires(Duration::from_secs(6)));
+        assert_eq!(cache.get("session").unwrap(), Some("abc".to_string()));
+
+        clock.advance(Duration::from_secs(6));
+        assert_eq!(cache.ttl("session").unwrap(), Ttl::Missing);
+        assert_eq!(cache.keys().unwrap(), vec!["user".to_string()]);
+        assert_eq!(cache.size().unwrap(), 2);
+        assert!(!cache.exists("session").unwrap());
+        assert_eq!((cache.size().unwrap(), cache.debug_state().unwrap().expired), (1, 1));
+
+        // SET clears the TTL
+        assert!(cache.expire("user", Duration::from_secs(1)).unwrap());
+        cache.set("user".to_string(), "bia".to_string()).unwrap();
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(cache.get("user").unwrap(), Some("bia".to_string()));
+
+        assert!(cache.expire("user", Duration::ZERO).unwrap());
+        assert!(!cache.del("user").unwrap());
+    }
+
+    #[test]
+    fn test_active_expire_cycle() {
+        let clock = Arc::new(ManualClock::new());
+        let cache = RustdisCache::with_clock(clock.clone());
+        for i in 0..100 {
+            cache.set(format!("temp:{}", i), "x".to_string()).unwrap();
+            cache.expire(&format!("temp:{}", i), Duration::from_secs(if i < 90 { 1 } else { 60 })).unwrap();
+        }
+        cache.set("keep".to_string(), "y".to_string()).unwrap();
+        assert_eq!(cache.debug_state().unwrap().volatile_keys, 100);
+
+        // Nothing has expired yet
+        assert_eq!(cache.active_expire_cycle(20).unwrap(), 0);
+
+        // Sampling only stops once few sampled keys are expired, so most of
+        // the expired keys go in one cycle; a full-size sample finds the rest
+        clock.advance(Duration::from_secs(2));
+        assert!(cache.active_expire_cycle(20).unwrap() > 0);
+        cache.active_expire_cycle(100).unwrap();
+        assert_eq!(cache.size().unwrap(), 11);
+        let state = cache.debug_state().unwrap();
+        assert_eq!((state.expired, state.volatile_keys), (90, 10));
+
+        let state = cache.debug_state().unwrap();
+        assert_eq!((state.expire_cycles, state.expired_by_cycles, state.expire_timed_out), (3, 90, 0));
+
+        // A spent budget stops the cycle after its first round
+        for i in 0..100 {
+            cache.set(format!("burst:{}", i), "x".to_string()).unwrap();
+            cache.expire(&format!("burst:{}", i), Duration::from_secs(1)).unwrap();
+        }
+        clock.advance(Duration::from_secs(2));
+        let removed = cache.active_expire_cycle_within(20, Duration::ZERO).unwrap();
+        assert!((1..=20).contains(&removed));
+        let state = cache.debug_state().unwrap();
+        assert_eq!((state.expired_last_cycle, state.expire_timed_out, state.expire_backoff), (removed as u64, 1, 1));
+        assert_eq!(ActiveExpireConfig::default().budget(), Duration::from_millis(25));
+
+        // Cycles cut short by their budget resume where the last one stopped,
+        // so every shard gets its turn rather than only the first
+        let sharded = RustdisCache::with_clock(clock.clone()).sharded(4);
+        for i in 0..40 {
+            sharded.set(format!("burst:{}", i), "x".to_string()).unwrap();
+            sharded.expire(&format!("burst:{}", i), Duration::from_secs(1)).unwrap();
+        }
+        clock.advance(Duration::from_secs(2));
+        for _ in 0..8 {
+            sharded.active_expire_cycle_within(100, Duration::ZERO).unwrap();
+        }
+        assert_eq!(sharded.debug_state().unwrap().volatile_keys, 0);
+
+        clock.advance(Duration::from_secs(60));
+        let config = ActiveExpireConfig { interval: Duration::from_millis(1), ..ActiveExpireConfig::default() };
+        let sweeper = cache.spawn_active_expire(config);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while cache.size().unwrap() > 1 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+        drop(sweeper);
+        assert_eq!(cache.keys().unwrap(), vec!["keep".to_string()]);
+
+        // Under load the pause between cycles lengthens
+        let busy = ActiveExpireConfig { interval: Duration::from_millis(5), busy_ops: 1, ..ActiveExpireConfig::default() };
+        let sweeper = cache.spawn_active_expire(busy);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while cache.debug_state().unwrap().expire_backoff < 2 && Instant::now() < deadline {
+            cache.get("keep").unwrap();
+        }
+        drop(sweeper);
+        assert!(cache.debug_state().unwrap().expire_backed_off > 0);
+    }
+
+    #[test]
+    fn test_hashes() {
+        let cache = RustdisCache::new();
+        let fields = |pairs: &[(&str, &str)]| pairs.iter().map(|(f, v)| (f.to_string(), v.to_string())).collect::<Vec<_>>();
+
+        assert_eq!(cache.hset("user:1".to_string(), fields(&[("name", "ana"), ("age", "30")])).unwrap(), 2);
+        assert_eq!(cache.hset("user:1".to_string(), fields(&[("age", "31"), ("city", "rio")])).unwrap(), 1);
+        assert_eq!(cache.hget("user:1", "age").unwrap(), Some("31".to_string()));
+        assert_eq!(cache.hget("user:1", "nope").unwrap(), None);
+        assert_eq!(
+            cache.hmget("user:1", &["city".to_string(), "x".to_string()]).unwrap(),
+            vec![Some("rio".to_string()), None]
+        );
+        assert_eq!(cache.hgetall("user:1").unwrap(), fields(&[("name", "ana"), ("age", "31"), ("city", "rio")]));
+        assert_eq!(cache.memory_info().unwrap().used_bytes, "user:1".len() + "nameana".len() + "age31".len() + "cityrio".len());
+
+        // Type mismatches are reported, not coerced
+        cache.set("plain".to_string(), "x".to_string()).unwrap();
+        assert!(cache.hget("plain", "f").unwrap_err().is::<WrongType>());
+        assert!(cache.hset("plain".to_string(), fields(&[("f", "v")])).unwrap_err().is::<WrongType>());
+        assert!(cache.get("user:1").unwrap_err().is::<WrongType>());
+
+        assert_eq!(cache.hdel("user:1", &["name".to_string(), "age".to_string(), "x".to_string()]).unwrap(), 2);
+        assert_eq!(cache.hlen("user:1").unwrap(), 1);
+        assert_eq!(cache.hdel("user:1", &["city".to_string()]).unwrap(), 1);
+        assert!(!cache.exists("user:1").unwrap());
+        assert_eq!(cache.memory_info().unwrap().used_bytes, "plainx".len());
+    }
+
+    #[test]
+    fn test_set_options() {
+        let clock = Arc::new(ManualClock::new());
+        let cache = RustdisCache::with_clock(clock.clone());
+        let lock = SetOptions {
+            condition: SetCondition::IfMissing,
+            expiry: SetExpiry::After(Duration::from_secs(30)),
+            get: false,
+        };
+
+        // NX with a TTL acquires a lock exactly once until it expires
+        assert!(cache.set_with("lock".to_string(), "a".to_string(), lock).unwrap().written);
+        assert!(!cache.set_with("lock".to_string(), "b".to_string(), lock).unwrap().written);
+        assert_eq!(cache.ttl("lock").unwrap(), Ttl::Expires(Duration::from_secs(30)));
+        clock.advance(Duration::from_secs(30));
+        assert!(cache.set_with("lock".to_string(), "b".to_string(), lock).unwrap().written);
+
+        let xx = SetOptions { condition: SetCondition::IfExists, ..SetOptions::default() };
+        assert!(!cache.set_with("missing".to_string(), "v".to_string(), xx).unwrap().written);
+        assert!(!cache.exists("missing").unwrap());
+
+        let keep = SetOptions { expiry: SetExpiry::Keep, get: true, ..SetOptions::default() };
+        let outcome = cache.set_with("lock".to_string(), "c".to_string(), keep).unwrap();
+        assert_eq!(outcome, SetOutcome { written: true, previous: Some("b".to_string()) });
+        assert_eq!(cache.ttl("lock").unwrap(), Ttl::Expires(Duration::from_secs(30)));
+        cache.set_with("lock".to_string(), "d".to_string(), SetOptions::default()).unwrap();
+        assert_eq!(cache.ttl("lock").unwrap(), Ttl::Persistent);
+
+        cache.hset("h".to_string(), vec![("f".to_string(), "v".to_string())]).unwrap();
+        let get = SetOptions { get: true, ..SetOptions::default() };
+        assert!(cache.set_with("h".to_string(), "v".to_string(), get).unwrap_err().is::<WrongType>());
+    }
+
+    #[test]
+    fn test_rename_and_copy() {
+        let clock = Arc::new(ManualClock::new());
+        let cache = RustdisCache::with_clock(clock.clone());
+        cache.set("a".to_string(), "1".to_string()).unwrap();
+        cache.expire("a", Duration::from_secs(30)).unwrap();
+        cache.set("b".to_string(), "2".to_string()).unwrap();
+        let used = cache.memory_info().unwrap().used_bytes;
+
+        assert!(cache.rename("missing", "x", true).unwrap_err().is::<NoSuchKey>());
+        assert!(!cache.rename("a", "b", false).unwrap());
+        assert!(!cache.rename("a", "a", false).unwrap());
+        assert!(cache.rename("a", "a", true).unwrap());
+
+        // The value keeps its TTL and the old key is gone
+        assert!(cache.rename("a", "renamed", true).unwrap());
+        assert_eq!(cache.get("a").unwrap(), None);
+        assert_eq!(cache.ttl("renamed").unwrap(), Ttl::Expires(Duration::from_secs(30)));
+        assert!(cache.rename("renamed", "b", true).unwrap());
+        assert_eq!(cache.get("b").unwrap().as_deref(), Some("1"));
+        assert_eq!(cache.size().unwrap(), 1);
+        assert_eq!(cache.memory_info().unwrap().used_bytes, used - 2);
+
+        // COPY leaves the source alone and works on any type
+        cache.hset("h".to_string(), vec![("f".to_string(), "v".to_string())]).unwrap();
+        assert!(cache.copy("h", "h2", false).unwrap());
+        assert!(!cache.copy("h", "h2", false).unwrap());
+        assert!(!cache.copy("missing", "h2", true).unwrap());
+        assert!(cache.copy("h", "h", true).is_err());
+        assert_eq!(cache.hget("h2", "f").unwrap().as_deref(), Some("v"));
+        assert!(cache.copy("b", "h2", true).unwrap());
+        assert_eq!(cache.get("h2").unwrap().as_deref(), Some("1"));
+        assert_eq!(cache.ttl("h2").unwrap(), Ttl::Expires(Duration::from_secs(30)));
+
+        // Expired sources count as missing
+        clock.advance(Duration::from_secs(31));
+        assert!(!cache.copy("b", "c", false).unwrap());
+        assert!(cache.rename("b", "c", true).unwrap_err().is::<NoSuchKey>());
+
+        // Namespace membership follows the key
+        cache.set_namespace_limit("recent:*", 1).unwrap();
+        cache.set("tmp".to_string(), "x".to_string()).unwrap();
+        assert!(cache.rename("tmp", "recent:1", true).unwrap());
+        assert!(cache.copy("recent:1", "recent:2", false).unwrap());
+        assert_eq!(cache.get("recent:1").unwrap(), None);
+        assert_eq!(cache.get("recent:2").unwrap().as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn test_binary_values() {
+        let cache = RustdisCache::new();
+        let binary = vec![0xff, 0x00, 0xfe];
+        cache.set_bytes("bin".to_string(), binary.clone()).unwrap();
+        cache.set("text".to_string(), "héllo".to_string()).unwrap();
+
+        assert_eq!(cache.get_bytes("bin").unwrap(), Some(binary.clone()));
+        assert_eq!(cache.get_bytes("text").unwrap(), Some("héllo".as_bytes().to_vec()));
+        assert!(cache.get("bin").unwrap_err().is::<NotUtf8>());
+        assert!(cache.mget(&["text".to_string(), "bin".to_string()]).unwrap_err().is::<NotUtf8>());
+
+        // Reading the old value as text fails before the write happens
+        let get = SetOptions { get: true, ..SetOptions::default() };
+        assert!(cache.set_with("bin".to_string(), "new".to_string(), get).unwrap_err().is::<NotUtf8>());
+        assert!(cache.getdel("bin").unwrap_err().is::<NotUtf8>());
+        let outcome = cache.set_bytes_with("bin".to_string(), b"new".to_vec(), get).unwrap();
+        assert_eq!(outcome.previous, Some(binary));
+        assert_eq!(cache.get("bin").unwrap().as_deref(), Some("new"));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let cache = RustdisCache::new();
+        cache.set_bytes("bin".to_string(), vec![0xff, 0x00]).unwrap();
+        cache.set("session".to_string(), "abc".to_string()).unwrap();
+        cache.expire("session", Duration::from_secs(60)).unwrap();
+        cache.hset("user:1".to_string(), vec![("name".to_string(), "ana".to_string())]).unwrap();
+        cache.pin("user:1").unwrap();
+        cache.generate_id(IdKind::Snowflake).unwrap();
+
+        let path = std::env::temp_dir().join(format!("rustdis-save-{}.rsnap", std::process::id()));
+        assert_eq!(cache.save_to(&path).unwrap(), 3);
+        assert!(cache.snapshots().last_save() > 0);
+
+        let loaded = RustdisCache::new();
+        assert_eq!(loaded.load_from(&path.with_extension("missing")).unwrap(), 0);
+        loaded.set("stale".to_string(), "x".to_string()).unwrap();
+        assert_eq!(loaded.load_from(&path).unwrap(), 3);
+        assert_eq!(loaded.snapshots().path().unwrap(), path);
+        assert_eq!(loaded.ids.snowflake_state().unwrap(), cache.ids.snowflake_state().unwrap());
+        assert_eq!(loaded.get("stale").unwrap(), None);
+        assert_eq!(loaded.get_bytes("bin").unwrap(), Some(vec![0xff, 0x00]));
+        assert_eq!(loaded.hget("user:1", "name").unwrap().as_deref(), Some("ana"));
+        assert!(loaded.snapshot().unwrap().iter().any(|entry| entry.key == "user:1" && entry.pinned));
+        let Ttl::Expires(ttl) = loaded.ttl("session").unwrap() else {
+            panic!("session lost its TTL");
+        };
+        assert!(ttl > Duration::from_secs(55) && ttl <= Duration::from_secs(60));
+
+        // BGSAVE writes to the configured path from a copy of the keyspace
+        let handle = loaded.bgsave().unwrap();
+        loaded.flush().unwrap();
+        handle.join().unwrap();
+        assert!(loaded.snapshots().last_bgsave_ok());
+        assert_eq!(loaded.load(&path).unwrap(), 3);
+
+        // Save points: due once enough writes follow the last save
+        assert!(loaded.config_set("save", "900").is_err());
+        loaded.config_set("save", "0 2").unwrap();
+        assert_eq!(loaded.config_get("save").unwrap()["save"], "0 2");
+        assert_eq!(loaded.changes_since_save(), 0);
+        loaded.set("a".to_string(), "1".to_string()).unwrap();
+        assert!(!loaded.snapshots().save_due(loaded.writes()).unwrap());
+        loaded.set("b".to_string(), "2".to_string()).unwrap();
+        assert!(loaded.snapshots().save_due(loaded.writes()).unwrap());
+        loaded.save().unwrap();
+        assert!(!loaded.snapshots().save_due(loaded.writes()).unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_get_and_modify() {
+        let clock = Arc::new(ManualClock::new());
+        let cache = RustdisCache::with_clock(clock.clone());
+        assert_eq!(cache.getset("k".to_string(), "a".to_string()).unwrap(), None);
+        cache.expire("k", Duration::from_secs(10)).unwrap();
+        assert_eq!(cache.getset("k".to_string(), "b".to_string()).unwrap().as_deref(), Some("a"));
+        assert_eq!(cache.ttl("k").unwrap(), Ttl::Persistent);
+
+        assert_eq!(cache.getex("k", SetExpiry::After(Duration::from_secs(5))).unwrap().as_deref(), Some("b"));
+        assert_eq!(cache.ttl("k").unwrap(), Ttl::Expires(Duration::from_secs(5)));
+        assert_eq!(cache.getex("k", SetExpiry::Keep).unwrap().as_deref(), Some("b"));
+        assert_eq!(cache.ttl("k").unwrap(), Ttl::Expires(Duration::from_secs(5)));
+        assert_eq!(cache.getex("k", SetExpiry::Clear).unwrap().as_deref(), Some("b"));
+        assert_eq!(cache.ttl("k").unwrap(), Ttl::Persistent);
+        assert_eq!(cache.getex("missing", SetExpiry::Clear).unwrap(), None);
+
+        // Only one concurrent GETDEL sees the value
+        let taken: usize = (0..8)
+            .map(|_| {
+                let cache = cache.clone();
+                std::thread::spawn(move || cache.getdel("k").unwrap().is_some() as usize)
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .sum();
+        assert_eq!(taken, 1);
+        assert!(!cache.exists("k").unwrap());
+
+        cache.set("gone".to_string(), "v".to_string()).unwrap();
+        cache.expire("gone", Duration::from_secs(1)).unwrap();
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(cache.getdel("gone").unwrap(), None);
+        cache.hset("h".to_string(), vec![("f".to_string(), "v".to_string())]).unwrap();
+        assert!(cache.getdel("h").unwrap_err().is::<WrongType>());
+        assert!(cache.getex("h", SetExpiry::Keep).unwrap_err().is::<WrongType>());
+    }
+
+    #[test]
+    fn test_mgetex() {
+        let clock = Arc::new(ManualClock::new());
+        let cache = RustdisCache::with_clock(clock.clone());
+        cache.set("a".to_string(), "1".to_string()).unwrap();
+        cache.set("b".to_string(), "2".to_string()).unwrap();
+        cache.expire("b", Duration::from_secs(2)).unwrap();
+        cache.hset("h".to_string(), vec![("f".to_string(), "v".to_string())]).unwrap();
+        let keys = ["a", "missing", "h", "b"].map(String::from);
+
+        let values = cache.mgetex(&keys, SetExpiry::After(Duration::from_secs(10))).unwrap();
+        assert_eq!(values, [Some("1".to_string()), None, None, Some("2".to_string())]);
+        assert_eq!(cache.ttl("a").unwrap(), Ttl::Expires(Duration::from_secs(10)));
+        assert_eq!(cache.ttl("b").unwrap(), Ttl::Expires(Duration::from_secs(10)));
+        assert_eq!(cache.ttl("h").unwrap(), Ttl::Persistent);
+
+        // Refreshed TTLs outlive the original ones
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(cache.mgetex(&keys[..1], SetExpiry::Clear).unwrap(), [Some("1".to_string())]);
+        assert_eq!(cache.ttl("a").unwrap(), Ttl::Persistent);
+        assert_eq!(cache.ttl("b").unwrap(), Ttl::Expires(Duration::from_secs(5)));
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(cache.mgetex(&keys, SetExpiry::Keep).unwrap(), [Some("1".to_string()), None, None, None]);
+    }
+
+    #[test]
+    fn test_mget_mset() {
+        let cache = RustdisCache::new();
+        let pairs = |pairs: &[(&str, &str)]| pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<Vec<_>>();
+        cache.mset(pairs(&[("a", "1"), ("b", "2"), ("a", "3")])).unwrap();
+        cache.hset("h".to_string(), pairs(&[("f", "v")])).unwrap();
+        let keys: Vec<String> = ["a", "h", "missing", "b"].iter().map(|key| key.to_string()).collect();
+        assert_eq!(cache.mget(&keys).unwrap(), vec![Some("3".to_string()), None, None, Some("2".to_string())]);
+
+        // All-or-nothing when the pairs can never fit
+        cache.config_set("maxmemory", "20").unwrap();
+        assert!(cache.mset(pairs(&[("c", "123456789"), ("d", "123456789"), ("e", "123")])).is_err());
+        assert!(!cache.exists("c").unwrap());
+    }
+
+    #[test]
+    fn test_counters() {
+        let clock = Arc::new(ManualClock::new());
+        let cache = RustdisCache::with_clock(clock.clone());
+        assert_eq!(cache.incr_by("hits", 1).unwrap(), 1);
+        assert_eq!(cache.incr_by("hits", -11).unwrap(), -10);
+        assert_eq!(cache.get("hits").unwrap().as_deref(), Some("-10"));
+        assert_eq!(cache.memory_info().unwrap().used_bytes, "hits-10".len());
+
+        // Counters keep their TTL
+        cache.expire("hits", Duration::from_secs(10)).unwrap();
+        cache.incr_by("hits", 5).unwrap();
+        assert!(matches!(cache.ttl("hits").unwrap(), Ttl::Expires(_)));
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(cache.incr_by("hits", 1).unwrap(), 1);
+
+        cache.set("name".to_string(), "ana".to_string()).unwrap();
+        assert_eq!(cache.incr_by("name", 1).unwrap_err().downcast::<NumberError>().unwrap(), NumberError::NotInteger);
+        cache.set("max".to_string(), i64::MAX.to_string()).unwrap();
+        assert_eq!(cache.incr_by("max", 1).unwrap_err().downcast::<NumberError>().unwrap(), NumberError::Overflow);
+        cache.hset("h".to_string(), vec![("f".to_string(), "1".to_string())]).unwrap();
+        assert!(cache.incr_by("h", 1).unwrap_err().is::<WrongType>());
+
+        assert_eq!(cache.incr_by_float("price", 10.5).unwrap(), 10.5);
+        assert_eq!(cache.incr_by_float("price", 0.25).unwrap(), 10.75);
+        assert_eq!(cache.incr_by("price", 1).unwrap_err().downcast::<NumberError>().unwrap(), NumberError::NotInteger);
+        assert_eq!(cache.incr_by_float("name", 1.0).unwrap_err().downcast::<NumberError>().unwrap(), NumberError::NotFloat);
+
+        // Concurrent increments don't lose updates
+        let workers: Vec<_> = (0..8)
+            .map(|_| {
+                let cache = cache.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        cache.incr_by("shared", 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+        workers.into_iter().for_each(|worker| worker.join().unwrap());
+        assert_eq!(cache.get("shared").unwrap().as_deref(), Some("800"));
+    }
+
+    #[test]
+    fn test_sorted_sets() {
+        let cache = RustdisCache::new();
+        let members = |pairs: &[(&str, f64)]| pairs.iter().map(|(m, s)| (m.to_string(), *s)).collect::<Vec<_>>();
+
+        assert_eq!(cache.zadd("board".to_string(), members(&[("ana", 30.0), ("bia", 10.0)])).unwrap(), 2);
+        assert_eq!(cache.zadd("board".to_string(), members(&[("bia", 50.0), ("caio", 20.0)])).unwrap(), 1);
+        assert_eq!(cache.zrange("board", 0, -1, false).unwrap(), members(&[("caio", 20.0), ("ana", 30.0), ("bia", 50.0)]));
+        assert_eq!(cache.zrange("board", 0, 0, true).unwrap(), members(&[("bia", 50.0)]));
+        assert_eq!(cache.zscore("board", "ana").unwrap(), Some(30.0));
+        assert_eq!(cache.zrank("board", "bia").unwrap(), Some(2));
+        assert_eq!(cache.zrank("board", "nope").unwrap(), None);
+        assert_eq!(cache.zcard("board").unwrap(), 3);
+        assert_eq!(cache.memory_info().unwrap().used_bytes, "board".len() + "anabiacaio".len() + 3 * 8);
+
+        cache.set("plain".to_string(), "x".to_string()).unwrap();
+        assert!(cache.zadd("plain".to_string(), members(&[("a", 1.0)])).unwrap_err().is::<WrongType>());
+        assert!(cache.zrange("plain", 0, -1, false).unwrap_err().is::<WrongType>());
+
+        assert_eq!(cache.zrem("board", &["ana".to_string(), "x".to_string()]).unwrap(), 1);
+        assert_eq!(cache.zrem("board", &["bia".to_string(), "caio".to_string()]).unwrap(), 2);
+        assert!(!cache.exists("board").unwrap());
+        assert_eq!(cache.memory_info().unwrap().used_bytes, "plainx".len());
+    }
+
+    #[test]
+    fn test_key_metadata() {
+        let cache = RustdisCache::new();
+        let fields = |pairs: &[(&str, &str)]| pairs.iter().map(|(f, v)| (f.to_string(), v.to_string())).collect::<Vec<_>>();
+        cache.set("img:1".to_string(), "png".to_string()).unwrap();
+        cache.set("img:2".to_string(), "gif".to_string()).unwrap();
+        assert!(cache.meta_set("missing", fields(&[("owner", "ana")])).unwrap_err().is::<NoSuchKey>());
+        assert_eq!(cache.meta_set("img:1", fields(&[("owner", "ana"), ("source", "upload")])).unwrap(), 2);
+        assert_eq!(cache.meta_set("img:1", fields(&[("owner", "bia")])).unwrap(), 0);
+        cache.meta_set("img:2", fields(&[("owner", "caio")])).unwrap();
+        assert_eq!(cache.memory_info().unwrap().used_bytes, 2 * 8 + "ownerbiasourceupload".len() + "ownercaio".len());
+
+        // Metadata survives overwrites and moves with RENAME
+        cache.set("img:1".to_string(), "jpeg".to_string()).unwrap();
+        cache.rename("img:1", "img:3", true).unwrap();
+        assert_eq!(cache.meta_get("img:3").unwrap(), fields(&[("owner", "bia"), ("source", "upload")]));
+        assert_eq!(cache.meta_get("img:1").unwrap(), []);
+
+        let owned_by = |pattern: &str| {
+            let filter = ScanFilter { pattern: Some("img:*".to_string()), meta: Some(("owner".to_string(), pattern.to_string())) };
+            let (next, keys) = cache.scan(0, 100, &filter).unwrap();
+            assert_eq!(next, 0);
+            keys
+        };
+        assert_eq!(owned_by("b*"), ["img:3"]);
+        assert_eq!(owned_by("*").len(), 2);
+        assert_eq!(cache.scan(0, 1, &ScanFilter::default()).unwrap(), (1, vec!["img:2".to_string()]));
+
+        let too_many: Vec<_> = (0..MAX_KEY_META_FIELDS).map(|i| (format!("f{}", i), "v".to_string())).collect();
+        assert!(cache.meta_set("img:3", too_many).is_err());
+        assert_eq!(cache.meta_del("img:3", &["source".to_string(), "nope".to_string()]).unwrap(), 1);
+        assert_eq!(cache.meta_get("img:3").unwrap(), fields(&[("owner", "bia")]));
+
+        let restored = RustdisCache::new();
+        restored.restore(cache.snapshot().unwrap()).unwrap();
+        assert_eq!(restored.meta_get("img:2").unwrap(), fields(&[("owner", "caio")]));
+        assert_eq!(restored.memory_info().unwrap().used_bytes, cache.memory_info().unwrap().used_bytes);
+    }
+
+    #[test]
+    fn test_pinned_keys_survive_eviction() {
+        let cache = RustdisCache::new();
+        cache.config_set("pin-patterns", "flags:*").unwrap();
+        cache.set("flags:checkout".to_string(), "on".to_string()).unwrap();
+        cache.set("session".to_string(), "abc".to_string()).unwrap();
+        assert!(cache.pin("session").unwrap());
+        assert!(!cache.pin("missing").unwrap());
+
+        let info = cache.memory_info().unwrap();
+        assert_eq!((info.pinned_keys, info.pinned_bytes), (2, 16 + 10));
+
+        // Everything is pinned, so there is nothing to evict
+        cache.config_set("maxmemory", "35").unwrap();
+        assert!(cache.set("other".to_string(), "x".repeat(10)).is_err());
+        assert!(cache.exists("session").unwrap());
+
+        assert!(cache.unpin("session").unwrap());
+        cache.set("other".to_string(), "x".repeat(10)).unwrap();
+        assert!(!cache.exists("session").unwrap());
+        assert!(cache.exists("flags:checkout").unwrap());
+    }
+
+    #[test]
+    fn test_overwrite_fails_when_only_the_written_key_is_evictable() {
+        let cache = RustdisCache::new();
+        cache.config_set("maxmemory", "20").unwrap();
+        cache.set("q".to_string(), "x".repeat(10)).unwrap();
+        assert!(cache.pin("q").unwrap());
+        cache.set("p".to_string(), "x".to_string()).unwrap();
+
+        // The key being overwritten is the only unpinned one, and can't make
+        // room for itself
+        let error = cache.set("p".to_string(), "x".repeat(12)).unwrap_err();
+        assert!(error.to_string().starts_with("OOM"));
+        assert_eq!(cache.get("p").unwrap().as_deref(), Some("x"));
+        assert!(cache.exists("q").unwrap());
+    }
+
+    #[test]
+    fn test_recently_written_keys_survive_eviction() {
+        let clock = Arc::new(ManualClock::new());
+        let cache = RustdisCache::with_clock(clock.clone());
+        cache.config_set("maxmemory-protect-recent", "30").unwrap();
+        cache.set("old".to_string(), "0123456789".to_string()).unwrap();
+        clock.advance(Duration::from_secs(60));
+        cache.set("fresh".to_string(), "0123456789".to_string()).unwrap();
+        clock.advance(Duration::from_secs(1));
+        // LRU alone would pick "fresh", which hasn't been read since its write
+        cache.get("old").unwrap();
+
+        cache.config_set("maxmemory", "30").unwrap();
+        cache.set("new".to_string(), "x".to_string()).unwrap();
+        assert!(!cache.exists("old").unwrap());
+        assert!(cache.exists("fresh").unwrap());
+
+        // With only recent keys left, they are evicted rather than failing
+        cache.set("newer".to_string(), "0123456789".to_string()).unwrap();
+        assert_eq!(cache.size().unwrap(), 2);
+        assert_eq!(cache.config_get("maxmemory-protect-recent").unwrap()["maxmemory-protect-recent"], "30");
+        assert!(cache.config_set("maxmemory-protect-recent", "soon").is_err());
+    }
+
+    #[test]
+    fn test_ttl_jitter_spreads_matching_keys() {
+        let clock = Arc::new(ManualClock::new());
+        let cache = RustdisCache::with_clock(clock.clone());
+        cache.config_set("ttl-jitter", "warm:* 10").unwrap();
+        let ex = SetOptions { expiry: SetExpiry::After(Duration::from_secs(100)), ..SetOptions::default() };
+        for i in 0..50 {
+            cache.set_with(format!("warm:{}", i), "v".to_string(), ex).unwrap();
+        }
+        cache.set_with("cold".to_string(), "v".to_string(), ex).unwrap();
+        cache.set("later".to_string(), "v".to_string()).unwrap();
+        cache.expire("later", Duration::from_secs(100)).unwrap();
+        cache.set("warm:late".to_string(), "v".to_string()).unwrap();
+        cache.expire("warm:late", Duration::from_secs(100)).unwrap();
+
+        let ttls: HashSet<Duration> = (0..50)
+            .map(|i| match cache.ttl(&format!("warm:{}", i)).unwrap() {
+                Ttl::Expires(ttl) => ttl,
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        assert!(ttls.iter().all(|ttl| (Duration::from_secs(90)..=Duration::from_secs(110)).contains(ttl)));
+        assert!(ttls.len() > 1);
+        assert_eq!(cache.ttl("cold").unwrap(), Ttl::Expires(Duration::from_secs(100)));
+        assert_eq!(cache.ttl("later").unwrap(), Ttl::Expires(Duration::from_secs(100)));
+        match cache.ttl("warm:late").unwrap() {
+            Ttl::Expires(ttl) => assert!((Duration::from_secs(90)..=Duration::from_secs(110)).contains(&ttl)),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // Every jittered key is gone once the widest spread has passed
+        clock.advance(Duration::from_secs(111));
+        assert!((0..50).all(|i| !cache.exists(&format!("warm:{}", i)).unwrap()));
+        assert!(!cache.exists("warm:late").unwrap());
+
+        assert_eq!(cache.config_get("ttl-jitter").unwrap()["ttl-jitter"], "warm:* 10");
+        assert!(cache.config_set("ttl-jitter", "warm:*").is_err());
+        assert!(cache.config_set("ttl-jitter", "warm:* 100").is_err());
+        cache.config_set("ttl-jitter", "").unwrap();
+        cache.set_with("warm:0".to_string(), "v".to_string(), ex).unwrap();
+        assert_eq!(cache.ttl("warm:0").unwrap(), Ttl::Expires(Duration::from_secs(100)));
+    }
+
+    #[test]
+    fn test_lfu_eviction() {
+        let clock = Arc::new(ManualClock::new());
+        let cache = RustdisCache::with_clock(clock.clone());
+        cache.config_set("maxmemory-policy", "allkeys-lfu").unwrap();
+        cache.set("hot".to_string(), "x".to_string()).unwrap();
+        for _ in 0..100 {
+            cache.get("hot").unwrap();
+        }
+        clock.advance(Duration::from_secs(1));
+        cache.set("cold".to_string(), "x".to_string()).unwrap();
+
+        // LRU would pick "hot", last read before "cold" was written
+        cache.config_set("maxmemory", "9").unwrap();
+        cache.set("new".to_string(), "x".to_string()).unwrap();
+        assert!(cache.exists("hot").unwrap());
+        assert!(!cache.exists("cold").unwrap());
+
+        // volatile-lfu only evicts keys with a TTL, however often they're read
+        cache.config_set("maxmemory-policy", "volatile-lfu").unwrap();
+        cache.expire("hot", Duration::from_secs(60)).unwrap();
+        cache.set("tmp".to_string(), "x".to_string()).unwrap();
+        assert!(!cache.exists("hot").unwrap());
+        assert!(cache.exists("new").unwrap());
+        assert!(cache.set("more".to_string(), "x".to_string()).is_err());
+
+        assert_eq!(cache.config_get("maxmemory-policy").unwrap()["maxmemory-policy"], "volatile-lfu");
+        assert!(cache.config_set("maxmemory-policy", "lifo").is_err());
+    }
+
+    #[test]
+    fn test_ttl_random_and_noeviction_policies() {
+        let cache = RustdisCache::new();
+        cache.config_set("maxmemory-policy", "volatile-ttl").unwrap();
+        for (key, ttl) in [("a", 300), ("b", 60), ("c", 600)] {
+            cache.set(key.to_string(), "x".to_string()).unwrap();
+            cache.expire(key, Duration::from_secs(ttl)).unwrap();
+        }
+        cache.set("d".to_string(), "x".to_string()).unwrap();
+        cache.config_set("maxmemory", "6").unwrap();
+        cache.set("e".to_string(), "x".to_string()).unwrap();
+        // Nearest deadline first, then the next; "d" has no TTL
+        assert_eq!(cache.size().unwrap(), 3);
+        assert!(!cache.exists("b").unwrap() && !cache.exists("a").unwrap());
+
+        cache.config_set("maxmemory-policy", "allkeys-random").unwrap();
+        cache.set("f".to_string(), "x".to_string()).unwrap();
+        assert_eq!((cache.size().unwrap(), cache.memory_info().unwrap().used_bytes), (3, 6));
+
+        // noeviction refuses writes that don't fit, and lowering maxmemory
+        // evicts nothing
+        cache.config_set("maxmemory-policy", "noeviction").unwrap();
+        assert!(cache.set("g".to_string(), "x".to_string()).is_err());
+        cache.config_set("maxmemory", "2").unwrap();
+        assert_eq!(cache.size().unwrap(), 3);
+        assert_eq!(cache.config_get("maxmemory-policy").unwrap()["maxmemory-policy"], "noeviction");
+    }
+
+    #[test]
+    fn test_memory_stats() {
+        let cache = RustdisCache::new();
+        cache.set("a".to_string(), "1234".to_string()).unwrap();
+        cache.hset("h".to_string(), vec![("f".to_string(), "v".to_string())]).unwrap();
+        let before = cache.memory_stats().unwrap();
+        assert_eq!((before.keys, before.volatile_keys, before.dataset_bytes), (2, 0, 5 + 3));
+        assert_eq!(before.overhead_bytes, 2 * KEY_OVERHEAD);
+
+        cache.expire("a", Duration::from_secs(60)).unwrap();
+        let stats = cache.memory_stats().unwrap();
+        assert_eq!(stats.volatile_keys, 1);
+        assert_eq!(stats.overhead_bytes, before.overhead_bytes + VOLATILE_KEY_OVERHEAD + 1);
+        assert_eq!(stats.total_bytes(), stats.dataset_bytes + stats.overhead_bytes);
+
+        // The peak outlives what made it
+        cache.hset("h".to_string(), vec![("g".to_string(), "0123456789".to_string())]).unwrap();
+        cache.del("h").unwrap();
+        let stats = cache.memory_stats().unwrap();
+        assert_eq!((stats.dataset_bytes, stats.peak_bytes), (5, 5 + 14));
+    }
+
+    #[test]
+    fn test_read_only() {
+        let clock = Arc::new(ManualClock::new());
+        let cache = RustdisCache::with_clock(clock.clone());
+        cache.set("a".to_string(), "1".to_string()).unwrap();
+        cache.set("b".to_string(), "2".to_string()).unwrap();
+        cache.expire("b", Duration::from_secs(1)).unwrap();
+        cache.set_read_only(true);
+
+        assert_eq!(cache.get("a").unwrap().as_deref(), Some("1"));
+        assert!(cache.set("a".to_string(), "2".to_string()).unwrap_err().is::<ReadOnly>());
+        assert!(cache.del("a").unwrap_err().is::<ReadOnly>());
+        assert!(cache.incr_by("a", 1).unwrap_err().is::<ReadOnly>());
+        assert!(cache.seen_add("webhooks", "evt_1", Duration::from_secs(60)).unwrap_err().is::<ReadOnly>());
+
+        // Expired keys read as missing but stay, and nothing is evicted
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(cache.get("b").unwrap(), None);
+        assert_eq!(cache.active_expire_cycle(20).unwrap(), 0);
+        cache.config_set("maxmemory", "1").unwrap();
+        assert_eq!(cache.size().unwrap(), 2);
+
+        cache.set_read_only(false);
+        cache.config_set("maxmemory", "0").unwrap();
+        cache.set("a".to_string(), "2".to_string()).unwrap();
+    }
+
+    #[test]
+    fn test_sample_keys() {
+        let cache = RustdisCache::new();
+        for i in 0..1000 {
+            let key = if i % 4 == 0 { format!("order:{}", i) } else { format!("user:{}", i) };
+            cache.set(key, "x".to_string()).unwrap();
+        }
+        cache.expire("order:0", Duration::from_secs(60)).unwrap();
+
+        let sample = cache.sample_keys(100, None).unwrap();
+        let distinct: HashSet<&str> = sample.keys.iter().map(|meta| meta.key.as_str()).collect();
+        assert_eq!((sample.keyspace, sample.examined, distinct.len()), (1000, 100, 100));
+
+        // A quarter of the keys are orders; the estimate lands near 250
+        let sample = cache.sample_keys(100, Some("order:*")).unwrap();
+        assert_eq!(sample.keys.len(), 100);
+        assert!(sample.keys.iter().all(|meta| meta.key.starts_with("order:") && meta.value_type == "string"));
+        assert!((150..=350).contains(&sample.estimated_matches()), "{}", sample.estimated_matches());
+
+        // Rare patterns come back short; small keyspaces are walked in full
+        assert!(cache.sample_keys(5, Some("nope:*")).unwrap().keys.is_empty());
+        let all = cache.sample_keys(5000, Some("order:*")).unwrap();
+        assert_eq!((all.examined, all.keys.len(), all.estimated_matches()), (1000, 250, 250));
+        assert!(all.keys.iter().any(|meta| meta.ttl.is_some()));
+
+        // Each draw picks its own shard, so even a single batch spans them
+        let sharded = RustdisCache::new().sharded(8);
+        for i in 0..1000 {
+            sharded.set(format!("user:{}", i), "x".to_string()).unwrap();
+        }
+        let sample = sharded.sample_keys(50, None).unwrap();
+        let shards: HashSet<usize> = sample.keys.iter().map(|meta| shard_of(&meta.key, 8)).collect();
+        assert!(shards.len() > 4, "{:?}", shards);
+    }
+
+    #[test]
+    fn test_sharded() {
+        let cache = RustdisCache::new().sharded(4);
+        assert_eq!((cache.shard_count(), cache.debug_state().unwrap().shards), (4, 4));
+        let threads: Vec<_> = (0..8)
+            .map(|thread| {
+                let cache = cache.clone();
+                thread::spawn(move || {
+                    for i in 0..50 {
+                        cache.incr_by(&format!("key:{}:{}", thread, i), 1).unwrap();
+                        cache.incr_by("shared", 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+        threads.into_iter().for_each(|thread| thread.join().unwrap());
+        assert_eq!(cache.get("shared").unwrap().as_deref(), Some("400"));
+        assert_eq!((cache.size().unwrap(), cache.keys().unwrap().len()), (401, 401));
+        let used: HashSet<usize> = cache.keys().unwrap().iter().map(|key| shard_of(key, 4)).collect();
+        assert_eq!(used.len(), 4);
+
+        // SCAN walks every shard in turn, visiting each key once
+        let (mut cursor, mut scanned) = (0, Vec::new());
+        loop {
+            let (next, keys) = cache.scan(cursor, 64, &ScanFilter::default()).unwrap();
+            scanned.extend(keys);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        scanned.sort();
+        let mut keys = cache.keys().unwrap();
+        keys.sort();
+        assert_eq!(scanned, keys);
+
+        // Multi-key commands span shards
+        cache.mset(vec![("a".to_string(), "1".to_string()), ("b".to_string(), "2".to_string()), ("c".to_string(), "3".to_string())]).unwrap();
+        assert!(cache.rename("a", "moved", true).unwrap());
+        let found = cache.mget(&["moved".to_string(), "a".to_string(), "c".to_string()]).unwrap();
+        assert_eq!(found, vec![Some("1".to_string()), None, Some("3".to_string())]);
+
+        // Namespace caps hold across shards
+        let evicted = cache.set_namespace_limit("key:0:*", 10).unwrap();
+        assert_eq!(evicted.len(), 40);
+        cache.set("key:0:new".to_string(), "x".to_string()).unwrap();
+        assert_eq!(cache.keys().unwrap().iter().filter(|key| key.starts_with("key:0:")).count(), 10);
+        cache.set_namespace_limit("key:0:*", 0).unwrap();
+
+        // maxmemory is split between the shards but reads back whole
+        cache.config_set("maxmemory", "1mb").unwrap();
+        assert_eq!(cache.config_get("maxmemory").unwrap()["maxmemory"], (1024 * 1024).to_string());
+        assert_eq!(cache.memory_info().unwrap().maxmemory, 1024 * 1024);
+
+        let entries = cache.snapshot().unwrap();
+        cache.flush().unwrap();
+        assert_eq!(cache.size().unwrap(), 0);
+        assert_eq!(cache.restore(entries).unwrap(), cache.size().unwrap());
+        assert_eq!(cache.get("moved").unwrap().as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn test_seen_add() {
+        let cache = RustdisCache::new();
+        let ttl = Duration::from_secs(300);
+
+        assert!(cache.seen_add("webhooks", "evt_1", ttl).unwrap());
+        assert!(!cache.seen_add("webhooks", "evt_1", ttl).unwrap());
+        assert!(cache.seen_add("other", "evt_1", ttl).unwrap());
+
+        // Filters aren't keys, but DEL still resets them
+        assert!(!cache.exists("webhooks").unwrap());
+        assert!(!cache.del("webhooks").unwrap());
+        assert!(cache.seen_add("webhooks", "evt_1", ttl).unwrap());
+
+        // Idle filters are reclaimed by active expiry
+        let clock = Arc::new(ManualClock::new());
+        let cache = RustdisCache::with_clock(clock.clone());
+        cache.seen_add("webhooks", "evt_1", ttl).unwrap();
+        clock.advance(ttl * 2);
+        cache.active_expire_cycle(20).unwrap();
+        assert_eq!(cache.debug_state().unwrap().seen_filters, 0);
+    }
 }
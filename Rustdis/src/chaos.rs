@@ -0,0 +1,126 @@
+use anyhow::Result;
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+use std::time::Duration;
+
+/// Fault rates for the chaos layer; every rate is a probability in `0.0..=1.0`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FaultConfig {
+    /// Extra latency added to delayed operations
+    pub delay: Duration,
+    pub delay_rate: f64,
+    /// Operations answered with an `INJECTED_FAULT` error instead of running
+    pub fail_rate: f64,
+    /// Replies the server silently never sends
+    pub drop_rate: f64,
+}
+
+/// Fault injection for testing how applications cope with a misbehaving cache
+///
+/// Only compiled with the `chaos` feature and inert until configured, either
+/// through `configure` or `CONFIG SET chaos-*`. There is no replication or
+/// AOF in this build, so only operation delays, failures and dropped
+/// replies can be injected. Failures are injected where commands execute;
+/// delays and dropped replies by the RESP, framed and HTTP transports, which
+/// own the connection.
+#[derive(Debug, Default)]
+pub struct FaultInjector {
+    config: RwLock<FaultConfig>,
+}
+
+impl FaultInjector {
+    pub fn configure(&self, config: FaultConfig) -> Result<()> {
+        *self.config.write().map_err(|_| anyhow::anyhow!("Failed to acquire chaos lock"))? = config;
+        Ok(())
+    }
+
+    pub fn config(&self) -> Result<FaultConfig> {
+        Ok(self.config.read().map_err(|_| anyhow::anyhow!("Failed to acquire chaos lock"))?.clone())
+    }
+
+    /// Latency to add to the current operation, if it drew a delay
+    pub fn delay(&self) -> Option<Duration> {
+        let (delay, rate) = self.config.read().map(|config| (config.delay, config.delay_rate)).ok()?;
+        roll(rate).then_some(delay)
+    }
+
+    /// Waits out the current operation's delay, if it drew one, without
+    /// blocking the runtime thread
+    pub async fn wait(&self) {
+        if let Some(delay) = self.delay() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Whether the current operation should fail
+    pub fn fail(&self) -> bool {
+        self.config.read().is_ok_and(|config| roll(config.fail_rate))
+    }
+
+    /// Whether the reply to the current operation should be dropped
+    pub fn drop_reply(&self) -> bool {
+        self.config.read().is_ok_and(|config| roll(config.drop_rate))
+    }
+
+    /// Current settings as CONFIG GET parameters
+    pub(crate) fn parameters(&self) -> Result<BTreeMap<String, String>> {
+        let config = self.config()?;
+        let mut parameters = BTreeMap::new();
+        parameters.insert("chaos-delay-ms".to_string(), config.delay.as_millis().to_string());
+        parameters.insert("chaos-delay-rate".to_string(), config.delay_rate.to_string());
+        parameters.insert("chaos-fail-rate".to_string(), config.fail_rate.to_string());
+        parameters.insert("chaos-drop-rate".to_string(), config.drop_rate.to_string());
+        Ok(parameters)
+    }
+
+    /// Applies a `chaos-*` CONFIG SET parameter, returning false for other names
+    pub(crate) fn set_parameter(&self, parameter: &str, value: &str) -> Result<bool> {
+        let mut config = self.config()?;
+        match parameter {
+            "chaos-delay-ms" => {
+                let millis = value.parse().map_err(|_| anyhow::anyhow!("chaos-delay-ms must be a number of milliseconds"))?;
+                config.delay = Duration::from_millis(millis);
+            }
+            "chaos-delay-rate" => config.delay_rate = parse_rate(parameter, value)?,
+            "chaos-fail-rate" => config.fail_rate = parse_rate(parameter, value)?,
+            "chaos-drop-rate" => config.drop_rate = parse_rate(parameter, value)?,
+            _ => return Ok(false),
+        }
+        self.configure(config)?;
+        Ok(true)
+    }
+}
+
+fn parse_rate(parameter: &str, value: &str) -> Result<f64> {
+    value
+        .parse()
+        .ok()
+        .filter(|rate: &f64| (0.0..=1.0).contains(rate))
+        .ok_or_else(|| anyhow::anyhow!("{} must be a probability between 0 and 1", parameter))
+}
+
+fn roll(rate: f64) -> bool {
+    rate > 0.0 && rand::thread_rng().gen_bool(rate.min(1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fault_rates() {
+        let faults = FaultInjector::default();
+        assert!(!faults.fail() && !faults.drop_reply() && faults.delay().is_none());
+
+        faults.set_parameter("chaos-fail-rate", "1").unwrap();
+        faults.set_parameter("chaos-delay-ms", "5").unwrap();
+        faults.set_parameter("chaos-delay-rate", "1.0").unwrap();
+        assert!(faults.fail());
+        assert_eq!(faults.delay(), Some(Duration::from_millis(5)));
+        assert!(!faults.drop_reply());
+
+        assert!(faults.set_parameter("chaos-drop-rate", "1.5").is_err());
+        assert!(!faults.set_parameter("maxmemory", "1mb").unwrap());
+    }
+}
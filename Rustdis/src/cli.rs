@@ -1,18 +1,88 @@
-use crate::cache::RustdisCache;
-use crate::protocol::{RustdisProtocol, Command, Response};
+use crate::cache::{RustdisCache, Ttl};
+use crate::protocol::{Command, CommandSpec, RustdisProtocol, Response, COMMAND_SPECS};
+use crate::pubsub::Subscriber;
+use crate::transaction::Transaction;
 use anyhow::Result;
-use std::io::{self, Write, BufRead, BufReader};
-use std::sync::Arc;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use smallvec::SmallVec;
+use std::borrow::Cow;
+use std::process::ExitCode;
+
+/// Values longer than this many characters are truncated unless disabled
+pub const DEFAULT_TRUNCATE_AT: usize = 1024;
+
+/// How the CLI renders values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayOptions {
+    /// Character limit for displayed values; `None` prints them in full
+    pub truncate_at: Option<usize>,
+    /// Hex-dump values that contain non-printable characters
+    pub hex: bool,
+    /// Show the value size and TTL after GET results
+    pub verbose: bool,
+}
+
+impl Default for DisplayOptions {
+    fn default() -> Self {
+        Self {
+            truncate_at: Some(DEFAULT_TRUNCATE_AT),
+            hex: false,
+            verbose: false,
+        }
+    }
+}
+
+/// Exit status of one-shot subcommands, so shell scripts can branch on results
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// The command succeeded and produced a value
+    Found = 0,
+    /// The key was missing, or the answer was false (EXISTS, DEL)
+    Nil = 1,
+    /// The command failed
+    Error = 2,
+    /// The server could not be reached; reserved for commands that talk to
+    /// a remote server
+    ConnectionFailure = 3,
+}
+
+impl ExitStatus {
+    pub fn of(response: &Response) -> Self {
+        match response {
+            Response::Error { .. } => Self::Error,
+            Response::StringOption(None) | Response::Boolean(false) => Self::Nil,
+            _ => Self::Found,
+        }
+    }
+}
+
+impl From<ExitStatus> for ExitCode {
+    fn from(status: ExitStatus) -> Self {
+        ExitCode::from(status as u8)
+    }
+}
 
 /// Simple CLI interface for Rustdis
 pub struct RustdisCli {
     protocol: RustdisProtocol,
+    display: DisplayOptions,
 }
 
 impl RustdisCli {
     pub fn new(cache: RustdisCache) -> Self {
+        Self::with_display(cache, DisplayOptions::default())
+    }
+
+    pub fn with_display(cache: RustdisCache, display: DisplayOptions) -> Self {
         Self {
             protocol: RustdisProtocol::new(cache),
+            display,
         }
     }
 
@@ -22,23 +92,23 @@ impl RustdisCli {
         println!("Type 'help' for available commands or 'quit' to exit.");
         println!();
 
-        let stdin = io::stdin();
-        let mut reader = BufReader::new(stdin.lock());
-        let mut line = String::new();
+        let mut editor = Editor::<CommandHints, DefaultHistory>::new()?;
+        editor.set_helper(Some(CommandHints));
+        let mut subscriber = self.protocol.cache().pubsub().subscriber();
+        let mut transaction = Transaction::default();
+        // Commands queued since MULTI, as typed
+        let mut queued: Vec<String> = Vec::new();
 
         loop {
-            print!("rustdis> ");
-            io::stdout().flush()?;
-
-            line.clear();
-            match reader.read_line(&mut line) {
-                Ok(0) => break, // EOF
-                Ok(_) => {
+            let prompt = if transaction.is_open() { "rustdis(TX)> " } else { "rustdis> " };
+            match editor.readline(prompt) {
+                Ok(line) => {
                     let input = line.trim();
-                    
+
                     if input.is_empty() {
                         continue;
                     }
+                    let _ = editor.add_history_entry(input);
 
                     if input == "quit" || input == "exit" {
                         println!("Goodbye! 👋");
@@ -50,20 +120,69 @@ impl RustdisCli {
                         continue;
                     }
 
+                    if input.eq_ignore_ascii_case("queue") {
+                        Self::queue_lines(&transaction, &queued).iter().for_each(|line| println!("{}", line));
+                        continue;
+                    }
+
                     // Try to parse as JSON first, then as simple commands
-                    let response = if input.starts_with('{') {
+                    if input.starts_with('{') {
                         // JSON command
                         match RustdisProtocol::parse_command(input) {
-                            Ok(cmd) => self.protocol.execute(cmd),
-                            Err(e) => Response::Error { error: format!("Invalid JSON: {}", e) },
+                            Ok(cmd) if transaction.handles(cmd.name()) => {
+                                let response = transaction.execute(&self.protocol, cmd);
+                                self.print_transaction_reply(input, &response, &transaction, &mut queued);
+                            }
+                            Ok(cmd) => match subscriber.execute(&cmd) {
+                                Some(replies) => {
+                                    replies.iter().for_each(|reply| self.print_response(reply));
+                                    self.stream_messages(&mut subscriber)?;
+                                }
+                                None => {
+                                    let key = match &cmd {
+                                        Command::Get { key, .. } => Some(key.clone()),
+                                        _ => None,
+                                    };
+                                    let response = self.protocol.execute(cmd);
+                                    self.print_response(&response);
+                                    if let Some(details) = key.and_then(|key| self.get_details(&key, &response)) {
+                                        println!("{}", details);
+                                    }
+                                }
+                            },
+                            Err(e) => self.print_response(&e.into()),
                         }
                     } else {
                         // Simple command parsing
-                        self.parse_simple_command(input)
-                    };
-
-                    self.print_response(&response);
+                        let parts: SmallVec<[&str; 8]> = input.split_whitespace().collect();
+                        let response = if transaction.handles(parts[0]) {
+                            let response = transaction.execute_args(&self.protocol, &parts);
+                            self.print_transaction_reply(input, &response, &transaction, &mut queued);
+                            if matches!(response, Response::Error { .. }) {
+                                Self::point_at_argument(input, &parts);
+                            }
+                            continue;
+                        } else if let Some(replies) = subscriber.execute_args(&parts) {
+                            replies.iter().for_each(|reply| self.print_response(reply));
+                            self.stream_messages(&mut subscriber)?;
+                            continue;
+                        } else {
+                            self.protocol.execute_args(&parts)
+                        };
+                        self.print_response(&response);
+                        if matches!(response, Response::Error { .. }) {
+                            Self::point_at_argument(input, &parts);
+                        }
+                        let key = match parts[..] {
+                            [name, key] if name.eq_ignore_ascii_case("GET") => Some(key),
+                            _ => None,
+                        };
+                        if let Some(details) = key.and_then(|key| self.get_details(key, &response)) {
+                            println!("{}", details);
+                        }
+                    }
                 }
+                Err(ReadlineError::Eof | ReadlineError::Interrupted) => break,
                 Err(e) => {
                     eprintln!("Error reading input: {}", e);
                     break;
@@ -74,91 +193,437 @@ impl RustdisCli {
         Ok(())
     }
 
-    /// Parse simple text commands (non-JSON)
-    fn parse_simple_command(&self, input: &str) -> Response {
-        let parts: Vec<&str> = input.split_whitespace().collect();
-        
-        if parts.is_empty() {
-            return Response::Error { error: "Empty command".to_string() };
+    /// After (P)SUBSCRIBE, prints messages as they arrive until Ctrl-C, then
+    /// leaves every channel and pattern and goes back to the prompt, like
+    /// redis-cli's subscribe mode
+    fn stream_messages(&self, subscriber: &mut Subscriber) -> Result<()> {
+        if !subscriber.is_subscribed() {
+            return Ok(());
         }
-
-        let command = match parts[0].to_uppercase().as_str() {
-            "GET" => {
-                if parts.len() != 2 {
-                    return Response::Error { error: "GET requires exactly one argument: GET <key>".to_string() };
-                }
-                Command::Get { key: parts[1].to_string() }
-            }
-            "SET" => {
-                if parts.len() != 3 {
-                    return Response::Error { error: "SET requires exactly two arguments: SET <key> <value>".to_string() };
-                }
-                Command::Set { 
-                    key: parts[1].to_string(), 
-                    value: parts[2].to_string() 
+        println!("Reading messages... (press Ctrl-C to quit)");
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        runtime.block_on(async {
+            let interrupted = tokio::signal::ctrl_c();
+            tokio::pin!(interrupted);
+            loop {
+                tokio::select! {
+                    Some(message) = subscriber.recv() => self.print_response(&message.into()),
+                    _ = &mut interrupted => break,
                 }
             }
-            "DEL" | "DELETE" => {
-                if parts.len() != 2 {
-                    return Response::Error { error: "DEL requires exactly one argument: DEL <key>".to_string() };
-                }
-                Command::Del { key: parts[1].to_string() }
-            }
-            "EXISTS" => {
-                if parts.len() != 2 {
-                    return Response::Error { error: "EXISTS requires exactly one argument: EXISTS <key>".to_string() };
-                }
-                Command::Exists { key: parts[1].to_string() }
-            }
-            "KEYS" => Command::Keys,
-            "FLUSH" | "FLUSHALL" => Command::Flush,
-            "SIZE" | "DBSIZE" => Command::Size,
-            "PING" => Command::Ping,
-            _ => {
-                return Response::Error { error: format!("Unknown command: {}", parts[0]) };
+        });
+        subscriber.execute_args(&["UNSUBSCRIBE"]);
+        subscriber.execute_args(&["PUNSUBSCRIBE"]);
+        // Drop what arrived between Ctrl-C and leaving
+        while subscriber.try_recv().is_some() {}
+        println!();
+        Ok(())
+    }
+
+    /// Runs one command for a one-shot subcommand
+    ///
+    /// Prints the JSON response, or with `quiet` just the bare value (nothing
+    /// for nil, booleans and OK) and errors as `CODE message` on stderr.
+    pub fn run_once(&self, command: Command, quiet: bool) -> Result<ExitStatus> {
+        let response = self.protocol.execute(command);
+        let status = ExitStatus::of(&response);
+        if !quiet {
+            println!("{}", RustdisProtocol::response_to_json(&response)?);
+        } else if let Response::Error { error, code } = &response {
+            eprintln!("{} {}", code.as_deref().unwrap_or("ERR"), error);
+        } else if let Some(raw) = Self::format_raw(&response) {
+            println!("{}", raw);
+        }
+        Ok(status)
+    }
+
+    /// Prints the reply to a command `transaction` took, remembering queued
+    /// commands in `queued` so EXEC's replies are shown next to the command
+    /// each one answers
+    fn print_transaction_reply(&self, input: &str, response: &Response, transaction: &Transaction, queued: &mut Vec<String>) {
+        match response {
+            Response::String(reply) if reply == "QUEUED" => queued.push(input.to_string()),
+            Response::Array(replies) if !transaction.is_open() && !queued.is_empty() && replies.len() == queued.len() => {
+                Self::exec_lines(queued, replies).iter().for_each(|line| println!("{}", line));
+                queued.clear();
+                return;
             }
-        };
+            _ => {}
+        }
+        if !transaction.is_open() {
+            queued.clear();
+        }
+        self.print_response(response);
+    }
 
-        self.protocol.execute(command)
+    /// One line per queued command with the reply EXEC gave it
+    fn exec_lines(queued: &[String], replies: &[Response]) -> Vec<String> {
+        queued
+            .iter()
+            .zip(replies)
+            .enumerate()
+            .map(|(i, (command, reply))| format!("{}) {} -> {}", i + 1, command, Self::format_inline(reply)))
+            .collect()
+    }
+
+    /// The `queue` listing: commands waiting for EXEC, in order
+    fn queue_lines(transaction: &Transaction, queued: &[String]) -> Vec<String> {
+        if !transaction.is_open() {
+            return vec!["(not in a transaction)".to_string()];
+        }
+        if queued.is_empty() {
+            return vec!["(empty queue)".to_string()];
+        }
+        queued.iter().enumerate().map(|(i, command)| format!("{}) {}", i + 1, command)).collect()
+    }
+
+    /// Underlines the argument a parse error blames, if any
+    fn point_at_argument(input: &str, parts: &[&str]) {
+        let Some(part) = RustdisProtocol::parse_args(parts).err().and_then(|e| parts.get(e.arg?).copied()) else {
+            return;
+        };
+        // `part` borrows from `input`, so its offset is a pointer difference
+        let offset = part.as_ptr() as usize - input.as_ptr() as usize;
+        println!("  {}", input);
+        println!("  {}{}", " ".repeat(input[..offset].chars().count()), "^".repeat(part.chars().count()));
     }
 
     /// Print response in a user-friendly format
     fn print_response(&self, response: &Response) {
         match response {
             Response::String(s) => println!("{}", s),
-            Response::StringOption(Some(s)) => println!("\"{}\"", s),
+            Response::StringOption(Some(s)) => println!("{}", self.format_value(s)),
             Response::StringOption(None) => println!("(nil)"),
             Response::Boolean(b) => println!("{}", if *b { 1 } else { 0 }),
             Response::Number(n) => println!("{}", n),
+            Response::Integer(n) => println!("{}", n),
+            Response::Float(f) => println!("{}", f),
             Response::StringArray(arr) => {
                 for (i, key) in arr.iter().enumerate() {
-                    println!("{}) \"{}\"", i + 1, key);
+                    println!("{}) \"{}\"", i + 1, escape(key));
                 }
                 if arr.is_empty() {
                     println!("(empty array)");
                 }
             }
+            Response::Array(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    println!("{}) {}", i + 1, Self::format_inline(item));
+                }
+                if items.is_empty() {
+                    println!("(empty array)");
+                }
+            }
             Response::Ok => println!("OK"),
-            Response::Error { error } => println!("Error: {}", error),
+            Response::Error { error, code: Some(code) } => println!("Error [{}]: {}", code, error),
+            Response::Error { error, code: None } => println!("Error: {}", error),
+            Response::Map(map) => {
+                for (key, value) in map {
+                    println!("{}: {}", key, Self::format_inline(value));
+                }
+            }
+            Response::Bytes(bytes) => println!("{}", hex_dump(bytes)),
+        }
+    }
+
+    /// With `--verbose`, the size and remaining TTL (PTTL) of a GET result
+    fn get_details(&self, key: &str, response: &Response) -> Option<String> {
+        let Response::StringOption(Some(value)) = response else {
+            return None;
+        };
+        if !self.display.verbose {
+            return None;
+        }
+        let ttl = match self.protocol.cache().ttl(key).ok()? {
+            Ttl::Expires(ttl) => format!("expires in {} ms", ttl.as_millis()),
+            Ttl::Persistent | Ttl::Missing => "no expiry".to_string(),
+        };
+        Some(format!("({} bytes, {})", value.len(), ttl))
+    }
+
+    /// Quotes and escapes a value, hex-dumping or truncating it as configured
+    fn format_value(&self, value: &str) -> String {
+        if self.display.hex && value.chars().any(|c| c.is_control()) {
+            return hex_dump(value.as_bytes());
+        }
+        match self.display.truncate_at {
+            Some(limit) if value.chars().count() > limit => {
+                let cut = value.char_indices().nth(limit).map_or(value.len(), |(index, _)| index);
+                format!("\"{}\"... ({} more bytes, use --no-truncate to see all)", escape(&value[..cut]), value.len() - cut)
+            }
+            _ => format!("\"{}\"", escape(value)),
+        }
+    }
+
+    /// Format a nested response on a single line
+    fn format_inline(response: &Response) -> String {
+        match response {
+            Response::String(s) => s.clone(),
+            Response::StringOption(Some(s)) => format!("\"{}\"", s),
+            Response::StringOption(None) => "(nil)".to_string(),
+            Response::Boolean(b) => if *b { "1" } else { "0" }.to_string(),
+            Response::Number(n) => n.to_string(),
+            Response::Integer(n) => n.to_string(),
+            Response::Float(f) => f.to_string(),
+            Response::StringArray(arr) => arr.join(", "),
+            Response::Array(items) => items.iter().map(Self::format_inline).collect::<Vec<_>>().join(", "),
+            Response::Ok => "OK".to_string(),
+            Response::Error { error, .. } => format!("Error: {}", error),
+            Response::Map(map) => map
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, Self::format_inline(value)))
+                .collect::<Vec<_>>()
+                .join(" "),
+            Response::Bytes(bytes) => format!("({} bytes)", bytes.len()),
+        }
+    }
+
+    /// Undecorated output for `--quiet`: values as is, one array item per line
+    ///
+    /// Responses whose meaning is carried by the exit status print nothing.
+    fn format_raw(response: &Response) -> Option<String> {
+        match response {
+            Response::String(s) | Response::StringOption(Some(s)) => Some(s.clone()),
+            Response::StringOption(None) | Response::Boolean(_) | Response::Ok | Response::Error { .. } => None,
+            Response::Number(n) => Some(n.to_string()),
+            Response::Integer(n) => Some(n.to_string()),
+            Response::Float(f) => Some(f.to_string()),
+            Response::StringArray(items) if items.is_empty() => None,
+            Response::StringArray(items) => Some(items.join("\n")),
+            Response::Array(items) if items.is_empty() => None,
+            Response::Array(items) => Some(
+                items
+                    .iter()
+                    .map(|item| Self::format_raw(item).unwrap_or_default())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+            Response::Bytes(bytes) => Some(String::from_utf8_lossy(bytes).into_owned()),
+            Response::Map(map) if map.is_empty() => None,
+            Response::Map(map) => Some(
+                map.iter()
+                    .map(|(key, value)| format!("{}\t{}", key, Self::format_inline(value)))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
         }
     }
 
     /// Show help information
     fn show_help(&self) {
         println!("Available commands:");
-        println!("  GET <key>           - Get value by key");
-        println!("  SET <key> <value>   - Set key-value pair");
-        println!("  DEL <key>           - Delete key");
-        println!("  EXISTS <key>        - Check if key exists");
-        println!("  KEYS                - List all keys");
-        println!("  FLUSH               - Clear all data");
-        println!("  SIZE                - Get number of keys");
-        println!("  PING                - Test connection");
+        for spec in COMMAND_SPECS {
+            let usage = if spec.args.is_empty() {
+                spec.name.to_string()
+            } else {
+                format!("{} {}", spec.name, spec.args)
+            };
+            println!("  {:<19} - {}", usage, spec.summary);
+        }
+        println!("  queue               - List the commands queued since MULTI");
         println!("  help                - Show this help");
         println!("  quit/exit           - Exit the program");
         println!();
         println!("You can also use JSON format:");
         println!("  {{\"command\": \"GET\", \"args\": {{\"key\": \"mykey\"}}}}");
+        println!("  {{\"version\": 1, \"command\": \"CAPABILITIES\"}}");
         println!();
     }
 }
+
+/// Escapes quotes, backslashes and non-printable characters like redis-cli
+///
+/// Printable non-ASCII text is kept as is so values in any script stay readable.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => {
+                let mut buf = [0u8; 4];
+                for byte in c.encode_utf8(&mut buf).bytes() {
+                    escaped.push_str(&format!("\\x{:02x}", byte));
+                }
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Classic 16-bytes-per-row hex dump with an ASCII column
+fn hex_dump(bytes: &[u8]) -> String {
+    bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(row, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let ascii: String = chunk
+                .iter()
+                .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+                .collect();
+            format!("{:08x}  {:<47}  |{}|", row * 16, hex.join(" "), ascii)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Inline argument hints for the interactive editor, shown in grey
+struct CommandHints;
+
+impl CommandHints {
+    /// Text to show after the cursor for a partially typed command line
+    fn hint_for(line: &str) -> Option<String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() || line.starts_with('{') {
+            return None;
+        }
+        let editing_word = !line.ends_with(char::is_whitespace);
+        COMMAND_SPECS.iter().find_map(|spec| Self::hint_from_spec(spec, &words, editing_word))
+    }
+
+    fn hint_from_spec(spec: &CommandSpec, words: &[&str], editing_word: bool) -> Option<String> {
+        let name: Vec<&str> = spec.name.split(' ').collect();
+        let args: Vec<&str> = spec.args.split_whitespace().collect();
+
+        if words.len() <= name.len() {
+            let (last, typed) = words.split_last()?;
+            if !typed.iter().zip(&name).all(|(word, part)| word.eq_ignore_ascii_case(part)) {
+                return None;
+            }
+            let current = name[typed.len()];
+            let rest = name[typed.len() + 1..].iter().chain(&args).copied().collect::<Vec<_>>().join(" ");
+            return if editing_word {
+                // Still typing a name word: complete it, then show the rest
+                if current.len() < last.len() || !current[..last.len()].eq_ignore_ascii_case(last) {
+                    return None;
+                }
+                let completion = &current[last.len()..];
+                Some(if rest.is_empty() { completion.to_string() } else { format!("{} {}", completion, rest) })
+            } else if last.eq_ignore_ascii_case(current) {
+                Some(rest)
+            } else {
+                None
+            };
+        }
+
+        if !words.iter().zip(&name).all(|(word, part)| word.eq_ignore_ascii_case(part)) {
+            return None;
+        }
+        let remaining = args.get(words.len() - name.len()..)?.join(" ");
+        if remaining.is_empty() {
+            None
+        } else if editing_word {
+            Some(format!(" {}", remaining))
+        } else {
+            Some(remaining)
+        }
+    }
+}
+
+impl Hinter for CommandHints {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<String> {
+        if pos < line.len() {
+            return None;
+        }
+        Self::hint_for(line).filter(|hint| !hint.is_empty())
+    }
+}
+
+impl Highlighter for CommandHints {
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("\x1b[90m{}\x1b[0m", hint))
+    }
+}
+
+impl Completer for CommandHints {
+    type Candidate = String;
+}
+
+impl Validator for CommandHints {}
+
+impl Helper for CommandHints {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_hints() {
+        assert_eq!(CommandHints::hint_for("GE").as_deref(), Some("T <key>"));
+        assert_eq!(CommandHints::hint_for("set ").as_deref(), Some("<key> <value>"));
+        assert_eq!(CommandHints::hint_for("SET user:1").as_deref(), Some(" <value>"));
+        assert_eq!(CommandHints::hint_for("SET user:1 ").as_deref(), Some("<value>"));
+        assert_eq!(CommandHints::hint_for("CONFIG ").as_deref(), Some("GET <param>"));
+        assert_eq!(CommandHints::hint_for("CONFIG SET maxmemory").as_deref(), Some(" <value>"));
+        assert_eq!(CommandHints::hint_for("SET a b"), None);
+        assert_eq!(CommandHints::hint_for("NOPE"), None);
+    }
+
+    #[test]
+    fn test_value_display() {
+        let cli = RustdisCli::new(RustdisCache::new());
+        assert_eq!(cli.format_value("olá\tmundo\u{0}"), "\"olá\\tmundo\\x00\"");
+
+        let short = RustdisCli::with_display(RustdisCache::new(), DisplayOptions { truncate_at: Some(3), ..Default::default() });
+        assert_eq!(short.format_value("abcdef"), "\"abc\"... (3 more bytes, use --no-truncate to see all)");
+
+        let hex = RustdisCli::with_display(RustdisCache::new(), DisplayOptions { hex: true, ..Default::default() });
+        assert_eq!(hex.format_value("a\u{1}"), format!("00000000  {:<47}  |a.|", "61 01"));
+        assert_eq!(hex.format_value("plain"), "\"plain\"");
+
+        let cache = RustdisCache::new();
+        cache.set("k".to_string(), "hello".to_string()).unwrap();
+        let verbose = RustdisCli::with_display(cache.clone(), DisplayOptions { verbose: true, ..Default::default() });
+        let reply = Response::StringOption(Some("hello".to_string()));
+        assert_eq!(verbose.get_details("k", &reply).as_deref(), Some("(5 bytes, no expiry)"));
+        cache.expire("k", std::time::Duration::from_secs(60)).unwrap();
+        assert!(verbose.get_details("k", &reply).unwrap().starts_with("(5 bytes, expires in "));
+        assert_eq!(verbose.get_details("k", &Response::StringOption(None)), None);
+        assert_eq!(cli.get_details("k", &reply), None);
+    }
+
+    #[test]
+    fn test_transaction_view() {
+        let protocol = RustdisProtocol::new(RustdisCache::new());
+        let mut transaction = Transaction::default();
+        assert_eq!(RustdisCli::queue_lines(&transaction, &[]), ["(not in a transaction)"]);
+        transaction.execute_args(&protocol, &["MULTI"]);
+        assert_eq!(RustdisCli::queue_lines(&transaction, &[]), ["(empty queue)"]);
+
+        let queued = ["SET n 1".to_string(), "INCR n".to_string(), "HSET n f v".to_string()];
+        assert_eq!(RustdisCli::queue_lines(&transaction, &queued), ["1) SET n 1", "2) INCR n", "3) HSET n f v"]);
+        for command in &queued {
+            let parts: Vec<&str> = command.split_whitespace().collect();
+            transaction.execute_args(&protocol, &parts);
+        }
+        let Response::Array(replies) = transaction.execute_args(&protocol, &["EXEC"]) else {
+            panic!("EXEC must reply with an array");
+        };
+        let lines = RustdisCli::exec_lines(&queued, &replies);
+        assert_eq!(lines[..2], ["1) SET n 1 -> OK", "2) INCR n -> 2"]);
+        assert!(lines[2].starts_with("3) HSET n f v -> Error: "));
+    }
+
+    #[test]
+    fn test_exit_status() {
+        assert_eq!(ExitStatus::of(&Response::StringOption(Some("v".to_string()))), ExitStatus::Found);
+        assert_eq!(ExitStatus::of(&Response::StringOption(None)), ExitStatus::Nil);
+        assert_eq!(ExitStatus::of(&Response::Boolean(true)), ExitStatus::Found);
+        assert_eq!(ExitStatus::of(&Response::Boolean(false)), ExitStatus::Nil);
+        assert_eq!(ExitStatus::of(&Response::error("boom")), ExitStatus::Error);
+        assert_eq!(ExitStatus::of(&Response::Number(0)), ExitStatus::Found);
+
+        let keys = Response::StringArray(vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(RustdisCli::format_raw(&keys).as_deref(), Some("a\nb"));
+        assert_eq!(RustdisCli::format_raw(&Response::StringOption(Some("a\"b".to_string()))).as_deref(), Some("a\"b"));
+        assert_eq!(RustdisCli::format_raw(&Response::Boolean(true)), None);
+        assert_eq!(RustdisCli::format_raw(&Response::Ok), None);
+    }
+}
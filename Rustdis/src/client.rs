@@ -0,0 +1,952 @@
+use crate::clock::{Clock, SystemClock};
+use crate::protocol::{Response, RustdisProtocol};
+use crate::resp::MAX_BULK_LEN;
+use anyhow::Result;
+use futures_core::Stream;
+use indexmap::IndexMap;
+use std::collections::VecDeque;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+
+/// Port used when a server URL doesn't name one
+pub const DEFAULT_PORT: u16 = 6379;
+
+/// Values a `KeyWatch` holds before its watcher waits for them to be read
+pub const MAX_PENDING_KEY_VALUES: usize = 16;
+
+/// Requests a `MultiplexedClient` queues before callers wait for room
+pub const MAX_QUEUED_REQUESTS: usize = 1024;
+
+/// Values kept by a near cache unless configured otherwise
+pub const DEFAULT_NEAR_CACHE_ENTRIES: usize = 1024;
+
+/// How long a near cache serves a value unless configured otherwise
+pub const DEFAULT_NEAR_CACHE_TTL: Duration = Duration::from_secs(1);
+
+/// Settings of a client's near cache (see `RespClient::with_near_cache`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NearCacheConfig {
+    /// Most values kept; the least recently read one makes room for a new one
+    pub max_entries: usize,
+    /// How long a value is served without asking the server again
+    pub ttl: Duration,
+}
+
+impl Default for NearCacheConfig {
+    fn default() -> Self {
+        Self { max_entries: DEFAULT_NEAR_CACHE_ENTRIES, ttl: DEFAULT_NEAR_CACHE_TTL }
+    }
+}
+
+/// Reads answered by a near cache and reads sent to the server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NearCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Settings of a client's circuit breaker (see
+/// `RespClient::with_circuit_breaker`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreakerConfig {
+    /// Failed requests in a row (errors or timeouts) that open the circuit
+    pub failure_threshold: u32,
+    /// How long a request may take before it counts as failed
+    pub timeout: Duration,
+    /// How long the circuit stays open before a request probes the server;
+    /// doubles after each failed probe
+    pub open_for: Duration,
+    /// Longest `open_for` grows to
+    pub max_open_for: Duration,
+    pub fallback: Fallback,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            timeout: Duration::from_secs(1),
+            open_for: Duration::from_secs(1),
+            max_open_for: Duration::from_secs(30),
+            fallback: Fallback::Error,
+        }
+    }
+}
+
+/// What a client answers when the server can't be reached
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Fallback {
+    /// Fail, at once while the circuit is open (`CircuitOpen`)
+    #[default]
+    Error,
+    /// `get` serves the near cache's last value for the key, however old;
+    /// other commands, and keys never read, fail as with `Error`
+    Stale,
+}
+
+/// Whether a client's requests reach the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests are sent
+    Closed,
+    /// Requests fail at once until `open_for` has passed
+    Open,
+    /// `open_for` has passed; the next request probes the server and closes
+    /// the circuit if it succeeds
+    HalfOpen,
+}
+
+/// Request refused without contacting the server, as the circuit is open
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitOpen {
+    /// Time left before a request probes the server again
+    pub retry_in: Duration,
+}
+
+impl fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Circuit open: server unavailable, retrying in {}ms", self.retry_in.as_millis())
+    }
+}
+
+impl std::error::Error for CircuitOpen {}
+
+#[derive(Debug)]
+struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    failures: u32,
+    /// When the open circuit lets a probe through; `None` when closed
+    open_until: Option<Instant>,
+    open_for: Duration,
+}
+
+impl CircuitBreaker {
+    fn new(config: CircuitBreakerConfig) -> Self {
+        Self { config, failures: 0, open_until: None, open_for: config.open_for }
+    }
+
+    fn state(&self, now: Instant) -> CircuitState {
+        match self.open_until {
+            None => CircuitState::Closed,
+            Some(until) if now < until => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Refuses requests while open; once `open_for` has passed they go
+    /// through as probes
+    fn check(&self, now: Instant) -> Result<(), CircuitOpen> {
+        match self.open_until {
+            Some(until) if now < until => Err(CircuitOpen { retry_in: until - now }),
+            _ => Ok(()),
+        }
+    }
+
+    fn succeeded(&mut self) {
+        self.failures = 0;
+        self.open_until = None;
+        self.open_for = self.config.open_for;
+    }
+
+    fn failed(&mut self, now: Instant) {
+        self.failures += 1;
+        if self.open_until.is_some() {
+            // A failed probe: back off further
+            self.open_for = (self.open_for * 2).min(self.config.max_open_for);
+        } else if self.failures < self.config.failure_threshold {
+            return;
+        }
+        self.open_until = Some(now + self.open_for);
+    }
+}
+
+/// Values read by `RespClient::get`, in order of last use
+#[derive(Debug)]
+struct NearCache {
+    config: NearCacheConfig,
+    entries: IndexMap<String, (String, Instant)>,
+    stats: NearCacheStats,
+}
+
+impl NearCache {
+    /// A value read less than `ttl` ago; older ones stay, for `stale`,
+    /// until they're replaced or make room
+    fn get(&mut self, key: &str, now: Instant) -> Option<String> {
+        let fresh = match self.entries.get_full(key) {
+            Some((index, _, (_, fetched))) if now.saturating_duration_since(*fetched) < self.config.ttl => Some(index),
+            _ => None,
+        };
+        let Some(index) = fresh else {
+            self.stats.misses += 1;
+            return None;
+        };
+        self.stats.hits += 1;
+        let last = self.entries.len() - 1;
+        self.entries.move_index(index, last);
+        Some(self.entries[last].0.clone())
+    }
+
+    /// The last value read for `key`, however old, and when it was read
+    fn stale(&self, key: &str) -> Option<(String, Instant)> {
+        self.entries.get(key).cloned()
+    }
+
+    fn insert(&mut self, key: &str, value: String, fetched: Instant) {
+        if self.config.max_entries == 0 {
+            return;
+        }
+        self.entries.shift_remove(key);
+        if self.entries.len() >= self.config.max_entries {
+            self.entries.shift_remove_index(0);
+        }
+        self.entries.insert(key.to_string(), (value, fetched));
+    }
+
+    /// Drops what a command sent through the client may have changed: its
+    /// keys, or everything for admin commands (e.g. FLUSH) and commands
+    /// Rustdis doesn't know
+    fn invalidate(&mut self, args: &[String]) {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        match RustdisProtocol::parse_args(&args) {
+            Ok(command) if !command.is_admin() => {
+                for key in command.keys() {
+                    self.entries.shift_remove(key);
+                }
+            }
+            _ => self.entries.clear(),
+        }
+    }
+}
+
+/// Minimal RESP2 client, for talking to Rustdis (`rustdis serve`) or Redis
+///
+/// Replies are decoded into `Response`: status replies become `String`,
+/// bulk strings `StringOption`, integers `Integer` and arrays `Array`. A
+/// request that fails (not an error reply, but a connection error or a
+/// reply that can't be read) drops the connection, and the next request
+/// connects again.
+pub struct RespClient {
+    address: String,
+    connection: Option<(BufReader<OwnedReadHalf>, BufWriter<OwnedWriteHalf>)>,
+    near: Option<NearCache>,
+    breaker: Option<CircuitBreaker>,
+    clock: Arc<dyn Clock>,
+}
+
+impl RespClient {
+    /// Connects to `redis://host[:port]` or a bare `host[:port]`
+    pub async fn connect(url: &str) -> Result<Self> {
+        let address = parse_url(url)?;
+        let connection = open(&address).await?;
+        Ok(Self { address, connection: Some(connection), near: None, breaker: None, clock: Arc::new(SystemClock) })
+    }
+
+    /// Time source of the near cache TTL and the circuit breaker; real time
+    /// unless set, e.g. to a `ManualClock` in tests
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Stops sending requests for a while once `failure_threshold` in a row
+    /// failed or took longer than `timeout`, so an outage costs callers an
+    /// immediate `CircuitOpen` error (or, with `Fallback::Stale`, a near cache
+    /// value) instead of a timeout each
+    ///
+    /// After `open_for`, the next request probes the server: success closes
+    /// the circuit, failure keeps it open twice as long, up to
+    /// `max_open_for`.
+    pub fn with_circuit_breaker(mut self, config: CircuitBreakerConfig) -> Self {
+        self.breaker = Some(CircuitBreaker::new(config));
+        self
+    }
+
+    /// State of the circuit breaker; `None` without one
+    pub fn circuit_state(&self) -> Option<CircuitState> {
+        self.breaker.as_ref().map(|breaker| breaker.state(self.clock.now()))
+    }
+
+    /// Keeps values read with `get` in process for `config.ttl`, so hot
+    /// keys skip the network
+    ///
+    /// Keys written through this client are dropped from the near cache at
+    /// once. Writes by other clients show up when the TTL runs out, as
+    /// Rustdis has no client tracking to push invalidations.
+    pub fn with_near_cache(mut self, config: NearCacheConfig) -> Self {
+        self.near = Some(NearCache { config, entries: IndexMap::new(), stats: NearCacheStats::default() });
+        self
+    }
+
+    /// Near cache hits and misses so far; `None` without a near cache
+    pub fn near_cache_stats(&self) -> Option<NearCacheStats> {
+        self.near.as_ref().map(|near| near.stats)
+    }
+
+    /// GET of a string value, answered from the near cache when it holds
+    /// a fresh copy, or any copy with `Fallback::Stale` when the server
+    /// can't be reached
+    pub async fn get(&mut self, key: &str) -> Result<Option<String>> {
+        let now = self.clock.now();
+        if let Some(value) = self.near.as_mut().and_then(|near| near.get(key, now)) {
+            return Ok(Some(value));
+        }
+        // Taken first, as sending GET drops the key from the near cache
+        let stale = match self.breaker.as_ref().map(|breaker| breaker.config.fallback) {
+            Some(Fallback::Stale) => self.near.as_ref().and_then(|near| near.stale(key)),
+            _ => None,
+        };
+        let reply = match (self.execute(&["GET", key]).await, stale) {
+            (Ok(reply), _) => reply,
+            (Err(_), Some((value, fetched))) => {
+                if let Some(near) = &mut self.near {
+                    near.insert(key, value.clone(), fetched);
+                }
+                return Ok(Some(value));
+            }
+            (Err(e), None) => return Err(e),
+        };
+        match reply {
+            Response::StringOption(value) => {
+                if let (Some(near), Some(value)) = (&mut self.near, &value) {
+                    near.insert(key, value.clone(), self.clock.now());
+                }
+                Ok(value)
+            }
+            Response::Error { error, code } => Err(anyhow::anyhow!("{} {}", code.as_deref().unwrap_or("ERR"), error)),
+            other => Err(anyhow::anyhow!("Unexpected GET reply: {:?}", other)),
+        }
+    }
+
+    /// Follows a string key's value: the stream yields it at once, then again
+    /// each time it changes (`None` once the key is gone)
+    ///
+    /// Built on keyspace notifications, so the server must publish them for
+    /// the key's writes, e.g. `CONFIG SET notify-keyspace-events KA`. Two
+    /// connections of its own are opened: one subscribed to
+    /// `__keyspace@0__:<key>` and one reading the value after each event.
+    /// The stream ends if either fails.
+    pub async fn watch_key(&self, key: &str) -> Result<KeyWatch> {
+        let (mut events, mut writer) = open(&self.address).await?;
+        let mut subscribe = Vec::new();
+        encode_command(&["SUBSCRIBE".to_string(), format!("__keyspace@0__:{}", key)], &mut subscribe);
+        writer.write_all(&subscribe).await?;
+        writer.flush().await?;
+        match read_reply(&mut events).await? {
+            Response::Array(_) => {}
+            other => return Err(anyhow::anyhow!("Unexpected SUBSCRIBE reply: {:?}", other)),
+        }
+
+        // Read after subscribing, so no change can fall in between
+        let mut reader = RespClient::connect(&self.address).await?;
+        let mut value = reader.get(key).await?;
+        let (sender, receiver) = mpsc::channel(MAX_PENDING_KEY_VALUES);
+        sender.send(value.clone()).await?;
+        let key = key.to_string();
+        tokio::spawn(async move {
+            // Kept open for as long as the subscription lasts
+            let _writer = writer;
+            loop {
+                let event = tokio::select! {
+                    _ = sender.closed() => return,
+                    event = read_reply(&mut events) => event,
+                };
+                let (Ok(Response::Array(_)), Ok(current)) = (event, reader.get(&key).await) else {
+                    return;
+                };
+                // Events that left the value alone (e.g. EXPIRE) aren't repeated
+                if current != value {
+                    value = current;
+                    if sender.send(value.clone()).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(KeyWatch { values: receiver })
+    }
+
+    /// Keys matching the glob `pattern`, paged through with SCAN
+    ///
+    /// Each page is fetched when the previous one has been consumed, so
+    /// the keyspace is never loaded at once. As with SCAN itself, keys
+    /// written while scanning may or may not be returned. Rustdis has no
+    /// cluster mode, so a `MOVED` redirect is never sent; from Redis, it
+    /// ends the scan with an error like any other error reply.
+    pub fn scan_match(&mut self, pattern: &str) -> KeyScan<'_> {
+        KeyScan { client: self, pattern: pattern.to_string(), cursor: Some("0".to_string()), keys: VecDeque::new() }
+    }
+
+    /// Sends one command and waits for its reply
+    pub async fn execute(&mut self, args: &[&str]) -> Result<Response> {
+        let mut replies = self.pipeline(&[args.iter().map(|arg| arg.to_string()).collect()]).await?;
+        Ok(replies.remove(0))
+    }
+
+    /// Sends every command in one write, then reads their replies in order
+    pub async fn pipeline(&mut self, commands: &[Vec<String>]) -> Result<Vec<Response>> {
+        let mut out = Vec::new();
+        for args in commands {
+            if let Some(near) = &mut self.near {
+                near.invalidate(args);
+            }
+            encode_command(args, &mut out);
+        }
+        if let Some(breaker) = &self.breaker {
+            breaker.check(self.clock.now())?;
+        }
+
+        let replies = match self.breaker.as_ref().map(|breaker| breaker.config.timeout) {
+            Some(timeout) => tokio::time::timeout(timeout, self.round_trip(&out, commands.len()))
+                .await
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("Request timed out after {}ms", timeout.as_millis()))),
+            None => self.round_trip(&out, commands.len()).await,
+        };
+        if replies.is_err() {
+            // Replies still on their way would be read as answers to the
+            // next request
+            self.connection = None;
+        }
+        if let Some(breaker) = &mut self.breaker {
+            match replies {
+                Ok(_) => breaker.succeeded(),
+                Err(_) => breaker.failed(self.clock.now()),
+            }
+        }
+        replies
+    }
+
+    /// Writes `out` and reads `count` replies, connecting first if needed
+    async fn round_trip(&mut self, out: &[u8], count: usize) -> Result<Vec<Response>> {
+        let (reader, writer) = match &mut self.connection {
+            Some(connection) => connection,
+            connection @ None => connection.insert(open(&self.address).await?),
+        };
+        writer.write_all(out).await?;
+        writer.flush().await?;
+
+        let mut replies = Vec::with_capacity(count);
+        for _ in 0..count {
+            replies.push(read_reply(reader).await?);
+        }
+        Ok(replies)
+    }
+}
+
+/// Values of a key followed with `RespClient::watch_key`, newest last
+///
+/// Usable as a `Stream`, or with `recv`. Dropping it ends the watch and
+/// closes its connections.
+#[derive(Debug)]
+pub struct KeyWatch {
+    values: mpsc::Receiver<Option<String>>,
+}
+
+impl KeyWatch {
+    /// The next value; `None` once the watch has ended
+    pub async fn recv(&mut self) -> Option<Option<String>> {
+        self.values.recv().await
+    }
+}
+
+impl Stream for KeyWatch {
+    type Item = Option<String>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.values.poll_recv(cx)
+    }
+}
+
+/// Keys matched by `RespClient::scan_match`, fetched a SCAN page at a time
+pub struct KeyScan<'a> {
+    client: &'a mut RespClient,
+    pattern: String,
+    /// Cursor of the next page; `None` once the server returned 0
+    cursor: Option<String>,
+    keys: VecDeque<String>,
+}
+
+impl KeyScan<'_> {
+    /// The next matching key; `None` once the scan is complete
+    ///
+    /// After an error, the scan ends.
+    pub async fn next(&mut self) -> Option<Result<String>> {
+        while self.keys.is_empty() {
+            let cursor = self.cursor.take()?;
+            match self.client.execute(&["SCAN", &cursor, "MATCH", &self.pattern]).await {
+                Ok(reply) => match parse_scan_reply(reply) {
+                    Ok((next, keys)) => {
+                        self.cursor = Some(next).filter(|next| next != "0");
+                        self.keys = keys.into();
+                    }
+                    Err(e) => return Some(Err(e)),
+                },
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        self.keys.pop_front().map(Ok)
+    }
+
+    /// Every remaining matching key
+    pub async fn collect(mut self) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+        while let Some(key) = self.next().await {
+            keys.push(key?);
+        }
+        Ok(keys)
+    }
+}
+
+/// Splits a SCAN reply into the next cursor and the page's keys
+pub(crate) fn parse_scan_reply(reply: Response) -> Result<(String, Vec<String>)> {
+    let Response::Array(mut items) = reply else {
+        return match reply {
+            Response::Error { error, code } => Err(anyhow::anyhow!("{} {}", code.as_deref().unwrap_or("ERR"), error)),
+            other => Err(anyhow::anyhow!("Unexpected SCAN reply: {:?}", other)),
+        };
+    };
+    let (Some(Response::Array(keys)), Some(Response::String(cursor) | Response::StringOption(Some(cursor))), None) =
+        (items.pop(), items.pop(), items.pop())
+    else {
+        return Err(anyhow::anyhow!("Unexpected SCAN reply: {:?}", items));
+    };
+    let keys = keys
+        .into_iter()
+        .map(|key| match key {
+            Response::StringOption(Some(key)) | Response::String(key) => Ok(key),
+            other => Err(anyhow::anyhow!("Unexpected SCAN key: {:?}", other)),
+        })
+        .collect::<Result<_>>()?;
+    Ok((cursor, keys))
+}
+
+async fn open(address: &str) -> Result<(BufReader<OwnedReadHalf>, BufWriter<OwnedWriteHalf>)> {
+    let stream = TcpStream::connect(address).await?;
+    stream.set_nodelay(true)?;
+    let (reader, writer) = stream.into_split();
+    Ok((BufReader::new(reader), BufWriter::new(writer)))
+}
+
+/// Commands queued on a multiplexed connection
+struct Request {
+    payload: Vec<u8>,
+    pending: Pending,
+}
+
+/// Commands sent on a multiplexed connection, waiting for their replies
+struct Pending {
+    replies: usize,
+    respond: oneshot::Sender<Result<Vec<Response>>>,
+}
+
+/// RESP2 client sharing one connection between many tasks, like redis-rs'
+/// `MultiplexedConnection`
+///
+/// Clones are cheap handles to the same connection. A background task
+/// writes queued commands together as they arrive and hands replies back
+/// in order, which is how RESP matches them to their commands; a slow
+/// command only delays those sent after it. Pub/sub commands are refused,
+/// as a subscribed connection stops answering in order. The connection
+/// closes once every handle is dropped.
+#[derive(Clone)]
+pub struct MultiplexedClient {
+    requests: mpsc::Sender<Request>,
+}
+
+impl MultiplexedClient {
+    /// Connects to `redis://host[:port]` or a bare `host[:port]`
+    pub async fn connect(url: &str) -> Result<Self> {
+        let (reader, writer) = open(&parse_url(url)?).await?;
+        let (requests, queued) = mpsc::channel(MAX_QUEUED_REQUESTS);
+        let (sent, pending) = mpsc::unbounded_channel();
+        tokio::spawn(write_requests(writer, queued, sent));
+        tokio::spawn(read_replies(reader, pending));
+        Ok(Self { requests })
+    }
+
+    /// Sends one command and waits for its reply
+    pub async fn execute(&self, args: &[&str]) -> Result<Response> {
+        let mut replies = self.pipeline(&[args.iter().map(|arg| arg.to_string()).collect()]).await?;
+        Ok(replies.remove(0))
+    }
+
+    /// Sends the commands back to back, so no other task's commands run
+    /// between them, and returns their replies in order
+    pub async fn pipeline(&self, commands: &[Vec<String>]) -> Result<Vec<Response>> {
+        let mut payload = Vec::new();
+        for args in commands {
+            if let Some(name) = args.first().filter(|name| ["SUBSCRIBE", "PSUBSCRIBE"].iter().any(|command| name.eq_ignore_ascii_case(command))) {
+                return Err(anyhow::anyhow!("{} is not supported on a multiplexed connection", name.to_uppercase()));
+            }
+            encode_command(args, &mut payload);
+        }
+        let (respond, replies) = oneshot::channel();
+        let closed = || anyhow::anyhow!("Multiplexed connection closed");
+        self.requests
+            .send(Request { payload, pending: Pending { replies: commands.len(), respond } })
+            .await
+            .map_err(|_| closed())?;
+        replies.await.map_err(|_| closed())?
+    }
+}
+
+/// Writes queued requests, as many per write as are waiting, and passes
+/// each on to `read_replies` before its bytes go out
+async fn write_requests(mut writer: BufWriter<OwnedWriteHalf>, mut queued: mpsc::Receiver<Request>, sent: mpsc::UnboundedSender<Pending>) {
+    while let Some(first) = queued.recv().await {
+        let mut request = Some(first);
+        while let Some(Request { payload, pending }) = request {
+            if let Err(rejected) = sent.send(pending) {
+                let _ = rejected.0.respond.send(Err(anyhow::anyhow!("Multiplexed connection closed")));
+                return;
+            }
+            // The reader fails the pending requests once the connection drops
+            if writer.write_all(&payload).await.is_err() {
+                return;
+            }
+            request = queued.try_recv().ok();
+        }
+        if writer.flush().await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Reads the replies of sent requests in order; once the connection fails,
+/// every request still waiting gets an error
+async fn read_replies(mut reader: BufReader<OwnedReadHalf>, mut pending: mpsc::UnboundedReceiver<Pending>) {
+    while let Some(request) = pending.recv().await {
+        let mut replies = Vec::with_capacity(request.replies);
+        for _ in 0..request.replies {
+            match read_reply(&mut reader).await {
+                Ok(reply) => replies.push(reply),
+                Err(e) => {
+                    let message = e.to_string();
+                    let _ = request.respond.send(Err(e));
+                    pending.close();
+                    while let Some(request) = pending.recv().await {
+                        let _ = request.respond.send(Err(anyhow::anyhow!("{}", message)));
+                    }
+                    return;
+                }
+            }
+        }
+        // The caller may have stopped waiting
+        let _ = request.respond.send(Ok(replies));
+    }
+}
+
+/// Appends `args` to `out` as a RESP array of bulk strings
+fn encode_command(args: &[String], out: &mut Vec<u8>) {
+    out.extend_from_slice(format!("*{}\r\n", args.len()).as_bytes());
+    for arg in args {
+        out.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        out.extend_from_slice(arg.as_bytes());
+        out.extend_from_slice(b"\r\n");
+    }
+}
+
+async fn read_reply(reader: &mut BufReader<OwnedReadHalf>) -> Result<Response> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        return Err(anyhow::anyhow!("Server closed the connection"));
+    }
+    let line = line.trim_end_matches(['\r', '\n']);
+    let (kind, rest) = line.split_at(line.len().min(1));
+
+    match kind {
+        "+" => Ok(Response::String(rest.to_string())),
+        "-" => {
+            let (code, error) = rest.split_once(' ').unwrap_or(("ERR", rest));
+            Ok(Response::Error { error: error.to_string(), code: Some(code.to_string()) })
+        }
+        ":" => Ok(Response::Integer(rest.parse().map_err(|_| anyhow::anyhow!("Invalid integer reply: {}", rest))?)),
+        "$" if rest == "-1" => Ok(Response::StringOption(None)),
+        "$" => {
+            let len = rest
+                .parse::<usize>()
+                .ok()
+                .filter(|len| *len <= MAX_BULK_LEN)
+                .ok_or_else(|| anyhow::anyhow!("Invalid bulk length: {}", rest))?;
+            let mut bulk = vec![0u8; len + 2];
+            reader.read_exact(&mut bulk).await?;
+            bulk.truncate(len);
+            let value = String::from_utf8(bulk).map_err(|_| anyhow::anyhow!("Reply is not UTF-8"))?;
+            Ok(Response::StringOption(Some(value)))
+        }
+        "*" if rest == "-1" => Ok(Response::StringOption(None)),
+        "*" => {
+            let count: usize = rest.parse().map_err(|_| anyhow::anyhow!("Invalid array length: {}", rest))?;
+            let mut items = Vec::with_capacity(count.min(1024));
+            for _ in 0..count {
+                items.push(Box::pin(read_reply(reader)).await?);
+            }
+            Ok(Response::Array(items))
+        }
+        _ => Err(anyhow::anyhow!("Unexpected reply: {}", line)),
+    }
+}
+
+/// Resolves a server URL to `host:port`
+fn parse_url(url: &str) -> Result<String> {
+    let address = url.strip_prefix("redis://").unwrap_or(url);
+    // Only the default database exists
+    let address = match address.split_once('/') {
+        Some((address, "" | "0")) => address,
+        Some(_) => return Err(anyhow::anyhow!("Only database 0 is supported: {}", url)),
+        None => address,
+    };
+    if address.is_empty() {
+        return Err(anyhow::anyhow!("Missing host in {}", url));
+    }
+    // Bracketed IPv6 hosts contain colons of their own
+    let has_port = address
+        .rsplit_once(':')
+        .is_some_and(|(host, port)| port.parse::<u16>().is_ok() && (!host.contains(':') || host.ends_with(']')));
+    Ok(if has_port { address.to_string() } else { format!("{}:{}", address, DEFAULT_PORT) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::RustdisCache;
+    use crate::clock::ManualClock;
+    use crate::protocol::RustdisProtocol;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_client_round_trip() {
+        assert_eq!(parse_url("redis://cache.local").unwrap(), "cache.local:6379");
+        assert_eq!(parse_url("redis://127.0.0.1:7000/0").unwrap(), "127.0.0.1:7000");
+        assert_eq!(parse_url("[::1]").unwrap(), "[::1]:6379");
+        assert!(parse_url("redis://host/3").is_err());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let protocol = RustdisProtocol::new(RustdisCache::new());
+            crate::resp::serve_connection(&protocol, stream).await.unwrap();
+        });
+
+        let mut client = RespClient::connect(&addr.to_string()).await.unwrap();
+        assert!(matches!(client.execute(&["SET", "k", "a b\r\nc"]).await.unwrap(), Response::String(ref s) if s == "OK"));
+        let replies = client
+            .pipeline(&[vec!["GET".into(), "k".into()], vec!["GET".into(), "nope".into()], vec!["TTL".into(), "k".into()], vec!["NOPE".into()]])
+            .await
+            .unwrap();
+        assert!(matches!(replies[0], Response::StringOption(Some(ref s)) if s == "a b\r\nc"));
+        assert!(matches!(replies[1], Response::StringOption(None)));
+        assert!(matches!(replies[2], Response::Integer(-1)));
+        assert!(matches!(replies[3], Response::Error { ref code, .. } if code.as_deref() == Some("UNKNOWN_COMMAND")));
+    }
+
+    #[tokio::test]
+    async fn test_near_cache() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let cache = RustdisCache::new();
+        let protocol = RustdisProtocol::new(cache.clone());
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            crate::resp::serve_connection(&protocol, stream).await.unwrap();
+        });
+        let clock = Arc::new(ManualClock::new());
+        let config = NearCacheConfig { max_entries: 2, ttl: Duration::from_millis(200) };
+        let mut client = RespClient::connect(&addr.to_string()).await.unwrap().with_near_cache(config).with_clock(clock.clone());
+        for key in ["a", "b", "c"] {
+            cache.set(key.to_string(), "1".to_string()).unwrap();
+        }
+
+        // Served locally until the TTL runs out, even when changed elsewhere
+        assert_eq!(client.get("a").await.unwrap().as_deref(), Some("1"));
+        cache.set("a".to_string(), "2".to_string()).unwrap();
+        assert_eq!(client.get("a").await.unwrap().as_deref(), Some("1"));
+        assert_eq!(client.near_cache_stats(), Some(NearCacheStats { hits: 1, misses: 1 }));
+        clock.advance(config.ttl);
+        assert_eq!(client.get("a").await.unwrap().as_deref(), Some("2"));
+
+        // Own writes invalidate at once
+        client.execute(&["SET", "a", "3"]).await.unwrap();
+        assert_eq!(client.get("a").await.unwrap().as_deref(), Some("3"));
+
+        // The least recently read key makes room: b goes, a stays
+        client.get("b").await.unwrap();
+        client.get("a").await.unwrap();
+        client.get("c").await.unwrap();
+        let before = client.near_cache_stats().unwrap();
+        client.get("a").await.unwrap();
+        client.get("b").await.unwrap();
+        assert_eq!(client.near_cache_stats().unwrap(), NearCacheStats { hits: before.hits + 1, misses: before.misses + 1 });
+
+        client.execute(&["FLUSH"]).await.unwrap();
+        assert_eq!(client.get("a").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_watch_key() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let cache = RustdisCache::new();
+        cache.config_set("notify-keyspace-events", "KA").unwrap();
+        let protocol = std::sync::Arc::new(RustdisProtocol::new(cache.clone()));
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let protocol = protocol.clone();
+                tokio::spawn(async move { crate::resp::serve_connection(&protocol, stream).await });
+            }
+        });
+
+        let mut client = RespClient::connect(&addr.to_string()).await.unwrap();
+        let mut flags = client.watch_key("config:flags").await.unwrap();
+        assert_eq!(flags.recv().await, Some(None));
+        client.execute(&["SET", "config:flags", "dark-mode"]).await.unwrap();
+        assert_eq!(flags.recv().await, Some(Some("dark-mode".to_string())));
+        // A TTL change leaves the value alone and yields nothing
+        client.execute(&["EXPIRE", "config:flags", "60"]).await.unwrap();
+        client.execute(&["SET", "config:flags", "beta"]).await.unwrap();
+        assert_eq!(flags.recv().await, Some(Some("beta".to_string())));
+        client.execute(&["DEL", "config:flags"]).await.unwrap();
+        assert_eq!(flags.recv().await, Some(None));
+    }
+
+    #[tokio::test]
+    async fn test_scan_match() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let cache = RustdisCache::new();
+        let protocol = RustdisProtocol::new(cache.clone());
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            crate::resp::serve_connection(&protocol, stream).await.unwrap();
+        });
+        // Several SCAN pages, some of them with no match at all
+        for i in 0..25 {
+            cache.set(format!("user:{}", i), "1".to_string()).unwrap();
+        }
+        for i in 0..20 {
+            cache.set(format!("session:{}", i), "1".to_string()).unwrap();
+        }
+
+        let mut client = RespClient::connect(&addr.to_string()).await.unwrap();
+        let mut keys = client.scan_match("user:*").collect().await.unwrap();
+        keys.sort_by_key(|key| key[5..].parse::<u32>().unwrap());
+        assert_eq!(keys, (0..25).map(|i| format!("user:{}", i)).collect::<Vec<_>>());
+
+        let mut scan = client.scan_match("nobody:*");
+        assert!(scan.next().await.is_none());
+        assert!(scan.next().await.is_none());
+        // The connection is still usable afterwards
+        assert_eq!(client.get("user:0").await.unwrap().as_deref(), Some("1"));
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let cache = RustdisCache::new();
+        // While down, the server hangs up on every connection it accepts
+        let up = Arc::new(AtomicBool::new(true));
+        let protocol = Arc::new(RustdisProtocol::new(cache.clone()));
+        let serving = up.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                if serving.load(Ordering::SeqCst) {
+                    let protocol = protocol.clone();
+                    tokio::spawn(async move { crate::resp::serve_connection(&protocol, stream).await });
+                }
+            }
+        });
+        let clock = Arc::new(ManualClock::new());
+        let near = NearCacheConfig { max_entries: 16, ttl: Duration::from_millis(10) };
+        let config = CircuitBreakerConfig {
+            failure_threshold: 2,
+            timeout: Duration::from_millis(500),
+            open_for: Duration::from_millis(100),
+            max_open_for: Duration::from_secs(1),
+            fallback: Fallback::Stale,
+        };
+        let mut client = RespClient::connect(&addr.to_string())
+            .await
+            .unwrap()
+            .with_near_cache(near)
+            .with_circuit_breaker(config)
+            .with_clock(clock.clone());
+        cache.set("a".to_string(), "1".to_string()).unwrap();
+        assert_eq!(client.get("a").await.unwrap().as_deref(), Some("1"));
+
+        // The server goes away, and the client's connection with it: two
+        // failed reads in a row open the circuit, both answered stale
+        up.store(false, Ordering::SeqCst);
+        client.connection = None;
+        clock.advance(near.ttl);
+        assert_eq!(client.get("a").await.unwrap().as_deref(), Some("1"));
+        assert_eq!(client.circuit_state(), Some(CircuitState::Closed));
+        assert_eq!(client.get("a").await.unwrap().as_deref(), Some("1"));
+        assert_eq!(client.circuit_state(), Some(CircuitState::Open));
+        let refused = client.execute(&["PING"]).await.unwrap_err();
+        assert!(refused.downcast_ref::<CircuitOpen>().is_some());
+        assert!(client.get("never-read").await.is_err());
+
+        // A failed probe keeps it open for longer
+        clock.advance(config.open_for);
+        assert_eq!(client.circuit_state(), Some(CircuitState::HalfOpen));
+        assert!(client.execute(&["PING"]).await.unwrap_err().downcast_ref::<CircuitOpen>().is_none());
+        let refused = client.execute(&["PING"]).await.unwrap_err();
+        assert_eq!(refused.downcast_ref::<CircuitOpen>().unwrap().retry_in, config.open_for * 2);
+
+        // Back up: the next probe reconnects and closes the circuit
+        up.store(true, Ordering::SeqCst);
+        clock.advance(config.open_for * 2);
+        assert!(matches!(client.execute(&["PING"]).await.unwrap(), Response::String(ref s) if s == "PONG"));
+        assert_eq!(client.circuit_state(), Some(CircuitState::Closed));
+    }
+
+    #[tokio::test]
+    async fn test_multiplexed_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let protocol = RustdisProtocol::new(RustdisCache::new());
+            crate::resp::serve_connection(&protocol, stream).await.unwrap();
+            // The next connection is dropped straight away
+            drop(listener.accept().await.unwrap());
+        });
+
+        // Many tasks on one connection each get their own replies
+        let client = MultiplexedClient::connect(&addr.to_string()).await.unwrap();
+        let tasks: Vec<_> = (0..50)
+            .map(|i| {
+                let client = client.clone();
+                tokio::spawn(async move {
+                    let key = format!("k{}", i);
+                    client.execute(&["SET", &key, &i.to_string()]).await.unwrap();
+                    let replies = client.pipeline(&[vec!["GET".into(), key.clone()], vec!["INCR".into(), key]]).await.unwrap();
+                    assert!(matches!(&replies[0], Response::StringOption(Some(value)) if *value == i.to_string()));
+                    assert!(matches!(replies[1], Response::Integer(n) if n == i + 1));
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert!(matches!(client.execute(&["SIZE"]).await.unwrap(), Response::Integer(50)));
+        assert!(client.execute(&["subscribe", "news"]).await.is_err());
+        assert!(matches!(client.execute(&["PING"]).await.unwrap(), Response::String(ref s) if s == "PONG"));
+        drop(client);
+
+        let closed = MultiplexedClient::connect(&addr.to_string()).await.unwrap();
+        assert!(closed.execute(&["PING"]).await.is_err());
+        assert!(closed.execute(&["PING"]).await.is_err());
+    }
+}
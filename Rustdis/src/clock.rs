@@ -0,0 +1,96 @@
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Source of logical time for everything time-dependent in the cache
+///
+/// Covers LRU access times and seen-filter windows, and the RESP client's
+/// near cache and circuit breaker (`RespClient::with_clock`). Latency measurements
+/// (lock waits, PROFILE) and ID generation always use real time.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// The manual clock behind this one, if it can be driven by tests
+    fn as_manual(&self) -> Option<&ManualClock> {
+        None
+    }
+}
+
+/// Real monotonic time
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Frozen clock that only moves when told to
+///
+/// Time is an offset from the instant the clock was created, so it never
+/// runs backwards past that origin.
+#[derive(Debug)]
+pub struct ManualClock {
+    origin: Instant,
+    offset_ms: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self {
+            origin: Instant::now(),
+            offset_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Moves the clock forward
+    pub fn advance(&self, by: Duration) {
+        self.offset_ms.fetch_add(by.as_millis() as u64, Ordering::SeqCst);
+    }
+
+    /// Sets the time elapsed since the clock's origin
+    pub fn set(&self, since_origin: Duration) {
+        self.offset_ms.store(since_origin.as_millis() as u64, Ordering::SeqCst);
+    }
+
+    /// Time elapsed since the clock's origin
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_millis(self.offset_ms.load(Ordering::SeqCst))
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.origin + self.elapsed()
+    }
+
+    fn as_manual(&self) -> Option<&ManualClock> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(clock.now() - start, Duration::from_secs(5));
+
+        clock.set(Duration::from_millis(1500));
+        assert_eq!(clock.elapsed(), Duration::from_millis(1500));
+        assert!(SystemClock.as_manual().is_none());
+    }
+}
@@ -0,0 +1,157 @@
+use crate::protocol::{Response, RustdisProtocol};
+use anyhow::Result;
+use std::fmt;
+use std::str::FromStr;
+
+/// Redis commands whose replies, in strict mode, match what Redis sends for
+/// the same request, errors included, in the forms Rustdis accepts (DEL and
+/// EXISTS take a single key)
+pub const REDIS_COMPAT_COMMANDS: &[&str] = &[
+    "GET", "SET", "DEL", "EXISTS", "KEYS", "SCAN", "PING", "DBSIZE", "FLUSHALL", "TYPE",
+    "EXPIRE", "PEXPIRE", "TTL", "PTTL", "INCR", "DECR", "INCRBY", "DECRBY", "INCRBYFLOAT",
+    "MGET", "MSET", "GETSET", "GETDEL", "GETEX", "RENAME", "RENAMENX", "COPY",
+    "HSET", "HGET", "HMGET", "HDEL", "HGETALL", "HKEYS", "HVALS", "HLEN",
+    "ZADD", "ZRANGE", "ZREVRANGE", "ZSCORE", "ZRANK", "ZREM", "ZCARD",
+    "CONFIG GET", "CONFIG SET", "PUBLISH", "SUBSCRIBE", "UNSUBSCRIBE", "PSUBSCRIBE", "PUNSUBSCRIBE",
+    "MULTI", "EXEC", "DISCARD", "WATCH", "UNWATCH", "AUTH", "QUIT",
+];
+
+/// How a RESP connection words its replies
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompatMode {
+    /// Rustdis' own error codes and messages, e.g. `-WRONG_ARITY GET
+    /// requires exactly one argument: GET <key>`
+    #[default]
+    Native,
+    /// Replies rewritten with `redis_reply`
+    Strict,
+}
+
+impl FromStr for CompatMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "native" => Ok(CompatMode::Native),
+            "strict" => Ok(CompatMode::Strict),
+            _ => Err(anyhow::anyhow!("Unknown compatibility mode: {} (expected native or strict)", s)),
+        }
+    }
+}
+
+impl fmt::Display for CompatMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompatMode::Native => write!(f, "native"),
+            CompatMode::Strict => write!(f, "strict"),
+        }
+    }
+}
+
+/// Rewrites `response`, the reply to `args`, the way Redis words it
+///
+/// Error codes and messages become Redis' (`ERR wrong number of arguments
+/// for 'get' command`, `ERR syntax error`, `ERR value is not an integer or
+/// out of range`...), PING echoes its argument, and strings nested in SCAN
+/// and CONFIG GET replies are sent as bulk strings rather than status
+/// replies. Replies already shaped like Redis' pass through unchanged.
+pub fn redis_reply(args: &[&str], response: Response) -> Response {
+    let name = args.first().map(|name| name.to_uppercase()).unwrap_or_default();
+    match (name.as_str(), response) {
+        (_, Response::Error { error, code }) => redis_error(args, code.as_deref(), &error),
+        ("PING", Response::String(_)) if args.len() == 2 => Response::StringOption(Some(args[1].to_string())),
+        ("SCAN", Response::Array(items)) => Response::Array(items.into_iter().map(bulk).collect()),
+        ("CONFIG", Response::Map(config)) => Response::Map(config.into_iter().map(|(name, value)| (name, bulk(value))).collect()),
+        // Errors in EXEC's reply come from the queued commands, whose
+        // arguments are gone by now
+        ("EXEC", Response::Array(replies)) => Response::Array(
+            replies
+                .into_iter()
+                .map(|reply| match reply {
+                    Response::Error { error, code } => redis_error(&[], code.as_deref(), &error),
+                    reply => reply,
+                })
+                .collect(),
+        ),
+        (_, response) => response,
+    }
+}
+
+fn bulk(response: Response) -> Response {
+    match response {
+        Response::String(value) => Response::StringOption(Some(value)),
+        response => response,
+    }
+}
+
+/// Redis' wording of an error reply to `args`
+fn redis_error(args: &[&str], code: Option<&str>, message: &str) -> Response {
+    let redis = |code: &str, message: &str| Response::coded_error(code, message);
+    let number = if message.contains("float") || message.contains("number") {
+        "value is not a valid float"
+    } else {
+        "value is not an integer or out of range"
+    };
+    match code.unwrap_or("ERR") {
+        "UNKNOWN_COMMAND" => {
+            let name = args.first().copied().unwrap_or_default();
+            let given: String = args.iter().skip(1).map(|arg| format!("'{}' ", arg)).collect();
+            redis("ERR", &format!("unknown command '{}', with args beginning with: {}", name, given))
+        }
+        // Parse errors blamed on an argument are bad options, not a wrong count
+        "WRONG_ARITY" if RustdisProtocol::parse_args(args).err().is_none_or(|e| e.arg.is_none()) => {
+            let name = args.first().map(|name| name.to_lowercase()).unwrap_or_default();
+            redis("ERR", &format!("wrong number of arguments for '{}' command", name))
+        }
+        "WRONG_ARITY" => redis("ERR", "syntax error"),
+        "INVALID_ARGUMENT" if message.contains("integer") || message.contains("float") || message.contains("number") => {
+            redis("ERR", number)
+        }
+        "INVALID_ARGUMENT" => redis("ERR", "syntax error"),
+        "NOT_A_NUMBER" => redis("ERR", number),
+        "OVERFLOW" => redis("ERR", "increment or decrement would overflow"),
+        "NO_SUCH_KEY" => redis("ERR", "no such key"),
+        "BUSYKEY" => redis("BUSYKEY", "Target key name already exists."),
+        "EXECABORT" => redis("EXECABORT", "Transaction discarded because of previous errors."),
+        "NOAUTH" => redis("NOAUTH", "Authentication required."),
+        "WRONGPASS" => redis("WRONGPASS", "invalid username-password pair or user is disabled."),
+        "ERR" => match message.strip_prefix("OOM ") {
+            Some(message) => redis("OOM", &format!("{}.", message)),
+            None => redis("ERR", message),
+        },
+        code => redis(code, message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::RustdisCache;
+
+    #[test]
+    fn test_redis_reply() {
+        let protocol = RustdisProtocol::new(RustdisCache::new());
+        let reply = |args: &[&str]| {
+            let mut out = Vec::new();
+            crate::resp::encode(&redis_reply(args, protocol.execute_args(args)), &mut out);
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(reply(&["GET"]), "-ERR wrong number of arguments for 'get' command\r\n");
+        assert_eq!(reply(&["SET", "k", "v", "EX"]), "-ERR syntax error\r\n");
+        assert_eq!(reply(&["SET", "k", "v", "NOPE"]), "-ERR syntax error\r\n");
+        assert_eq!(reply(&["SET", "k", "v", "EX", "soon"]), "-ERR value is not an integer or out of range\r\n");
+        assert_eq!(reply(&["FOO", "a", "b"]), "-ERR unknown command 'FOO', with args beginning with: 'a' 'b' \r\n");
+        protocol.execute_args(&["SET", "k", "v"]);
+        assert_eq!(reply(&["INCR", "k"]), "-ERR value is not an integer or out of range\r\n");
+        assert_eq!(reply(&["INCRBYFLOAT", "k", "1"]), "-ERR value is not a valid float\r\n");
+        assert_eq!(reply(&["HSET", "k", "f", "v"]), "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n");
+        assert_eq!(reply(&["RENAME", "missing", "k"]), "-ERR no such key\r\n");
+        assert_eq!(reply(&["PING"]), "+PONG\r\n");
+        assert_eq!(reply(&["PING", "hello"]), "$5\r\nhello\r\n");
+        assert_eq!(reply(&["SCAN", "0", "MATCH", "none"]), "*2\r\n$1\r\n0\r\n*0\r\n");
+        assert_eq!(reply(&["CONFIG", "GET", "maxmemory"]), "*2\r\n$9\r\nmaxmemory\r\n$1\r\n0\r\n");
+        assert_eq!(reply(&["HGETALL", "missing"]), "*0\r\n");
+        assert_eq!(reply(&["EXISTS", "k"]), ":1\r\n");
+    }
+}
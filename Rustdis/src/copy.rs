@@ -0,0 +1,204 @@
+use crate::client::{parse_scan_reply, RespClient};
+use crate::protocol::Response;
+use anyhow::Result;
+use std::time::{Duration, Instant};
+
+/// Keys fetched and written per pipelined round trip
+pub const DEFAULT_COPY_BATCH: usize = 100;
+
+#[derive(Debug, Clone)]
+pub struct CopyOptions {
+    /// Glob pattern selecting the keys to copy
+    pub pattern: String,
+    /// Replace keys that already exist on the destination
+    pub overwrite: bool,
+    /// Keys per pipelined round trip
+    pub batch: usize,
+}
+
+impl Default for CopyOptions {
+    fn default() -> Self {
+        Self {
+            pattern: "*".to_string(),
+            overwrite: false,
+            batch: DEFAULT_COPY_BATCH,
+        }
+    }
+}
+
+/// Progress of a copy, reported after every batch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CopyStats {
+    /// Keys matching the pattern on the source, so far
+    pub matched: usize,
+    pub copied: usize,
+    /// Keys left alone: already on the destination or gone from the source
+    pub skipped: usize,
+    /// Keys the destination refused
+    pub failed: usize,
+    pub elapsed: Duration,
+}
+
+impl CopyStats {
+    pub fn processed(&self) -> usize {
+        self.copied + self.skipped + self.failed
+    }
+
+    /// Keys processed per second so far
+    pub fn throughput(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.processed() as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Copies keys matching `options.pattern` from `source` to `destination`
+///
+/// The source is paged through with SCAN, `options.batch` keys at a time,
+/// so its keyspace is never listed at once. Every type moves with
+/// DUMP/RESTORE, whose payloads are base64 and binary-safe, keeping the
+/// remaining TTL (PTTL); both servers must be Rustdis, as DUMP payloads are
+/// in its snapshot format. Without `overwrite`, RESTORE refuses keys already
+/// on the destination (BUSYKEY) and they count as skipped.
+pub async fn copy_keys(
+    source: &mut RespClient,
+    destination: &mut RespClient,
+    options: &CopyOptions,
+    mut progress: impl FnMut(&CopyStats),
+) -> Result<CopyStats> {
+    let started = Instant::now();
+    let batch = options.batch.max(1).to_string();
+    let mut stats = CopyStats::default();
+    let mut cursor = "0".to_string();
+    loop {
+        let reply = source.execute(&["SCAN", &cursor, "MATCH", &options.pattern, "COUNT", &batch]).await?;
+        let (next, keys) = parse_scan_reply(reply)?;
+        stats.matched += keys.len();
+
+        let reads: Vec<Vec<String>> = keys
+            .iter()
+            .flat_map(|key| [vec!["DUMP".to_string(), key.clone()], vec!["PTTL".to_string(), key.clone()]])
+            .collect();
+        let mut writes = Vec::new();
+        for (key, reply) in keys.iter().zip(source.pipeline(&reads).await?.chunks(2)) {
+            let [Response::StringOption(Some(payload)) | Response::String(payload), ttl] = reply else {
+                stats.skipped += 1;
+                continue;
+            };
+            let ttl = match ttl {
+                Response::Integer(ttl) if *ttl > 0 => ttl.to_string(),
+                _ => "0".to_string(),
+            };
+            let mut restore = vec!["RESTORE".to_string(), key.clone(), ttl, payload.clone()];
+            if options.overwrite {
+                restore.push("REPLACE".to_string());
+            }
+            writes.push(restore);
+        }
+        for reply in destination.pipeline(&writes).await? {
+            match reply {
+                Response::Error { code: Some(code), .. } if code == "BUSYKEY" => stats.skipped += 1,
+                Response::Error { .. } => stats.failed += 1,
+                _ => stats.copied += 1,
+            }
+        }
+
+        stats.elapsed = started.elapsed();
+        progress(&stats);
+        if next == "0" {
+            break;
+        }
+        cursor = next;
+    }
+    Ok(stats)
+}
+
+/// Keys on `source` matching the glob `pattern`, sorted
+pub(crate) async fn matching_keys(source: &mut RespClient, pattern: &str) -> Result<Vec<String>> {
+    let mut keys = source.scan_match(pattern).collect().await?;
+    keys.sort();
+    Ok(keys)
+}
+
+/// Value and remaining TTL of each of `keys` on `source`, in one pipelined
+/// round trip; `None` for keys that are gone or don't hold a string
+pub(crate) async fn read_values(source: &mut RespClient, keys: &[String]) -> Result<Vec<Option<(String, Option<Duration>)>>> {
+    let reads: Vec<Vec<String>> = keys
+        .iter()
+        .flat_map(|key| [vec!["GET".to_string(), key.clone()], vec!["PTTL".to_string(), key.clone()]])
+        .collect();
+    let replies = source.pipeline(&reads).await?;
+    Ok(replies
+        .chunks(2)
+        .map(|reply| match reply {
+            [Response::StringOption(Some(value)), ttl] => {
+                let ttl = match ttl {
+                    Response::Integer(ttl) if *ttl > 0 => Some(Duration::from_millis(*ttl as u64)),
+                    _ => None,
+                };
+                Some((value.clone(), ttl))
+            }
+            _ => None,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::RustdisCache;
+    use crate::protocol::RustdisProtocol;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    /// RESP server over `cache` on an ephemeral port
+    async fn serve(cache: RustdisCache) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let protocol = Arc::new(RustdisProtocol::new(cache));
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let protocol = protocol.clone();
+                tokio::spawn(async move { crate::resp::serve_connection(&protocol, stream).await });
+            }
+        });
+        format!("redis://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn test_copy_keys() {
+        let from = RustdisCache::new();
+        let to = RustdisCache::new();
+        for (key, value) in [("session:1", "a"), ("session:2", "b"), ("session:3", "c"), ("user:1", "u")] {
+            from.set(key.to_string(), value.to_string()).unwrap();
+        }
+        from.expire("session:1", Duration::from_secs(60)).unwrap();
+        from.hset("session:h".to_string(), vec![("f".to_string(), "v".to_string())]).unwrap();
+        from.set_bytes("session:bin".to_string(), vec![0xff, 0x00, 0xfe]).unwrap();
+        to.set("session:2".to_string(), "keep".to_string()).unwrap();
+
+        let mut source = RespClient::connect(&serve(from.clone()).await).await.unwrap();
+        let mut destination = RespClient::connect(&serve(to.clone()).await).await.unwrap();
+        let options = CopyOptions { pattern: "session:*".to_string(), batch: 2, ..CopyOptions::default() };
+        let mut reports = 0;
+        let stats = copy_keys(&mut source, &mut destination, &options, |_| reports += 1).await.unwrap();
+
+        assert_eq!((stats.matched, stats.copied, stats.skipped, stats.failed), (5, 4, 1, 0));
+        assert!(reports >= 3);
+        assert_eq!(to.get("session:1").unwrap().as_deref(), Some("a"));
+        assert_eq!(to.get("session:2").unwrap().as_deref(), Some("keep"));
+        assert_eq!(to.get("user:1").unwrap(), None);
+        assert_eq!(to.hget("session:h", "f").unwrap().as_deref(), Some("v"));
+        assert_eq!(to.get_bytes("session:bin").unwrap(), Some(vec![0xff, 0x00, 0xfe]));
+        assert!(matches!(to.ttl("session:1").unwrap(), crate::cache::Ttl::Expires(ttl) if ttl > Duration::from_secs(50)));
+        assert!(matches!(to.ttl("session:h").unwrap(), crate::cache::Ttl::Persistent));
+
+        let overwrite = CopyOptions { overwrite: true, ..options };
+        let stats = copy_keys(&mut source, &mut destination, &overwrite, |_| {}).await.unwrap();
+        assert_eq!((stats.copied, stats.skipped), (5, 0));
+        assert_eq!(to.get("session:2").unwrap().as_deref(), Some("b"));
+    }
+}
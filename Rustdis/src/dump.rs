@@ -0,0 +1,307 @@
+use crate::blob::Blob;
+use crate::cache::{self, RustdisCache, Value};
+use crate::glob::glob_match;
+use crate::protocol::ValueEncoding;
+use crate::snapshot::SnapshotEntry;
+use crate::zset::SortedSet;
+use anyhow::Result;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+/// Version written in every JSON dump; `import` refuses any other
+pub const DUMP_VERSION: u32 = 1;
+
+/// The whole keyspace as written by `export`
+///
+/// ```json
+/// {"version": 1, "keys": [
+///   {"key": "user:1", "type": "string", "value": "ana", "expires_at": 1700000000000},
+///   {"key": "img:1", "type": "string", "value": "iVBORw==", "encoding": "base64"},
+///   {"key": "cart:1", "type": "hash", "value": {"sku": "42"}, "pinned": true},
+///   {"key": "rank", "type": "zset", "value": [["bia", 2.5], ["caio", "+inf"]]},
+///   {"key": "log", "type": "blob", "max_len": 1024, "value": "aGVsbG8="}
+/// ]}
+/// ```
+#[derive(Debug, Serialize, Deserialize)]
+struct DumpFile {
+    version: u32,
+    keys: Vec<DumpEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DumpEntry {
+    key: String,
+    #[serde(flatten)]
+    value: DumpValue,
+    /// Unix time in milliseconds at which the key expires
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pinned: bool,
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    meta: IndexMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum DumpValue {
+    /// Text as is; values that aren't UTF-8 in base64
+    String {
+        value: String,
+        #[serde(default, skip_serializing_if = "ValueEncoding::is_utf8")]
+        encoding: ValueEncoding,
+    },
+    Hash { value: IndexMap<String, String> },
+    Zset { value: Vec<(String, Score)> },
+    /// Contents in base64
+    Blob { max_len: usize, value: String },
+}
+
+/// A score as a JSON number, or `"+inf"`/`"-inf"`, which JSON numbers can't
+/// hold
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Score {
+    Number(f64),
+    Infinite(String),
+}
+
+impl From<f64> for Score {
+    fn from(score: f64) -> Self {
+        match score {
+            f64::INFINITY => Score::Infinite("+inf".to_string()),
+            f64::NEG_INFINITY => Score::Infinite("-inf".to_string()),
+            score => Score::Number(score),
+        }
+    }
+}
+
+impl TryFrom<Score> for f64 {
+    type Error = anyhow::Error;
+
+    fn try_from(score: Score) -> Result<Self> {
+        match score {
+            Score::Number(score) => Ok(score),
+            Score::Infinite(text) => match text.as_str() {
+                "+inf" | "inf" => Ok(f64::INFINITY),
+                "-inf" => Ok(f64::NEG_INFINITY),
+                _ => Err(anyhow::anyhow!("Invalid score in dump: {}", text)),
+            },
+        }
+    }
+}
+
+impl From<SnapshotEntry> for DumpEntry {
+    fn from(entry: SnapshotEntry) -> Self {
+        let value = match entry.value {
+            Value::String(bytes) => match String::from_utf8(bytes) {
+                Ok(value) => DumpValue::String { value, encoding: ValueEncoding::Utf8 },
+                Err(e) => DumpValue::String { value: BASE64.encode(e.into_bytes()), encoding: ValueEncoding::Base64 },
+            },
+            Value::Hash(fields) => DumpValue::Hash { value: fields },
+            Value::SortedSet(set) => DumpValue::Zset { value: set.iter().map(|(member, score)| (member.to_string(), score.into())).collect() },
+            Value::Blob(blob) => DumpValue::Blob { max_len: blob.max_len(), value: BASE64.encode(blob.read(0, blob.len())) },
+        };
+        DumpEntry { key: entry.key, value, expires_at: entry.expires_at, pinned: entry.pinned, meta: entry.meta.into_iter().collect() }
+    }
+}
+
+impl TryFrom<DumpEntry> for SnapshotEntry {
+    type Error = anyhow::Error;
+
+    fn try_from(entry: DumpEntry) -> Result<Self> {
+        let invalid_base64 = |_| anyhow::anyhow!("Value of {} is not valid base64", entry.key);
+        let value = match entry.value {
+            DumpValue::String { value, encoding: ValueEncoding::Utf8 } => Value::String(value.into_bytes()),
+            DumpValue::String { value, encoding: ValueEncoding::Base64 } => Value::String(BASE64.decode(value).map_err(invalid_base64)?),
+            DumpValue::Hash { value } => Value::Hash(value),
+            DumpValue::Zset { value } => {
+                let mut set = SortedSet::new();
+                for (member, score) in value {
+                    set.insert(member, score.try_into()?);
+                }
+                Value::SortedSet(set)
+            }
+            DumpValue::Blob { max_len, value } => {
+                let mut blob = Blob::new(max_len);
+                blob.append(&BASE64.decode(value).map_err(invalid_base64)?)?;
+                Value::Blob(blob)
+            }
+        };
+        Ok(SnapshotEntry { key: entry.key, value, expires_at: entry.expires_at, pinned: entry.pinned, meta: entry.meta.into_iter().collect() })
+    }
+}
+
+/// Moves keys under one prefix to another on import
+///
+/// Written `FROM*=TO*`, e.g. `stage:*=prod:*` turns `stage:user:1` into
+/// `prod:user:1`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyRemap {
+    pub from: String,
+    pub to: String,
+}
+
+impl KeyRemap {
+    /// `key` under the new prefix, `None` if it isn't under `from`
+    pub fn apply(&self, key: &str) -> Option<String> {
+        key.strip_prefix(&self.from).map(|rest| format!("{}{}", self.to, rest))
+    }
+}
+
+impl FromStr for KeyRemap {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow::anyhow!("Invalid remap: expected FROM*=TO*, e.g. stage:*=prod:*");
+        let (from, to) = s.split_once('=').ok_or_else(invalid)?;
+        let (from, to) = (from.strip_suffix('*').ok_or_else(invalid)?, to.strip_suffix('*').ok_or_else(invalid)?);
+        Ok(Self { from: from.to_string(), to: to.to_string() })
+    }
+}
+
+impl fmt::Display for KeyRemap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}*={}*", self.from, self.to)
+    }
+}
+
+/// How `import` applies a dump
+#[derive(Debug, Clone, Default)]
+pub struct ImportOptions {
+    /// Applied to each key in turn, the first that matches winning; keys no
+    /// rule matches keep their name
+    pub remap: Vec<KeyRemap>,
+    /// Multiplies the TTL each key has left, e.g. 0.5 halves it
+    pub ttl_scale: Option<f64>,
+    /// Keeps the keys already in the cache, replacing only those the dump
+    /// holds, instead of replacing the whole keyspace
+    pub merge: bool,
+}
+
+/// Writes every live key of `cache` to `out` as indented JSON, or those
+/// `pattern` matches, returning the number of keys written
+pub fn export(cache: &RustdisCache, pattern: Option<&str>, mut out: impl Write) -> Result<usize> {
+    let keys: Vec<DumpEntry> = cache
+        .snapshot()?
+        .into_iter()
+        .filter(|entry| pattern.is_none_or(|pattern| glob_match(pattern, &entry.key)))
+        .map(DumpEntry::from)
+        .collect();
+    let written = keys.len();
+    serde_json::to_writer_pretty(&mut out, &DumpFile { version: DUMP_VERSION, keys })?;
+    writeln!(out)?;
+    out.flush()?;
+    Ok(written)
+}
+
+/// Replaces the keyspace of `cache` with a dump written by `export`, or
+/// merges it in, returning the number of keys loaded
+///
+/// The whole dump is checked before the keyspace is touched. As when
+/// loading a snapshot, keys that expired since the export are skipped.
+pub fn import(cache: &RustdisCache, options: &ImportOptions, input: impl Read) -> Result<usize> {
+    let dump: DumpFile = serde_json::from_reader(input)?;
+    if dump.version != DUMP_VERSION {
+        return Err(anyhow::anyhow!("Unsupported dump version {}", dump.version));
+    }
+    if options.ttl_scale.is_some_and(|scale| !(scale.is_finite() && scale > 0.0)) {
+        return Err(anyhow::anyhow!("TTL scale must be a positive number"));
+    }
+    let now = cache::unix_millis();
+    let mut entries = dump.keys.into_iter().map(SnapshotEntry::try_from).collect::<Result<Vec<_>>>()?;
+    for entry in &mut entries {
+        if let Some(key) = options.remap.iter().find_map(|remap| remap.apply(&entry.key)) {
+            entry.key = key;
+        }
+        if let (Some(scale), Some(deadline)) = (options.ttl_scale, entry.expires_at) {
+            // Keys that already expired stay expired
+            entry.expires_at = Some(now + (deadline.saturating_sub(now) as f64 * scale) as u64);
+        }
+    }
+    if !options.merge {
+        return cache.restore(entries);
+    }
+
+    let imported: HashSet<&str> = entries.iter().map(|entry| entry.key.as_str()).collect();
+    let mut merged: Vec<SnapshotEntry> = cache.snapshot()?.into_iter().filter(|entry| !imported.contains(entry.key.as_str())).collect();
+    let kept = merged.len();
+    merged.extend(entries);
+    Ok(cache.restore(merged)?.saturating_sub(kept))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_dump_round_trip() {
+        let cache = RustdisCache::new();
+        cache.set("user:1".to_string(), "ana".to_string()).unwrap();
+        cache.expire("user:1", Duration::from_secs(60)).unwrap();
+        cache.set_bytes("img:1".to_string(), vec![0x89, 0x50, 0xff]).unwrap();
+        cache.meta_set("img:1", vec![("content-type".to_string(), "image/png".to_string())]).unwrap();
+        cache.hset("cart:1".to_string(), vec![("sku".to_string(), "42".to_string())]).unwrap();
+        cache.pin("cart:1").unwrap();
+        cache.zadd("rank".to_string(), vec![("bia".to_string(), 2.5), ("caio".to_string(), f64::INFINITY)]).unwrap();
+        cache.blob_create("log".to_string(), 1024).unwrap();
+        cache.blob_append("log", b"hello").unwrap();
+
+        let mut out = Vec::new();
+        assert_eq!(export(&cache, None, &mut out).unwrap(), 5);
+        let json = String::from_utf8(out).unwrap();
+        assert!(json.contains(r#""value": "ana""#));
+        assert!(json.contains(r#""encoding": "base64""#));
+        assert!(json.contains(r#""+inf""#));
+
+        let imported = RustdisCache::new();
+        imported.set("stale".to_string(), "x".to_string()).unwrap();
+        assert_eq!(import(&imported, &ImportOptions::default(), json.as_bytes()).unwrap(), 5);
+        assert!(matches!(imported.ttl("user:1").unwrap(), crate::cache::Ttl::Expires(ttl) if ttl > Duration::from_secs(50)));
+        // Expiry times are rebased on the clock at each step, so may be off
+        // by a millisecond
+        let without_expiry = |cache: &RustdisCache| {
+            cache.snapshot().unwrap().into_iter().map(|entry| SnapshotEntry { expires_at: None, ..entry }).collect::<Vec<_>>()
+        };
+        assert_eq!(without_expiry(&imported), without_expiry(&cache));
+
+        let future = json.replacen(r#""version": 1"#, r#""version": 2"#, 1);
+        assert!(import(&imported, &ImportOptions::default(), future.as_bytes()).is_err());
+        assert_eq!(imported.size().unwrap(), 5);
+    }
+
+    #[test]
+    fn test_namespace_promotion() {
+        let stage = RustdisCache::new();
+        stage.set("stage:user:1".to_string(), "ana".to_string()).unwrap();
+        stage.expire("stage:user:1", Duration::from_secs(100)).unwrap();
+        stage.hset("stage:cart:1".to_string(), vec![("sku".to_string(), "42".to_string())]).unwrap();
+        stage.set("other".to_string(), "x".to_string()).unwrap();
+        let mut out = Vec::new();
+        assert_eq!(export(&stage, Some("stage:*"), &mut out).unwrap(), 2);
+
+        let prod = RustdisCache::new();
+        prod.set("prod:user:1".to_string(), "old".to_string()).unwrap();
+        prod.set("prod:keep".to_string(), "y".to_string()).unwrap();
+        let options = ImportOptions { remap: vec!["stage:*=prod:*".parse().unwrap()], ttl_scale: Some(0.5), merge: true };
+        assert_eq!(import(&prod, &options, out.as_slice()).unwrap(), 2);
+
+        let mut keys = prod.keys().unwrap();
+        keys.sort();
+        assert_eq!(keys, ["prod:cart:1", "prod:keep", "prod:user:1"]);
+        assert_eq!(prod.get("prod:user:1").unwrap().as_deref(), Some("ana"));
+        assert!(matches!(prod.ttl("prod:user:1").unwrap(), crate::cache::Ttl::Expires(ttl) if ttl <= Duration::from_secs(50)));
+        assert!(matches!(prod.ttl("prod:cart:1").unwrap(), crate::cache::Ttl::Persistent));
+
+        assert!("stage:=prod:".parse::<KeyRemap>().is_err());
+        let invalid = ImportOptions { ttl_scale: Some(0.0), ..ImportOptions::default() };
+        assert!(import(&prod, &invalid, out.as_slice()).is_err());
+    }
+}
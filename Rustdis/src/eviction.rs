@@ -0,0 +1,229 @@
+use anyhow::Result;
+use rand::Rng;
+use std::fmt;
+use std::str::FromStr;
+
+/// Default number of keys sampled per eviction, as in Redis
+pub const DEFAULT_SAMPLES: usize = 5;
+
+/// LFU counter of a new key, so it isn't the first to go before it had a
+/// chance to be read, as in Redis
+pub const LFU_INIT_VAL: u8 = 5;
+
+/// How much harder each LFU counter increment gets; with 10, as in Redis,
+/// the counter saturates around a million accesses
+const LFU_LOG_FACTOR: f64 = 10.0;
+
+/// Idle time that takes one off an LFU counter (Redis' `lfu-decay-time 1`)
+pub const LFU_DECAY_MS: u64 = 60_000;
+
+/// Candidates kept between evictions
+const POOL_SIZE: usize = 16;
+
+/// Redis-style eviction pool for sampled eviction
+///
+/// Each eviction samples a few random keys and offers them to the pool,
+/// which keeps the best candidates seen so far (highest score, e.g. idle
+/// time for LRU). Carrying candidates over between evictions makes the
+/// approximation much closer to true LRU than picking the best of a single
+/// sample. Scores go stale as keys are accessed, so callers must re-check
+/// a popped candidate before evicting it.
+#[derive(Debug, Default)]
+pub struct EvictionPool {
+    /// Sorted by ascending score; the best candidate is last
+    candidates: Vec<(u64, String)>,
+}
+
+impl EvictionPool {
+    /// Offers a sampled key with its eviction score
+    pub fn offer(&mut self, score: u64, key: &str) {
+        if let Some(existing) = self.candidates.iter().position(|(_, k)| k == key) {
+            self.candidates.remove(existing);
+        }
+        if self.candidates.len() >= POOL_SIZE {
+            if self.candidates[0].0 >= score {
+                return;
+            }
+            self.candidates.remove(0);
+        }
+        let at = self.candidates.partition_point(|(s, _)| *s <= score);
+        self.candidates.insert(at, (score, key.to_string()));
+    }
+
+    /// Takes the best candidate out of the pool
+    pub fn pop(&mut self) -> Option<(u64, String)> {
+        self.candidates.pop()
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.candidates.clear();
+    }
+}
+
+/// Which keys maxmemory evicts, and in what order
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Least recently used among all keys
+    #[default]
+    AllKeysLru,
+    /// Least recently used among keys with a TTL
+    VolatileLru,
+    /// Least frequently used among all keys
+    AllKeysLfu,
+    /// Least frequently used among keys with a TTL
+    VolatileLfu,
+    /// Nearest expiration first, among keys with a TTL
+    VolatileTtl,
+    /// Any key, at random
+    AllKeysRandom,
+    /// Any key with a TTL, at random
+    VolatileRandom,
+    /// Nothing is evicted; writes that don't fit fail with OOM
+    NoEviction,
+}
+
+impl EvictionPolicy {
+    /// Whether only keys with a TTL may be evicted
+    pub fn is_volatile(self) -> bool {
+        matches!(
+            self,
+            EvictionPolicy::VolatileLru | EvictionPolicy::VolatileLfu | EvictionPolicy::VolatileTtl | EvictionPolicy::VolatileRandom
+        )
+    }
+
+    pub fn is_lfu(self) -> bool {
+        matches!(self, EvictionPolicy::AllKeysLfu | EvictionPolicy::VolatileLfu)
+    }
+
+    pub fn is_random(self) -> bool {
+        matches!(self, EvictionPolicy::AllKeysRandom | EvictionPolicy::VolatileRandom)
+    }
+}
+
+impl FromStr for EvictionPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "allkeys-lru" => Ok(EvictionPolicy::AllKeysLru),
+            "volatile-lru" => Ok(EvictionPolicy::VolatileLru),
+            "allkeys-lfu" => Ok(EvictionPolicy::AllKeysLfu),
+            "volatile-lfu" => Ok(EvictionPolicy::VolatileLfu),
+            "volatile-ttl" => Ok(EvictionPolicy::VolatileTtl),
+            "allkeys-random" => Ok(EvictionPolicy::AllKeysRandom),
+            "volatile-random" => Ok(EvictionPolicy::VolatileRandom),
+            "noeviction" => Ok(EvictionPolicy::NoEviction),
+            _ => Err(anyhow::anyhow!(
+                "Unknown maxmemory-policy: {} (expected allkeys-lru, volatile-lru, allkeys-lfu, volatile-lfu, \
+                 volatile-ttl, allkeys-random, volatile-random or noeviction)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for EvictionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvictionPolicy::AllKeysLru => write!(f, "allkeys-lru"),
+            EvictionPolicy::VolatileLru => write!(f, "volatile-lru"),
+            EvictionPolicy::AllKeysLfu => write!(f, "allkeys-lfu"),
+            EvictionPolicy::VolatileLfu => write!(f, "volatile-lfu"),
+            EvictionPolicy::VolatileTtl => write!(f, "volatile-ttl"),
+            EvictionPolicy::AllKeysRandom => write!(f, "allkeys-random"),
+            EvictionPolicy::VolatileRandom => write!(f, "volatile-random"),
+            EvictionPolicy::NoEviction => write!(f, "noeviction"),
+        }
+    }
+}
+
+/// LFU counter after one more access
+///
+/// The counter is logarithmic, as in Redis: past `LFU_INIT_VAL`, each
+/// increment is less likely than the last, so 8 bits tell a key read a
+/// handful of times from one read millions of times.
+pub fn lfu_increment(counter: u8) -> u8 {
+    if counter == u8::MAX {
+        return counter;
+    }
+    let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+    let p = 1.0 / (base * LFU_LOG_FACTOR + 1.0);
+    if rand::thread_rng().gen::<f64>() < p {
+        counter + 1
+    } else {
+        counter
+    }
+}
+
+/// LFU counter after `idle_ms` without access: one less per `LFU_DECAY_MS`,
+/// so keys that were hot once don't stay ahead of ones that are hot now
+pub fn lfu_decay(counter: u8, idle_ms: u64) -> u8 {
+    let periods = (idle_ms / LFU_DECAY_MS).min(u8::MAX as u64) as u8;
+    counter.saturating_sub(periods)
+}
+
+/// Parses a memory size like `1048576`, `512kb`, `100mb` or `2gb`
+pub fn parse_memory(value: &str) -> Option<usize> {
+    let lower = value.trim().to_lowercase();
+    let (digits, unit) = match lower.find(|c: char| !c.is_ascii_digit()) {
+        Some(at) => lower.split_at(at),
+        None => (lower.as_str(), ""),
+    };
+    let multiplier = match unit {
+        "" | "b" => 1,
+        "k" | "kb" => 1024,
+        "m" | "mb" => 1024 * 1024,
+        "g" | "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    digits.parse::<usize>().ok()?.checked_mul(multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool_keeps_best_candidates() {
+        let mut pool = EvictionPool::default();
+        for score in 0..40u64 {
+            pool.offer(score, &format!("key{}", score));
+        }
+        assert_eq!(pool.len(), POOL_SIZE);
+        pool.offer(5, "key39");
+        assert_eq!(pool.pop(), Some((38, "key38".to_string())));
+
+        assert_eq!(parse_memory("100mb"), Some(100 * 1024 * 1024));
+        assert_eq!(parse_memory("42"), Some(42));
+        assert_eq!(parse_memory("lots"), None);
+    }
+
+    #[test]
+    fn test_lfu_counter() {
+        let mut counter = LFU_INIT_VAL;
+        for _ in 0..100 {
+            counter = lfu_increment(counter);
+        }
+        // Logarithmic: a hundred accesses only add a few points
+        assert!(counter > LFU_INIT_VAL && counter < 30, "{}", counter);
+        assert_eq!(lfu_increment(u8::MAX), u8::MAX);
+        assert_eq!(lfu_decay(counter, LFU_DECAY_MS - 1), counter);
+        assert_eq!(lfu_decay(counter, 3 * LFU_DECAY_MS), counter - 3);
+        assert_eq!(lfu_decay(counter, u64::MAX), 0);
+
+        assert_eq!("ALLKEYS-LFU".parse::<EvictionPolicy>().unwrap(), EvictionPolicy::AllKeysLfu);
+        assert!("volatile-lfu".parse::<EvictionPolicy>().unwrap().is_volatile());
+        assert!("volatile-ttl".parse::<EvictionPolicy>().unwrap().is_volatile());
+        assert!("allkeys-random".parse::<EvictionPolicy>().unwrap().is_random());
+        assert_eq!("noeviction".parse::<EvictionPolicy>().unwrap().to_string(), "noeviction");
+        assert!("lifo".parse::<EvictionPolicy>().is_err());
+    }
+}
@@ -0,0 +1,149 @@
+use crate::cache::{unix_millis, RustdisCache};
+use anyhow::Result;
+use std::fmt;
+use std::str::FromStr;
+
+/// Hash holding every flag: field is the flag name, value its state
+pub const FLAGS_KEY: &str = "rustdis:flags";
+
+/// Sorted set of flag changes, scored by Unix time in milliseconds; members
+/// read `<ms> <name> <state>`, so they can be listed with ZRANGE
+pub const FLAG_AUDIT_KEY: &str = "rustdis:flags:audit";
+
+/// Audit events kept; older ones are dropped as new ones arrive
+pub const MAX_FLAG_AUDIT: usize = 1000;
+
+/// State of a feature flag
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum FlagState {
+    On,
+    Off,
+    /// On for this percentage (0-100) of subjects
+    Percent(u8),
+}
+
+impl FlagState {
+    /// Whether `subject` sees the flag `name` as on
+    ///
+    /// Subjects are bucketed by a stable hash of the flag name and subject,
+    /// so a subject keeps its answer across checks and servers, and raising
+    /// the percentage only ever turns subjects on.
+    pub fn enabled_for(&self, name: &str, subject: &str) -> bool {
+        match *self {
+            FlagState::On => true,
+            FlagState::Off => false,
+            FlagState::Percent(percent) => bucket(name, subject) < u64::from(percent),
+        }
+    }
+}
+
+impl FromStr for FlagState {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "on" => Ok(FlagState::On),
+            "off" => Ok(FlagState::Off),
+            other => match other.strip_suffix('%').unwrap_or(other).parse() {
+                Ok(percent) if percent <= 100 => Ok(FlagState::Percent(percent)),
+                _ => Err(anyhow::anyhow!("Invalid flag state: {} (expected on, off or a percentage from 0 to 100)", s)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for FlagState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlagState::On => write!(f, "on"),
+            FlagState::Off => write!(f, "off"),
+            FlagState::Percent(percent) => write!(f, "{}", percent),
+        }
+    }
+}
+
+impl TryFrom<String> for FlagState {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<FlagState> for String {
+    fn from(state: FlagState) -> Self {
+        state.to_string()
+    }
+}
+
+/// FLAG.SET - stores the state of `name` and records an audit event
+pub fn set(cache: &RustdisCache, name: &str, state: FlagState) -> Result<()> {
+    cache.hset(FLAGS_KEY.to_string(), vec![(name.to_string(), state.to_string())])?;
+    let now = unix_millis();
+    cache.zadd(FLAG_AUDIT_KEY.to_string(), vec![(format!("{} {} {}", now, name, state), now as f64)])?;
+    let excess = cache.zcard(FLAG_AUDIT_KEY)?.saturating_sub(MAX_FLAG_AUDIT);
+    if excess > 0 {
+        let oldest: Vec<String> = cache.zrange(FLAG_AUDIT_KEY, 0, excess as i64 - 1, false)?.into_iter().map(|(member, _)| member).collect();
+        cache.zrem(FLAG_AUDIT_KEY, &oldest)?;
+    }
+    Ok(())
+}
+
+/// FLAG.CHECK - whether `name` is on for `subject`; unknown flags are off
+pub fn check(cache: &RustdisCache, name: &str, subject: &str) -> Result<bool> {
+    match cache.hget(FLAGS_KEY, name)? {
+        Some(state) => Ok(state.parse::<FlagState>()?.enabled_for(name, subject)),
+        None => Ok(false),
+    }
+}
+
+/// FLAG.LIST - every flag and its state, in the order they were first set
+pub fn list(cache: &RustdisCache) -> Result<Vec<(String, FlagState)>> {
+    cache
+        .hgetall(FLAGS_KEY)?
+        .into_iter()
+        .map(|(name, state)| Ok((name, state.parse()?)))
+        .collect()
+}
+
+/// Bucket 0-99 of `subject` for the flag `name`
+///
+/// FNV-1a rather than the std hasher, whose output may change between Rust
+/// releases and would reshuffle subjects on upgrade.
+fn bucket(name: &str, subject: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in name.bytes().chain([0]).chain(subject.bytes()) {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash % 100
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags() {
+        let cache = RustdisCache::new();
+        set(&cache, "new-checkout", FlagState::On).unwrap();
+        set(&cache, "dark-mode", "25%".parse().unwrap()).unwrap();
+        assert!(check(&cache, "new-checkout", "user:1").unwrap());
+        assert!(!check(&cache, "missing", "user:1").unwrap());
+        assert!("101".parse::<FlagState>().is_err());
+        assert!("maybe".parse::<FlagState>().is_err());
+
+        // Roughly a quarter of subjects, each answered the same every time
+        let enabled = (0..1000).filter(|i| check(&cache, "dark-mode", &format!("user:{}", i)).unwrap()).count();
+        assert!((150..350).contains(&enabled), "{} of 1000 enabled", enabled);
+        let subject = (0..1000).map(|i| format!("user:{}", i)).find(|subject| check(&cache, "dark-mode", subject).unwrap()).unwrap();
+        set(&cache, "dark-mode", FlagState::Percent(50)).unwrap();
+        assert!(check(&cache, "dark-mode", &subject).unwrap());
+
+        assert_eq!(list(&cache).unwrap(), [("new-checkout".to_string(), FlagState::On), ("dark-mode".to_string(), FlagState::Percent(50))]);
+        let audit = cache.zrange(FLAG_AUDIT_KEY, 0, -1, false).unwrap();
+        assert_eq!(audit.len(), 3);
+        assert!(audit.iter().any(|(event, _)| event.ends_with(" dark-mode 50")));
+    }
+}
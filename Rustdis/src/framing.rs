@@ -0,0 +1,160 @@
+use crate::protocol::{Response, RustdisProtocol};
+use anyhow::Result;
+use std::io::{ErrorKind, Read, Write};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+/// Handshake sent by both sides when a connection opts into framing
+pub const MAGIC: &[u8; 4] = b"RDF1";
+
+/// Largest accepted frame payload
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+/// Writes one frame: a big-endian u32 payload length followed by the payload
+pub fn write_frame<W: Write>(writer: &mut W, payload: &[u8]) -> Result<()> {
+    if payload.len() > MAX_FRAME_LEN {
+        return Err(anyhow::anyhow!("Frame of {} bytes exceeds the {} byte limit", payload.len(), MAX_FRAME_LEN));
+    }
+    writer.write_all(&(payload.len() as u32).to_be_bytes())?;
+    writer.write_all(payload)?;
+    Ok(())
+}
+
+/// Reads one frame, returning `None` on a clean end of stream
+pub fn read_frame<R: Read>(reader: &mut R) -> Result<Option<Vec<u8>>> {
+    let mut header = [0u8; 4];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let mut payload = vec![0u8; frame_len(header)?];
+    reader.read_exact(&mut payload)?;
+    Ok(Some(payload))
+}
+
+/// `read_frame` for the server's async connections
+async fn read_frame_async<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<Vec<u8>>> {
+    let mut header = [0u8; 4];
+    match reader.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let mut payload = vec![0u8; frame_len(header)?];
+    reader.read_exact(&mut payload).await?;
+    Ok(Some(payload))
+}
+
+fn frame_len(header: [u8; 4]) -> Result<usize> {
+    let len = u32::from_be_bytes(header) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(anyhow::anyhow!("Frame of {} bytes exceeds the {} byte limit", len, MAX_FRAME_LEN));
+    }
+    Ok(len)
+}
+
+/// Client side of the handshake: send the magic and expect it echoed back
+pub fn negotiate_client<R: Read, W: Write>(reader: &mut R, writer: &mut W) -> Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.flush()?;
+    expect_magic(reader)
+}
+
+/// Server side of the handshake: expect the magic and echo it back
+pub async fn negotiate_server<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(reader: &mut R, writer: &mut W) -> Result<()> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic).await?;
+    check_magic(&magic)?;
+    writer.write_all(MAGIC).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+fn expect_magic<R: Read>(reader: &mut R) -> Result<()> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    check_magic(&magic)
+}
+
+fn check_magic(magic: &[u8; 4]) -> Result<()> {
+    if magic != MAGIC {
+        return Err(anyhow::anyhow!("Peer does not speak the framed protocol"));
+    }
+    Ok(())
+}
+
+/// Serves framed CBOR commands until the peer closes the stream
+///
+/// Each frame carries one CBOR command envelope and is answered by one
+/// CBOR response frame, in order. Replies are only flushed once every
+/// already-buffered request has been handled, so pipelined batches cost
+/// one write per batch rather than one per command.
+pub async fn serve<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(protocol: &RustdisProtocol, reader: &mut BufReader<R>, writer: &mut W) -> Result<()> {
+    negotiate_server(reader, writer).await?;
+
+    let mut frame = Vec::new();
+    while let Some(payload) = read_frame_async(reader).await? {
+        #[cfg(feature = "chaos")]
+        protocol.faults().wait().await;
+        let response = match RustdisProtocol::parse_command_cbor(&payload) {
+            Ok(command) => protocol.execute(command),
+            Err(e) => Response::from(e),
+        };
+        #[cfg(feature = "chaos")]
+        if protocol.faults().drop_reply() {
+            continue;
+        }
+        frame.clear();
+        write_frame(&mut frame, &RustdisProtocol::response_to_cbor(&response)?)?;
+        writer.write_all(&frame).await?;
+
+        if reader.buffer().is_empty() {
+            writer.flush().await?;
+        }
+    }
+    writer.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::RustdisCache;
+    use std::io::Cursor;
+
+    fn cbor(value: serde_json::Value) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(&value, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[tokio::test]
+    async fn test_framed_pipeline() {
+        let cache = RustdisCache::new();
+        let protocol = RustdisProtocol::new(cache.clone());
+
+        let mut input = MAGIC.to_vec();
+        write_frame(&mut input, &cbor(serde_json::json!({"command": "SET", "args": {"key": "a", "value": "1"}}))).unwrap();
+        write_frame(&mut input, &cbor(serde_json::json!({"command": "GET", "args": {"key": "a"}}))).unwrap();
+
+        let mut output = Vec::new();
+        serve(&protocol, &mut BufReader::new(Cursor::new(input)), &mut output).await.unwrap();
+
+        let mut replies = Cursor::new(output);
+        negotiate_client(&mut replies, &mut Vec::new()).unwrap();
+        let set: Response = ciborium::from_reader(read_frame(&mut replies).unwrap().unwrap().as_slice()).unwrap();
+        let get: Response = ciborium::from_reader(read_frame(&mut replies).unwrap().unwrap().as_slice()).unwrap();
+        assert!(matches!(set, Response::String(ref s) if s == "OK"));
+        assert!(matches!(get, Response::String(ref s) if s == "1"));
+        assert!(read_frame(&mut replies).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_missing_handshake() {
+        let protocol = RustdisProtocol::new(RustdisCache::new());
+        let mut reader = BufReader::new(Cursor::new(b"GET a\r\n".to_vec()));
+        assert!(serve(&protocol, &mut reader, &mut Vec::new()).await.is_err());
+    }
+}
@@ -0,0 +1,311 @@
+use crate::eviction;
+use crate::protocol::Response;
+use anyhow::Result;
+use rhai::{Array, CallFnOptions, Dynamic, Engine, EvalAltResult, FnAccess, Map, Position, Scope, AST};
+use std::any::TypeId;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+/// First line of every library, naming it: `#!rhai name=<library>`
+const LIBRARY_HEADER: &str = "#!rhai name=";
+
+/// Most arguments `command` accepts, command name included
+const MAX_CALL_ARGS: usize = 16;
+
+/// Budget of a single FCALL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FunctionLimits {
+    /// Rhai operations (roughly, expressions evaluated) before the call is
+    /// stopped; 0 for no limit
+    pub max_operations: u64,
+    /// Largest string, in bytes, the call may build; arrays and maps are
+    /// capped at as many items as fit in this many bytes. Rhai doesn't
+    /// track total memory, so this bounds each value rather than their sum.
+    /// 0 for no limit
+    pub max_memory: usize,
+}
+
+impl Default for FunctionLimits {
+    fn default() -> Self {
+        Self { max_operations: 1_000_000, max_memory: 64 * 1024 * 1024 }
+    }
+}
+
+/// A library registered with FUNCTION LOAD
+#[derive(Debug)]
+struct Library {
+    ast: Arc<AST>,
+    /// Functions callable with FCALL, i.e. the library's non-private ones
+    functions: Vec<String>,
+}
+
+/// Server-side functions written in Rhai (FUNCTION LOAD / FCALL)
+///
+/// Only compiled with the `functions` feature. A library is a Rhai script
+/// starting with `#!rhai name=<library>`; each of its non-private functions
+/// takes `(keys, args)`, two arrays of strings, and becomes callable by name
+/// with FCALL. Inside, `command("SET", key, value)` runs a command and returns
+/// its reply, or raises the command's error. Functions are kept in memory
+/// only; snapshots don't include them.
+#[derive(Debug, Default)]
+pub struct FunctionRegistry {
+    libraries: RwLock<BTreeMap<String, Library>>,
+    limits: RwLock<FunctionLimits>,
+}
+
+impl FunctionRegistry {
+    /// FUNCTION LOAD: compiles a library and registers its functions,
+    /// returning the library name
+    ///
+    /// Replacing an existing library requires `replace`; a function name
+    /// already taken by another library is refused either way.
+    pub fn load(&self, code: &str, replace: bool) -> Result<String> {
+        let (header, body) = code.split_once('\n').unwrap_or((code, ""));
+        let name = header
+            .trim_end()
+            .strip_prefix(LIBRARY_HEADER)
+            .filter(|name| !name.is_empty() && !name.contains(char::is_whitespace))
+            .ok_or_else(|| anyhow::anyhow!("Library code must start with {}<library>", LIBRARY_HEADER))?;
+        // The header line is kept blank so errors point at the right line
+        let ast = engine(self.limits()?)
+            .compile(format!("\n{}", body))
+            .map_err(|e| anyhow::anyhow!("Library {} doesn't compile: {}", name, e))?;
+        let mut functions = Vec::new();
+        for function in ast.iter_functions().filter(|function| function.access != FnAccess::Private) {
+            if function.params.len() != 2 {
+                return Err(anyhow::anyhow!("Function {} must take (keys, args); private helpers may take anything", function.name));
+            }
+            functions.push(function.name.to_string());
+        }
+        if functions.is_empty() {
+            return Err(anyhow::anyhow!("Library {} defines no function", name));
+        }
+
+        let mut libraries = self.libraries.write().map_err(|_| anyhow::anyhow!("Failed to acquire functions lock"))?;
+        if libraries.contains_key(name) && !replace {
+            return Err(anyhow::anyhow!("Library {} already exists", name));
+        }
+        let taken = libraries
+            .iter()
+            .filter(|(library, _)| library.as_str() != name)
+            .flat_map(|(library, loaded)| loaded.functions.iter().map(move |function| (library, function)))
+            .find(|(_, function)| functions.contains(function));
+        if let Some((library, function)) = taken {
+            return Err(anyhow::anyhow!("Function {} already exists in library {}", function, library));
+        }
+        libraries.insert(name.to_string(), Library { ast: Arc::new(ast), functions });
+        Ok(name.to_string())
+    }
+
+    /// FUNCTION DELETE: drops a library and its functions
+    pub fn delete(&self, library: &str) -> Result<()> {
+        let mut libraries = self.libraries.write().map_err(|_| anyhow::anyhow!("Failed to acquire functions lock"))?;
+        libraries
+            .remove(library)
+            .map(|_| ())
+            .ok_or_else(|| anyhow::anyhow!("No such library: {}", library))
+    }
+
+    /// FUNCTION LIST: every library and its callable functions, by name
+    pub fn list(&self) -> Result<BTreeMap<String, Vec<String>>> {
+        let libraries = self.libraries.read().map_err(|_| anyhow::anyhow!("Failed to acquire functions lock"))?;
+        Ok(libraries.iter().map(|(name, library)| (name.clone(), library.functions.clone())).collect())
+    }
+
+    /// FCALL: runs `function` with its keys and arguments, within the
+    /// current limits, returning its result as a reply
+    ///
+    /// `run` executes the commands the function sends with `command`; making
+    /// the whole call atomic is up to the caller.
+    pub fn call(
+        &self,
+        function: &str,
+        keys: Vec<String>,
+        args: Vec<String>,
+        run: impl Fn(Vec<String>) -> Response + Send + Sync + 'static,
+    ) -> Result<Response> {
+        let ast = {
+            let libraries = self.libraries.read().map_err(|_| anyhow::anyhow!("Failed to acquire functions lock"))?;
+            libraries
+                .values()
+                .find(|library| library.functions.iter().any(|name| name == function))
+                .map(|library| library.ast.clone())
+                .ok_or_else(|| anyhow::anyhow!("Function not found: {}", function))?
+        };
+        let mut engine = engine(self.limits()?);
+        let run = Arc::new(run);
+        for arity in 1..=MAX_CALL_ARGS {
+            let run = run.clone();
+            // `call` itself is taken by Rhai, for function pointers
+            engine.register_raw_fn("command", vec![TypeId::of::<Dynamic>(); arity], move |_, args| {
+                let reply = run(args.iter().map(|arg| arg.to_string()).collect());
+                to_dynamic(reply).map_err(|error| EvalAltResult::ErrorRuntime(error.into(), Position::NONE).into())
+            });
+        }
+
+        let strings = |values: Vec<String>| values.into_iter().map(Dynamic::from).collect::<Array>();
+        // Only the function runs, not the library's top-level statements
+        let options = CallFnOptions::new().eval_ast(false);
+        engine
+            .call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &ast, function, (strings(keys), strings(args)))
+            .map(to_response)
+            .map_err(|e| anyhow::anyhow!("Function {} failed: {}", function, e))
+    }
+
+    pub fn limits(&self) -> Result<FunctionLimits> {
+        Ok(*self.limits.read().map_err(|_| anyhow::anyhow!("Failed to acquire functions lock"))?)
+    }
+
+    /// Current limits as CONFIG GET parameters
+    pub(crate) fn parameters(&self) -> Result<BTreeMap<String, String>> {
+        let limits = self.limits()?;
+        let mut parameters = BTreeMap::new();
+        parameters.insert("function-max-operations".to_string(), limits.max_operations.to_string());
+        parameters.insert("function-max-memory".to_string(), limits.max_memory.to_string());
+        Ok(parameters)
+    }
+
+    /// Applies a `function-*` CONFIG SET parameter, returning false for other names
+    pub(crate) fn set_parameter(&self, parameter: &str, value: &str) -> Result<bool> {
+        let mut limits = self.limits.write().map_err(|_| anyhow::anyhow!("Failed to acquire functions lock"))?;
+        match parameter {
+            "function-max-operations" => {
+                limits.max_operations = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("function-max-operations must be a non-negative integer"))?;
+            }
+            "function-max-memory" => {
+                limits.max_memory = eviction::parse_memory(value)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid function-max-memory value: {}", value))?;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+/// A sandboxed engine enforcing `limits`: scripts can't reach files, the
+/// network or the process, and `print`/`debug` output is discarded
+fn engine(limits: FunctionLimits) -> Engine {
+    let mut engine = Engine::new();
+    let items = match limits.max_memory {
+        0 => 0,
+        bytes => (bytes / std::mem::size_of::<Dynamic>()).max(1),
+    };
+    engine
+        .set_max_operations(limits.max_operations)
+        .set_max_string_size(limits.max_memory)
+        .set_max_array_size(items)
+        .set_max_map_size(items)
+        .on_print(|_| {})
+        .on_debug(|_, _, _| {});
+    engine
+}
+
+/// A command reply as a Rhai value; error replies become the error message
+fn to_dynamic(response: Response) -> Result<Dynamic, String> {
+    Ok(match response {
+        Response::Ok => Dynamic::from("OK".to_string()),
+        Response::String(value) | Response::StringOption(Some(value)) => Dynamic::from(value),
+        Response::StringOption(None) => Dynamic::UNIT,
+        Response::Bytes(value) => Dynamic::from_blob(value),
+        Response::Boolean(value) => Dynamic::from(value),
+        Response::Number(value) => Dynamic::from(value as i64),
+        Response::Integer(value) => Dynamic::from(value),
+        Response::Float(value) => Dynamic::from(value),
+        Response::StringArray(items) => Dynamic::from(items.into_iter().map(Dynamic::from).collect::<Array>()),
+        Response::Array(items) => Dynamic::from(items.into_iter().map(to_dynamic).collect::<Result<Array, _>>()?),
+        Response::Map(entries) => Dynamic::from(
+            entries
+                .into_iter()
+                .map(|(name, value)| Ok((name.into(), to_dynamic(value)?)))
+                .collect::<Result<Map, String>>()?,
+        ),
+        Response::Error { error, code } => return Err(format!("{} {}", code.as_deref().unwrap_or("ERR"), error)),
+    })
+}
+
+/// A function's result as a reply: `()` is nil, strings are bulk strings
+fn to_response(value: Dynamic) -> Response {
+    if value.is_unit() {
+        Response::StringOption(None)
+    } else if let Ok(value) = value.as_bool() {
+        Response::Boolean(value)
+    } else if let Ok(value) = value.as_int() {
+        Response::Integer(value)
+    } else if let Ok(value) = value.as_float() {
+        Response::Float(value)
+    } else if value.is_array() {
+        Response::Array(value.cast::<Array>().into_iter().map(to_response).collect())
+    } else if value.is_map() {
+        Response::Map(value.cast::<Map>().into_iter().map(|(name, value)| (name.to_string(), to_response(value))).collect())
+    } else {
+        Response::StringOption(Some(value.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::RustdisCache;
+    use crate::protocol::{Response, RustdisProtocol};
+
+    const LIBRARY: &str = r#"#!rhai name=stock
+fn reserve(keys, args) {
+    let left = command("GET", keys[0]);
+    if left == () || parse_int(left) < parse_int(args[0]) {
+        return false;
+    }
+    command("DECRBY", keys[0], args[0])
+}
+fn spin(keys, args) {
+    loop {}
+}
+fn grow(keys, args) {
+    let value = "x";
+    loop { value += value; }
+}
+fn flush(keys, args) {
+    command("FLUSH")
+}
+fn touch(keys, args) {
+    command("HSET", keys[0], "field", "value")
+}
+private fn helper(value) {
+    value
+}
+"#;
+
+    #[test]
+    fn test_functions() {
+        let protocol = RustdisProtocol::new(RustdisCache::new());
+        assert!(matches!(protocol.execute_args(&["FUNCTION", "LOAD", "fn f(keys, args) {}"]), Response::Error { .. }));
+        assert!(matches!(protocol.execute_args(&["FUNCTION", "LOAD", "#!rhai name=bad\nfn f(key) {}"]), Response::Error { .. }));
+        assert!(matches!(protocol.execute_args(&["FUNCTION", "LOAD", LIBRARY]), Response::StringOption(Some(ref name)) if name == "stock"));
+        assert!(matches!(protocol.execute_args(&["FUNCTION", "LOAD", LIBRARY]), Response::Error { .. }));
+        assert!(matches!(protocol.execute_args(&["FUNCTION", "LOAD", "REPLACE", LIBRARY]), Response::StringOption(_)));
+        // Function names are global across libraries
+        let clash = LIBRARY.replace("name=stock", "name=other");
+        assert!(matches!(protocol.execute_args(&["FUNCTION", "LOAD", &clash]), Response::Error { .. }));
+        let Response::Map(libraries) = protocol.execute_args(&["FUNCTION", "LIST"]) else {
+            panic!("FUNCTION LIST must reply with a map");
+        };
+        assert!(matches!(&libraries["stock"], Response::StringArray(functions) if functions.len() == 5 && !functions.contains(&"helper".to_string())));
+
+        protocol.execute_args(&["SET", "item:1", "5"]);
+        assert!(matches!(protocol.execute_args(&["FCALL", "reserve", "1", "item:1", "3"]), Response::Integer(2)));
+        assert!(matches!(protocol.execute_args(&["FCALL", "reserve", "1", "item:1", "3"]), Response::Boolean(false)));
+        assert!(matches!(protocol.execute_args(&["FCALL", "reserve", "2", "item:1"]), Response::Error { .. }));
+
+        // Command errors, admin commands and the limits all fail the call
+        assert!(matches!(protocol.execute_args(&["FCALL", "touch", "1", "item:1"]), Response::Error { ref error, .. } if error.contains("WRONGTYPE")));
+        assert!(matches!(protocol.execute_args(&["FCALL", "flush", "0"]), Response::Error { .. }));
+        protocol.execute_args(&["CONFIG", "SET", "function-max-operations", "10000"]);
+        protocol.execute_args(&["CONFIG", "SET", "function-max-memory", "1kb"]);
+        assert!(matches!(protocol.execute_args(&["FCALL", "spin", "0"]), Response::Error { .. }));
+        assert!(matches!(protocol.execute_args(&["FCALL", "grow", "0"]), Response::Error { .. }));
+        assert!(matches!(protocol.execute_args(&["EXISTS", "item:1"]), Response::Boolean(true)));
+
+        assert!(matches!(protocol.execute_args(&["FUNCTION", "DELETE", "stock"]), Response::Ok));
+        assert!(matches!(protocol.execute_args(&["FCALL", "reserve", "1", "item:1", "1"]), Response::Error { .. }));
+    }
+}
@@ -0,0 +1,204 @@
+use crate::cache::{RustdisCache, Value};
+use crate::glob::glob_match;
+use async_graphql::{Context, EmptySubscription, Enum, Object, Result, Schema, SimpleObject};
+
+/// Most keys returned by one `keys` query
+pub const MAX_SCAN_KEYS: usize = 1000;
+
+/// Deepest query accepted; the schema is flat, so anything deeper is abuse
+const MAX_QUERY_DEPTH: usize = 8;
+
+pub type RustdisSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+/// Schema served at `/api/graphql`, resolving against `cache`
+pub fn schema(cache: RustdisCache) -> RustdisSchema {
+    Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+        .data(cache)
+        .limit_depth(MAX_QUERY_DEPTH)
+        .finish()
+}
+
+/// Kind of value stored under a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+pub enum ValueType {
+    String,
+    Hash,
+    SortedSet,
+    Blob,
+}
+
+/// One field of a hash
+#[derive(Debug, Clone, SimpleObject)]
+pub struct HashField {
+    pub field: String,
+    pub value: String,
+}
+
+/// One member of a sorted set
+#[derive(Debug, Clone, SimpleObject)]
+pub struct ScoredMember {
+    pub member: String,
+    pub score: f64,
+}
+
+/// A key together with its current value
+///
+/// `value` is set for UTF-8 strings and blobs (null for binary ones),
+/// `fields` for hashes and `members` for sorted sets.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct KeyEntry {
+    pub key: String,
+    #[graphql(name = "type")]
+    pub value_type: ValueType,
+    pub value: Option<String>,
+    pub fields: Option<Vec<HashField>>,
+    /// Members in ascending score order
+    pub members: Option<Vec<ScoredMember>>,
+    /// Size of the value in bytes
+    pub bytes: usize,
+}
+
+impl KeyEntry {
+    fn new(key: String, value: Value) -> Self {
+        match value {
+            Value::String(value) => Self {
+                key,
+                value_type: ValueType::String,
+                bytes: value.len(),
+                value: String::from_utf8(value).ok(),
+                fields: None,
+                members: None,
+            },
+            Value::Hash(hash) => Self {
+                key,
+                value_type: ValueType::Hash,
+                bytes: hash.iter().map(|(field, value)| field.len() + value.len()).sum(),
+                value: None,
+                fields: Some(hash.into_iter().map(|(field, value)| HashField { field, value }).collect()),
+                members: None,
+            },
+            Value::SortedSet(set) => Self {
+                key,
+                value_type: ValueType::SortedSet,
+                bytes: set.size(),
+                value: None,
+                fields: None,
+                members: Some(set.iter().map(|(member, score)| ScoredMember { member: member.to_string(), score }).collect()),
+            },
+            Value::Blob(blob) => Self {
+                key,
+                value_type: ValueType::Blob,
+                bytes: blob.len(),
+                value: String::from_utf8(blob.read(0, blob.len()).to_vec()).ok(),
+                fields: None,
+                members: None,
+            },
+        }
+    }
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Looks up one key; null when it doesn't exist
+    async fn key(&self, ctx: &Context<'_>, key: String) -> Result<Option<KeyEntry>> {
+        let cache = ctx.data::<RustdisCache>()?;
+        Ok(cache.get_value(&key)?.map(|value| KeyEntry::new(key, value)))
+    }
+
+    /// Keys matching a glob pattern (default `*`), sorted, up to `limit`
+    async fn keys(&self, ctx: &Context<'_>, pattern: Option<String>, limit: Option<usize>) -> Result<Vec<KeyEntry>> {
+        let cache = ctx.data::<RustdisCache>()?;
+        let limit = limit.unwrap_or(MAX_SCAN_KEYS);
+        if limit > MAX_SCAN_KEYS {
+            return Err(format!("limit must be at most {}", MAX_SCAN_KEYS).into());
+        }
+
+        let pattern = pattern.as_deref().unwrap_or("*");
+        let mut keys: Vec<String> = cache.keys()?.into_iter().filter(|key| glob_match(pattern, key)).collect();
+        keys.sort();
+
+        let mut entries = Vec::new();
+        for key in keys {
+            if entries.len() == limit {
+                break;
+            }
+            // Keys deleted since the scan are skipped
+            if let Some(value) = cache.get_value(&key)? {
+                entries.push(KeyEntry::new(key, value));
+            }
+        }
+        Ok(entries)
+    }
+
+    async fn exists(&self, ctx: &Context<'_>, key: String) -> Result<bool> {
+        Ok(ctx.data::<RustdisCache>()?.exists(&key)?)
+    }
+
+    /// Number of keys in the cache
+    async fn size(&self, ctx: &Context<'_>) -> Result<usize> {
+        Ok(ctx.data::<RustdisCache>()?.size()?)
+    }
+}
+
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    /// Sets a key and returns the stored entry
+    async fn set(&self, ctx: &Context<'_>, key: String, value: String) -> Result<KeyEntry> {
+        ctx.data::<RustdisCache>()?.set(key.clone(), value.clone())?;
+        Ok(KeyEntry::new(key, Value::String(value.into_bytes())))
+    }
+
+    /// Deletes a key, returning whether it existed
+    async fn delete(&self, ctx: &Context<'_>, key: String) -> Result<bool> {
+        Ok(ctx.data::<RustdisCache>()?.del(&key)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_graphql_queries() {
+        let cache = RustdisCache::new();
+        let schema = schema(cache.clone());
+
+        let set = schema.execute(r#"mutation { set(key: "user:1", value: "ana") { key bytes } }"#).await;
+        assert!(set.errors.is_empty(), "{:?}", set.errors);
+        cache.set("user:2".to_string(), "bia".to_string()).unwrap();
+        cache.hset("order:1".to_string(), vec![("total".to_string(), "10".to_string())]).unwrap();
+        cache.zadd("board".to_string(), vec![("ana".to_string(), 2.5)]).unwrap();
+
+        let query = r#"{
+            key(key: "user:1") { value type }
+            order: key(key: "order:1") { type fields { field value } }
+            board: key(key: "board") { type members { member score } }
+            missing: key(key: "nope") { value }
+            keys(pattern: "user:*") { key }
+            size
+        }"#;
+        let data = schema.execute(query).await.data.into_json().unwrap();
+        assert_eq!(
+            data,
+            serde_json::json!({
+                "key": { "value": "ana", "type": "STRING" },
+                "order": { "type": "HASH", "fields": [{ "field": "total", "value": "10" }] },
+                "board": { "type": "SORTED_SET", "members": [{ "member": "ana", "score": 2.5 }] },
+                "missing": null,
+                "keys": [{ "key": "user:1" }, { "key": "user:2" }],
+                "size": 4
+            })
+        );
+
+        let deleted = schema.execute(r#"mutation { delete(key: "user:1") }"#).await.data.into_json().unwrap();
+        assert_eq!(deleted, serde_json::json!({ "delete": true }));
+        assert!(!cache.exists("user:1").unwrap());
+
+        let too_many = schema.execute("{ keys(limit: 5000) { key } }").await;
+        assert!(!too_many.errors.is_empty());
+    }
+}
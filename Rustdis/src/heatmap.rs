@@ -0,0 +1,170 @@
+use crate::analyze::namespace_of;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::io::Write;
+use std::time::Duration;
+
+/// Width of the buckets accesses are counted in
+pub const HEATMAP_RESOLUTION: Duration = Duration::from_secs(60);
+
+/// Row width of a heatmap when none is asked for
+pub const DEFAULT_HEATMAP_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Buckets kept: one day at the default resolution
+pub const HEATMAP_BUCKETS: usize = 24 * 60;
+
+/// Most distinct prefixes tracked; accesses to later ones count under
+/// `OTHER_PREFIX`, so the heatmap stays small however keys are named
+pub const MAX_HEATMAP_PREFIXES: usize = 1000;
+
+/// Row label for keys without a `prefix:` namespace
+pub const NO_PREFIX: &str = "(none)";
+
+/// Row label for prefixes past `MAX_HEATMAP_PREFIXES`
+pub const OTHER_PREFIX: &str = "(other)";
+
+/// Access counts per key prefix, bucketed by time
+///
+/// A prefix is the key's namespace (see `analyze::namespace_of`). Counts are
+/// kept for the last `HEATMAP_BUCKETS` buckets of `HEATMAP_RESOLUTION`.
+#[derive(Debug, Default)]
+pub struct Heatmap {
+    buckets: VecDeque<Bucket>,
+    prefixes: HashSet<String>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Bucket number: start time divided by the resolution
+    index: u64,
+    counts: HashMap<String, u64>,
+}
+
+/// Access counts over time: one row per time window, one column per prefix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeatmapMatrix {
+    pub window: Duration,
+    /// Column labels, sorted
+    pub prefixes: Vec<String>,
+    /// Window start and the count for each prefix, oldest first; start times
+    /// are in the clock the matrix was built with
+    pub rows: Vec<(u64, Vec<u64>)>,
+}
+
+impl Heatmap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts one access to each of `keys` at `now` (milliseconds)
+    pub fn record<'a>(&mut self, now: u64, keys: impl IntoIterator<Item = &'a str>) {
+        let index = now / HEATMAP_RESOLUTION.as_millis() as u64;
+        if self.buckets.back().is_none_or(|bucket| bucket.index < index) {
+            self.buckets.push_back(Bucket { index, counts: HashMap::new() });
+        }
+        while self.buckets.front().is_some_and(|bucket| bucket.index + (HEATMAP_BUCKETS as u64) <= index) {
+            self.buckets.pop_front();
+        }
+        let Some(bucket) = self.buckets.back_mut() else {
+            return;
+        };
+        for key in keys {
+            let prefix = namespace_of(key).unwrap_or(NO_PREFIX);
+            let prefix = if self.prefixes.contains(prefix) {
+                prefix
+            } else if self.prefixes.len() < MAX_HEATMAP_PREFIXES {
+                self.prefixes.insert(prefix.to_string());
+                prefix
+            } else {
+                OTHER_PREFIX
+            };
+            match bucket.counts.get_mut(prefix) {
+                Some(count) => *count += 1,
+                None => {
+                    bucket.counts.insert(prefix.to_string(), 1);
+                }
+            }
+        }
+    }
+
+    /// Counts for prefixes starting with `prefix`, summed into `window`s
+    /// from the oldest bucket with accesses up to `now`
+    ///
+    /// `window` is rounded down to a multiple of `HEATMAP_RESOLUTION`, and up
+    /// to at least one bucket.
+    pub fn matrix(&self, now: u64, prefix: &str, window: Duration) -> HeatmapMatrix {
+        let resolution = HEATMAP_RESOLUTION.as_millis() as u64;
+        let per_row = (window.as_millis() as u64 / resolution).max(1);
+        let window_ms = per_row * resolution;
+        let current = now / resolution;
+        let oldest = current.saturating_sub(HEATMAP_BUCKETS as u64 - 1);
+        let buckets: Vec<&Bucket> = self.buckets.iter().filter(|bucket| (oldest..=current).contains(&bucket.index)).collect();
+
+        let prefixes: Vec<String> = buckets
+            .iter()
+            .flat_map(|bucket| bucket.counts.keys())
+            .filter(|name| name.starts_with(prefix))
+            .cloned()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let Some(first) = buckets.first() else {
+            return HeatmapMatrix { window: Duration::from_millis(window_ms), prefixes, rows: Vec::new() };
+        };
+
+        let (first_row, last_row) = (first.index / per_row, current / per_row);
+        let mut rows: Vec<(u64, Vec<u64>)> =
+            (first_row..=last_row).map(|row| (row * window_ms, vec![0; prefixes.len()])).collect();
+        for bucket in buckets {
+            let counts = &mut rows[(bucket.index / per_row - first_row) as usize].1;
+            for (column, name) in prefixes.iter().enumerate() {
+                counts[column] += bucket.counts.get(name).copied().unwrap_or(0);
+            }
+        }
+        HeatmapMatrix { window: Duration::from_millis(window_ms), prefixes, rows }
+    }
+}
+
+impl HeatmapMatrix {
+    /// Writes the matrix as CSV: a `start_ms` column, then one per prefix
+    pub fn write_csv(&self, mut out: impl Write) -> std::io::Result<()> {
+        let header: Vec<String> = self.prefixes.iter().map(|prefix| crate::analyze::csv_field(prefix)).collect();
+        writeln!(out, "start_ms,{}", header.join(","))?;
+        for (start, counts) in &self.rows {
+            let counts: Vec<String> = counts.iter().map(u64::to_string).collect();
+            writeln!(out, "{},{}", start, counts.join(","))?;
+        }
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heatmap_buckets() {
+        let minute = HEATMAP_RESOLUTION.as_millis() as u64;
+        let mut heatmap = Heatmap::new();
+        heatmap.record(0, ["user:1", "user:2", "cart:9"]);
+        heatmap.record(minute * 3 + 5, ["user:1", "plain"]);
+        heatmap.record(minute * 4, ["cart:1"]);
+
+        let matrix = heatmap.matrix(minute * 4, "", Duration::from_secs(120));
+        assert_eq!(matrix.prefixes, ["(none)", "cart", "user"]);
+        assert_eq!(matrix.rows, [(0, vec![0, 1, 2]), (minute * 2, vec![1, 0, 1]), (minute * 4, vec![0, 1, 0])]);
+
+        let users = heatmap.matrix(minute * 4, "us", Duration::ZERO);
+        assert_eq!((users.prefixes.len(), users.rows.len(), users.window), (1, 5, HEATMAP_RESOLUTION));
+
+        let mut csv = Vec::new();
+        matrix.write_csv(&mut csv).unwrap();
+        assert!(String::from_utf8(csv).unwrap().starts_with("start_ms,(none),cart,user\n0,0,1,2\n"));
+
+        // A day later the old buckets have aged out
+        let day = minute * HEATMAP_BUCKETS as u64;
+        heatmap.record(day + minute * 4, ["user:1"]);
+        let later = heatmap.matrix(day + minute * 4, "", HEATMAP_RESOLUTION);
+        assert_eq!(later.prefixes, ["user"]);
+        assert_eq!(later.rows, [(day + minute * 4, vec![1])]);
+    }
+}
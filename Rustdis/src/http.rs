@@ -0,0 +1,1393 @@
+use crate::api::RustdisApi;
+use crate::auth::{AclUser, Authenticator};
+use crate::cache::{value_tag, RustdisCache, SetCondition, TypedValue};
+use crate::heatmap::DEFAULT_HEATMAP_WINDOW;
+use crate::idgen::IdKind;
+use crate::metrics::HttpMetrics;
+use crate::protocol::{Command, RustdisProtocol, ValueEncoding};
+use anyhow::Result;
+use axum::body::{Body, Bytes};
+use axum::extract::{ConnectInfo, DefaultBodyLimit, Extension, MatchedPath, Path, Query, Request, State};
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response as HttpResponse};
+use axum::routing::{delete, get, post};
+use axum::Router;
+use serde_json::{Map, Value};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::future::IntoFuture;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tower_http::compression::predicate::{NotForContentType, Predicate, SizeAbove};
+use tower_http::compression::CompressionLayer;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::set_header::SetResponseHeaderLayer;
+
+/// Responses smaller than this many bytes are never compressed
+pub const DEFAULT_COMPRESSION_THRESHOLD: u16 = 1024;
+
+/// Largest key accepted by the HTTP API
+pub const MAX_KEY_BYTES: usize = 64 * 1024;
+
+/// Largest value accepted by the HTTP API (same as a framed protocol frame)
+pub const MAX_VALUE_BYTES: usize = crate::framing::MAX_FRAME_LEN;
+
+/// Largest value accepted by the HTTP API once base64-encoded
+const MAX_BASE64_VALUE_BYTES: usize = MAX_VALUE_BYTES.div_ceil(3) * 4;
+
+/// Largest request body; leaves headroom over a max base64 value for the key and JSON framing
+const MAX_BODY_BYTES: usize = MAX_BASE64_VALUE_BYTES + MAX_KEY_BYTES + 1024;
+
+/// Most operations accepted in one `POST /api/batch`
+pub const MAX_BATCH_OPERATIONS: usize = 1000;
+
+/// How long responses to idempotent requests are kept for replay
+pub const DEFAULT_IDEMPOTENCY_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Longest accepted `Idempotency-Key`
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+/// Headers sent on every response unless disabled
+const SECURITY_HEADERS: &[(&str, &str)] = &[
+    ("x-content-type-options", "nosniff"),
+    ("x-frame-options", "DENY"),
+    ("referrer-policy", "no-referrer"),
+    ("content-security-policy", "default-src 'none'; frame-ancestors 'none'"),
+    ("cache-control", "no-store"),
+];
+
+/// HTTP server settings
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    pub addr: SocketAddr,
+    /// Minimum response size for gzip/deflate compression
+    pub compression_threshold: u16,
+    /// Cross-origin access for browser clients; `None` disables CORS
+    pub cors: Option<CorsConfig>,
+    /// Send `SECURITY_HEADERS` on every response
+    pub security_headers: bool,
+    /// Replay window for `Idempotency-Key` requests; zero disables replay
+    pub idempotency_window: Duration,
+    /// Separate listener for admin operations; when set, `addr` only serves
+    /// the data plane
+    pub admin_addr: Option<SocketAddr>,
+    /// Print one line per request (client, method, path, status, latency)
+    pub access_log: bool,
+    /// Checks `Authorization: Bearer` credentials; without providers every
+    /// request is let in
+    pub auth: Authenticator,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            addr: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+            cors: None,
+            security_headers: true,
+            idempotency_window: DEFAULT_IDEMPOTENCY_WINDOW,
+            admin_addr: None,
+            access_log: true,
+            auth: Authenticator::default(),
+        }
+    }
+}
+
+/// CORS policy for browser-based dashboards calling the API directly
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorsConfig {
+    /// Allowed origins, e.g. `https://dash.example.com`; `*` allows any origin
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    /// Allow cookies and auth headers; not allowed together with `*`
+    pub allow_credentials: bool,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec!["*".to_string()],
+            allowed_methods: ["GET", "POST", "DELETE", "OPTIONS"].map(String::from).to_vec(),
+            allowed_headers: ["content-type", "authorization"].map(String::from).to_vec(),
+            allow_credentials: false,
+        }
+    }
+}
+
+impl CorsConfig {
+    fn layer(&self) -> Result<CorsLayer> {
+        let any_origin = self.allowed_origins.iter().any(|origin| origin == "*");
+        if any_origin && self.allow_credentials {
+            return Err(anyhow::anyhow!("CORS credentials cannot be allowed for any origin (`*`)"));
+        }
+
+        let origins = if any_origin {
+            AllowOrigin::any()
+        } else {
+            let origins = self
+                .allowed_origins
+                .iter()
+                .map(|origin| HeaderValue::from_str(origin).map_err(|_| anyhow::anyhow!("Invalid CORS origin: {}", origin)))
+                .collect::<Result<Vec<_>>>()?;
+            AllowOrigin::list(origins)
+        };
+        let methods = self
+            .allowed_methods
+            .iter()
+            .map(|method| method.parse::<Method>().map_err(|_| anyhow::anyhow!("Invalid CORS method: {}", method)))
+            .collect::<Result<Vec<_>>>()?;
+        let headers = self
+            .allowed_headers
+            .iter()
+            .map(|name| name.parse::<HeaderName>().map_err(|_| anyhow::anyhow!("Invalid CORS header: {}", name)))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods(methods)
+            .allow_headers(headers)
+            .allow_credentials(self.allow_credentials))
+    }
+}
+
+type SharedApi = Arc<RustdisApi>;
+
+/// Client error with the same `{"error", "code"}` envelope as protocol errors
+///
+/// `field` names the offending body field or query parameter, when there is one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub message: String,
+    pub field: Option<&'static str>,
+}
+
+impl ApiError {
+    fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            code,
+            message: message.into(),
+            field: None,
+        }
+    }
+
+    fn field(mut self, field: &'static str) -> Self {
+        self.field = Some(field);
+        self
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> HttpResponse {
+        let mut body = serde_json::json!({ "error": self.message, "code": self.code });
+        if let Some(field) = self.field {
+            body["field"] = Value::from(field);
+        }
+        (self.status, [(header::CONTENT_TYPE, "application/json")], body.to_string()).into_response()
+    }
+}
+
+/// State of one `Idempotency-Key`
+#[derive(Debug)]
+enum IdempotencySlot {
+    /// The first request with this key is still running
+    InFlight { fingerprint: u64, started: Instant },
+    /// Response stored for replay
+    Done {
+        fingerprint: u64,
+        stored: Instant,
+        status: StatusCode,
+        content_type: Option<HeaderValue>,
+        body: Bytes,
+    },
+}
+
+impl IdempotencySlot {
+    fn since(&self) -> Instant {
+        match self {
+            Self::InFlight { started, .. } => *started,
+            Self::Done { stored, .. } => *stored,
+        }
+    }
+}
+
+/// Responses to mutating requests, keyed by the client's `Idempotency-Key`
+#[derive(Debug)]
+struct IdempotencyStore {
+    window: Duration,
+    slots: Mutex<HashMap<String, IdempotencySlot>>,
+}
+
+impl IdempotencyStore {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            slots: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Claims `key` for a new request, or answers with the stored response
+    /// or a conflict if it was already used
+    fn begin(&self, key: &str, fingerprint: u64) -> Result<Option<HttpResponse>, ApiError> {
+        let mut slots = self.slots.lock().map_err(|_| lock_error())?;
+        let now = Instant::now();
+        slots.retain(|_, slot| now.duration_since(slot.since()) < self.window);
+
+        let reused = || {
+            ApiError {
+                status: StatusCode::UNPROCESSABLE_ENTITY,
+                ..ApiError::new("IDEMPOTENCY_KEY_REUSED", "Idempotency-Key was already used for a different request")
+            }
+        };
+        match slots.get(key) {
+            Some(IdempotencySlot::InFlight { fingerprint: first, .. }) if *first != fingerprint => Err(reused()),
+            Some(IdempotencySlot::InFlight { .. }) => Err(ApiError {
+                status: StatusCode::CONFLICT,
+                ..ApiError::new("IDEMPOTENCY_IN_PROGRESS", "A request with this Idempotency-Key is still being processed")
+            }),
+            Some(IdempotencySlot::Done { fingerprint: first, .. }) if *first != fingerprint => Err(reused()),
+            Some(IdempotencySlot::Done { status, content_type, body, .. }) => {
+                let mut response = (*status, body.clone()).into_response();
+                if let Some(content_type) = content_type {
+                    response.headers_mut().insert(header::CONTENT_TYPE, content_type.clone());
+                }
+                response.headers_mut().insert("idempotent-replayed", HeaderValue::from_static("true"));
+                Ok(Some(response))
+            }
+            None => {
+                slots.insert(key.to_string(), IdempotencySlot::InFlight { fingerprint, started: now });
+                Ok(None)
+            }
+        }
+    }
+
+    /// Stores the response for `key`; server errors are dropped so they can be retried
+    fn finish(&self, key: &str, fingerprint: u64, status: StatusCode, content_type: Option<HeaderValue>, body: Bytes) {
+        let Ok(mut slots) = self.slots.lock() else { return };
+        if status.is_server_error() {
+            slots.remove(key);
+            return;
+        }
+        let stored = Instant::now();
+        slots.insert(key.to_string(), IdempotencySlot::Done { fingerprint, stored, status, content_type, body });
+    }
+
+    fn abandon(&self, key: &str) {
+        if let Ok(mut slots) = self.slots.lock() {
+            slots.remove(key);
+        }
+    }
+}
+
+/// Replays the stored response for retried POST/DELETE requests that carry
+/// the same `Idempotency-Key`, method, path and body
+async fn idempotency(State(store): State<Arc<IdempotencyStore>>, request: Request, next: Next) -> HttpResponse {
+    let is_mutation = matches!(*request.method(), Method::POST | Method::DELETE);
+    let key = request.headers().get("idempotency-key").map(|key| key.to_str().map(str::to_string));
+    let key = match key {
+        Some(Ok(key)) if is_mutation && !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LEN => key,
+        Some(_) if is_mutation => {
+            let message = format!("Idempotency-Key must be 1 to {} visible ASCII characters", MAX_IDEMPOTENCY_KEY_LEN);
+            return ApiError::new("INVALID_ARGUMENT", message).field("Idempotency-Key").into_response();
+        }
+        _ => return next.run(request).await,
+    };
+
+    let (parts, body) = request.into_parts();
+    let Ok(body) = axum::body::to_bytes(body, MAX_BODY_BYTES).await else {
+        return too_large("body", MAX_BODY_BYTES).into_response();
+    };
+    let mut hasher = DefaultHasher::new();
+    (parts.method.as_str(), parts.uri.to_string(), &body[..]).hash(&mut hasher);
+    let fingerprint = hasher.finish();
+
+    match store.begin(&key, fingerprint) {
+        Ok(Some(replay)) => return replay,
+        Ok(None) => {}
+        Err(e) => return e.into_response(),
+    }
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    let (parts, body) = response.into_parts();
+    match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => {
+            store.finish(&key, fingerprint, parts.status, parts.headers.get(header::CONTENT_TYPE).cloned(), body.clone());
+            HttpResponse::from_parts(parts, Body::from(body))
+        }
+        Err(e) => {
+            store.abandon(&key);
+            internal_error(anyhow::anyhow!("Failed to buffer response: {}", e))
+        }
+    }
+}
+
+/// Per-listener request observation: Prometheus metrics and the access log
+#[derive(Debug)]
+struct Observer {
+    metrics: Arc<HttpMetrics>,
+    access_log: bool,
+}
+
+/// Records the request in the endpoint's metrics and, if enabled, the access
+/// log: client, user (`-` when unauthenticated), request line, status and
+/// latency
+///
+/// Endpoints are labelled by route template; unknown paths share the
+/// `unmatched` label.
+async fn observe(State(observer): State<Arc<Observer>>, request: Request, next: Next) -> HttpResponse {
+    let started = Instant::now();
+    let method = request.method().clone();
+    let uri_path = request.uri().path().to_string();
+    let route = request.extensions().get::<MatchedPath>().map_or("unmatched", MatchedPath::as_str).to_string();
+    let client = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|info| info.0);
+
+    let response = next.run(request).await;
+    let latency = started.elapsed();
+    let status = response.status().as_u16();
+    observer.metrics.record(method.as_str(), &route, status, latency);
+    if observer.access_log {
+        let client = client.map_or_else(|| "-".to_string(), |client| client.to_string());
+        let principal = response.extensions().get::<Principal>().map_or("-", |principal| principal.0.as_str());
+        println!("{} {} \"{} {}\" {} {:.3}ms", client, principal, method, uri_path, status, latency.as_secs_f64() * 1000.0);
+    }
+    response
+}
+
+/// Delays requests and drops responses as configured by `CONFIG SET chaos-*`
+///
+/// A dropped response is never sent: the request still runs, then the
+/// connection hangs until the client gives up, as over a lossy network.
+#[cfg(feature = "chaos")]
+async fn inject_faults(State(api): State<SharedApi>, request: Request, next: Next) -> HttpResponse {
+    let faults = api.cache().faults();
+    faults.wait().await;
+    let response = next.run(request).await;
+    if faults.drop_reply() {
+        std::future::pending::<()>().await;
+    }
+    response
+}
+
+fn lock_error() -> ApiError {
+    ApiError {
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        ..ApiError::new("INTERNAL", "Failed to acquire idempotency lock")
+    }
+}
+
+/// Body of `POST /api/set`
+#[derive(Debug, PartialEq, Eq)]
+struct SetBody {
+    key: String,
+    value: String,
+    encoding: ValueEncoding,
+}
+
+impl SetBody {
+    fn parse(body: &[u8]) -> Result<Self, ApiError> {
+        let mut fields = json_object(body)?;
+        let key = take_string(&mut fields, "key", MAX_KEY_BYTES)?;
+        let encoding = if fields.contains_key("encoding") {
+            encoding(&take_string(&mut fields, "encoding", 16)?)?
+        } else {
+            ValueEncoding::Utf8
+        };
+        let max_value = if encoding == ValueEncoding::Base64 { MAX_BASE64_VALUE_BYTES } else { MAX_VALUE_BYTES };
+        let value = take_string(&mut fields, "value", max_value)?;
+        if let Some(unknown) = fields.keys().next() {
+            return Err(ApiError::new("UNKNOWN_FIELD", format!("Unknown field `{}`; expected `key`, `value` and `encoding`", unknown)));
+        }
+        Ok(Self { key, value, encoding })
+    }
+}
+
+/// `encoding` body field or query parameter
+fn encoding(value: &str) -> Result<ValueEncoding, ApiError> {
+    value.parse().map_err(|e: anyhow::Error| ApiError::new("INVALID_ARGUMENT", e.to_string()).field("encoding"))
+}
+
+/// Body of `POST /api/batch`
+#[derive(Debug, PartialEq)]
+struct BatchBody {
+    operations: Vec<Value>,
+    atomic: bool,
+}
+
+impl BatchBody {
+    fn parse(body: &[u8]) -> Result<Self, ApiError> {
+        let mut fields = json_object(body)?;
+        let operations = match fields.remove("operations") {
+            Some(Value::Array(operations)) if operations.len() > MAX_BATCH_OPERATIONS => {
+                return Err(ApiError {
+                    status: StatusCode::PAYLOAD_TOO_LARGE,
+                    ..ApiError::new("TOO_MANY_OPERATIONS", format!("At most {} operations per batch", MAX_BATCH_OPERATIONS))
+                        .field("operations")
+                });
+            }
+            Some(Value::Array(operations)) => operations,
+            Some(other) => {
+                let message = format!("`operations` must be an array, got {}", json_type(&other));
+                return Err(ApiError::new("INVALID_TYPE", message).field("operations"));
+            }
+            None => return Err(ApiError::new("MISSING_FIELD", "Missing required field `operations`").field("operations")),
+        };
+        let atomic = match fields.remove("atomic") {
+            None => false,
+            Some(Value::Bool(atomic)) => atomic,
+            Some(other) => {
+                let message = format!("`atomic` must be a boolean, got {}", json_type(&other));
+                return Err(ApiError::new("INVALID_TYPE", message).field("atomic"));
+            }
+        };
+        if let Some(unknown) = fields.keys().next() {
+            return Err(ApiError::new("UNKNOWN_FIELD", format!("Unknown field `{}`; expected `operations` and `atomic`", unknown)));
+        }
+        Ok(Self { operations, atomic })
+    }
+}
+
+fn json_object(body: &[u8]) -> Result<Map<String, Value>, ApiError> {
+    match serde_json::from_slice(body) {
+        Ok(Value::Object(fields)) => Ok(fields),
+        Ok(_) => Err(ApiError::new("INVALID_TYPE", "Request body must be a JSON object")),
+        Err(e) => Err(ApiError::new("INVALID_JSON", e.to_string())),
+    }
+}
+
+fn take_string(fields: &mut Map<String, Value>, field: &'static str, max_bytes: usize) -> Result<String, ApiError> {
+    match fields.remove(field) {
+        Some(Value::String(value)) if value.len() > max_bytes => Err(too_large(field, max_bytes)),
+        Some(Value::String(value)) => Ok(value),
+        Some(other) => Err(ApiError::new("INVALID_TYPE", format!("`{}` must be a string, got {}", field, json_type(&other))).field(field)),
+        None => Err(ApiError::new("MISSING_FIELD", format!("Missing required field `{}`", field)).field(field)),
+    }
+}
+
+/// Required query parameter, validated like a body field
+fn query_param<'a>(params: &'a HashMap<String, String>, field: &'static str, max_bytes: usize) -> Result<&'a str, ApiError> {
+    match params.get(field) {
+        Some(value) if value.len() > max_bytes => Err(too_large(field, max_bytes)),
+        Some(value) => Ok(value),
+        None => Err(ApiError::new("MISSING_FIELD", format!("Missing required query parameter `{}`", field)).field(field)),
+    }
+}
+
+/// Key or field taken from the route, validated like a body field
+fn path_param<'a>(value: &'a str, field: &'static str) -> Result<&'a str, ApiError> {
+    match value.len() {
+        len if len > MAX_KEY_BYTES => Err(too_large(field, MAX_KEY_BYTES)),
+        _ => Ok(value),
+    }
+}
+
+fn too_large(field: &'static str, max_bytes: usize) -> ApiError {
+    ApiError {
+        status: StatusCode::PAYLOAD_TOO_LARGE,
+        ..ApiError::new("VALUE_TOO_LARGE", format!("`{}` exceeds the {} byte limit", field, max_bytes)).field(field)
+    }
+}
+
+fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+/// Whether a listener accepts admin operations (see `Command::is_admin`)
+#[derive(Debug, Clone, Copy)]
+struct AdminAccess(bool);
+
+/// Commands run by each fixed endpoint (method and route), checked against
+/// the user's role; `/api/command` and `/api/batch` check the commands
+/// they're sent, and endpoints not listed here are open to any
+/// authenticated user
+const ENDPOINT_COMMANDS: &[(&str, &str, &[&str])] = &[
+    ("GET", "/api/get", &["GET"]),
+    ("POST", "/api/set", &["SET"]),
+    ("DELETE", "/api/del", &["DEL"]),
+    ("GET", "/api/exists", &["EXISTS"]),
+    ("GET", "/api/keys", &["KEYS"]),
+    ("GET", "/api/keys/{key}", &["GET"]),
+    ("PUT", "/api/keys/{key}", &["SET"]),
+    ("DELETE", "/api/keys/{key}", &["DEL"]),
+    ("GET", "/api/keys/{key}/fields", &["HGETALL"]),
+    ("GET", "/api/keys/{key}/fields/{field}", &["HGET"]),
+    ("PUT", "/api/keys/{key}/fields/{field}", &["HSET"]),
+    ("DELETE", "/api/keys/{key}/fields/{field}", &["HDEL"]),
+    ("GET", "/api/size", &["SIZE"]),
+    ("GET", "/api/ping", &["PING"]),
+    ("GET", "/api/genid", &["GENID"]),
+    ("GET", "/api/heatmap", &["HEATMAP"]),
+    ("DELETE", "/api/flush", &["FLUSH"]),
+    ("POST", "/api/graphql", &["GET", "KEYS", "SET", "DEL"]),
+];
+
+/// Requires `Authorization: Bearer <secret>` when authentication is on
+///
+/// The secret is checked as the default user's password or as a token
+/// (e.g. an OIDC JWT). Fixed endpoints are refused with 403 `NOPERM` when
+/// the user's role lacks their commands (see `ENDPOINT_COMMANDS`).
+async fn authenticate(State(auth): State<Authenticator>, mut request: Request, next: Next) -> HttpResponse {
+    if !auth.is_required() {
+        return next.run(request).await;
+    }
+    let secret = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let Some(user) = secret.and_then(|secret| auth.authenticate(None, secret.trim())) else {
+        let error = ApiError {
+            status: StatusCode::UNAUTHORIZED,
+            ..ApiError::new("UNAUTHORIZED", "Missing or invalid bearer token")
+        };
+        let mut response = error.into_response();
+        response.headers_mut().insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        return response;
+    };
+    let path = request.extensions().get::<MatchedPath>().map(|path| path.as_str());
+    let method = request.method().as_str();
+    let commands = ENDPOINT_COMMANDS
+        .iter()
+        .find(|(endpoint_method, endpoint, _)| *endpoint_method == method && Some(*endpoint) == path)
+        .map_or(&[][..], |(_, _, commands)| commands);
+    let principal = Principal(user.name.clone());
+    let mut response = match commands.iter().find(|command| !user.role.allows_name(command)) {
+        Some(denied) => no_permission(&user, denied).into_response(),
+        None => {
+            request.extensions_mut().insert(user);
+            next.run(request).await
+        }
+    };
+    response.extensions_mut().insert(principal);
+    response
+}
+
+/// User a request authenticated as, attached to its response so the access
+/// log (outside authentication) can name it
+#[derive(Debug, Clone)]
+struct Principal(String);
+
+/// Routes for the endpoints documented in `RustdisApi::api_docs`
+///
+/// Responses are gzip- or deflate-compressed when the client sends a
+/// matching `Accept-Encoding` and the body is above the configured threshold.
+/// With `admin_addr` set, admin operations are left to `admin_router`.
+/// Fails if the CORS configuration is invalid.
+pub fn router(api: SharedApi, config: &HttpConfig) -> Result<Router> {
+    build_router(api, config, AdminAccess(config.admin_addr.is_none()))
+}
+
+/// Routes for the admin listener: the full API, admin operations included
+pub fn admin_router(api: SharedApi, config: &HttpConfig) -> Result<Router> {
+    build_router(api, config, AdminAccess(true))
+}
+
+fn build_router(api: SharedApi, config: &HttpConfig, admin: AdminAccess) -> Result<Router> {
+    let mut routes = Router::new()
+        .route("/api/get", get(get_value))
+        .route("/api/set", post(set_value))
+        .route("/api/del", delete(del_value))
+        .route("/api/exists", get(exists))
+        .route("/api/keys", get(keys))
+        .route("/api/keys/{key}", get(get_key).put(put_key).delete(delete_key))
+        .route("/api/keys/{key}/fields", get(get_fields))
+        .route("/api/keys/{key}/fields/{field}", get(get_field).put(put_field).delete(delete_field))
+        .route("/api/size", get(size))
+        .route("/api/ping", get(ping))
+        .route("/api/genid", get(genid))
+        .route("/api/heatmap", get(heatmap))
+        .route("/api/command", post(command))
+        .route("/api/batch", post(batch))
+        .route("/api/docs", get(docs));
+    if admin.0 {
+        routes = routes.route("/api/flush", delete(flush));
+    }
+    let observer = Arc::new(Observer {
+        metrics: Arc::new(HttpMetrics::default()),
+        access_log: config.access_log,
+    });
+    routes = routes.route("/metrics", get(metrics).with_state(observer.metrics.clone()));
+    #[cfg(feature = "graphql")]
+    let routes = routes.route(
+        "/api/graphql",
+        post(graphql).with_state(crate::graphql::schema(api.cache().clone())),
+    );
+    #[cfg(feature = "chaos")]
+    let routes = routes.layer(middleware::from_fn_with_state(api.clone(), inject_faults));
+    let mut router = routes.layer(DefaultBodyLimit::max(MAX_BODY_BYTES)).with_state(api);
+
+    // Inside compression, so stored responses are replayable under any Accept-Encoding
+    if !config.idempotency_window.is_zero() {
+        let store = Arc::new(IdempotencyStore::new(config.idempotency_window));
+        router = router.layer(middleware::from_fn_with_state(store, idempotency));
+    }
+    // Outside idempotency, so stored responses are only replayed to
+    // authenticated clients
+    router = router
+        .layer(middleware::from_fn_with_state(config.auth.clone(), authenticate))
+        .layer(Extension(admin));
+    // Images are served as stored and are compressed already
+    let compress = SizeAbove::new(config.compression_threshold).and(NotForContentType::IMAGES);
+    router = router
+        .layer(CompressionLayer::new().compress_when(compress))
+        .layer(middleware::map_response(weaken_compressed_etag));
+
+    if config.security_headers {
+        for (name, value) in SECURITY_HEADERS {
+            router = router.layer(SetResponseHeaderLayer::if_not_present(
+                HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            ));
+        }
+    }
+    // CORS goes outside everything else so preflight requests are answered
+    // before any other layer runs
+    if let Some(cors) = &config.cors {
+        router = router.layer(cors.layer()?);
+    }
+    // Observation is outermost so replays, preflights and rejections are counted too
+    Ok(router.layer(middleware::from_fn_with_state(observer, observe)))
+}
+
+/// Serves the HTTP API, and the admin API if configured, until the process
+/// is stopped
+pub async fn serve(cache: RustdisCache, config: HttpConfig) -> Result<()> {
+    let api = Arc::new(RustdisApi::new(cache));
+    let listener = tokio::net::TcpListener::bind(config.addr).await?;
+    let router = router(api.clone(), &config)?;
+    println!("🌐 Rustdis HTTP API listening on http://{}", listener.local_addr()?);
+
+    // Connection info gives the access log its client addresses
+    let router = router.into_make_service_with_connect_info::<SocketAddr>();
+    let Some(admin_addr) = config.admin_addr else {
+        axum::serve(listener, router).await?;
+        return Ok(());
+    };
+    let admin_listener = tokio::net::TcpListener::bind(admin_addr).await?;
+    let admin = admin_router(api, &config)?.into_make_service_with_connect_info::<SocketAddr>();
+    println!("🔐 Rustdis admin API listening on http://{}", admin_listener.local_addr()?);
+    tokio::try_join!(
+        axum::serve(listener, router).into_future(),
+        axum::serve(admin_listener, admin).into_future(),
+    )?;
+    Ok(())
+}
+
+type Params = Query<HashMap<String, String>>;
+
+async fn get_value(State(api): State<SharedApi>, Query(params): Params, headers: HeaderMap) -> Result<HttpResponse, ApiError> {
+    read_value(&api, query_param(&params, "key", MAX_KEY_BYTES)?, &params, &headers)
+}
+
+async fn get_key(State(api): State<SharedApi>, Path(key): Path<String>, Query(params): Params, headers: HeaderMap) -> Result<HttpResponse, ApiError> {
+    read_value(&api, path_param(&key, "key")?, &params, &headers)
+}
+
+/// Strings with a `content-type` metadata field are served as is, unless an
+/// `encoding` asks for the JSON reply
+fn read_value(api: &RustdisApi, key: &str, params: &HashMap<String, String>, headers: &HeaderMap) -> Result<HttpResponse, ApiError> {
+    if !params.contains_key("encoding") {
+        match api.api_get_typed(key) {
+            Ok(Some(typed)) => return Ok(typed_response(typed, headers)),
+            Ok(None) => {}
+            Err(e) => return Ok(internal_error(e)),
+        }
+    }
+    let encoding = params.get("encoding").map_or(Ok(ValueEncoding::Utf8), |value| encoding(value))?;
+    Ok(json(api.api_get(key, encoding)))
+}
+
+/// Serves a value as its content type, with an ETag and a Cache-Control
+/// lifetime matching its TTL; 304 when `If-None-Match` holds the ETag
+///
+/// Content types that aren't valid header values are sent as
+/// `application/octet-stream`.
+fn typed_response(typed: TypedValue, headers: &HeaderMap) -> HttpResponse {
+    let etag = etag(&typed.value);
+    let cache_control = match typed.ttl {
+        Some(ttl) => format!("max-age={}", ttl.as_secs()),
+        None => "no-cache".to_string(),
+    };
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        // Weak comparison, as RFC 9110 asks of `If-None-Match`
+        .is_some_and(|tags| tags.split(',').map(|tag| tag.trim().trim_start_matches("W/")).any(|tag| tag == "*" || tag == etag));
+    let caching = [(header::ETAG, etag), (header::CACHE_CONTROL, cache_control)];
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, caching).into_response();
+    }
+    let content_type = HeaderValue::from_str(&typed.content_type).unwrap_or(HeaderValue::from_static("application/octet-stream"));
+    ([(header::CONTENT_TYPE, content_type)], caching, typed.value).into_response()
+}
+
+/// Strong ETag of a string value; compressed responses get it weakened by
+/// `weaken_compressed_etag`
+fn etag(value: &[u8]) -> String {
+    format!("\"{:016x}\"", value_tag(value))
+}
+
+/// Tag of a strong ETag made by `etag`; `None` for any other ETag, weak
+/// ones included, since `If-Match` compares strongly (RFC 9110 §13.1.1)
+fn parse_etag(etag: &str) -> Option<u64> {
+    let hex = etag.strip_prefix('"')?.strip_suffix('"')?;
+    match hex.len() {
+        16 => u64::from_str_radix(hex, 16).ok(),
+        _ => None,
+    }
+}
+
+/// Marks the ETag of a response compression re-encoded as weak, as its
+/// bytes are no longer the ones the strong tag stands for
+async fn weaken_compressed_etag(mut response: HttpResponse) -> HttpResponse {
+    if !response.headers().contains_key(header::CONTENT_ENCODING) {
+        return response;
+    }
+    let weak = response
+        .headers()
+        .get(header::ETAG)
+        .filter(|etag| etag.as_bytes().starts_with(b"\""))
+        .and_then(|etag| HeaderValue::from_bytes(&[b"W/", etag.as_bytes()].concat()).ok());
+    if let Some(weak) = weak {
+        response.headers_mut().insert(header::ETAG, weak);
+    }
+    response
+}
+
+/// Stores the raw body at `key`; `If-Match` makes the write conditional on
+/// the current value's ETag, `If-None-Match: *` on the key not existing yet
+async fn put_key(State(api): State<SharedApi>, Path(key): Path<String>, headers: HeaderMap, body: Bytes) -> Result<HttpResponse, ApiError> {
+    path_param(&key, "key")?;
+    if body.len() > MAX_VALUE_BYTES {
+        return Err(too_large("body", MAX_VALUE_BYTES));
+    }
+    let precondition_failed = || ApiError {
+        status: StatusCode::PRECONDITION_FAILED,
+        ..ApiError::new("PRECONDITION_FAILED", format!("Key `{}` doesn't meet the request's precondition", key))
+    };
+    let condition = write_condition(&headers)?.ok_or_else(precondition_failed)?;
+    let etag = etag(&body);
+    match api.api_put(key.clone(), body.to_vec(), condition) {
+        Ok(true) => Ok((StatusCode::NO_CONTENT, [(header::ETAG, etag)]).into_response()),
+        Ok(false) => Err(precondition_failed()),
+        Err(e) => Ok(internal_error(e)),
+    }
+}
+
+/// Write condition of the `If-Match` and `If-None-Match` headers; `None`
+/// when `If-Match` holds an ETag that can never match
+fn write_condition(headers: &HeaderMap) -> Result<Option<SetCondition>, ApiError> {
+    let header = |name| headers.get(name).map(|value: &HeaderValue| value.to_str().unwrap_or_default().trim());
+    match (header(header::IF_MATCH), header(header::IF_NONE_MATCH)) {
+        (None, None) => Ok(Some(SetCondition::Always)),
+        (None, Some("*")) => Ok(Some(SetCondition::IfMissing)),
+        (None, Some(_)) => Err(ApiError::new("INVALID_ARGUMENT", "Only `If-None-Match: *` is supported on writes").field("If-None-Match")),
+        (Some("*"), None) => Ok(Some(SetCondition::IfExists)),
+        (Some(etags), None) if etags.contains(',') => {
+            Err(ApiError::new("INVALID_ARGUMENT", "`If-Match` takes a single ETag").field("If-Match"))
+        }
+        (Some(etag), None) => Ok(parse_etag(etag).map(SetCondition::IfTag)),
+        (Some(_), Some(_)) => Err(ApiError::new("INVALID_ARGUMENT", "Send `If-Match` or `If-None-Match`, not both")),
+    }
+}
+
+async fn set_value(State(api): State<SharedApi>, body: Bytes) -> Result<HttpResponse, ApiError> {
+    let body = SetBody::parse(&body)?;
+    Ok(json(api.api_set(body.key, body.value, body.encoding)))
+}
+
+async fn del_value(State(api): State<SharedApi>, Query(params): Params) -> Result<HttpResponse, ApiError> {
+    Ok(json(api.api_del(query_param(&params, "key", MAX_KEY_BYTES)?)))
+}
+
+async fn delete_key(State(api): State<SharedApi>, Path(key): Path<String>) -> Result<HttpResponse, ApiError> {
+    Ok(json(api.api_del(path_param(&key, "key")?)))
+}
+
+async fn get_fields(State(api): State<SharedApi>, Path(key): Path<String>) -> Result<HttpResponse, ApiError> {
+    Ok(json(api.api_hgetall(path_param(&key, "key")?)))
+}
+
+async fn get_field(State(api): State<SharedApi>, Path((key, field)): Path<(String, String)>) -> Result<HttpResponse, ApiError> {
+    Ok(json(api.api_hget(path_param(&key, "key")?, path_param(&field, "field")?)))
+}
+
+/// Sets a hash field to the raw body, which must be UTF-8 like every hash
+/// value
+async fn put_field(State(api): State<SharedApi>, Path((key, field)): Path<(String, String)>, body: Bytes) -> Result<HttpResponse, ApiError> {
+    path_param(&key, "key")?;
+    path_param(&field, "field")?;
+    if body.len() > MAX_VALUE_BYTES {
+        return Err(too_large("body", MAX_VALUE_BYTES));
+    }
+    let value = String::from_utf8(body.to_vec()).map_err(|_| ApiError::new("NOT_UTF8", "Hash field values must be UTF-8").field("body"))?;
+    Ok(json(api.api_hset(key, field, value)))
+}
+
+async fn delete_field(State(api): State<SharedApi>, Path((key, field)): Path<(String, String)>) -> Result<HttpResponse, ApiError> {
+    Ok(json(api.api_hdel(path_param(&key, "key")?, path_param(&field, "field")?)))
+}
+
+async fn exists(State(api): State<SharedApi>, Query(params): Params) -> Result<HttpResponse, ApiError> {
+    Ok(json(api.api_exists(query_param(&params, "key", MAX_KEY_BYTES)?)))
+}
+
+async fn keys(State(api): State<SharedApi>, Query(params): Params) -> Result<HttpResponse, ApiError> {
+    match params.get("pattern") {
+        Some(pattern) if pattern.len() > MAX_KEY_BYTES => Err(too_large("pattern", MAX_KEY_BYTES)),
+        Some(pattern) => Ok(json(api.api_keys_matching(pattern))),
+        None => Ok(json(api.api_keys())),
+    }
+}
+
+async fn flush(State(api): State<SharedApi>) -> HttpResponse {
+    json(api.api_flush())
+}
+
+async fn size(State(api): State<SharedApi>) -> HttpResponse {
+    json(api.api_size())
+}
+
+async fn ping(State(api): State<SharedApi>) -> HttpResponse {
+    json(api.api_ping())
+}
+
+async fn genid(State(api): State<SharedApi>, Query(params): Params) -> Result<HttpResponse, ApiError> {
+    let kind = match params.get("kind") {
+        Some(kind) => kind
+            .parse::<IdKind>()
+            .map_err(|e| ApiError::new("INVALID_ARGUMENT", e.to_string()).field("kind"))?,
+        None => IdKind::default(),
+    };
+    Ok(json(api.api_genid(kind)))
+}
+
+async fn heatmap(State(api): State<SharedApi>, Query(params): Params) -> Result<HttpResponse, ApiError> {
+    let prefix = params.get("prefix").cloned().unwrap_or_default();
+    let window = match params.get("window").map(|window| window.parse::<u64>()) {
+        Some(Ok(window)) if window > 0 => window,
+        Some(_) => return Err(ApiError::new("INVALID_ARGUMENT", "`window` must be a positive number of seconds").field("window")),
+        None => DEFAULT_HEATMAP_WINDOW.as_secs(),
+    };
+    match params.get("format").map(String::as_str) {
+        Some("csv") => match api.api_heatmap_csv(&prefix, window) {
+            Ok(body) => Ok(([(header::CONTENT_TYPE, "text/csv")], body).into_response()),
+            Err(e) => Ok(internal_error(e)),
+        },
+        Some("json") | None => Ok(json(api.api_heatmap(prefix, window))),
+        Some(other) => Err(ApiError::new("INVALID_ARGUMENT", format!("Unknown format: {} (expected json or csv)", other)).field("format")),
+    }
+}
+
+/// Runs a command envelope; parse failures are answered with 400 and the
+/// protocol's error code
+async fn command(
+    State(api): State<SharedApi>,
+    Extension(admin): Extension<AdminAccess>,
+    user: Option<Extension<AclUser>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let is_cbor = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/cbor"));
+
+    if is_cbor {
+        let command = RustdisProtocol::parse_command_cbor(&body).map_err(|e| ApiError::new(e.code, e.message))?;
+        require_access(&command, admin, user.as_deref())?;
+        return Ok(match api.api_execute_cbor(command) {
+            Ok(reply) => ([(header::CONTENT_TYPE, "application/cbor")], reply).into_response(),
+            Err(e) => internal_error(e),
+        });
+    }
+    let body = std::str::from_utf8(&body).map_err(|_| ApiError::new("INVALID_JSON", "Request body must be UTF-8 JSON"))?;
+    let command = RustdisProtocol::parse_command(body).map_err(|e| ApiError::new(e.code, e.message))?;
+    require_access(&command, admin, user.as_deref())?;
+    Ok(json(api.api_execute(command)))
+}
+
+/// Refuses admin commands off the admin listener, then commands the user's
+/// role doesn't allow
+fn require_access(command: &Command, admin: AdminAccess, user: Option<&AclUser>) -> Result<(), ApiError> {
+    if command.is_admin() && !admin.0 {
+        return Err(ApiError {
+            status: StatusCode::FORBIDDEN,
+            ..ApiError::new("ADMIN_ONLY", "Admin commands are only accepted on the admin listener")
+        });
+    }
+    match user.and_then(|user| Some((user, user.role.denied(command)?))) {
+        Some((user, denied)) => Err(no_permission(user, denied)),
+        None => Ok(()),
+    }
+}
+
+fn no_permission(user: &AclUser, command: &str) -> ApiError {
+    ApiError {
+        status: StatusCode::FORBIDDEN,
+        ..ApiError::new("NOPERM", user.no_permission(command))
+    }
+}
+
+/// Runs every operation in order and reports each outcome, so one bad
+/// operation doesn't fail the whole request
+async fn batch(
+    State(api): State<SharedApi>,
+    Extension(admin): Extension<AdminAccess>,
+    user: Option<Extension<AclUser>>,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let body = BatchBody::parse(&body)?;
+    Ok(json(api.api_batch(body.operations, body.atomic, admin.0, user.as_deref())))
+}
+
+/// Executes a GraphQL request; GraphQL errors are reported in the response
+/// body with a 200, as the GraphQL over HTTP convention expects
+#[cfg(feature = "graphql")]
+async fn graphql(State(schema): State<crate::graphql::RustdisSchema>, body: Bytes) -> Result<HttpResponse, ApiError> {
+    let request: async_graphql::Request = serde_json::from_slice(&body).map_err(|e| ApiError::new("INVALID_JSON", e.to_string()))?;
+    let response = schema.execute(request).await;
+    Ok(json(serde_json::to_string(&response).map_err(anyhow::Error::from)))
+}
+
+/// Prometheus metrics for this listener
+async fn metrics(State(metrics): State<Arc<HttpMetrics>>) -> HttpResponse {
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], metrics.render()).into_response()
+}
+
+async fn docs(State(api): State<SharedApi>) -> HttpResponse {
+    ([(header::CONTENT_TYPE, "text/markdown; charset=utf-8")], api.api_docs()).into_response()
+}
+
+fn json(result: Result<String>) -> HttpResponse {
+    match result {
+        Ok(body) => ([(header::CONTENT_TYPE, "application/json")], body).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+fn internal_error(error: anyhow::Error) -> HttpResponse {
+    let body = serde_json::json!({ "error": error.to_string() }).to_string();
+    (StatusCode::INTERNAL_SERVER_ERROR, [(header::CONTENT_TYPE, "application/json")], body).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_compresses_large_responses() {
+        let cache = RustdisCache::new();
+        let app = router(Arc::new(RustdisApi::new(cache.clone())), &HttpConfig::default()).unwrap();
+        cache.set("big".to_string(), "x".repeat(4096)).unwrap();
+
+        let request = |uri: &str| Request::get(uri).header(header::ACCEPT_ENCODING, "gzip").body(Body::empty()).unwrap();
+
+        let big = app.clone().oneshot(request("/api/get?key=big")).await.unwrap();
+        assert_eq!(big.status(), StatusCode::OK);
+        assert_eq!(big.headers()[header::CONTENT_ENCODING], "gzip");
+        let compressed = big.into_body().collect().await.unwrap().to_bytes();
+        assert!(compressed.len() < 4096);
+
+        // Tiny replies are sent as-is
+        let small = app.oneshot(request("/api/ping")).await.unwrap();
+        assert!(small.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(small.into_body().collect().await.unwrap().to_bytes(), "\"PONG\"");
+    }
+
+    #[tokio::test]
+    async fn test_serves_typed_values_as_is() {
+        let cache = RustdisCache::new();
+        let app = router(Arc::new(RustdisApi::new(cache.clone())), &HttpConfig::default()).unwrap();
+        cache.set_bytes("img".to_string(), vec![0x89, b'P', b'N', b'G']).unwrap();
+        cache.meta_set("img", vec![("Content-Type".to_string(), "image/png".to_string())]).unwrap();
+        cache.set("frag".to_string(), "{}".to_string()).unwrap();
+        cache.expire("frag", Duration::from_secs(300)).unwrap();
+        cache.meta_set("frag", vec![("content-type".to_string(), "application/json".to_string())]).unwrap();
+
+        let image = app.clone().oneshot(Request::get("/api/get?key=img").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(image.status(), StatusCode::OK);
+        assert_eq!(image.headers()[header::CONTENT_TYPE], "image/png");
+        assert_eq!(image.headers()[header::CACHE_CONTROL], "no-cache");
+        let etag = image.headers()[header::ETAG].clone();
+        assert_eq!(image.into_body().collect().await.unwrap().to_bytes(), &[0x89, b'P', b'N', b'G'][..]);
+
+        let revalidate = Request::get("/api/get?key=img").header(header::IF_NONE_MATCH, etag).body(Body::empty()).unwrap();
+        assert_eq!(app.clone().oneshot(revalidate).await.unwrap().status(), StatusCode::NOT_MODIFIED);
+
+        let fragment = app.clone().oneshot(Request::get("/api/get?key=frag").body(Body::empty()).unwrap()).await.unwrap();
+        let max_age = fragment.headers()[header::CACHE_CONTROL].to_str().unwrap().strip_prefix("max-age=").unwrap().parse::<u64>().unwrap();
+        assert!((299..=300).contains(&max_age));
+        assert_eq!(fragment.into_body().collect().await.unwrap().to_bytes(), "{}");
+
+        // Compressed, the same value only carries a weak ETag
+        cache.set("page".to_string(), "<p>rustdis</p>".repeat(100)).unwrap();
+        cache.meta_set("page", vec![("content-type".to_string(), "text/html".to_string())]).unwrap();
+        let gzip = |key: &str| Request::get(format!("/api/get?key={}", key)).header(header::ACCEPT_ENCODING, "gzip");
+        let page = app.clone().oneshot(gzip("page").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(page.headers()[header::CONTENT_ENCODING], "gzip");
+        let weak = page.headers()[header::ETAG].clone();
+        assert!(weak.to_str().unwrap().starts_with("W/\""));
+        let revalidate = gzip("page").header(header::IF_NONE_MATCH, weak).body(Body::empty()).unwrap();
+        assert_eq!(app.clone().oneshot(revalidate).await.unwrap().status(), StatusCode::NOT_MODIFIED);
+
+        // An explicit encoding still gets the JSON reply
+        let encoded = app.oneshot(Request::get("/api/get?key=img&encoding=base64").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(encoded.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(encoded.headers()[header::CACHE_CONTROL], "no-store");
+    }
+
+    #[tokio::test]
+    async fn test_resource_routes() {
+        let cache = RustdisCache::new();
+        let app = router(Arc::new(RustdisApi::new(cache.clone())), &HttpConfig::default()).unwrap();
+        let send = |method: Method, uri: &str, body: &'static str| {
+            let request = Request::builder().method(method).uri(uri).body(Body::from(body)).unwrap();
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                (status, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+
+        assert_eq!(send(Method::PUT, "/api/keys/user%3A1", "ana").await.0, StatusCode::NO_CONTENT);
+        cache.set("order:1".to_string(), "x".to_string()).unwrap();
+        assert_eq!(send(Method::GET, "/api/keys/user:1", "").await, (StatusCode::OK, r#""ana""#.to_string()));
+        assert_eq!(send(Method::GET, "/api/keys?pattern=user:*", "").await.1, r#"["user:1"]"#);
+        assert_eq!(send(Method::DELETE, "/api/keys/user:1", "").await.1, "true");
+        assert_eq!(send(Method::GET, "/api/keys/user:1", "").await.1, "null");
+
+        assert_eq!(send(Method::PUT, "/api/keys/cart:1/fields/sku", "42").await.1, "1");
+        assert_eq!(send(Method::PUT, "/api/keys/cart:1/fields/qty", "2").await.1, "1");
+        assert_eq!(send(Method::GET, "/api/keys/cart:1/fields/sku", "").await.1, r#""42""#);
+        assert_eq!(send(Method::GET, "/api/keys/cart:1/fields", "").await.1, r#"{"qty":"2","sku":"42"}"#);
+        assert_eq!(send(Method::DELETE, "/api/keys/cart:1/fields/sku", "").await.1, "1");
+        assert_eq!(cache.hgetall("cart:1").unwrap(), [("qty".to_string(), "2".to_string())]);
+        let (status, body) = send(Method::GET, "/api/keys/order:1/fields/sku", "").await;
+        assert!(body.contains("WRONGTYPE"), "{} {}", status, body);
+    }
+
+    #[tokio::test]
+    async fn test_conditional_put() {
+        let cache = RustdisCache::new();
+        let app = router(Arc::new(RustdisApi::new(cache.clone())), &HttpConfig::default()).unwrap();
+        let put = |value: &'static str, condition: Option<(HeaderName, HeaderValue)>| {
+            let mut request = Request::put("/api/keys/doc").body(Body::from(value)).unwrap();
+            request.headers_mut().extend(condition);
+            app.clone().oneshot(request)
+        };
+        let if_none_match = || Some((header::IF_NONE_MATCH, HeaderValue::from_static("*")));
+
+        // Create-only writes succeed once
+        let created = put("v1", if_none_match()).await.unwrap();
+        assert_eq!(created.status(), StatusCode::NO_CONTENT);
+        let v1 = created.headers()[header::ETAG].clone();
+        assert_eq!(put("other", if_none_match()).await.unwrap().status(), StatusCode::PRECONDITION_FAILED);
+
+        // Only the writer holding the current ETag wins
+        let updated = put("v2", Some((header::IF_MATCH, v1.clone()))).await.unwrap();
+        assert_eq!(updated.status(), StatusCode::NO_CONTENT);
+        let v2 = updated.headers()[header::ETAG].clone();
+        let stale = put("v3", Some((header::IF_MATCH, v1))).await.unwrap();
+        assert_eq!(stale.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(cache.get("doc").unwrap().as_deref(), Some("v2"));
+        let weak = HeaderValue::from_str(&format!("W/{}", v2.to_str().unwrap())).unwrap();
+        assert_eq!(put("v3", Some((header::IF_MATCH, weak))).await.unwrap().status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(put("v3", Some((header::IF_MATCH, v2))).await.unwrap().status(), StatusCode::NO_CONTENT);
+        let foreign = Some((header::IF_MATCH, HeaderValue::from_static("\"abc\"")));
+        assert_eq!(put("v4", foreign).await.unwrap().status(), StatusCode::PRECONDITION_FAILED);
+
+        // The ETag is the one typed reads send
+        cache.meta_set("doc", vec![("content-type".to_string(), "text/plain".to_string())]).unwrap();
+        let read = app.clone().oneshot(Request::get("/api/get?key=doc").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(put("v4", Some((header::IF_MATCH, read.headers()[header::ETAG].clone()))).await.unwrap().status(), StatusCode::NO_CONTENT);
+
+        let tagged = Some((header::IF_NONE_MATCH, HeaderValue::from_static("\"abc\"")));
+        assert_eq!(put("v5", tagged).await.unwrap().status(), StatusCode::BAD_REQUEST);
+        assert_eq!(cache.get("doc").unwrap().as_deref(), Some("v4"));
+    }
+
+    #[tokio::test]
+    async fn test_cors_and_security_headers() {
+        let cors = CorsConfig {
+            allowed_origins: vec!["https://dash.example.com".to_string()],
+            allow_credentials: true,
+            ..Default::default()
+        };
+        let config = HttpConfig { cors: Some(cors), ..Default::default() };
+        let app = router(Arc::new(RustdisApi::new(RustdisCache::new())), &config).unwrap();
+
+        let preflight = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/api/set")
+            .header(header::ORIGIN, "https://dash.example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(preflight).await.unwrap();
+        assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "https://dash.example.com");
+        assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+
+        let other = Request::get("/api/ping").header(header::ORIGIN, "https://evil.example.com").body(Body::empty()).unwrap();
+        let response = app.oneshot(other).await.unwrap();
+        assert!(response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+        assert_eq!(response.headers()[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+
+        let wildcard = CorsConfig { allow_credentials: true, ..Default::default() };
+        let config = HttpConfig { cors: Some(wildcard), ..Default::default() };
+        assert!(router(Arc::new(RustdisApi::new(RustdisCache::new())), &config).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key_replays_response() {
+        let app = router(Arc::new(RustdisApi::new(RustdisCache::new())), &HttpConfig::default()).unwrap();
+        let genid = |key: Option<&str>, body: &str| {
+            let mut request = Request::post("/api/command");
+            if let Some(key) = key {
+                request = request.header("idempotency-key", key);
+            }
+            request.body(Body::from(body.to_string())).unwrap()
+        };
+        let send = |request: Request<Body>| {
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let replayed = response.headers().contains_key("idempotent-replayed");
+                (response.status(), replayed, response.into_body().collect().await.unwrap().to_bytes())
+            }
+        };
+
+        let (status, replayed, first) = send(genid(Some("req-1"), r#"{"command": "GENID"}"#)).await;
+        assert_eq!((status, replayed), (StatusCode::OK, false));
+        let (_, replayed, retry) = send(genid(Some("req-1"), r#"{"command": "GENID"}"#)).await;
+        assert!(replayed);
+        assert_eq!(first, retry);
+
+        // Without a key every call runs
+        let (_, _, fresh) = send(genid(None, r#"{"command": "GENID"}"#)).await;
+        assert_ne!(first, fresh);
+
+        let (status, _, _) = send(genid(Some("req-1"), r#"{"command": "GENID", "args": {"kind": "ulid"}}"#)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_validation_errors() {
+        let app = router(Arc::new(RustdisApi::new(RustdisCache::new())), &HttpConfig::default()).unwrap();
+        let error = |response: HttpResponse| async move {
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (status, serde_json::from_slice::<Value>(&body).unwrap())
+        };
+        let post = |uri: &str, body: &str| Request::post(uri).body(Body::from(body.to_string())).unwrap();
+
+        let (status, body) = error(app.clone().oneshot(post("/api/set", r#"{"key": 1, "value": "v"}"#)).await.unwrap()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!((body["code"].as_str(), body["field"].as_str()), (Some("INVALID_TYPE"), Some("key")));
+
+        let (_, body) = error(app.clone().oneshot(post("/api/set", r#"{"key": "k"}"#)).await.unwrap()).await;
+        assert_eq!((body["code"].as_str(), body["field"].as_str()), (Some("MISSING_FIELD"), Some("value")));
+
+        let (_, body) = error(app.clone().oneshot(post("/api/set", "{")).await.unwrap()).await;
+        assert_eq!(body["code"], "INVALID_JSON");
+
+        let huge_key = format!(r#"{{"key": "{}", "value": "v"}}"#, "k".repeat(MAX_KEY_BYTES + 1));
+        let (status, body) = error(app.clone().oneshot(post("/api/set", &huge_key)).await.unwrap()).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::PAYLOAD_TOO_LARGE, Some("VALUE_TOO_LARGE")));
+
+        let missing = Request::get("/api/get").body(Body::empty()).unwrap();
+        let (_, body) = error(app.clone().oneshot(missing).await.unwrap()).await;
+        assert_eq!((body["code"].as_str(), body["field"].as_str()), (Some("MISSING_FIELD"), Some("key")));
+
+        let (status, body) = error(app.oneshot(post("/api/command", r#"{"command": "NOPE"}"#)).await.unwrap()).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::BAD_REQUEST, Some("UNKNOWN_COMMAND")));
+    }
+
+    #[tokio::test]
+    async fn test_base64_values() {
+        let cache = RustdisCache::new();
+        let app = router(Arc::new(RustdisApi::new(cache.clone())), &HttpConfig::default()).unwrap();
+        let send = |request: Request<Body>| {
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                (status, serde_json::from_slice::<Value>(&body).unwrap())
+            }
+        };
+        let post = |body: &str| Request::post("/api/set").body(Body::from(body.to_string())).unwrap();
+        let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+
+        // 0xff 0x00 0x01 isn't UTF-8
+        let (status, _) = send(post(r#"{"key": "bin", "value": "/wAB", "encoding": "base64"}"#)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(cache.get_bytes("bin").unwrap(), Some(vec![0xff, 0x00, 0x01]));
+        assert_eq!(send(get("/api/get?key=bin&encoding=base64")).await.1, "/wAB");
+        assert_eq!(send(get("/api/get?key=bin")).await.1["code"], "NOT_UTF8");
+
+        let (_, body) = send(post(r#"{"key": "bin", "value": "!!", "encoding": "base64"}"#)).await;
+        assert_eq!(body["code"], "INVALID_ARGUMENT");
+        let (status, body) = send(get("/api/get?key=bin&encoding=hex")).await;
+        assert_eq!((status, body["field"].as_str()), (StatusCode::BAD_REQUEST, Some("encoding")));
+    }
+
+    #[tokio::test]
+    async fn test_batch_partial_results() {
+        let cache = RustdisCache::new();
+        let app = router(Arc::new(RustdisApi::new(cache.clone())), &HttpConfig::default()).unwrap();
+        let batch = |body: &str| Request::post("/api/batch").body(Body::from(body.to_string())).unwrap();
+
+        let body = r#"{"operations": [
+            {"command": "SET", "args": {"key": "a", "value": "1"}},
+            {"command": "NOPE"},
+            {"command": "GET", "args": {"key": "a"}}
+        ]}"#;
+        let response = app.clone().oneshot(batch(body)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = serde_json::from_slice(&response.into_body().collect().await.unwrap().to_bytes()).unwrap();
+        assert_eq!((body["succeeded"].as_u64(), body["failed"].as_u64()), (Some(2), Some(1)));
+        assert_eq!(body["results"][1]["code"], "UNKNOWN_COMMAND");
+        assert_eq!(body["results"][2], serde_json::json!({ "status": "ok", "result": "1" }));
+        assert_eq!(cache.get("a").unwrap(), Some("1".to_string()));
+
+        // Atomic batches run all or nothing at all
+        let run = |body: &'static str| {
+            let response = app.clone().oneshot(batch(body));
+            async move { serde_json::from_slice::<Value>(&response.await.unwrap().into_body().collect().await.unwrap().to_bytes()).unwrap() }
+        };
+        let atomic = r#"{"atomic": true, "operations": [
+            {"command": "SET", "args": {"key": "a", "value": "2"}},
+            {"command": "NOPE"}
+        ]}"#;
+        let body = run(atomic).await;
+        assert_eq!((body["results"][0]["code"].as_str(), body["results"][1]["code"].as_str()), (Some("EXECABORT"), Some("UNKNOWN_COMMAND")));
+        assert_eq!(cache.get("a").unwrap(), Some("1".to_string()));
+        let atomic = r#"{"atomic": true, "operations": [
+            {"command": "SET", "args": {"key": "a", "value": "2"}},
+            {"command": "INCR", "args": {"key": "a"}}
+        ]}"#;
+        let body = run(atomic).await;
+        assert_eq!(body["results"][1], serde_json::json!({ "status": "ok", "result": 3 }));
+        let missing = app.oneshot(batch("{}")).await.unwrap();
+        assert_eq!(missing.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_admin_listener_separation() {
+        let api = Arc::new(RustdisApi::new(RustdisCache::new()));
+        let config = HttpConfig { admin_addr: Some(SocketAddr::from(([127, 0, 0, 1], 8081))), ..HttpConfig::default() };
+        let data = router(api.clone(), &config).unwrap();
+        let admin = admin_router(api, &config).unwrap();
+        let post = |uri: &str, body: &str| Request::post(uri).body(Body::from(body.to_string())).unwrap();
+        let flush = || Request::delete("/api/flush").body(Body::empty()).unwrap();
+
+        assert_eq!(data.clone().oneshot(flush()).await.unwrap().status(), StatusCode::NOT_FOUND);
+        let config_set = r#"{"command": "CONFIG.SET", "args": {"parameter": "maxmemory", "value": "1"}}"#;
+        let refused = data.clone().oneshot(post("/api/command", config_set)).await.unwrap();
+        assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+        let profiled = r#"{"command": "PROFILE", "args": {"command": {"command": "FLUSH"}}}"#;
+        assert_eq!(data.clone().oneshot(post("/api/command", profiled)).await.unwrap().status(), StatusCode::FORBIDDEN);
+
+        let batch = format!(r#"{{"operations": [{{"command": "PING"}}, {}]}}"#, config_set);
+        let response = data.clone().oneshot(post("/api/batch", &batch)).await.unwrap();
+        let body: Value = serde_json::from_slice(&response.into_body().collect().await.unwrap().to_bytes()).unwrap();
+        assert_eq!((body["succeeded"].as_u64(), body["results"][1]["code"].as_str()), (Some(1), Some("ADMIN_ONLY")));
+        assert_eq!(data.oneshot(post("/api/command", r#"{"command": "PING"}"#)).await.unwrap().status(), StatusCode::OK);
+
+        assert_eq!(admin.clone().oneshot(flush()).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(admin.oneshot(post("/api/command", config_set)).await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_bearer_auth() {
+        use crate::auth::{Roles, StaticPasswords};
+        use crate::protocol::SUPPORTED_COMMANDS;
+
+        let mut roles = Roles::new();
+        roles.define("reader=GET,PING").unwrap();
+        let mut passwords = StaticPasswords::new();
+        let (user, password) = StaticPasswords::parse_user("default:pw:reader", &roles).unwrap();
+        passwords.insert(user, password);
+        let config = HttpConfig { auth: Authenticator::new().with_provider(passwords), ..HttpConfig::default() };
+        let app = router(Arc::new(RustdisApi::new(RustdisCache::new())), &config).unwrap();
+        let request = |token: Option<&str>, method: Method, uri: &str, body: &str| {
+            let mut request = Request::builder().method(method).uri(uri);
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+            }
+            request.body(Body::from(body.to_string())).unwrap()
+        };
+        let reply = |response: HttpResponse| async move {
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (status, serde_json::from_slice::<Value>(&body).unwrap())
+        };
+
+        let missing = app.clone().oneshot(request(None, Method::GET, "/api/ping", "")).await.unwrap();
+        assert_eq!(missing.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(missing.headers()[header::WWW_AUTHENTICATE], "Bearer");
+        let wrong = app.clone().oneshot(request(Some("nope"), Method::GET, "/api/ping", "")).await.unwrap();
+        assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED);
+        let ok = app.clone().oneshot(request(Some("pw"), Method::GET, "/api/get?key=k", "")).await.unwrap();
+        assert_eq!(ok.status(), StatusCode::OK);
+        // The access log names the user
+        assert_eq!(ok.extensions().get::<Principal>().map(|principal| principal.0.as_str()), Some("default"));
+        assert!(missing.extensions().get::<Principal>().is_none());
+
+        // The reader role only allows GET and PING, on every kind of endpoint
+        let set = request(Some("pw"), Method::POST, "/api/set", r#"{"key": "k", "value": "v"}"#);
+        let (status, body) = reply(app.clone().oneshot(set).await.unwrap()).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::FORBIDDEN, Some("NOPERM")));
+        let flush = app.clone().oneshot(request(Some("pw"), Method::DELETE, "/api/flush", "")).await.unwrap();
+        assert_eq!(flush.status(), StatusCode::FORBIDDEN);
+        let command = request(Some("pw"), Method::POST, "/api/command", r#"{"command": "SIZE"}"#);
+        let (status, body) = reply(app.clone().oneshot(command).await.unwrap()).await;
+        assert_eq!((status, body["error"].as_str()), (StatusCode::FORBIDDEN, Some("User default has no permissions to run the 'SIZE' command")));
+        let batch = r#"{"operations": [{"command": "PING"}, {"command": "DEL", "args": {"key": "k"}}]}"#;
+        let (_, body) = reply(app.oneshot(request(Some("pw"), Method::POST, "/api/batch", batch)).await.unwrap()).await;
+        assert_eq!((body["succeeded"].as_u64(), body["results"][1]["code"].as_str()), (Some(1), Some("NOPERM")));
+
+        for (_, _, commands) in ENDPOINT_COMMANDS {
+            assert!(commands.iter().all(|command| SUPPORTED_COMMANDS.contains(command)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_endpoint_metrics() {
+        let config = HttpConfig { access_log: false, ..HttpConfig::default() };
+        let app = router(Arc::new(RustdisApi::new(RustdisCache::new())), &config).unwrap();
+        let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+
+        app.clone().oneshot(get("/api/get?key=a")).await.unwrap();
+        app.clone().oneshot(get("/api/get")).await.unwrap();
+        app.clone().oneshot(get("/nope/123")).await.unwrap();
+
+        let response = app.oneshot(get("/metrics")).await.unwrap();
+        let text = String::from_utf8(response.into_body().collect().await.unwrap().to_bytes().to_vec()).unwrap();
+        assert!(text.contains("rustdis_http_requests_total{method=\"GET\",path=\"/api/get\",status=\"200\"} 1\n"));
+        assert!(text.contains("rustdis_http_requests_total{method=\"GET\",path=\"/api/get\",status=\"400\"} 1\n"));
+        assert!(text.contains("rustdis_http_request_duration_seconds_count{method=\"GET\",path=\"/api/get\"} 2\n"));
+        // Raw paths never become labels
+        assert!(text.contains("path=\"unmatched\",status=\"404\""));
+        assert!(!text.contains("/nope/123"));
+    }
+}
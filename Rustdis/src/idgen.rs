@@ -0,0 +1,157 @@
+use anyhow::Result;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Custom epoch for snowflake IDs (2024-01-01T00:00:00Z, in milliseconds)
+const SNOWFLAKE_EPOCH_MS: u64 = 1_704_067_200_000;
+const NODE_BITS: u32 = 10;
+const SEQUENCE_BITS: u32 = 12;
+pub const MAX_NODE_ID: u16 = (1 << NODE_BITS) - 1;
+const MAX_SEQUENCE: u16 = (1 << SEQUENCE_BITS) - 1;
+
+/// Kinds of IDs GENID can produce
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdKind {
+    #[default]
+    Uuid,
+    Ulid,
+    Snowflake,
+}
+
+impl FromStr for IdKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "uuid" => Ok(IdKind::Uuid),
+            "ulid" => Ok(IdKind::Ulid),
+            "snowflake" => Ok(IdKind::Snowflake),
+            _ => Err(anyhow::anyhow!("Unknown ID kind: {} (expected uuid, ulid or snowflake)", s)),
+        }
+    }
+}
+
+/// Last snowflake issued, used to keep IDs monotonic
+///
+/// Saved in snapshots so a restart onto a clock that went backwards can't
+/// reissue IDs from before the restart.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SnowflakeState {
+    pub last_ms: u64,
+    pub sequence: u16,
+}
+
+/// Server-side unique ID generator
+///
+/// Snowflake IDs are 41 bits of milliseconds since `SNOWFLAKE_EPOCH_MS`,
+/// 10 bits of node ID and 12 bits of per-millisecond sequence. If the wall
+/// clock moves backwards the generator keeps issuing from the last
+/// timestamp it used (borrowing the next millisecond when the sequence is
+/// exhausted), so IDs stay unique and ordered without blocking.
+#[derive(Debug, Default)]
+pub struct IdGenerator {
+    node_id: u16,
+    state: Mutex<SnowflakeState>,
+}
+
+impl IdGenerator {
+    pub fn new(node_id: u16) -> Result<Self> {
+        if node_id > MAX_NODE_ID {
+            return Err(anyhow::anyhow!("Node ID must be between 0 and {}", MAX_NODE_ID));
+        }
+        Ok(Self {
+            node_id,
+            state: Mutex::new(SnowflakeState::default()),
+        })
+    }
+
+    pub fn node_id(&self) -> u16 {
+        self.node_id
+    }
+
+    /// The last snowflake timestamp and sequence issued
+    pub fn snowflake_state(&self) -> Result<SnowflakeState> {
+        Ok(*self.state.lock().map_err(|_| anyhow::anyhow!("Failed to acquire ID generator lock"))?)
+    }
+
+    /// Resumes from a saved state; never moves the generator backwards
+    pub fn restore_snowflake(&self, saved: SnowflakeState) -> Result<()> {
+        let mut state = self.state.lock().map_err(|_| anyhow::anyhow!("Failed to acquire ID generator lock"))?;
+        *state = (*state).max(saved);
+        Ok(())
+    }
+
+    /// Generates a new ID of the given kind
+    pub fn generate(&self, kind: IdKind) -> Result<String> {
+        match kind {
+            IdKind::Uuid => Ok(uuid::Uuid::new_v4().to_string()),
+            IdKind::Ulid => Ok(ulid::Ulid::new().to_string()),
+            IdKind::Snowflake => Ok(self.next_snowflake(Self::now_ms())?.to_string()),
+        }
+    }
+
+    fn next_snowflake(&self, now_ms: u64) -> Result<u64> {
+        let mut state = self.state.lock().map_err(|_| anyhow::anyhow!("Failed to acquire ID generator lock"))?;
+
+        if now_ms > state.last_ms {
+            state.last_ms = now_ms;
+            state.sequence = 0;
+        } else if state.sequence < MAX_SEQUENCE {
+            // Same millisecond, or the clock went backwards
+            state.sequence += 1;
+        } else {
+            state.last_ms += 1;
+            state.sequence = 0;
+        }
+
+        let elapsed = state.last_ms.saturating_sub(SNOWFLAKE_EPOCH_MS);
+        Ok((elapsed << (NODE_BITS + SEQUENCE_BITS))
+            | ((self.node_id as u64) << SEQUENCE_BITS)
+            | state.sequence as u64)
+    }
+
+    fn now_ms() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snowflake_monotonic_under_clock_skew() {
+        let ids = IdGenerator::new(7).unwrap();
+        let base = SNOWFLAKE_EPOCH_MS + 1_000;
+
+        let first = ids.next_snowflake(base).unwrap();
+        let second = ids.next_snowflake(base).unwrap();
+        // Clock jumps backwards
+        let third = ids.next_snowflake(base - 500).unwrap();
+        let fourth = ids.next_snowflake(base + 1).unwrap();
+
+        assert!(first < second && second < third && third < fourth);
+        assert_eq!((first >> SEQUENCE_BITS) & MAX_NODE_ID as u64, 7);
+        assert!(IdGenerator::new(MAX_NODE_ID + 1).is_err());
+
+        // A restarted generator resumes after the saved state
+        let restarted = IdGenerator::new(7).unwrap();
+        restarted.restore_snowflake(ids.snowflake_state().unwrap()).unwrap();
+        restarted.restore_snowflake(SnowflakeState::default()).unwrap();
+        assert!(restarted.next_snowflake(base - 500).unwrap() > fourth);
+    }
+
+    #[test]
+    fn test_generate_kinds() {
+        let ids = IdGenerator::default();
+        assert_eq!(ids.generate(IdKind::Uuid).unwrap().len(), 36);
+        assert_eq!(ids.generate(IdKind::Ulid).unwrap().len(), 26);
+        assert!(ids.generate(IdKind::Snowflake).unwrap().parse::<u64>().is_ok());
+        assert_eq!("ULID".parse::<IdKind>().unwrap(), IdKind::Ulid);
+    }
+}
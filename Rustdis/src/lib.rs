@@ -0,0 +1,40 @@
+pub mod allocator;
+pub mod analyze;
+pub mod auth;
+pub mod backend;
+pub mod blob;
+pub mod cache;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod client;
+pub mod clock;
+pub mod compat;
+pub mod copy;
+pub mod dump;
+pub mod eviction;
+pub mod flags;
+pub mod framing;
+#[cfg(feature = "functions")]
+pub mod functions;
+pub mod glob;
+pub mod heatmap;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+pub mod http;
+pub mod idgen;
+pub mod metrics;
+pub mod profile;
+pub mod pubsub;
+pub mod resp;
+#[cfg(feature = "scripting")]
+pub mod scripting;
+pub mod seen;
+pub mod snapshot;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod transaction;
+pub mod warm;
+pub mod zset;
+pub mod protocol;
+pub mod cli;
+pub mod api;
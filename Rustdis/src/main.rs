@@ -1,19 +1,110 @@
-mod cache;
-mod protocol;
-mod cli;
-mod api;
-
-use cache::RustdisCache;
-use cli::RustdisCli;
-use api::RustdisApi;
-use clap::{Parser, Subcommand};
+use rustdis::cache::{ActiveExpireConfig, RustdisCache, MAX_SHARDS};
+use rustdis::cli::{DisplayOptions, ExitStatus, RustdisCli, DEFAULT_TRUNCATE_AT};
+use rustdis::analyze::{self, ExportFormat};
+use rustdis::api::RustdisApi;
+use rustdis::auth::{Authenticator, Roles, StaticPasswords};
+use rustdis::client::RespClient;
+use rustdis::copy::{self, CopyOptions, DEFAULT_COPY_BATCH};
+use rustdis::dump::{self, ImportOptions, KeyRemap};
+use rustdis::http::{self, CorsConfig, HttpConfig, DEFAULT_COMPRESSION_THRESHOLD, DEFAULT_IDEMPOTENCY_WINDOW};
+use rustdis::idgen::IdKind;
+use rustdis::protocol::{Command, ValueEncoding};
+use rustdis::resp;
+use rustdis::snapshot::{parse_save_points, DEFAULT_SNAPSHOT_PATH};
+use rustdis::warm::{self, WarmRule};
+use clap::{Args, Parser, Subcommand};
 use anyhow::Result;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+#[cfg(feature = "mimalloc")]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
 #[derive(Parser)]
 #[command(name = "rustdis")]
 #[command(about = "A Redis clone written in Rust")]
 #[command(version = "0.1.0")]
 struct Cli {
+    /// Node ID (0-1023) embedded in snowflake IDs
+    #[arg(long, global = true, default_value_t = 0)]
+    node_id: u16,
+
+    /// Print long values in full instead of truncating them
+    #[arg(long, global = true)]
+    no_truncate: bool,
+
+    /// Hex-dump values that contain non-printable characters
+    #[arg(long, global = true)]
+    hex: bool,
+
+    /// Show value sizes and TTLs alongside GET results
+    #[arg(long, global = true)]
+    verbose: bool,
+
+    /// Print bare values without JSON, and errors as `CODE message` on stderr
+    #[arg(long, short, global = true)]
+    quiet: bool,
+
+    /// Split the keyspace into this many shards, each behind its own lock,
+    /// so writes to keys in different shards don't wait for each other
+    #[arg(long, global = true, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..=MAX_SHARDS as u64))]
+    shards: u64,
+
+    /// Milliseconds between background sweeps for expired keys (0 disables)
+    #[arg(long, global = true, default_value_t = ActiveExpireConfig::default().interval.as_millis() as u64)]
+    active_expire_ms: u64,
+
+    /// Background sweeps per second, as Redis' hz (sets --active-expire-ms)
+    #[arg(long, global = true, conflicts_with = "active_expire_ms", value_parser = clap::value_parser!(u64).range(1..=500))]
+    hz: Option<u64>,
+
+    /// Keys with a TTL sampled per background sweep round
+    #[arg(long, global = true, default_value_t = ActiveExpireConfig::default().samples)]
+    active_expire_samples: usize,
+
+    /// Percent of each sweep interval a sweep may run for
+    #[arg(long, global = true, default_value_t = ActiveExpireConfig::default().max_effort, value_parser = clap::value_parser!(u8).range(1..=100))]
+    active_expire_effort: u8,
+
+    /// Store operations per second above which sweeps back off (0 never backs off)
+    #[arg(long, global = true, default_value_t = ActiveExpireConfig::default().busy_ops)]
+    active_expire_busy_ops: u64,
+
+    /// Snapshot file loaded at startup if present, and written by SAVE and BGSAVE
+    #[arg(long, global = true, default_value = DEFAULT_SNAPSHOT_PATH)]
+    snapshot: PathBuf,
+
+    /// Keep durable state in this directory: the snapshot is loaded from and
+    /// saved to <DIR>/dump.rsnap, and CLI commands save their changes on exit
+    #[arg(long, global = true, conflicts_with = "snapshot")]
+    data_dir: Option<PathBuf>,
+
+    /// Load this snapshot and serve it read-only, e.g. for analytics away
+    /// from production: writes fail with READONLY, and keys never expire,
+    /// get evicted or get saved in the background
+    #[arg(long, global = true, value_name = "PATH", conflicts_with_all = ["snapshot", "data_dir", "warm"])]
+    serve_snapshot: Option<PathBuf>,
+
+    /// Save points as `<seconds> <changes>` pairs, e.g. "900 1 60 10000":
+    /// BGSAVE once that many writes happened that long after the last save
+    /// (empty disables; adjustable with CONFIG SET save)
+    #[arg(long, global = true, default_value = "")]
+    save: String,
+
+    /// Preload keys matching PATTERN from the RESP server SOURCE when a
+    /// server starts, and every SECS seconds if given (repeatable)
+    #[arg(long, global = true, value_name = "PATTERN=SOURCE[,every=SECS]")]
+    warm: Vec<WarmRule>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -25,11 +116,54 @@ enum Commands {
     /// Run a single command and exit
     Get { key: String },
     /// Set a key-value pair and exit
-    Set { key: String, value: String },
+    Set {
+        key: String,
+        value: String,
+        /// Only set the key if it doesn't exist
+        #[arg(long, conflicts_with = "xx")]
+        nx: bool,
+        /// Only set the key if it already exists
+        #[arg(long)]
+        xx: bool,
+        /// Expire after this many seconds
+        #[arg(long, conflicts_with_all = ["px", "keepttl"])]
+        ex: Option<u64>,
+        /// Expire after this many milliseconds
+        #[arg(long, conflicts_with = "keepttl")]
+        px: Option<u64>,
+        /// Keep the key's current TTL
+        #[arg(long)]
+        keepttl: bool,
+        /// Print the previous value
+        #[arg(long)]
+        get: bool,
+    },
     /// Delete a key and exit
     Del { key: String },
     /// Check if key exists and exit
     Exists { key: String },
+    /// Increment an integer value by 1 and exit
+    Incr { key: String },
+    /// Decrement an integer value by 1 and exit
+    Decr { key: String },
+    /// Increment an integer value and exit
+    Incrby {
+        key: String,
+        #[arg(allow_negative_numbers = true)]
+        delta: i64,
+    },
+    /// Decrement an integer value and exit
+    Decrby {
+        key: String,
+        #[arg(allow_negative_numbers = true)]
+        delta: i64,
+    },
+    /// Increment a numeric value by a float and exit
+    Incrbyfloat {
+        key: String,
+        #[arg(allow_negative_numbers = true)]
+        delta: f64,
+    },
     /// List all keys and exit
     Keys,
     /// Clear all data and exit
@@ -38,69 +172,413 @@ enum Commands {
     Size,
     /// Test connection and exit
     Ping,
+    /// Generate a unique ID and exit
+    Genid {
+        /// uuid, ulid or snowflake
+        #[arg(default_value = "uuid")]
+        kind: IdKind,
+    },
     /// Show API documentation
     ApiDocs,
+    /// Export key metadata (type, size, TTL, idle time, namespace) for offline analysis
+    Analyze {
+        /// File to write; stdout if omitted
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// csv, or parquet (needs the `parquet` feature)
+        #[arg(long, default_value = "csv")]
+        format: ExportFormat,
+    },
+    /// Write a snapshot of the keys loaded from --snapshot to a file and exit
+    Save {
+        /// File to write
+        path: PathBuf,
+    },
+    /// Write every key of the loaded snapshot (values, types, TTLs) as JSON and exit
+    Export {
+        /// File to write; stdout if omitted
+        #[arg(long)]
+        file: Option<PathBuf>,
+        /// Only export keys matching this glob pattern, e.g. a namespace
+        #[arg(long = "match", value_name = "PATTERN")]
+        pattern: Option<String>,
+    },
+    /// Replace the keyspace with a JSON dump written by `export`, save it to
+    /// the snapshot and exit
+    Import {
+        /// File to read; stdin if omitted
+        #[arg(long)]
+        file: Option<PathBuf>,
+        /// Move keys under one prefix to another, e.g. 'stage:*=prod:*'
+        /// (repeatable; the first matching rule wins)
+        #[arg(long, value_name = "FROM*=TO*")]
+        remap: Vec<KeyRemap>,
+        /// Multiply the TTL each key has left, e.g. 0.5 to halve it
+        #[arg(long, value_name = "FACTOR")]
+        ttl_scale: Option<f64>,
+        /// Keep the keys already in the snapshot, replacing only those in the dump
+        #[arg(long)]
+        merge: bool,
+    },
+    /// Serve the Redis protocol (RESP2) over TCP, e.g. for redis-cli
+    Serve {
+        /// Port to listen on
+        #[arg(long, default_value_t = 6379)]
+        port: u16,
+        /// Address to bind
+        #[arg(long, default_value = "127.0.0.1")]
+        bind: IpAddr,
+        /// Word replies exactly like Redis does (errors, nested strings),
+        /// for clients that parse them; COMPAT switches a connection back
+        #[arg(long)]
+        redis_compat_strict: bool,
+        #[command(flatten)]
+        auth: AuthArgs,
+    },
+    /// Copy keys of every type (with their TTLs) between two Rustdis servers
+    Copy {
+        /// Source server, e.g. redis://127.0.0.1:6379
+        #[arg(long)]
+        from: String,
+        /// Destination server
+        #[arg(long)]
+        to: String,
+        /// Only copy keys matching this glob pattern
+        #[arg(long, default_value = "*")]
+        pattern: String,
+        /// Replace keys that already exist on the destination
+        #[arg(long)]
+        overwrite: bool,
+        /// Keys per pipelined round trip
+        #[arg(long, default_value_t = DEFAULT_COPY_BATCH)]
+        batch: usize,
+    },
+    /// Serve the HTTP API
+    Http {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        addr: SocketAddr,
+        /// Minimum response size in bytes before gzip/deflate is applied
+        #[arg(long, default_value_t = DEFAULT_COMPRESSION_THRESHOLD)]
+        compress_min_bytes: u16,
+        /// Enable CORS for this origin (repeatable; `*` allows any origin)
+        #[arg(long = "cors-origin")]
+        cors_origins: Vec<String>,
+        /// Methods allowed for cross-origin requests
+        #[arg(long = "cors-method", requires = "cors_origins")]
+        cors_methods: Vec<String>,
+        /// Request headers allowed for cross-origin requests
+        #[arg(long = "cors-header", requires = "cors_origins")]
+        cors_headers: Vec<String>,
+        /// Allow credentials (cookies, Authorization) on cross-origin requests
+        #[arg(long, requires = "cors_origins")]
+        cors_credentials: bool,
+        /// Don't send the default security headers
+        #[arg(long)]
+        no_security_headers: bool,
+        /// Seconds a response is replayed for retries with the same Idempotency-Key (0 disables)
+        #[arg(long, default_value_t = DEFAULT_IDEMPOTENCY_WINDOW.as_secs())]
+        idempotency_window_secs: u64,
+        /// Serve admin operations (FLUSH, CONFIG, DEBUG, ...) only on this separate address
+        #[arg(long)]
+        admin_addr: Option<SocketAddr>,
+        /// Don't print a line per request
+        #[arg(long)]
+        no_access_log: bool,
+        #[command(flatten)]
+        auth: AuthArgs,
+    },
+}
+
+/// Authentication providers for the servers, tried in the order below;
+/// with none configured every client is let in
+#[derive(Args)]
+struct AuthArgs {
+    /// Define a role allowed to run only these commands (repeatable);
+    /// `admin` and `readwrite` are built in
+    #[arg(long = "role", value_name = "NAME=CMD[,CMD...]")]
+    roles: Vec<String>,
+    /// User with a static password, as name:password[:role] (repeatable;
+    /// the role defaults to readwrite); the `default` user is checked when
+    /// no username is given
+    #[arg(long = "user", value_name = "NAME:PASSWORD[:ROLE]")]
+    users: Vec<String>,
+    /// Accept OIDC tokens (JWTs) issued by this issuer
+    #[cfg(feature = "oidc")]
+    #[arg(long, requires_all = ["oidc_audience", "oidc_key"])]
+    oidc_issuer: Option<String>,
+    /// Audience OIDC tokens must be issued for
+    #[cfg(feature = "oidc")]
+    #[arg(long, requires = "oidc_issuer")]
+    oidc_audience: Option<String>,
+    /// PEM file with the issuer's public signing key
+    #[cfg(feature = "oidc")]
+    #[arg(long, requires = "oidc_issuer")]
+    oidc_key: Option<PathBuf>,
+    /// Claim holding the token's roles, as a dotted path
+    #[cfg(feature = "oidc")]
+    #[arg(long, default_value = rustdis::auth::DEFAULT_ROLES_CLAIM)]
+    oidc_roles_claim: String,
+    /// Map a token role to an ACL user, as role=user[:role] (repeatable;
+    /// the first listed role the token carries wins)
+    #[cfg(feature = "oidc")]
+    #[arg(long = "oidc-role", value_name = "ROLE=USER[:ROLE]", requires = "oidc_issuer")]
+    oidc_roles: Vec<String>,
+}
+
+impl AuthArgs {
+    fn authenticator(self) -> Result<Authenticator> {
+        let mut auth = Authenticator::new();
+        // Users name roles, so every role is defined first
+        let mut roles = Roles::new();
+        for spec in &self.roles {
+            roles.define(spec)?;
+        }
+        if !self.users.is_empty() {
+            let mut passwords = StaticPasswords::new();
+            for spec in &self.users {
+                let (user, password) = StaticPasswords::parse_user(spec, &roles)?;
+                passwords.insert(user, password);
+            }
+            auth = auth.with_provider(passwords);
+        }
+        #[cfg(feature = "oidc")]
+        if let (Some(issuer), Some(audience), Some(key)) = (self.oidc_issuer, self.oidc_audience, self.oidc_key) {
+            let config = rustdis::auth::OidcConfig {
+                issuer,
+                audience,
+                roles_claim: self.oidc_roles_claim,
+                roles: self.oidc_roles.iter().map(|spec| parse_role(spec, &roles)).collect::<Result<_>>()?,
+            };
+            auth = auth.with_provider(rustdis::auth::OidcValidator::from_pem(config, &std::fs::read(key)?)?);
+        }
+        Ok(auth)
+    }
+}
+
+#[cfg(feature = "oidc")]
+fn parse_role(spec: &str, roles: &Roles) -> Result<(String, rustdis::auth::AclUser)> {
+    let (role, user) = spec.split_once('=').ok_or_else(|| anyhow::anyhow!("Invalid role mapping: expected role=user[:role]"))?;
+    Ok((role.to_string(), roles.user(user)?))
+}
+
+/// Exit codes: 0 found, 1 nil or false, 2 error, 3 connection failure
+fn main() -> ExitCode {
+    match run() {
+        Ok(status) => status.into(),
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            ExitStatus::Error.into()
+        }
+    }
 }
 
-fn main() -> Result<()> {
+fn run() -> Result<ExitStatus> {
     let cli = Cli::parse();
-    let cache = RustdisCache::new();
+    let cache = RustdisCache::with_node_id(cli.node_id)?.sharded(cli.shards as usize);
+    let snapshot = match (&cli.serve_snapshot, &cli.data_dir) {
+        (Some(path), _) if !path.exists() => return Err(anyhow::anyhow!("Snapshot {} not found", path.display())),
+        (Some(path), _) => path.clone(),
+        (None, Some(dir)) => {
+            std::fs::create_dir_all(dir)?;
+            dir.join(DEFAULT_SNAPSHOT_PATH)
+        }
+        (None, None) => cli.snapshot.clone(),
+    };
+    let loaded = cache.load_from(&snapshot)?;
+    let read_only = cli.serve_snapshot.is_some();
+    cache.set_read_only(read_only);
+    if loaded > 0 && !cli.quiet && matches!(cli.command, Some(Commands::Serve { .. } | Commands::Http { .. })) {
+        eprintln!("Loaded {} keys from {}", loaded, snapshot.display());
+    }
+    cache.snapshots().set_save_points(parse_save_points(&cli.save)?)?;
+    let active_expire_ms = cli.hz.map_or(cli.active_expire_ms, |hz| 1000 / hz);
+    let _active_expire = (active_expire_ms > 0 && !read_only).then(|| {
+        cache.spawn_active_expire(ActiveExpireConfig {
+            interval: Duration::from_millis(active_expire_ms),
+            samples: cli.active_expire_samples,
+            max_effort: cli.active_expire_effort,
+            busy_ops: cli.active_expire_busy_ops,
+        })
+    });
+    let _auto_save = (!read_only).then(|| cache.spawn_auto_save());
+    let durable = cli.data_dir.is_some();
+    let display = DisplayOptions {
+        truncate_at: (!cli.no_truncate).then_some(DEFAULT_TRUNCATE_AT),
+        hex: cli.hex,
+        verbose: cli.verbose,
+    };
 
-    match cli.command {
+    // One-shot subcommands run a single command and report through the exit status
+    let command = match cli.command {
         Some(Commands::Cli) | None => {
-            // Start interactive CLI
-            let cli_interface = RustdisCli::new(cache);
-            cli_interface.run()?;
+            RustdisCli::with_display(cache.clone(), display).run()?;
+            save_changes(&cache, durable)?;
+            return Ok(ExitStatus::Found);
         }
-        Some(Commands::Get { key }) => {
-            let api = RustdisApi::new(cache);
-            let result = api.api_get(&key)?;
-            println!("{}", result);
+        Some(Commands::Get { key }) => Command::get(key),
+        Some(Commands::Set { key, value, nx, xx, ex, px, keepttl, get }) => {
+            Command::Set { key, value, encoding: ValueEncoding::Utf8, nx, xx, ex, px, keepttl, get }
         }
-        Some(Commands::Set { key, value }) => {
+        Some(Commands::Del { key }) => Command::Del { key },
+        Some(Commands::Exists { key }) => Command::Exists { key },
+        Some(Commands::Incr { key }) => Command::Incr { key },
+        Some(Commands::Decr { key }) => Command::Decr { key },
+        Some(Commands::Incrby { key, delta }) => Command::IncrBy { key, delta },
+        Some(Commands::Decrby { key, delta }) => Command::DecrBy { key, delta },
+        Some(Commands::Incrbyfloat { key, delta }) => Command::IncrByFloat { key, delta },
+        Some(Commands::Keys) => Command::Keys,
+        Some(Commands::Flush) => Command::Flush,
+        Some(Commands::Size) => Command::Size,
+        Some(Commands::Ping) => Command::Ping,
+        Some(Commands::Genid { kind }) => Command::GenId { kind },
+        Some(Commands::ApiDocs) => {
             let api = RustdisApi::new(cache);
-            let result = api.api_set(key, value)?;
-            println!("{}", result);
+            println!("{}", api.api_docs());
+            return Ok(ExitStatus::Found);
         }
-        Some(Commands::Del { key }) => {
-            let api = RustdisApi::new(cache);
-            let result = api.api_del(&key)?;
-            println!("{}", result);
+        Some(Commands::Analyze { out, format }) => {
+            let written = match &out {
+                Some(path) => analyze::export(&cache, format, BufWriter::new(File::create(path)?))?,
+                None => analyze::export(&cache, format, BufWriter::new(std::io::stdout()))?,
+            };
+            if !cli.quiet {
+                eprintln!("Exported {} keys", written);
+            }
+            return Ok(ExitStatus::Found);
         }
-        Some(Commands::Exists { key }) => {
-            let api = RustdisApi::new(cache);
-            let result = api.api_exists(&key)?;
-            println!("{}", result);
+        Some(Commands::Save { path }) => {
+            let saved = cache.save_to(&path)?;
+            if !cli.quiet {
+                eprintln!("Saved {} keys to {}", saved, path.display());
+            }
+            return Ok(ExitStatus::Found);
         }
-        Some(Commands::Keys) => {
-            let api = RustdisApi::new(cache);
-            let result = api.api_keys()?;
-            println!("{}", result);
+        Some(Commands::Export { file, pattern }) => {
+            let pattern = pattern.as_deref();
+            let written = match &file {
+                Some(path) => dump::export(&cache, pattern, BufWriter::new(File::create(path)?))?,
+                None => dump::export(&cache, pattern, BufWriter::new(std::io::stdout()))?,
+            };
+            if !cli.quiet {
+                eprintln!("Exported {} keys", written);
+            }
+            return Ok(ExitStatus::Found);
         }
-        Some(Commands::Flush) => {
-            let api = RustdisApi::new(cache);
-            let result = api.api_flush()?;
-            println!("{}", result);
+        Some(Commands::Import { file, remap, ttl_scale, merge }) => {
+            let options = ImportOptions { remap, ttl_scale, merge };
+            let loaded = match &file {
+                Some(path) => dump::import(&cache, &options, BufReader::new(File::open(path)?))?,
+                None => dump::import(&cache, &options, std::io::stdin().lock())?,
+            };
+            cache.save_to(&snapshot)?;
+            if !cli.quiet {
+                eprintln!("Imported {} keys into {}", loaded, snapshot.display());
+            }
+            return Ok(ExitStatus::Found);
         }
-        Some(Commands::Size) => {
-            let api = RustdisApi::new(cache);
-            let result = api.api_size()?;
-            println!("{}", result);
+        Some(Commands::Serve { port, bind, redis_compat_strict, auth }) => {
+            let auth = auth.authenticator()?;
+            if redis_compat_strict {
+                cache.config_set("redis-compat-strict", "yes")?;
+            }
+            server_runtime(&cache, cli.warm)?.block_on(resp::serve(cache, SocketAddr::new(bind, port), auth))?;
+            return Ok(ExitStatus::Found);
         }
-        Some(Commands::Ping) => {
-            let api = RustdisApi::new(cache);
-            let result = api.api_ping()?;
-            println!("{}", result);
+        Some(Commands::Copy { from, to, pattern, overwrite, batch }) => {
+            let options = CopyOptions { pattern, overwrite, batch };
+            return tokio::runtime::Runtime::new()?.block_on(copy_between(&from, &to, &options, cli.quiet));
         }
-        Some(Commands::ApiDocs) => {
-            let api = RustdisApi::new(cache);
-            println!("{}", api.api_docs());
+        Some(Commands::Http {
+            addr,
+            compress_min_bytes,
+            cors_origins,
+            cors_methods,
+            cors_headers,
+            cors_credentials,
+            no_security_headers,
+            idempotency_window_secs,
+            admin_addr,
+            no_access_log,
+            auth,
+        }) => {
+            let cors = (!cors_origins.is_empty()).then(|| {
+                let defaults = CorsConfig::default();
+                CorsConfig {
+                    allowed_origins: cors_origins,
+                    allowed_methods: if cors_methods.is_empty() { defaults.allowed_methods } else { cors_methods },
+                    allowed_headers: if cors_headers.is_empty() { defaults.allowed_headers } else { cors_headers },
+                    allow_credentials: cors_credentials,
+                }
+            });
+            let config = HttpConfig {
+                addr,
+                compression_threshold: compress_min_bytes,
+                cors,
+                security_headers: !no_security_headers,
+                idempotency_window: Duration::from_secs(idempotency_window_secs),
+                admin_addr,
+                access_log: !no_access_log,
+                auth: auth.authenticator()?,
+            };
+            server_runtime(&cache, cli.warm)?.block_on(http::serve(cache, config))?;
+            return Ok(ExitStatus::Found);
         }
-    }
+    };
+    let status = RustdisCli::with_display(cache.clone(), display).run_once(command, cli.quiet)?;
+    save_changes(&cache, durable)?;
+    Ok(status)
+}
 
+/// Saves the snapshot if `durable` and anything was written, so CLI
+/// commands leave their changes in the data directory
+fn save_changes(cache: &RustdisCache, durable: bool) -> Result<()> {
+    if durable && cache.changes_since_save() > 0 {
+        cache.save()?;
+    }
     Ok(())
 }
 
+/// Runtime for a server, with the warm rules already started on it
+fn server_runtime(cache: &RustdisCache, warm: Vec<WarmRule>) -> Result<tokio::runtime::Runtime> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    let _entered = runtime.enter();
+    warm::spawn(cache, warm)?;
+    Ok(runtime)
+}
+
+/// Runs `rustdis copy`, reporting progress on stderr unless `quiet`
+async fn copy_between(from: &str, to: &str, options: &CopyOptions, quiet: bool) -> Result<ExitStatus> {
+    let (Some(mut source), Some(mut destination)) = (connect(from).await, connect(to).await) else {
+        return Ok(ExitStatus::ConnectionFailure);
+    };
+
+    let stats = copy::copy_keys(&mut source, &mut destination, options, |stats| {
+        if !quiet {
+            // The total isn't known until SCAN finishes
+            eprintln!("{} keys ({:.0} keys/s)", stats.processed(), stats.throughput());
+        }
+    })
+    .await?;
+    if !quiet {
+        println!(
+            "Copied {} keys, skipped {}, failed {} in {:.2}s",
+            stats.copied,
+            stats.skipped,
+            stats.failed,
+            stats.elapsed.as_secs_f64()
+        );
+    }
+    Ok(if stats.failed > 0 { ExitStatus::Error } else { ExitStatus::Found })
+}
+
+async fn connect(url: &str) -> Option<RespClient> {
+    RespClient::connect(url)
+        .await
+        .map_err(|e| eprintln!("Error: could not connect to {}: {}", url, e))
+        .ok()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -117,9 +595,9 @@ mod tests {
         
         // Test through API
         let api = RustdisApi::new(cache.clone());
-        api.api_set("integration_test".to_string(), "test_value".to_string()).unwrap();
+        api.api_set("integration_test".to_string(), "test_value".to_string(), ValueEncoding::Utf8).unwrap();
         
-        let result = api.api_get("integration_test").unwrap();
+        let result = api.api_get("integration_test", ValueEncoding::Utf8).unwrap();
         assert!(result.contains("test_value"));
         
         // Test direct cache access
@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds, in seconds, of latency histogram buckets (Prometheus' defaults)
+pub const LATENCY_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Cumulative latency histogram in Prometheus' layout
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Histogram {
+    /// Observations per bucket in `LATENCY_BUCKETS`, not cumulative
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, latency: Duration) {
+        let seconds = latency.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Appends `_bucket`, `_sum` and `_count` samples for this histogram
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (bound, observed) in LATENCY_BUCKETS.iter().zip(self.buckets) {
+            cumulative += observed;
+            let _ = writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, bound, cumulative);
+        }
+        let _ = writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, labels, self.count);
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, self.count);
+    }
+}
+
+/// Per-endpoint request counters and latency histograms for the HTTP API
+///
+/// Endpoints are keyed by route template (e.g. `/api/get`), never by raw
+/// URI, so label cardinality stays bounded.
+#[derive(Debug, Default)]
+pub struct HttpMetrics {
+    endpoints: Mutex<BTreeMap<(String, String), Endpoint>>,
+}
+
+#[derive(Debug, Default)]
+struct Endpoint {
+    latency: Histogram,
+    /// Responses by status code
+    statuses: BTreeMap<u16, u64>,
+}
+
+impl HttpMetrics {
+    pub fn record(&self, method: &str, path: &str, status: u16, latency: Duration) {
+        // Metrics are best-effort; a poisoned lock only loses samples
+        let Ok(mut endpoints) = self.endpoints.lock() else { return };
+        let endpoint = endpoints.entry((method.to_string(), path.to_string())).or_default();
+        endpoint.latency.observe(latency);
+        *endpoint.statuses.entry(status).or_default() += 1;
+    }
+
+    /// Latency histogram recorded for one endpoint
+    pub fn histogram(&self, method: &str, path: &str) -> Option<Histogram> {
+        let endpoints = self.endpoints.lock().ok()?;
+        endpoints.get(&(method.to_string(), path.to_string())).map(|endpoint| endpoint.latency.clone())
+    }
+
+    /// Prometheus text exposition of every endpoint seen so far
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let Ok(endpoints) = self.endpoints.lock() else { return out };
+
+        out.push_str("# HELP rustdis_http_requests_total HTTP requests by endpoint and status.\n");
+        out.push_str("# TYPE rustdis_http_requests_total counter\n");
+        for ((method, path), endpoint) in endpoints.iter() {
+            for (status, count) in &endpoint.statuses {
+                let _ = writeln!(
+                    out,
+                    "rustdis_http_requests_total{{method=\"{}\",path=\"{}\",status=\"{}\"}} {}",
+                    method, path, status, count
+                );
+            }
+        }
+
+        out.push_str("# HELP rustdis_http_request_duration_seconds HTTP request latency by endpoint.\n");
+        out.push_str("# TYPE rustdis_http_request_duration_seconds histogram\n");
+        for ((method, path), endpoint) in endpoints.iter() {
+            let labels = format!("method=\"{}\",path=\"{}\"", method, path);
+            endpoint.latency.render(&mut out, "rustdis_http_request_duration_seconds", &labels);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_http_metrics_render() {
+        let metrics = HttpMetrics::default();
+        metrics.record("GET", "/api/get", 200, Duration::from_millis(3));
+        metrics.record("GET", "/api/get", 400, Duration::from_millis(30));
+        metrics.record("POST", "/api/set", 200, Duration::from_secs(20));
+
+        let histogram = metrics.histogram("GET", "/api/get").unwrap();
+        assert_eq!(histogram.count(), 2);
+
+        let text = metrics.render();
+        assert!(text.contains("rustdis_http_requests_total{method=\"GET\",path=\"/api/get\",status=\"400\"} 1\n"));
+        assert!(text.contains("rustdis_http_request_duration_seconds_bucket{method=\"GET\",path=\"/api/get\",le=\"0.005\"} 1\n"));
+        assert!(text.contains("rustdis_http_request_duration_seconds_bucket{method=\"GET\",path=\"/api/get\",le=\"0.05\"} 2\n"));
+        // Slower than the largest bucket only shows up in +Inf
+        assert!(text.contains("rustdis_http_request_duration_seconds_bucket{method=\"POST\",path=\"/api/set\",le=\"10\"} 0\n"));
+        assert!(text.contains("rustdis_http_request_duration_seconds_bucket{method=\"POST\",path=\"/api/set\",le=\"+Inf\"} 1\n"));
+    }
+}
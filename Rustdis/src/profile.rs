@@ -0,0 +1,56 @@
+use std::cell::Cell;
+use std::time::Duration;
+
+/// Per-thread operation counters, read before and after a command by PROFILE
+///
+/// Counters are cumulative and never reset; a profile is the difference
+/// between two snapshots taken on the same thread.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub lock_acquisitions: u64,
+    pub lock_wait_ns: u64,
+    pub keys_touched: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+}
+
+impl Counters {
+    /// Counters accumulated since `start`
+    pub fn since(self, start: Counters) -> Counters {
+        Counters {
+            lock_acquisitions: self.lock_acquisitions - start.lock_acquisitions,
+            lock_wait_ns: self.lock_wait_ns - start.lock_wait_ns,
+            keys_touched: self.keys_touched - start.keys_touched,
+            bytes_read: self.bytes_read - start.bytes_read,
+            bytes_written: self.bytes_written - start.bytes_written,
+        }
+    }
+}
+
+thread_local! {
+    static COUNTERS: Cell<Counters> = Cell::new(Counters::default());
+}
+
+/// Current counters for the calling thread
+pub fn snapshot() -> Counters {
+    COUNTERS.with(Cell::get)
+}
+
+pub(crate) fn record_lock_wait(waited: Duration) {
+    COUNTERS.with(|counters| {
+        let mut current = counters.get();
+        current.lock_acquisitions += 1;
+        current.lock_wait_ns += waited.as_nanos() as u64;
+        counters.set(current);
+    });
+}
+
+pub(crate) fn record_access(keys: usize, read: usize, written: usize) {
+    COUNTERS.with(|counters| {
+        let mut current = counters.get();
+        current.keys_touched += keys as u64;
+        current.bytes_read += read as u64;
+        current.bytes_written += written as u64;
+        counters.set(current);
+    });
+}
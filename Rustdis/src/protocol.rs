@@ -1,19 +1,745 @@
-use crate::cache::RustdisCache;
+use crate::allocator;
+use crate::blob::BlobFull;
+use crate::heatmap::DEFAULT_HEATMAP_WINDOW;
+use crate::compat::CompatMode;
+use crate::cache::{BusyKey, NoSuchKey, NotUtf8, NumberError, ReadOnly, RustdisCache, SaveInProgress, ScanFilter, SetCondition, SetExpiry, SetOptions, SetOutcome, Ttl, WrongType};
+use crate::flags::{self, FlagState};
+use crate::idgen::IdKind;
+use crate::profile;
+#[cfg(feature = "scripting")]
+use crate::scripting::NoScript;
+use crate::pubsub::{EventFlags, KeyspaceEvents};
 use anyhow::Result;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use indexmap::IndexMap;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, Instant};
 use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
+
+/// Current JSON protocol version
+///
+/// Compatibility policy: requests without a `version` field are treated as
+/// the current version. Adding commands, optional arguments or response
+/// fields does not bump the version, so clients must ignore unknown
+/// response fields and capabilities. Removing or changing the meaning of
+/// anything bumps the version; requests for a newer version than the server
+/// speaks are rejected with `UNSUPPORTED_VERSION`.
+pub const PROTOCOL_VERSION: u64 = 1;
+
+/// Oldest JSON protocol version still accepted
+pub const MIN_PROTOCOL_VERSION: u64 = 1;
+
+/// Names of all commands understood by `parse_command`, as sent on the wire
+pub const SUPPORTED_COMMANDS: &[&str] = &[
+    "GET", "SET", "DEL", "EXISTS", "KEYS", "SCAN", "FLUSH", "SIZE", "PING",
+    "NS.LIMIT", "NS.LIMITS", "SEEN.ADD", "GENID", "CAPABILITIES",
+    "CONFIG.GET", "CONFIG.SET", "EVICTION.DEBUG", "PIN", "UNPIN", "INFO",
+    "DEBUG.STATE", "DEBUG.SET-TIME", "PROFILE", "EXPIRE", "PEXPIRE", "TTL", "PTTL",
+    "HSET", "HGET", "HMGET", "HDEL", "HGETALL", "HKEYS", "HVALS", "HLEN",
+    "ZADD", "ZRANGE", "ZREVRANGE", "ZSCORE", "ZRANK", "ZREM", "ZCARD",
+    "INCR", "DECR", "INCRBY", "DECRBY", "INCRBYFLOAT", "MGET", "MGETEX", "MSET",
+    "GETSET", "GETDEL", "GETEX", "RENAME", "RENAMENX", "COPY", "TYPE", "DUMP", "RESTORE",
+    "BLOB.CREATE", "BLOB.APPEND", "BLOB.READ", "BLOB.LEN", "HEATMAP",
+    "SAVE", "BGSAVE", "LASTSAVE", "WARM.STATUS", "FLAG.SET", "FLAG.CHECK", "FLAG.LIST",
+    "META.SET", "META.GET", "META.DEL", "PUBLISH", "SUBSCRIBE", "UNSUBSCRIBE",
+    "PSUBSCRIBE", "PUNSUBSCRIBE", "MULTI", "EXEC", "DISCARD", "WATCH", "UNWATCH",
+    "FUNCTION.LOAD", "FUNCTION.DELETE", "FUNCTION.LIST", "FCALL", "EVAL", "EVALSHA", "SCRIPT.LOAD", "COMPAT", "MEMORY.USAGE", "MEMORY.STATS", "SAMPLEKEYS",
+];
+
+/// Commands that wipe data, change server settings or expose internals
+/// (see `Command::is_admin`)
+pub const ADMIN_COMMANDS: &[&str] = &[
+    "FLUSH", "NS.LIMIT", "CONFIG.GET", "CONFIG.SET", "EVICTION.DEBUG", "DEBUG.STATE", "DEBUG.SET-TIME",
+    "SAVE", "BGSAVE", "WARM.STATUS", "FUNCTION.LOAD", "FUNCTION.DELETE",
+];
+
+/// Text-syntax metadata for a command, used by help output and CLI hints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandSpec {
+    /// Name as typed, including any subcommand (e.g. `CONFIG GET`)
+    pub name: &'static str,
+    /// Argument placeholders: `<required>` and `[optional]`
+    pub args: &'static str,
+    pub summary: &'static str,
+}
+
+const fn spec(name: &'static str, args: &'static str, summary: &'static str) -> CommandSpec {
+    CommandSpec { name, args, summary }
+}
+
+/// Registry of text commands accepted by `parse_args`, in help order
+pub const COMMAND_SPECS: &[CommandSpec] = &[
+    spec("GET", "<key>", "Get value by key"),
+    spec("SET", "<key> <value>", "Set key-value pair; options: NX|XX, EX <s>|PX <ms>|KEEPTTL, GET"),
+    spec("DEL", "<key>", "Delete key"),
+    spec("GETSET", "<key> <value>", "Set a value and return the previous one"),
+    spec("GETDEL", "<key>", "Get a value and delete the key"),
+    spec("GETEX", "<key> [EX s|PX ms|PERSIST]", "Get a value and set or remove its TTL"),
+    spec("RENAME", "<key> <newkey>", "Move a value to another key, replacing it"),
+    spec("RENAMENX", "<key> <newkey>", "Move a value to another key only if that key doesn't exist"),
+    spec("COPY", "<source> <destination> [REPLACE]", "Copy a value and its TTL to another key"),
+    spec("DUMP", "<key>", "Serialize a value and its metadata to a base64 payload for RESTORE"),
+    spec("RESTORE", "<key> <ttl-ms> <payload> [REPLACE]", "Recreate a DUMP payload under a key (ttl 0 for none)"),
+    spec("MGET", "<key> [key ...]", "Get several values at once"),
+    spec("MGETEX", "<EX s|PX ms|PERSIST> <key> [key ...]", "Get several values and set or remove their TTLs in one step"),
+    spec("MSET", "<key> <value> [key value ...]", "Set several key-value pairs at once"),
+    spec("INCR", "<key>", "Add 1 to an integer value (a missing key counts as 0)"),
+    spec("DECR", "<key>", "Subtract 1 from an integer value"),
+    spec("INCRBY", "<key> <delta>", "Add delta to an integer value"),
+    spec("DECRBY", "<key> <delta>", "Subtract delta from an integer value"),
+    spec("INCRBYFLOAT", "<key> <delta>", "Add a floating point delta to a numeric value"),
+    spec("EXISTS", "<key>", "Check if key exists"),
+    spec("TYPE", "<key>", "Type of the value at a key (string, hash, zset, blob or none)"),
+    spec("MEMORY USAGE", "<key> [SAMPLES count]", "Approximate bytes used by a key's name, value and metadata"),
+    spec("MEMORY STATS", "", "Dataset, overhead and peak bytes, and keys with a TTL"),
+    spec("KEYS", "", "List all keys"),
+    spec("SCAN", "<cursor> [MATCH pattern] [COUNT n] [META field pattern]", "Walk the keyspace in batches, optionally filtered by name or metadata"),
+    spec("SAMPLEKEYS", "<count> [pattern]", "Uniform random sample of keys with their metadata, and an estimate of how many match"),
+    spec("FLUSH", "", "Clear all data"),
+    spec("SIZE", "", "Get number of keys"),
+    spec("PING", "", "Test connection"),
+    spec("NS.LIMIT", "<pattern> <max>", "Cap keys under a prefix (0 removes the cap)"),
+    spec("NS.LIMITS", "", "List namespace caps"),
+    spec("SEEN.ADD", "<key> <item> <ttl>", "Record item, 1 if not seen in the last ttl seconds"),
+    spec("GENID", "[uuid|ulid|snowflake]", "Generate a unique ID"),
+    spec("CAPABILITIES", "", "Show protocol version, commands and features"),
+    spec("CONFIG GET", "<param>", "Read a parameter (maxmemory, maxmemory-samples, maxmemory-policy, pin-patterns, ttl-jitter, or *)"),
+    spec("CONFIG SET", "<param> <value>", "Change a parameter at runtime"),
+    spec("EVICTION DEBUG", "[trials]", "Compare sampled eviction against exact LRU or LFU"),
+    spec("META.SET", "<key> <field> <value> [field value ...]", "Attach metadata fields to a key, returns how many were added"),
+    spec("META.GET", "<key> [field]", "Get one metadata field of a key, or all of them"),
+    spec("META.DEL", "<key> <field> [field ...]", "Remove metadata fields from a key"),
+    spec("PUBLISH", "<channel> <message>", "Send a message to a channel, returns how many subscribers received it"),
+    spec("SUBSCRIBE", "<channel> [channel ...]", "Receive the messages published to channels (RESP connections and the CLI)"),
+    spec("UNSUBSCRIBE", "[channel ...]", "Stop receiving messages from channels, or from all of them"),
+    spec("PSUBSCRIBE", "<pattern> [pattern ...]", "Receive the messages published to channels matching glob patterns"),
+    spec("PUNSUBSCRIBE", "[pattern ...]", "Stop receiving messages for patterns, or for all of them"),
+    spec("MULTI", "", "Start a transaction: later commands are queued until EXEC (RESP connections and the CLI)"),
+    spec("EXEC", "", "Run the queued commands with no other command in between, returns their replies"),
+    spec("DISCARD", "", "Drop the queued commands and leave the transaction"),
+    spec("WATCH", "<key> [key ...]", "Make the next EXEC fail (nil reply) if any of the keys is written first"),
+    spec("UNWATCH", "", "Forget every watched key"),
+    spec("FUNCTION LOAD", "[REPLACE] <code>", "Register a library of Rhai functions, returns its name (needs the functions feature)"),
+    spec("FUNCTION DELETE", "<library>", "Drop a library and its functions"),
+    spec("FUNCTION LIST", "", "Every library and its functions"),
+    spec("FCALL", "<function> <numkeys> [key ...] [arg ...]", "Run a function atomically, returns its result"),
+    spec("EVAL", "<script> <numkeys> [key ...] [arg ...]", "Run a Lua script atomically, returns its result (needs the scripting feature)"),
+    spec("EVALSHA", "<sha1> <numkeys> [key ...] [arg ...]", "Run a script cached by EVAL or SCRIPT LOAD"),
+    spec("SCRIPT LOAD", "<script>", "Cache a Lua script without running it, returns its SHA1"),
+    spec("COMPAT", "[native|strict]", "Show or switch whether this connection words replies exactly like Redis (RESP connections)"),
+    spec("PIN", "<key>", "Exempt a key from maxmemory eviction"),
+    spec("UNPIN", "<key>", "Make a pinned key evictable again"),
+    spec("INFO", "", "Show memory usage, including pinned keys"),
+    spec("DEBUG STATE", "", "Dump internal state (store, eviction, expiry, lock waits) for bug reports"),
+    spec("DEBUG SET-TIME", "<ms>", "Set a manual (test) clock to <ms> since its origin"),
+    spec("PROFILE", "<command> [args...]", "Run a command and report lock waits, keys and bytes it touched"),
+    spec("EXPIRE", "<key> <seconds>", "Delete a key after a timeout (1 if set, 0 if missing)"),
+    spec("PEXPIRE", "<key> <ms>", "Like EXPIRE, in milliseconds"),
+    spec("TTL", "<key>", "Seconds until a key expires (-1 no expiry, -2 missing)"),
+    spec("PTTL", "<key>", "Like TTL, in milliseconds"),
+    spec("HSET", "<key> <field> <value> [field value ...]", "Set hash fields, returns how many were added"),
+    spec("HGET", "<key> <field>", "Get a hash field"),
+    spec("HMGET", "<key> <field> [field ...]", "Get several hash fields"),
+    spec("HDEL", "<key> <field> [field ...]", "Delete hash fields"),
+    spec("HGETALL", "<key>", "Get every field and value of a hash"),
+    spec("HKEYS", "<key>", "List the fields of a hash"),
+    spec("HVALS", "<key>", "List the values of a hash"),
+    spec("HLEN", "<key>", "Count the fields of a hash"),
+    spec("ZADD", "<key> <score> <member> [score member ...]", "Set sorted set scores, returns how many were added"),
+    spec("ZRANGE", "<key> <start> <stop> [WITHSCORES]", "Members by rank, lowest score first (negative ranks count from the end)"),
+    spec("ZREVRANGE", "<key> <start> <stop> [WITHSCORES]", "Members by rank, highest score first"),
+    spec("ZSCORE", "<key> <member>", "Get the score of a member"),
+    spec("ZRANK", "<key> <member>", "Position of a member, lowest score first"),
+    spec("ZREM", "<key> <member> [member ...]", "Remove sorted set members"),
+    spec("ZCARD", "<key>", "Count the members of a sorted set"),
+    spec("BLOB.CREATE", "<key> <max-bytes>", "Create an empty append-only blob, 0 if the key exists"),
+    spec("BLOB.APPEND", "<key> <data>", "Append to a blob in place, returns its new length"),
+    spec("BLOB.READ", "<key> <offset> <len>", "Read up to len bytes of a blob from offset"),
+    spec("BLOB.LEN", "<key>", "Current length of a blob"),
+    spec("HEATMAP", "[prefix|*] [window-seconds]", "Accesses per key prefix over the last day, one row per window"),
+    spec("SAVE", "", "Write a snapshot of every key to disk, blocking until done"),
+    spec("BGSAVE", "", "Write a snapshot in the background"),
+    spec("LASTSAVE", "", "Unix time of the last successful snapshot, 0 if none"),
+    spec("WARM.STATUS", "", "Progress of the warm rules preloading keys from other servers"),
+    spec("FLAG.SET", "<name> <on|off|percent>", "Set a feature flag, recording an audit event"),
+    spec("FLAG.CHECK", "<name> <subject>", "Whether a flag is on for a subject (percentages hash the subject)"),
+    spec("FLAG.LIST", "", "Every feature flag and its state"),
+];
+
+/// Protocol features advertised by CAPABILITIES
+pub const PROTOCOL_FEATURES: &[&str] = &["json", "cbor", "framed-cbor", "versioned-envelope", "error-codes"];
+
+/// Longest command name `parse_args` can match (e.g. `CAPABILITIES`)
+const MAX_COMMAND_NAME: usize = 16;
+
+/// Uppercases an ASCII command name into `buf` without allocating
+///
+/// Names that don't fit are returned unchanged and won't match any command.
+fn ascii_upper<'a>(name: &'a str, buf: &'a mut [u8; MAX_COMMAND_NAME]) -> &'a str {
+    let Some(upper) = buf.get_mut(..name.len()) else {
+        return name;
+    };
+    upper.copy_from_slice(name.as_bytes());
+    upper.make_ascii_uppercase();
+    // Uppercasing ASCII bytes keeps the string valid UTF-8
+    std::str::from_utf8(upper).unwrap_or(name)
+}
 
 /// Command types supported by Rustdis
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "command", content = "args", rename_all = "UPPERCASE")]
+#[serde(tag = "command", content = "args", rename_all = "UPPERCASE", deny_unknown_fields)]
 pub enum Command {
-    Get { key: String },
-    Set { key: String, value: String },
+    Get {
+        key: String,
+        #[serde(default, skip_serializing_if = "ValueEncoding::is_utf8")]
+        encoding: ValueEncoding,
+    },
+    /// Options follow Redis: `nx`/`xx` only write if the key is missing or
+    /// present, `ex`/`px` set a TTL in seconds or milliseconds, `keepttl`
+    /// keeps the current one and `get` returns the previous value.
+    /// `encoding` applies to `value` and to the previous value.
+    Set {
+        key: String,
+        value: String,
+        #[serde(default, skip_serializing_if = "ValueEncoding::is_utf8")]
+        encoding: ValueEncoding,
+        #[serde(default, skip_serializing_if = "is_false")]
+        nx: bool,
+        #[serde(default, skip_serializing_if = "is_false")]
+        xx: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ex: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        px: Option<u64>,
+        #[serde(default, skip_serializing_if = "is_false")]
+        keepttl: bool,
+        #[serde(default, skip_serializing_if = "is_false")]
+        get: bool,
+    },
     Del { key: String },
     Exists { key: String },
+    Type { key: String },
+    #[serde(rename = "MEMORY.USAGE")]
+    MemoryUsage { key: String },
+    #[serde(rename = "MEMORY.STATS")]
+    MemoryStats,
     Keys,
+    /// Walks `count` keyspace slots from `cursor`; `meta` is a metadata field
+    /// and a glob its value must match
+    Scan {
+        cursor: usize,
+        #[serde(default, rename = "match", skip_serializing_if = "Option::is_none")]
+        pattern: Option<String>,
+        #[serde(default = "default_scan_count")]
+        count: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        meta: Option<(String, String)>,
+    },
+    SampleKeys {
+        count: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pattern: Option<String>,
+    },
     Flush,
     Size,
     Ping,
+    #[serde(rename = "NS.LIMIT")]
+    NsLimit { pattern: String, max_keys: usize },
+    #[serde(rename = "NS.LIMITS")]
+    NsLimits,
+    /// `ttl` is in seconds
+    #[serde(rename = "SEEN.ADD")]
+    SeenAdd { key: String, item: String, ttl: u64 },
+    #[serde(rename = "GENID")]
+    GenId {
+        #[serde(default)]
+        kind: IdKind,
+    },
+    Capabilities,
+    #[serde(rename = "CONFIG.GET")]
+    ConfigGet { parameter: String },
+    #[serde(rename = "CONFIG.SET")]
+    ConfigSet { parameter: String, value: String },
+    #[serde(rename = "EVICTION.DEBUG")]
+    EvictionDebug {
+        #[serde(default = "default_eviction_trials")]
+        trials: usize,
+    },
+    /// `fields` maps metadata fields to values, applied in order
+    #[serde(rename = "META.SET")]
+    MetaSet { key: String, fields: IndexMap<String, String> },
+    /// Every field when `field` is unset
+    #[serde(rename = "META.GET")]
+    MetaGet {
+        key: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        field: Option<String>,
+    },
+    #[serde(rename = "META.DEL")]
+    MetaDel { key: String, fields: Vec<String> },
+    Publish { channel: String, message: String },
+    /// Only runs on connections that can deliver messages (see
+    /// `pubsub::Subscriber`)
+    Subscribe { channels: Vec<String> },
+    /// No channels leaves every channel
+    Unsubscribe {
+        #[serde(default)]
+        channels: Vec<String>,
+    },
+    /// Glob patterns, matched against channel names as KEYS matches keys;
+    /// runs where SUBSCRIBE does
+    PSubscribe { patterns: Vec<String> },
+    /// No patterns leaves every pattern
+    PUnsubscribe {
+        #[serde(default)]
+        patterns: Vec<String>,
+    },
+    /// Only runs on connections that keep a transaction open between
+    /// commands (see `transaction::Transaction`)
+    Multi,
+    /// Runs where MULTI does
+    Exec,
+    /// Runs where MULTI does
+    Discard,
+    /// Runs where MULTI does
+    Watch { keys: Vec<String> },
+    /// Runs where MULTI does
+    Unwatch,
+    /// Library code starting with `#!rhai name=<library>` (see
+    /// `functions::FunctionRegistry`)
+    #[serde(rename = "FUNCTION.LOAD")]
+    FunctionLoad {
+        code: String,
+        #[serde(default)]
+        replace: bool,
+    },
+    #[serde(rename = "FUNCTION.DELETE")]
+    FunctionDelete { library: String },
+    #[serde(rename = "FUNCTION.LIST")]
+    FunctionList,
+    /// Runs with no other command in between, like EXEC
+    FCall {
+        function: String,
+        #[serde(default)]
+        keys: Vec<String>,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+    /// Lua code (see `scripting::ScriptRegistry`); runs with no other
+    /// command in between, like EXEC
+    Eval {
+        script: String,
+        #[serde(default)]
+        keys: Vec<String>,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+    /// Runs with no other command in between, like EXEC
+    EvalSha {
+        sha: String,
+        #[serde(default)]
+        keys: Vec<String>,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+    #[serde(rename = "SCRIPT.LOAD")]
+    ScriptLoad { script: String },
+    /// Only runs on RESP connections, whose replies it words (see
+    /// `compat::redis_reply`); no mode reports the current one
+    Compat {
+        #[serde(default)]
+        mode: Option<CompatMode>,
+    },
+    Pin { key: String },
+    Unpin { key: String },
+    Info,
+    #[serde(rename = "DEBUG.STATE")]
+    DebugState,
+    /// Sets a manual clock to `millis` since its origin
+    #[serde(rename = "DEBUG.SET-TIME")]
+    DebugSetTime { millis: u64 },
+    /// Runs `command` and reports what it cost alongside its response
+    Profile { command: Box<Command> },
+    Expire { key: String, seconds: u64 },
+    #[serde(rename = "PEXPIRE")]
+    PExpire { key: String, millis: u64 },
+    Ttl { key: String },
+    #[serde(rename = "PTTL")]
+    PTtl { key: String },
+    /// `fields` maps field names to values, applied in order
+    HSet { key: String, fields: IndexMap<String, String> },
+    HGet { key: String, field: String },
+    HMGet { key: String, fields: Vec<String> },
+    HDel { key: String, fields: Vec<String> },
+    HGetAll { key: String },
+    HKeys { key: String },
+    HVals { key: String },
+    HLen { key: String },
+    /// `members` maps members to scores, applied in order
+    ZAdd { key: String, members: IndexMap<String, f64> },
+    /// Ranks `start` to `stop`, inclusive; negative ranks count from the end
+    ZRange {
+        key: String,
+        start: i64,
+        stop: i64,
+        #[serde(default)]
+        withscores: bool,
+    },
+    #[serde(rename = "ZREVRANGE")]
+    ZRevRange {
+        key: String,
+        start: i64,
+        stop: i64,
+        #[serde(default)]
+        withscores: bool,
+    },
+    ZScore { key: String, member: String },
+    ZRank { key: String, member: String },
+    ZRem { key: String, members: Vec<String> },
+    ZCard { key: String },
+    #[serde(rename = "MGET")]
+    MGet { keys: Vec<String> },
+    #[serde(rename = "GETSET")]
+    GetSet { key: String, value: String },
+    #[serde(rename = "GETDEL")]
+    GetDel { key: String },
+    /// Like GETEX for each key, under one lock: `ex`/`px` set a new TTL on
+    /// every string key read and `persist` removes it
+    #[serde(rename = "MGETEX")]
+    MGetEx {
+        keys: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ex: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        px: Option<u64>,
+        #[serde(default, skip_serializing_if = "is_false")]
+        persist: bool,
+    },
+    /// `ex`/`px` set a new TTL and `persist` removes it; without any of them
+    /// the TTL is left alone
+    #[serde(rename = "GETEX")]
+    GetEx {
+        key: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ex: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        px: Option<u64>,
+        #[serde(default, skip_serializing_if = "is_false")]
+        persist: bool,
+    },
+    #[serde(rename = "RENAME")]
+    Rename { key: String, newkey: String },
+    #[serde(rename = "RENAMENX")]
+    RenameNx { key: String, newkey: String },
+    /// Without `replace` nothing is copied if `destination` exists
+    #[serde(rename = "COPY")]
+    Copy {
+        source: String,
+        destination: String,
+        #[serde(default, skip_serializing_if = "is_false")]
+        replace: bool,
+    },
+    /// Replies with the payload in base64
+    Dump { key: String },
+    /// `payload` is a base64 DUMP reply; `ttl` is in milliseconds, 0 for none
+    Restore {
+        key: String,
+        ttl: u64,
+        payload: String,
+        #[serde(default, skip_serializing_if = "is_false")]
+        replace: bool,
+    },
+    /// Empty blob that appends can grow to `max_bytes`
+    #[serde(rename = "BLOB.CREATE")]
+    BlobCreate { key: String, max_bytes: usize },
+    /// `encoding` applies to `value`
+    #[serde(rename = "BLOB.APPEND")]
+    BlobAppend {
+        key: String,
+        value: String,
+        #[serde(default, skip_serializing_if = "ValueEncoding::is_utf8")]
+        encoding: ValueEncoding,
+    },
+    /// Up to `len` bytes from `offset`; `encoding` applies to the reply
+    #[serde(rename = "BLOB.READ")]
+    BlobRead {
+        key: String,
+        offset: usize,
+        len: usize,
+        #[serde(default, skip_serializing_if = "ValueEncoding::is_utf8")]
+        encoding: ValueEncoding,
+    },
+    #[serde(rename = "BLOB.LEN")]
+    BlobLen { key: String },
+    /// Accesses per key prefix starting with `prefix` (empty for all),
+    /// summed into windows of `window` seconds
+    Heatmap {
+        #[serde(default)]
+        prefix: String,
+        #[serde(default = "default_heatmap_window")]
+        window: u64,
+    },
+    Save,
+    #[serde(rename = "BGSAVE")]
+    BgSave,
+    #[serde(rename = "LASTSAVE")]
+    LastSave,
+    #[serde(rename = "WARM.STATUS")]
+    WarmStatus,
+    #[serde(rename = "FLAG.SET")]
+    FlagSet { name: String, state: FlagState },
+    #[serde(rename = "FLAG.CHECK")]
+    FlagCheck { name: String, subject: String },
+    #[serde(rename = "FLAG.LIST")]
+    FlagList,
+    /// `pairs` maps keys to values, all written under one lock
+    #[serde(rename = "MSET")]
+    MSet { pairs: IndexMap<String, String> },
+    Incr { key: String },
+    Decr { key: String },
+    #[serde(rename = "INCRBY")]
+    IncrBy { key: String, delta: i64 },
+    #[serde(rename = "DECRBY")]
+    DecrBy { key: String, delta: i64 },
+    #[serde(rename = "INCRBYFLOAT")]
+    IncrByFloat { key: String, delta: f64 },
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// How a string value is written in JSON: as text, or base64 so binary
+/// values survive the round trip
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueEncoding {
+    #[default]
+    Utf8,
+    Base64,
+}
+
+impl ValueEncoding {
+    pub(crate) fn is_utf8(&self) -> bool {
+        *self == ValueEncoding::Utf8
+    }
+}
+
+impl std::str::FromStr for ValueEncoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "utf8" => Ok(ValueEncoding::Utf8),
+            "base64" => Ok(ValueEncoding::Base64),
+            _ => Err(anyhow::anyhow!("Unknown value encoding: {} (expected utf8 or base64)", s)),
+        }
+    }
+}
+
+fn default_eviction_trials() -> usize {
+    100
+}
+
+/// SCAN's COUNT when none is given, as in Redis
+fn default_scan_count() -> usize {
+    10
+}
+
+fn default_heatmap_window() -> u64 {
+    DEFAULT_HEATMAP_WINDOW.as_secs()
+}
+
+impl Command {
+    /// Plain GET of a UTF-8 value
+    pub fn get(key: String) -> Self {
+        Command::Get { key, encoding: ValueEncoding::Utf8 }
+    }
+
+    /// Plain SET of a UTF-8 value, without options
+    pub fn set(key: String, value: String) -> Self {
+        Command::Set { key, value, encoding: ValueEncoding::Utf8, nx: false, xx: false, ex: None, px: None, keepttl: false, get: false }
+    }
+
+    /// Wire name, as listed in `SUPPORTED_COMMANDS`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Get { .. } => "GET",
+            Command::Set { .. } => "SET",
+            Command::Del { .. } => "DEL",
+            Command::Exists { .. } => "EXISTS",
+            Command::Type { .. } => "TYPE",
+            Command::MemoryUsage { .. } => "MEMORY.USAGE",
+            Command::MemoryStats => "MEMORY.STATS",
+            Command::Keys => "KEYS",
+            Command::Scan { .. } => "SCAN",
+            Command::SampleKeys { .. } => "SAMPLEKEYS",
+            Command::Flush => "FLUSH",
+            Command::Size => "SIZE",
+            Command::Ping => "PING",
+            Command::NsLimit { .. } => "NS.LIMIT",
+            Command::NsLimits => "NS.LIMITS",
+            Command::SeenAdd { .. } => "SEEN.ADD",
+            Command::GenId { .. } => "GENID",
+            Command::Capabilities => "CAPABILITIES",
+            Command::ConfigGet { .. } => "CONFIG.GET",
+            Command::ConfigSet { .. } => "CONFIG.SET",
+            Command::EvictionDebug { .. } => "EVICTION.DEBUG",
+            Command::MetaSet { .. } => "META.SET",
+            Command::MetaGet { .. } => "META.GET",
+            Command::MetaDel { .. } => "META.DEL",
+            Command::Publish { .. } => "PUBLISH",
+            Command::Subscribe { .. } => "SUBSCRIBE",
+            Command::Unsubscribe { .. } => "UNSUBSCRIBE",
+            Command::PSubscribe { .. } => "PSUBSCRIBE",
+            Command::PUnsubscribe { .. } => "PUNSUBSCRIBE",
+            Command::Multi => "MULTI",
+            Command::Exec => "EXEC",
+            Command::Discard => "DISCARD",
+            Command::Watch { .. } => "WATCH",
+            Command::Unwatch => "UNWATCH",
+            Command::FunctionLoad { .. } => "FUNCTION.LOAD",
+            Command::FunctionDelete { .. } => "FUNCTION.DELETE",
+            Command::FunctionList => "FUNCTION.LIST",
+            Command::FCall { .. } => "FCALL",
+            Command::Eval { .. } => "EVAL",
+            Command::EvalSha { .. } => "EVALSHA",
+            Command::ScriptLoad { .. } => "SCRIPT.LOAD",
+            Command::Compat { .. } => "COMPAT",
+            Command::Pin { .. } => "PIN",
+            Command::Unpin { .. } => "UNPIN",
+            Command::Info => "INFO",
+            Command::DebugState => "DEBUG.STATE",
+            Command::DebugSetTime { .. } => "DEBUG.SET-TIME",
+            Command::Profile { .. } => "PROFILE",
+            Command::Expire { .. } => "EXPIRE",
+            Command::PExpire { .. } => "PEXPIRE",
+            Command::Ttl { .. } => "TTL",
+            Command::PTtl { .. } => "PTTL",
+            Command::HSet { .. } => "HSET",
+            Command::HGet { .. } => "HGET",
+            Command::HMGet { .. } => "HMGET",
+            Command::HDel { .. } => "HDEL",
+            Command::HGetAll { .. } => "HGETALL",
+            Command::HKeys { .. } => "HKEYS",
+            Command::HVals { .. } => "HVALS",
+            Command::HLen { .. } => "HLEN",
+            Command::ZAdd { .. } => "ZADD",
+            Command::ZRange { .. } => "ZRANGE",
+            Command::ZRevRange { .. } => "ZREVRANGE",
+            Command::ZScore { .. } => "ZSCORE",
+            Command::ZRank { .. } => "ZRANK",
+            Command::ZRem { .. } => "ZREM",
+            Command::ZCard { .. } => "ZCARD",
+            Command::MGet { .. } => "MGET",
+            Command::MGetEx { .. } => "MGETEX",
+            Command::GetSet { .. } => "GETSET",
+            Command::GetDel { .. } => "GETDEL",
+            Command::GetEx { .. } => "GETEX",
+            Command::Rename { .. } => "RENAME",
+            Command::RenameNx { .. } => "RENAMENX",
+            Command::Copy { .. } => "COPY",
+            Command::Dump { .. } => "DUMP",
+            Command::Restore { .. } => "RESTORE",
+            Command::BlobCreate { .. } => "BLOB.CREATE",
+            Command::BlobAppend { .. } => "BLOB.APPEND",
+            Command::BlobRead { .. } => "BLOB.READ",
+            Command::BlobLen { .. } => "BLOB.LEN",
+            Command::Heatmap { .. } => "HEATMAP",
+            Command::Save => "SAVE",
+            Command::BgSave => "BGSAVE",
+            Command::LastSave => "LASTSAVE",
+            Command::WarmStatus => "WARM.STATUS",
+            Command::FlagSet { .. } => "FLAG.SET",
+            Command::FlagCheck { .. } => "FLAG.CHECK",
+            Command::FlagList => "FLAG.LIST",
+            Command::MSet { .. } => "MSET",
+            Command::Incr { .. } => "INCR",
+            Command::Decr { .. } => "DECR",
+            Command::IncrBy { .. } => "INCRBY",
+            Command::DecrBy { .. } => "DECRBY",
+            Command::IncrByFloat { .. } => "INCRBYFLOAT",
+        }
+    }
+
+    /// Keys the command reads or writes, as counted by the heatmap; empty
+    /// for server commands
+    pub fn keys(&self) -> Vec<&str> {
+        match self {
+            Command::Get { key, .. }
+            | Command::Set { key, .. }
+            | Command::Del { key }
+            | Command::Exists { key }
+            | Command::Type { key }
+            | Command::MemoryUsage { key }
+            | Command::SeenAdd { key, .. }
+            | Command::MetaSet { key, .. }
+            | Command::MetaGet { key, .. }
+            | Command::MetaDel { key, .. }
+            | Command::Expire { key, .. }
+            | Command::PExpire { key, .. }
+            | Command::Ttl { key }
+            | Command::PTtl { key }
+            | Command::HSet { key, .. }
+            | Command::HGet { key, .. }
+            | Command::HMGet { key, .. }
+            | Command::HDel { key, .. }
+            | Command::HGetAll { key }
+            | Command::HKeys { key }
+            | Command::HVals { key }
+            | Command::HLen { key }
+            | Command::ZAdd { key, .. }
+            | Command::ZRange { key, .. }
+            | Command::ZRevRange { key, .. }
+            | Command::ZScore { key, .. }
+            | Command::ZRank { key, .. }
+            | Command::ZRem { key, .. }
+            | Command::ZCard { key }
+            | Command::GetSet { key, .. }
+            | Command::GetDel { key }
+            | Command::GetEx { key, .. }
+            | Command::Dump { key }
+            | Command::Restore { key, .. }
+            | Command::BlobCreate { key, .. }
+            | Command::BlobAppend { key, .. }
+            | Command::BlobRead { key, .. }
+            | Command::BlobLen { key }
+            | Command::Incr { key }
+            | Command::Decr { key }
+            | Command::IncrBy { key, .. }
+            | Command::DecrBy { key, .. }
+            | Command::IncrByFloat { key, .. } => vec![key],
+            Command::Rename { key, newkey } | Command::RenameNx { key, newkey } => vec![key, newkey],
+            Command::Copy { source, destination, .. } => vec![source, destination],
+            Command::MGet { keys }
+            | Command::MGetEx { keys, .. }
+            | Command::Watch { keys }
+            | Command::FCall { keys, .. }
+            | Command::Eval { keys, .. }
+            | Command::EvalSha { keys, .. } => keys.iter().map(String::as_str).collect(),
+            Command::MSet { pairs } => pairs.keys().map(String::as_str).collect(),
+            Command::Profile { command } => command.keys(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Whether the command wipes data, changes server settings or exposes
+    /// internals, and so is refused on data-plane listeners
+    pub fn is_admin(&self) -> bool {
+        match self {
+            Command::Profile { command } => command.is_admin(),
+            command => ADMIN_COMMANDS.contains(&command.name()),
+        }
+    }
+
+    /// Whether the command runs server-side code (FCALL, EVAL, EVALSHA),
+    /// which runs with no other command in between and can't be nested
+    pub fn runs_code(&self) -> bool {
+        matches!(self, Command::FCall { .. } | Command::Eval { .. } | Command::EvalSha { .. })
+    }
 }
 
 /// Response types from Rustdis operations
@@ -24,9 +750,181 @@ pub enum Response {
     StringOption(Option<String>),
     Boolean(bool),
     Number(usize),
+    /// Signed integer, for replies with negative sentinels such as TTL's -1/-2
+    Integer(i64),
+    Float(f64),
     StringArray(Vec<String>),
+    /// Mixed or nullable items, e.g. HMGET's missing fields
+    Array(Vec<Response>),
+    #[serde(serialize_with = "serialize_ok")]
     Ok,
-    Error { error: String },
+    Error {
+        error: String,
+        /// Machine-readable error code, e.g. `UNKNOWN_COMMAND`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        code: Option<String>,
+    },
+    Map(BTreeMap<String, Response>),
+    /// Value that isn't UTF-8, replied to RESP clients as it is (see
+    /// `execute_raw_args`); JSON carries it base64 encoded
+    #[serde(serialize_with = "serialize_base64", skip_deserializing)]
+    Bytes(Vec<u8>),
+}
+
+impl Response {
+    /// Generic error without a machine-readable code
+    pub fn error(error: impl Into<String>) -> Self {
+        Response::Error { error: error.into(), code: None }
+    }
+
+    /// Error carrying a machine-readable code
+    pub fn coded_error(code: &str, error: impl Into<String>) -> Self {
+        Response::Error { error: error.into(), code: Some(code.to_string()) }
+    }
+}
+
+/// Structured error raised while parsing a JSON command envelope
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolError {
+    /// One of `INVALID_JSON`, `INVALID_CBOR`, `UNSUPPORTED_VERSION`, `UNKNOWN_COMMAND`,
+    /// `UNKNOWN_FIELD`, `MISSING_FIELD`, `INVALID_COMMAND`, `WRONG_ARITY` or
+    /// `INVALID_ARGUMENT`
+    pub code: &'static str,
+    pub message: String,
+    /// Index of the offending text argument, when one can be blamed
+    pub arg: Option<usize>,
+}
+
+impl ProtocolError {
+    fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self { code, message: message.into(), arg: None }
+    }
+
+    fn at(mut self, arg: usize) -> Self {
+        self.arg = Some(arg);
+        self
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl From<ProtocolError> for Response {
+    fn from(error: ProtocolError) -> Self {
+        Response::coded_error(error.code, error.message)
+    }
+}
+
+/// Error response for a failed cache operation; type mismatches get the
+/// `WRONGTYPE` code, as in Redis, bad arithmetic `NOT_A_NUMBER` or
+/// `OVERFLOW`, binary values read as text `NOT_UTF8` and overfull blobs
+/// `BLOB_FULL`
+fn cache_error(error: anyhow::Error) -> Response {
+    if error.is::<WrongType>() {
+        Response::coded_error("WRONGTYPE", error.to_string())
+    } else if error.is::<BlobFull>() {
+        Response::coded_error("BLOB_FULL", error.to_string())
+    } else if error.is::<NotUtf8>() {
+        Response::coded_error("NOT_UTF8", error.to_string())
+    } else if error.is::<BusyKey>() {
+        Response::coded_error("BUSYKEY", error.to_string())
+    } else if error.is::<NoSuchKey>() {
+        Response::coded_error("NO_SUCH_KEY", error.to_string())
+    } else if error.is::<ReadOnly>() {
+        Response::coded_error("READONLY", error.to_string())
+    } else if error.is::<SaveInProgress>() {
+        Response::coded_error("SAVE_IN_PROGRESS", error.to_string())
+    } else if let Some(number) = error.downcast_ref::<NumberError>() {
+        let code = if *number == NumberError::Overflow { "OVERFLOW" } else { "NOT_A_NUMBER" };
+        Response::coded_error(code, error.to_string())
+    } else {
+        Response::error(error.to_string())
+    }
+}
+
+/// Bytes of a value sent with `encoding`
+fn decode_value(value: String, encoding: ValueEncoding) -> Result<Vec<u8>, ProtocolError> {
+    match encoding {
+        ValueEncoding::Utf8 => Ok(value.into_bytes()),
+        ValueEncoding::Base64 => BASE64
+            .decode(&value)
+            .map_err(|_| ProtocolError::new("INVALID_ARGUMENT", "Value is not valid base64")),
+    }
+}
+
+/// Bytes of a value to reply with in `encoding`; fails with `NotUtf8` if
+/// they aren't text
+fn encode_value(value: Vec<u8>, encoding: ValueEncoding) -> Result<String> {
+    match encoding {
+        ValueEncoding::Utf8 => String::from_utf8(value).map_err(|_| NotUtf8.into()),
+        ValueEncoding::Base64 => Ok(BASE64.encode(value)),
+    }
+}
+
+/// Validates SET flags and turns them into cache options
+fn set_options(nx: bool, xx: bool, ex: Option<u64>, px: Option<u64>, keepttl: bool, get: bool) -> Result<SetOptions, ProtocolError> {
+    let invalid = |message: &str| Err(ProtocolError::new("INVALID_ARGUMENT", message));
+    let condition = match (nx, xx) {
+        (true, true) => return invalid("SET NX and XX can't be combined"),
+        (true, false) => SetCondition::IfMissing,
+        (false, true) => SetCondition::IfExists,
+        (false, false) => SetCondition::Always,
+    };
+    let expiry = match (ex, px, keepttl) {
+        (Some(0), ..) | (_, Some(0), _) => return invalid("SET expire time must be positive"),
+        (Some(seconds), None, false) => SetExpiry::After(Duration::from_secs(seconds)),
+        (None, Some(millis), false) => SetExpiry::After(Duration::from_millis(millis)),
+        (None, None, true) => SetExpiry::Keep,
+        (None, None, false) => SetExpiry::Clear,
+        _ => return invalid("SET accepts only one of EX, PX and KEEPTTL"),
+    };
+    Ok(SetOptions { condition, expiry, get })
+}
+
+/// Splits the arguments after `<name> <function|script> <numkeys>` into keys
+/// and the rest, for FCALL, EVAL and EVALSHA
+fn keys_and_args(args: &[&str], name: &str) -> Result<(Vec<String>, Vec<String>), ProtocolError> {
+    let Some(numkeys) = args[2].parse::<usize>().ok().filter(|numkeys| *numkeys <= args.len() - 3) else {
+        return Err(ProtocolError::new("INVALID_ARGUMENT", format!("{} numkeys must be a non-negative integer no greater than the number of arguments", name)).at(2));
+    };
+    let (keys, rest) = args[3..].split_at(numkeys);
+    Ok((keys.iter().map(|key| key.to_string()).collect(), rest.iter().map(|arg| arg.to_string()).collect()))
+}
+
+/// Validates GETEX flags and turns them into a TTL update
+fn getex_expiry(ex: Option<u64>, px: Option<u64>, persist: bool) -> Result<SetExpiry, ProtocolError> {
+    let invalid = |message: &str| Err(ProtocolError::new("INVALID_ARGUMENT", message));
+    match (ex, px, persist) {
+        (Some(0), ..) | (_, Some(0), _) => invalid("GETEX expire time must be positive"),
+        (Some(seconds), None, false) => Ok(SetExpiry::After(Duration::from_secs(seconds))),
+        (None, Some(millis), false) => Ok(SetExpiry::After(Duration::from_millis(millis))),
+        (None, None, true) => Ok(SetExpiry::Clear),
+        (None, None, false) => Ok(SetExpiry::Keep),
+        _ => invalid("GETEX accepts only one of EX, PX and PERSIST"),
+    }
+}
+
+/// Serializes `Response::Ok` as the `"OK"` status string instead of `null`
+fn serialize_ok<S: serde::Serializer>(serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str("OK")
+}
+
+fn serialize_base64<S: serde::Serializer>(bytes: &[u8], serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&BASE64.encode(bytes))
+}
+
+/// Reply for a value read as bytes: text when it's UTF-8, the bytes
+/// themselves otherwise
+fn raw_value(value: Vec<u8>) -> Response {
+    match String::from_utf8(value) {
+        Ok(value) => Response::StringOption(Some(value)),
+        Err(e) => Response::Bytes(e.into_bytes()),
+    }
 }
 
 /// Protocol handler for processing commands
@@ -42,87 +940,1783 @@ impl RustdisProtocol {
 
     /// Process a command and return a response
     pub fn execute(&self, command: Command) -> Response {
-        match command {
-            Command::Get { key } => {
-                match self.cache.get(&key) {
-                    Ok(value) => Response::StringOption(value),
-                    Err(e) => Response::Error { error: e.to_string() },
+        #[cfg(feature = "chaos")]
+        if let Some(fault) = self.inject_fault() {
+            return fault;
+        }
+        // Functions and scripts run with no other command in between, as
+        // EXEC does
+        if command.runs_code() {
+            return self.cache.exclusive(|| self.run(command));
+        }
+        self.cache.shared(|| self.run(command))
+    }
+
+    /// Runs the commands of a transaction (EXEC), with no command from any
+    /// other connection in between, and returns their replies in order
+    ///
+    /// As in Redis, a command that fails doesn't stop the others or undo
+    /// what ran before it. If any `watched` key no longer has the version it
+    /// had at WATCH (see `RustdisCache::key_version`), nothing runs and the
+    /// reply is nil.
+    pub fn exec(&self, commands: Vec<Command>, watched: &[(String, Option<u64>)]) -> Response {
+        #[cfg(feature = "chaos")]
+        if let Some(fault) = self.inject_fault() {
+            return fault;
+        }
+        self.cache.exclusive(|| {
+            for (key, version) in watched {
+                match self.cache.key_version(key) {
+                    Ok(current) if current == *version => {}
+                    Ok(_) => return Response::StringOption(None),
+                    Err(e) => return Response::error(e.to_string()),
                 }
             }
-            Command::Set { key, value } => {
-                match self.cache.set(key, value) {
-                    Ok(()) => Response::Ok,
-                    Err(e) => Response::Error { error: e.to_string() },
+            Response::Array(commands.into_iter().map(|command| self.run(command)).collect())
+        })
+    }
+
+    /// Runs a command sent by a function with `command`, already holding the
+    /// FCALL's exclusive access
+    #[cfg(feature = "functions")]
+    fn run_from_function(&self, args: &[String]) -> Response {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        self.run_nested(Self::parse_args(&args), "function")
+    }
+
+    /// Runs a command sent by a script with `redis.call`, already holding the
+    /// EVAL's exclusive access
+    #[cfg(feature = "scripting")]
+    fn run_from_script(&self, args: &[Vec<u8>]) -> Response {
+        let args: Vec<&[u8]> = args.iter().map(Vec::as_slice).collect();
+        self.run_nested(Self::parse_raw_args(&args), "script")
+    }
+
+    /// Admin commands and other functions or scripts are refused, so code
+    /// run on the server can't bypass ACLs or recurse
+    #[cfg(any(feature = "functions", feature = "scripting"))]
+    fn run_nested(&self, command: Result<Command, ProtocolError>, caller: &str) -> Response {
+        match command {
+            Ok(command) if command.is_admin() || command.runs_code() => {
+                Response::error(format!("{} can't be called from a {}", command.name(), caller))
+            }
+            Ok(command) => self.run(command),
+            Err(e) => e.into(),
+        }
+    }
+
+    /// The cache commands run against
+    pub fn cache(&self) -> &RustdisCache {
+        &self.cache
+    }
+
+    /// Fault injector of the underlying cache
+    #[cfg(feature = "chaos")]
+    pub fn faults(&self) -> &crate::chaos::FaultInjector {
+        self.cache.faults()
+    }
+
+    /// Applies injected failures ahead of running an operation
+    #[cfg(feature = "chaos")]
+    fn inject_fault(&self) -> Option<Response> {
+        self.cache
+            .faults()
+            .fail()
+            .then(|| Response::coded_error("INJECTED_FAULT", "Operation failed by fault injection"))
+    }
+
+    fn run(&self, command: Command) -> Response {
+        // Only a poisoned lock fails, and the command can still run
+        let _ = self.cache.record_heat(command.keys());
+        if !self.cache.keyspace_events().flags().is_enabled() {
+            return self.dispatch(command);
+        }
+        let response = self.dispatch(command.clone());
+        self.notify_keyspace(command, &response);
+        response
+    }
+
+    fn dispatch(&self, command: Command) -> Response {
+        match command {
+            Command::Get { key, encoding } => self.get(&key, encoding),
+            Command::Set { key, value, encoding, nx, xx, ex, px, keepttl, get } => match set_options(nx, xx, ex, px, keepttl, get) {
+                Ok(options) => self.set(key, value, encoding, options),
+                Err(e) => e.into(),
+            },
+            Command::Del { key } => self.del(&key),
+            Command::Exists { key } => self.exists(&key),
+            Command::Type { key } => {
+                match self.cache.type_of(&key) {
+                    Ok(name) => Response::String(name.unwrap_or("none").to_string()),
+                    Err(e) => Response::error(e.to_string()),
                 }
             }
-            Command::Del { key } => {
-                match self.cache.del(&key) {
-                    Ok(deleted) => Response::Boolean(deleted),
-                    Err(e) => Response::Error { error: e.to_string() },
+            Command::MemoryUsage { key } => {
+                match self.cache.memory_usage(&key) {
+                    Ok(Some(bytes)) => Response::Number(bytes),
+                    Ok(None) => Response::StringOption(None),
+                    Err(e) => Response::error(e.to_string()),
                 }
             }
-            Command::Exists { key } => {
-                match self.cache.exists(&key) {
-                    Ok(exists) => Response::Boolean(exists),
-                    Err(e) => Response::Error { error: e.to_string() },
+            Command::MemoryStats => {
+                match self.cache.memory_stats() {
+                    Ok(stats) => {
+                        let mut map = BTreeMap::new();
+                        map.insert("keys".to_string(), Response::Number(stats.keys));
+                        map.insert("volatile_keys".to_string(), Response::Number(stats.volatile_keys));
+                        map.insert("dataset_bytes".to_string(), Response::Number(stats.dataset_bytes));
+                        map.insert("overhead_bytes".to_string(), Response::Number(stats.overhead_bytes));
+                        map.insert("total_bytes".to_string(), Response::Number(stats.total_bytes()));
+                        map.insert("peak_bytes".to_string(), Response::Number(stats.peak_bytes));
+                        map.insert("maxmemory".to_string(), Response::Number(stats.maxmemory));
+                        match allocator::stats() {
+                            Ok(Some(allocator)) => {
+                                map.insert("allocator".to_string(), Response::String(allocator.allocator.to_string()));
+                                if let Some(allocated) = allocator.allocated {
+                                    map.insert("allocator.allocated".to_string(), Response::Number(allocated));
+                                }
+                                map.insert("allocator.active".to_string(), Response::Number(allocator.active));
+                                map.insert("allocator.resident".to_string(), Response::Number(allocator.resident));
+                                if let Some(ratio) = allocator.fragmentation_ratio {
+                                    map.insert("allocator.fragmentation_ratio".to_string(), Response::Float(ratio));
+                                }
+                            }
+                            Ok(None) => {}
+                            Err(e) => return Response::error(e.to_string()),
+                        }
+                        Response::Map(map)
+                    }
+                    Err(e) => Response::error(e.to_string()),
                 }
             }
             Command::Keys => {
                 match self.cache.keys() {
                     Ok(keys) => Response::StringArray(keys),
-                    Err(e) => Response::Error { error: e.to_string() },
+                    Err(e) => Response::error(e.to_string()),
+                }
+            }
+            Command::Scan { cursor, pattern, count, meta } => {
+                match self.cache.scan(cursor, count, &ScanFilter { pattern, meta }) {
+                    Ok((next, keys)) => Response::Array(vec![Response::String(next.to_string()), Response::StringArray(keys)]),
+                    Err(e) => Response::error(e.to_string()),
+                }
+            }
+            Command::SampleKeys { count, pattern } => {
+                match self.cache.sample_keys(count, pattern.as_deref()) {
+                    Ok(sample) => {
+                        let mut map = BTreeMap::new();
+                        map.insert("keyspace".to_string(), Response::Number(sample.keyspace));
+                        map.insert("examined".to_string(), Response::Number(sample.examined));
+                        map.insert("estimated_matches".to_string(), Response::Number(sample.estimated_matches()));
+                        let keys = sample.keys.into_iter().map(|meta| {
+                            let mut key = BTreeMap::new();
+                            key.insert("type".to_string(), Response::String(meta.value_type.to_string()));
+                            key.insert("bytes".to_string(), Response::Number(meta.bytes));
+                            key.insert("ttl_ms".to_string(), Response::Integer(meta.ttl.map_or(-1, |ttl| ttl.as_millis() as i64)));
+                            key.insert("idle_ms".to_string(), Response::Number(meta.idle.as_millis() as usize));
+                            key.insert("key".to_string(), Response::String(meta.key));
+                            Response::Map(key)
+                        });
+                        map.insert("keys".to_string(), Response::Array(keys.collect()));
+                        Response::Map(map)
+                    }
+                    Err(e) => Response::error(e.to_string()),
                 }
             }
             Command::Flush => {
                 match self.cache.flush() {
                     Ok(()) => Response::Ok,
-                    Err(e) => Response::Error { error: e.to_string() },
+                    Err(e) => cache_error(e),
                 }
             }
             Command::Size => {
                 match self.cache.size() {
                     Ok(size) => Response::Number(size),
-                    Err(e) => Response::Error { error: e.to_string() },
+                    Err(e) => Response::error(e.to_string()),
                 }
             }
             Command::Ping => Response::String("PONG".to_string()),
+            Command::NsLimit { pattern, max_keys } => {
+                match self.cache.set_namespace_limit(&pattern, max_keys) {
+                    Ok(evicted) => Response::Number(evicted.len()),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::NsLimits => {
+                match self.cache.namespace_limits() {
+                    Ok(limits) => Response::StringArray(
+                        limits.into_iter().map(|(pattern, max)| format!("{} {}", pattern, max)).collect(),
+                    ),
+                    Err(e) => Response::error(e.to_string()),
+                }
+            }
+            Command::SeenAdd { key, item, ttl } => {
+                match self.cache.seen_add(&key, &item, Duration::from_secs(ttl)) {
+                    Ok(added) => Response::Boolean(added),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::Capabilities => Self::capabilities(),
+            Command::ConfigGet { parameter } => {
+                match self.cache.config_get(&parameter) {
+                    Ok(config) => Response::Map(
+                        config.into_iter().map(|(name, value)| (name, Response::String(value))).collect(),
+                    ),
+                    Err(e) => Response::error(e.to_string()),
+                }
+            }
+            Command::ConfigSet { parameter, value } => {
+                match self.cache.config_set(&parameter, &value) {
+                    Ok(()) => Response::Ok,
+                    Err(e) => Response::error(e.to_string()),
+                }
+            }
+            Command::EvictionDebug { trials } => {
+                match self.cache.eviction_debug(trials) {
+                    Ok(report) => {
+                        let mut map = BTreeMap::new();
+                        map.insert("keys".to_string(), Response::Number(report.keys));
+                        map.insert("samples".to_string(), Response::Number(report.samples));
+                        map.insert("pool_size".to_string(), Response::Number(report.pool_size));
+                        map.insert("evictions".to_string(), Response::Number(report.evictions as usize));
+                        map.insert("used_bytes".to_string(), Response::Number(report.used_bytes));
+                        map.insert("maxmemory".to_string(), Response::Number(report.maxmemory));
+                        map.insert("policy".to_string(), Response::String(report.policy.to_string()));
+                        map.insert("trials".to_string(), Response::Number(report.trials));
+                        map.insert("mean_rank".to_string(), Response::Float(report.mean_rank));
+                        map.insert("worst_rank".to_string(), Response::Float(report.worst_rank));
+                        Response::Map(map)
+                    }
+                    Err(e) => Response::error(e.to_string()),
+                }
+            }
+            Command::MetaSet { key, fields } => {
+                match self.cache.meta_set(&key, fields.into_iter().collect()) {
+                    Ok(added) => Response::Number(added),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::MetaGet { key, field } => match (self.cache.meta_get(&key), field) {
+                (Ok(meta), None) => Response::Map(meta.into_iter().map(|(field, value)| (field, Response::String(value))).collect()),
+                (Ok(meta), Some(field)) => Response::StringOption(meta.into_iter().find(|(name, _)| *name == field).map(|(_, value)| value)),
+                (Err(e), _) => cache_error(e),
+            },
+            Command::MetaDel { key, fields } => {
+                match self.cache.meta_del(&key, &fields) {
+                    Ok(removed) => Response::Number(removed),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::Publish { channel, message } => {
+                match self.cache.pubsub().publish(&channel, &message) {
+                    Ok(received) => Response::Number(received),
+                    Err(e) => cache_error(e),
+                }
+            }
+            command @ (Command::Subscribe { .. }
+            | Command::Unsubscribe { .. }
+            | Command::PSubscribe { .. }
+            | Command::PUnsubscribe { .. }
+            | Command::Multi
+            | Command::Exec
+            | Command::Discard
+            | Command::Watch { .. }
+            | Command::Unwatch) => {
+                Response::error(format!("{} is only available on RESP connections and in the interactive CLI", command.name()))
+            }
+            command @ Command::Compat { .. } => Response::error(format!("{} is only available on RESP connections", command.name())),
+            #[cfg(feature = "functions")]
+            Command::FunctionLoad { code, replace } => match self.cache.functions().load(&code, replace) {
+                Ok(library) => Response::StringOption(Some(library)),
+                Err(e) => Response::error(e.to_string()),
+            },
+            #[cfg(feature = "functions")]
+            Command::FunctionDelete { library } => match self.cache.functions().delete(&library) {
+                Ok(()) => Response::Ok,
+                Err(e) => Response::error(e.to_string()),
+            },
+            #[cfg(feature = "functions")]
+            Command::FunctionList => match self.cache.functions().list() {
+                Ok(libraries) => Response::Map(
+                    libraries.into_iter().map(|(library, functions)| (library, Response::StringArray(functions))).collect(),
+                ),
+                Err(e) => Response::error(e.to_string()),
+            },
+            #[cfg(feature = "functions")]
+            Command::FCall { function, keys, args } => {
+                let protocol = self.clone();
+                match self.cache.functions().call(&function, keys, args, move |args| protocol.run_from_function(&args)) {
+                    Ok(response) => response,
+                    Err(e) => Response::error(e.to_string()),
+                }
+            }
+            #[cfg(not(feature = "functions"))]
+            command @ (Command::FunctionLoad { .. } | Command::FunctionDelete { .. } | Command::FunctionList | Command::FCall { .. }) => {
+                Response::error(format!("{} requires building with --features functions", command.name()))
+            }
+            #[cfg(feature = "scripting")]
+            Command::Eval { script, keys, args } => {
+                let protocol = self.clone();
+                match self.cache.scripts().eval(&script, keys, args, move |args| protocol.run_from_script(&args)) {
+                    Ok(response) => response,
+                    Err(e) => Response::error(e.to_string()),
+                }
+            }
+            #[cfg(feature = "scripting")]
+            Command::EvalSha { sha, keys, args } => {
+                let protocol = self.clone();
+                match self.cache.scripts().eval_sha(&sha, keys, args, move |args| protocol.run_from_script(&args)) {
+                    Ok(response) => response,
+                    Err(e) if e.is::<NoScript>() => Response::coded_error("NOSCRIPT", e.to_string()),
+                    Err(e) => Response::error(e.to_string()),
+                }
+            }
+            #[cfg(feature = "scripting")]
+            Command::ScriptLoad { script } => match self.cache.scripts().load(&script) {
+                Ok(sha) => Response::StringOption(Some(sha)),
+                Err(e) => Response::error(e.to_string()),
+            },
+            #[cfg(not(feature = "scripting"))]
+            command @ (Command::Eval { .. } | Command::EvalSha { .. } | Command::ScriptLoad { .. }) => {
+                Response::error(format!("{} requires building with --features scripting", command.name()))
+            }
+            Command::Pin { key } => {
+                match self.cache.pin(&key) {
+                    Ok(pinned) => Response::Boolean(pinned),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::Unpin { key } => {
+                match self.cache.unpin(&key) {
+                    Ok(unpinned) => Response::Boolean(unpinned),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::Info => {
+                match self.cache.memory_info() {
+                    Ok(info) => {
+                        let mut memory = BTreeMap::new();
+                        memory.insert("keys".to_string(), Response::Number(info.keys));
+                        memory.insert("used_bytes".to_string(), Response::Number(info.used_bytes));
+                        memory.insert("maxmemory".to_string(), Response::Number(info.maxmemory));
+                        memory.insert("evictions".to_string(), Response::Number(info.evictions as usize));
+                        memory.insert("pinned_keys".to_string(), Response::Number(info.pinned_keys));
+                        memory.insert("pinned_bytes".to_string(), Response::Number(info.pinned_bytes));
+                        let mut sections = BTreeMap::new();
+                        sections.insert("memory".to_string(), Response::Map(memory));
+                        Response::Map(sections)
+                    }
+                    Err(e) => Response::error(e.to_string()),
+                }
+            }
+            Command::DebugState => {
+                match self.cache.debug_state() {
+                    Ok(state) => {
+                        let mut store = BTreeMap::new();
+                        store.insert("shards".to_string(), Response::Number(state.shards));
+                        store.insert("keys".to_string(), Response::Number(state.keys));
+                        store.insert("used_bytes".to_string(), Response::Number(state.used_bytes));
+                        store.insert("maxmemory".to_string(), Response::Number(state.maxmemory));
+                        store.insert("namespaces".to_string(), Response::Number(state.namespaces));
+                        store.insert("seen_filters".to_string(), Response::Number(state.seen_filters));
+
+                        let mut eviction = BTreeMap::new();
+                        eviction.insert("evictions".to_string(), Response::Number(state.evictions as usize));
+                        eviction.insert("pending".to_string(), Response::Number(state.pending_evictions));
+                        eviction.insert("expired".to_string(), Response::Number(state.expired as usize));
+                        store.insert("volatile_keys".to_string(), Response::Number(state.volatile_keys));
+
+                        let mut expiry = BTreeMap::new();
+                        expiry.insert("cycles".to_string(), Response::Number(state.expire_cycles as usize));
+                        expiry.insert("expired".to_string(), Response::Number(state.expired_by_cycles as usize));
+                        expiry.insert("expired_last_cycle".to_string(), Response::Number(state.expired_last_cycle as usize));
+                        expiry.insert("timed_out".to_string(), Response::Number(state.expire_timed_out as usize));
+                        expiry.insert("backed_off".to_string(), Response::Number(state.expire_backed_off as usize));
+                        expiry.insert("backoff".to_string(), Response::Number(state.expire_backoff as usize));
+
+                        let mut locks = BTreeMap::new();
+                        locks.insert("reads".to_string(), Response::Number(state.lock_reads as usize));
+                        locks.insert("writes".to_string(), Response::Number(state.lock_writes as usize));
+                        locks.insert("read_wait_us".to_string(), Response::Number(state.lock_read_wait_us as usize));
+                        locks.insert("write_wait_us".to_string(), Response::Number(state.lock_write_wait_us as usize));
+                        locks.insert("max_wait_us".to_string(), Response::Number(state.lock_max_wait_us as usize));
+
+                        let mut map = BTreeMap::new();
+                        map.insert("store".to_string(), Response::Map(store));
+                        map.insert("eviction".to_string(), Response::Map(eviction));
+                        map.insert("expiry".to_string(), Response::Map(expiry));
+                        map.insert("locks".to_string(), Response::Map(locks));
+                        Response::Map(map)
+                    }
+                    Err(e) => Response::error(e.to_string()),
+                }
+            }
+            #[cfg(any(test, feature = "testing"))]
+            Command::DebugSetTime { millis } => {
+                match self.cache.set_time(Duration::from_millis(millis)) {
+                    Ok(()) => Response::Ok,
+                    Err(e) => Response::error(e.to_string()),
+                }
+            }
+            #[cfg(not(any(test, feature = "testing")))]
+            Command::DebugSetTime { .. } => Response::error("DEBUG SET-TIME requires a build with the testing feature"),
+            Command::Profile { command } => self.profile(*command),
+            Command::Expire { key, seconds } => self.expire(&key, Duration::from_secs(seconds)),
+            Command::PExpire { key, millis } => self.expire(&key, Duration::from_millis(millis)),
+            // Rounded to the nearest second, as Redis does
+            Command::Ttl { key } => self.ttl(&key, |left| ((left.as_millis() + 500) / 1000) as i64),
+            Command::PTtl { key } => self.ttl(&key, |left| left.as_millis() as i64),
+            Command::HSet { key, fields } => {
+                match self.cache.hset(key, fields.into_iter().collect()) {
+                    Ok(added) => Response::Number(added),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::HGet { key, field } => {
+                match self.cache.hget(&key, &field) {
+                    Ok(value) => Response::StringOption(value),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::HMGet { key, fields } => {
+                match self.cache.hmget(&key, &fields) {
+                    Ok(values) => Response::Array(values.into_iter().map(Response::StringOption).collect()),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::HDel { key, fields } => {
+                match self.cache.hdel(&key, &fields) {
+                    Ok(removed) => Response::Number(removed),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::HGetAll { key } => {
+                match self.cache.hgetall(&key) {
+                    Ok(pairs) => Response::Map(pairs.into_iter().map(|(field, value)| (field, Response::String(value))).collect()),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::HKeys { key } => {
+                match self.cache.hgetall(&key) {
+                    Ok(pairs) => Response::StringArray(pairs.into_iter().map(|(field, _)| field).collect()),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::HVals { key } => {
+                match self.cache.hgetall(&key) {
+                    Ok(pairs) => Response::StringArray(pairs.into_iter().map(|(_, value)| value).collect()),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::HLen { key } => {
+                match self.cache.hlen(&key) {
+                    Ok(len) => Response::Number(len),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::ZAdd { key, members } => {
+                match self.cache.zadd(key, members.into_iter().collect()) {
+                    Ok(added) => Response::Number(added),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::ZRange { key, start, stop, withscores } => self.zrange(&key, start, stop, withscores, false),
+            Command::ZRevRange { key, start, stop, withscores } => self.zrange(&key, start, stop, withscores, true),
+            Command::ZScore { key, member } => {
+                match self.cache.zscore(&key, &member) {
+                    Ok(Some(score)) => Response::Float(score),
+                    Ok(None) => Response::StringOption(None),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::ZRank { key, member } => {
+                match self.cache.zrank(&key, &member) {
+                    Ok(Some(rank)) => Response::Number(rank),
+                    Ok(None) => Response::StringOption(None),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::ZRem { key, members } => {
+                match self.cache.zrem(&key, &members) {
+                    Ok(removed) => Response::Number(removed),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::ZCard { key } => {
+                match self.cache.zcard(&key) {
+                    Ok(len) => Response::Number(len),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::MGet { keys } => {
+                match self.cache.mget(&keys) {
+                    Ok(values) => Response::Array(values.into_iter().map(Response::StringOption).collect()),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::MGetEx { keys, ex, px, persist } => match getex_expiry(ex, px, persist) {
+                Ok(expiry) => match self.cache.mgetex(&keys, expiry) {
+                    Ok(values) => Response::Array(values.into_iter().map(Response::StringOption).collect()),
+                    Err(e) => cache_error(e),
+                },
+                Err(e) => e.into(),
+            },
+            Command::GetSet { key, value } => {
+                match self.cache.getset(key, value) {
+                    Ok(previous) => Response::StringOption(previous),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::GetDel { key } => {
+                match self.cache.getdel(&key) {
+                    Ok(value) => Response::StringOption(value),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::GetEx { key, ex, px, persist } => match getex_expiry(ex, px, persist) {
+                Ok(expiry) => match self.cache.getex(&key, expiry) {
+                    Ok(value) => Response::StringOption(value),
+                    Err(e) => cache_error(e),
+                },
+                Err(e) => e.into(),
+            },
+            Command::Rename { key, newkey } => {
+                match self.cache.rename(&key, &newkey, true) {
+                    Ok(_) => Response::Ok,
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::RenameNx { key, newkey } => {
+                match self.cache.rename(&key, &newkey, false) {
+                    Ok(renamed) => Response::Boolean(renamed),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::Copy { source, destination, replace } => {
+                match self.cache.copy(&source, &destination, replace) {
+                    Ok(copied) => Response::Boolean(copied),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::Dump { key } => {
+                match self.cache.dump(&key) {
+                    Ok(payload) => Response::StringOption(payload.map(|payload| BASE64.encode(payload))),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::Restore { key, ttl, payload, replace } => match BASE64.decode(&payload) {
+                Ok(payload) => {
+                    let ttl = (ttl > 0).then(|| Duration::from_millis(ttl));
+                    match self.cache.restore_key(key, &payload, ttl, replace) {
+                        Ok(()) => Response::Ok,
+                        Err(e) => cache_error(e),
+                    }
+                }
+                Err(_) => ProtocolError::new("INVALID_ARGUMENT", "RESTORE payload is not valid base64").into(),
+            },
+            Command::BlobCreate { key, max_bytes } => {
+                match self.cache.blob_create(key, max_bytes) {
+                    Ok(created) => Response::Boolean(created),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::BlobAppend { key, value, encoding } => match decode_value(value, encoding) {
+                Ok(value) => match self.cache.blob_append(&key, &value) {
+                    Ok(len) => Response::Number(len),
+                    Err(e) => cache_error(e),
+                },
+                Err(e) => e.into(),
+            },
+            Command::BlobRead { key, offset, len, encoding } => {
+                match self.cache.blob_read(&key, offset, len).and_then(|value| value.map(|value| encode_value(value, encoding)).transpose()) {
+                    Ok(value) => Response::StringOption(value),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::BlobLen { key } => {
+                match self.cache.blob_len(&key) {
+                    Ok(len) => Response::Number(len),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::Heatmap { prefix, window } => {
+                match self.cache.heatmap(&prefix, Duration::from_secs(window)) {
+                    Ok(matrix) => {
+                        let rows = matrix
+                            .rows
+                            .into_iter()
+                            .map(|(start, counts)| {
+                                let start = Response::Integer(start as i64);
+                                Response::Array(std::iter::once(start).chain(counts.into_iter().map(|count| Response::Number(count as usize))).collect())
+                            })
+                            .collect();
+                        let mut map = BTreeMap::new();
+                        map.insert("window".to_string(), Response::Number(matrix.window.as_secs() as usize));
+                        map.insert("prefixes".to_string(), Response::StringArray(matrix.prefixes));
+                        map.insert("rows".to_string(), Response::Array(rows));
+                        Response::Map(map)
+                    }
+                    Err(e) => Response::error(e.to_string()),
+                }
+            }
+            Command::Save => {
+                match self.cache.save() {
+                    Ok(_) => Response::Ok,
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::BgSave => {
+                match self.cache.bgsave() {
+                    Ok(_) => Response::String("Background saving started".to_string()),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::LastSave => Response::Integer(self.cache.snapshots().last_save() as i64),
+            Command::WarmStatus => {
+                match self.cache.warmers().statuses() {
+                    Ok(statuses) => Response::Array(
+                        statuses
+                            .into_iter()
+                            .map(|status| {
+                                let mut map = BTreeMap::new();
+                                map.insert("rule".to_string(), Response::String(status.rule));
+                                map.insert("running".to_string(), Response::Boolean(status.running));
+                                map.insert("runs".to_string(), Response::Number(status.runs as usize));
+                                map.insert("loaded".to_string(), Response::Number(status.loaded));
+                                map.insert("skipped".to_string(), Response::Number(status.skipped));
+                                map.insert("last_duration_ms".to_string(), Response::Number(status.last_duration.as_millis() as usize));
+                                map.insert("last_error".to_string(), Response::StringOption(status.last_error));
+                                Response::Map(map)
+                            })
+                            .collect(),
+                    ),
+                    Err(e) => Response::error(e.to_string()),
+                }
+            }
+            Command::FlagSet { name, state } => {
+                match flags::set(&self.cache, &name, state) {
+                    Ok(()) => Response::Ok,
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::FlagCheck { name, subject } => {
+                match flags::check(&self.cache, &name, &subject) {
+                    Ok(enabled) => Response::Boolean(enabled),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::FlagList => {
+                match flags::list(&self.cache) {
+                    Ok(flags) => Response::Map(flags.into_iter().map(|(name, state)| (name, Response::String(state.to_string()))).collect()),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::MSet { pairs } => {
+                match self.cache.mset(pairs.into_iter().collect()) {
+                    Ok(()) => Response::Ok,
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::Incr { key } => self.incr_by(&key, 1),
+            Command::Decr { key } => self.incr_by(&key, -1),
+            Command::IncrBy { key, delta } => self.incr_by(&key, delta),
+            Command::DecrBy { key, delta } => match delta.checked_neg() {
+                Some(delta) => self.incr_by(&key, delta),
+                None => cache_error(NumberError::Overflow.into()),
+            },
+            Command::IncrByFloat { key, delta } => {
+                match self.cache.incr_by_float(&key, delta) {
+                    Ok(value) => Response::Float(value),
+                    Err(e) => cache_error(e),
+                }
+            }
+            Command::GenId { kind } => {
+                match self.cache.generate_id(kind) {
+                    Ok(id) => Response::String(id),
+                    Err(e) => Response::error(e.to_string()),
+                }
+            }
         }
     }
 
-    /// Parse a JSON string into a command
-    pub fn parse_command(input: &str) -> Result<Command> {
-        let command: Command = serde_json::from_str(input)?;
+    /// `parse_args` for arguments as RESP carries them
+    ///
+    /// A SET value that isn't UTF-8 is carried base64 encoded, with the
+    /// command's `encoding` set to match; every other argument must be text.
+    pub fn parse_raw_args(args: &[&[u8]]) -> Result<Command, ProtocolError> {
+        let binary_value = match args {
+            [name, _, value, ..] if name.eq_ignore_ascii_case(b"SET") => std::str::from_utf8(value).is_err().then(|| BASE64.encode(value)),
+            _ => None,
+        };
+        let text = args
+            .iter()
+            .enumerate()
+            .map(|(i, arg)| match &binary_value {
+                Some(value) if i == 2 => Ok(value.as_str()),
+                _ => std::str::from_utf8(arg).map_err(|_| ProtocolError::new("INVALID_ARGUMENT", "Only a SET value may be binary; other arguments must be UTF-8").at(i)),
+            })
+            .collect::<Result<SmallVec<[&str; 8]>, _>>()?;
+        let mut command = Self::parse_args(&text)?;
+        if let (Command::Set { encoding, .. }, Some(_)) = (&mut command, &binary_value) {
+            *encoding = ValueEncoding::Base64;
+        }
         Ok(command)
     }
 
-    /// Convert a response to JSON string
-    pub fn response_to_json(response: &Response) -> Result<String> {
-        let json = serde_json::to_string(response)?;
-        Ok(json)
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_protocol_operations() {
-        let cache = RustdisCache::new();
-        let protocol = RustdisProtocol::new(cache);
+    /// Parse a command from borrowed text arguments, e.g. `["SET", "key", "value"]`
+    ///
+    /// The command name is matched case-insensitively without allocating;
+    /// arguments are only copied into owned Strings for the returned Command.
+    pub fn parse_args(args: &[&str]) -> Result<Command, ProtocolError> {
+        if args.is_empty() {
+            return Err(ProtocolError::new("INVALID_COMMAND", "Empty command"));
+        }
 
-        // Test SET command
-        let set_cmd = Command::Set {
-            key: "test_key".to_string(),
-            value: "test_value".to_string(),
+        let mut name = [0u8; MAX_COMMAND_NAME];
+        let command = match ascii_upper(args[0], &mut name) {
+            "GET" => {
+                if args.len() != 2 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "GET requires exactly one argument: GET <key>"));
+                }
+                Command::get(args[1].to_string())
+            }
+            "SET" => {
+                if args.len() < 3 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "SET requires a key and a value: SET <key> <value> [options]"));
+                }
+                let (mut nx, mut xx, mut ex, mut px, mut keepttl, mut get) = (false, false, None, None, false, false);
+                let mut i = 3;
+                while i < args.len() {
+                    let option_at = i;
+                    let mut option = [0u8; MAX_COMMAND_NAME];
+                    match ascii_upper(args[i], &mut option) {
+                        "NX" => nx = true,
+                        "XX" => xx = true,
+                        "KEEPTTL" => keepttl = true,
+                        "GET" => get = true,
+                        unit @ ("EX" | "PX") => {
+                            let Some(ttl) = args.get(i + 1) else {
+                                return Err(ProtocolError::new("WRONG_ARITY", format!("SET {} requires a value", unit)).at(i));
+                            };
+                            let Some(ttl) = ttl.parse().ok().filter(|ttl| *ttl > 0) else {
+                                return Err(ProtocolError::new("INVALID_ARGUMENT", format!("SET {} must be a positive integer", unit)).at(i + 1));
+                            };
+                            if unit == "EX" { ex = Some(ttl) } else { px = Some(ttl) }
+                            i += 1;
+                        }
+                        _ => return Err(ProtocolError::new("INVALID_ARGUMENT", format!("Unknown SET option: {}", args[i])).at(i)),
+                    }
+                    // Blame the option that made the combination invalid
+                    set_options(nx, xx, ex, px, keepttl, get).map_err(|e| e.at(option_at))?;
+                    i += 1;
+                }
+                Command::Set { key: args[1].to_string(), value: args[2].to_string(), encoding: ValueEncoding::Utf8, nx, xx, ex, px, keepttl, get }
+            }
+            "DEL" | "DELETE" => {
+                if args.len() != 2 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "DEL requires exactly one argument: DEL <key>"));
+                }
+                Command::Del { key: args[1].to_string() }
+            }
+            "EXISTS" => {
+                if args.len() != 2 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "EXISTS requires exactly one argument: EXISTS <key>"));
+                }
+                Command::Exists { key: args[1].to_string() }
+            }
+            "TYPE" => {
+                if args.len() != 2 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "TYPE requires exactly one argument: TYPE <key>"));
+                }
+                Command::Type { key: args[1].to_string() }
+            }
+            "MEMORY" => {
+                let usage = "MEMORY usage: MEMORY USAGE <key> [SAMPLES count] | MEMORY STATS";
+                match (args.get(1).map(|sub| sub.to_uppercase()).as_deref(), args.len()) {
+                    (Some("STATS"), 2) => Command::MemoryStats,
+                    (Some("USAGE"), 3) => Command::MemoryUsage { key: args[2].to_string() },
+                    // Sizes are tracked exactly, so there is nothing to
+                    // sample; SAMPLES is accepted for redis-cli --memkeys
+                    // and the like
+                    (Some("USAGE"), 5) => {
+                        if !args[3].eq_ignore_ascii_case("SAMPLES") {
+                            return Err(ProtocolError::new("WRONG_ARITY", usage).at(3));
+                        }
+                        if args[4].parse::<usize>().is_err() {
+                            return Err(ProtocolError::new("INVALID_ARGUMENT", "MEMORY USAGE SAMPLES must be a non-negative integer").at(4));
+                        }
+                        Command::MemoryUsage { key: args[2].to_string() }
+                    }
+                    _ => return Err(ProtocolError::new("WRONG_ARITY", usage)),
+                }
+            }
+            "KEYS" => Command::Keys,
+            "SCAN" => {
+                let usage = "SCAN requires a cursor: SCAN <cursor> [MATCH pattern] [COUNT n] [META field pattern]";
+                let Some(cursor) = args.get(1) else {
+                    return Err(ProtocolError::new("WRONG_ARITY", usage));
+                };
+                let Ok(cursor) = cursor.parse() else {
+                    return Err(ProtocolError::new("INVALID_ARGUMENT", "SCAN cursor must be a non-negative integer").at(1));
+                };
+                let (mut pattern, mut count, mut meta) = (None, default_scan_count(), None);
+                let mut i = 2;
+                while i < args.len() {
+                    let mut option = [0u8; MAX_COMMAND_NAME];
+                    let option = ascii_upper(args[i], &mut option);
+                    let width = if option == "META" { 3 } else { 2 };
+                    if i + width > args.len() {
+                        return Err(ProtocolError::new("WRONG_ARITY", format!("SCAN {} is missing a value", option)).at(i));
+                    }
+                    match option {
+                        "MATCH" => pattern = Some(args[i + 1].to_string()),
+                        "COUNT" => {
+                            let Some(n) = args[i + 1].parse().ok().filter(|n| *n > 0) else {
+                                return Err(ProtocolError::new("INVALID_ARGUMENT", "SCAN COUNT must be a positive integer").at(i + 1));
+                            };
+                            count = n;
+                        }
+                        "META" => meta = Some((args[i + 1].to_string(), args[i + 2].to_string())),
+                        _ => return Err(ProtocolError::new("INVALID_ARGUMENT", format!("Unknown SCAN option: {}", args[i])).at(i)),
+                    }
+                    i += width;
+                }
+                Command::Scan { cursor, pattern, count, meta }
+            }
+            "SAMPLEKEYS" => {
+                if !matches!(args.len(), 2 | 3) {
+                    return Err(ProtocolError::new("WRONG_ARITY", "SAMPLEKEYS takes a count and an optional pattern: SAMPLEKEYS <count> [pattern]"));
+                }
+                let Some(count) = args[1].parse().ok().filter(|count| *count > 0) else {
+                    return Err(ProtocolError::new("INVALID_ARGUMENT", "SAMPLEKEYS count must be a positive integer").at(1));
+                };
+                Command::SampleKeys { count, pattern: args.get(2).map(|pattern| pattern.to_string()) }
+            }
+            "FLUSH" | "FLUSHALL" => Command::Flush,
+            "SIZE" | "DBSIZE" => Command::Size,
+            "PING" => Command::Ping,
+            "SAVE" => Command::Save,
+            "BGSAVE" => Command::BgSave,
+            "LASTSAVE" => Command::LastSave,
+            "WARM.STATUS" => Command::WarmStatus,
+            "FLAG.SET" => {
+                if args.len() != 3 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "FLAG.SET requires exactly two arguments: FLAG.SET <name> <on|off|percent>"));
+                }
+                let Ok(state) = args[2].parse() else {
+                    return Err(ProtocolError::new("INVALID_ARGUMENT", "FLAG.SET state must be on, off or a percentage from 0 to 100").at(2));
+                };
+                Command::FlagSet { name: args[1].to_string(), state }
+            }
+            "FLAG.CHECK" => {
+                if args.len() != 3 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "FLAG.CHECK requires exactly two arguments: FLAG.CHECK <name> <subject>"));
+                }
+                Command::FlagCheck { name: args[1].to_string(), subject: args[2].to_string() }
+            }
+            "FLAG.LIST" => Command::FlagList,
+            "NS.LIMIT" => {
+                if args.len() != 3 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "NS.LIMIT requires exactly two arguments: NS.LIMIT <pattern> <max_keys>"));
+                }
+                let Ok(max_keys) = args[2].parse() else {
+                    return Err(ProtocolError::new("INVALID_ARGUMENT", "NS.LIMIT max_keys must be a non-negative integer").at(2));
+                };
+                Command::NsLimit { pattern: args[1].to_string(), max_keys }
+            }
+            "NS.LIMITS" => Command::NsLimits,
+            "SEEN.ADD" => {
+                if args.len() != 4 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "SEEN.ADD requires exactly three arguments: SEEN.ADD <key> <item> <ttl>"));
+                }
+                let Ok(ttl) = args[3].parse() else {
+                    return Err(ProtocolError::new("INVALID_ARGUMENT", "SEEN.ADD ttl must be a number of seconds").at(3));
+                };
+                Command::SeenAdd { key: args[1].to_string(), item: args[2].to_string(), ttl }
+            }
+            "CAPABILITIES" => Command::Capabilities,
+            "META.SET" => {
+                if args.len() < 4 || !args.len().is_multiple_of(2) {
+                    return Err(ProtocolError::new("WRONG_ARITY", "META.SET requires field-value pairs: META.SET <key> <field> <value> [field value ...]"));
+                }
+                let fields = args[2..].chunks(2).map(|pair| (pair[0].to_string(), pair[1].to_string())).collect();
+                Command::MetaSet { key: args[1].to_string(), fields }
+            }
+            "META.GET" => {
+                if !(2..=3).contains(&args.len()) {
+                    return Err(ProtocolError::new("WRONG_ARITY", "META.GET requires a key and an optional field: META.GET <key> [field]"));
+                }
+                Command::MetaGet { key: args[1].to_string(), field: args.get(2).map(|field| field.to_string()) }
+            }
+            "META.DEL" => {
+                if args.len() < 3 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "META.DEL requires a key and at least one field: META.DEL <key> <field> [field ...]"));
+                }
+                Command::MetaDel { key: args[1].to_string(), fields: args[2..].iter().map(|field| field.to_string()).collect() }
+            }
+            "PUBLISH" => {
+                if args.len() != 3 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "PUBLISH requires exactly two arguments: PUBLISH <channel> <message>"));
+                }
+                Command::Publish { channel: args[1].to_string(), message: args[2].to_string() }
+            }
+            "SUBSCRIBE" => {
+                if args.len() < 2 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "SUBSCRIBE requires at least one channel: SUBSCRIBE <channel> [channel ...]"));
+                }
+                Command::Subscribe { channels: args[1..].iter().map(|channel| channel.to_string()).collect() }
+            }
+            "UNSUBSCRIBE" => Command::Unsubscribe { channels: args[1..].iter().map(|channel| channel.to_string()).collect() },
+            "PSUBSCRIBE" => {
+                if args.len() < 2 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "PSUBSCRIBE requires at least one pattern: PSUBSCRIBE <pattern> [pattern ...]"));
+                }
+                Command::PSubscribe { patterns: args[1..].iter().map(|pattern| pattern.to_string()).collect() }
+            }
+            "PUNSUBSCRIBE" => Command::PUnsubscribe { patterns: args[1..].iter().map(|pattern| pattern.to_string()).collect() },
+            "MULTI" => Command::Multi,
+            "EXEC" => Command::Exec,
+            "DISCARD" => Command::Discard,
+            "WATCH" => {
+                if args.len() < 2 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "WATCH requires at least one key: WATCH <key> [key ...]"));
+                }
+                Command::Watch { keys: args[1..].iter().map(|key| key.to_string()).collect() }
+            }
+            "UNWATCH" => Command::Unwatch,
+            "FUNCTION" => match (args.get(1).map(|sub| sub.to_uppercase()).as_deref(), args.len()) {
+                (Some("LOAD"), 3) => Command::FunctionLoad { code: args[2].to_string(), replace: false },
+                (Some("LOAD"), 4) if args[2].eq_ignore_ascii_case("REPLACE") => {
+                    Command::FunctionLoad { code: args[3].to_string(), replace: true }
+                }
+                (Some("DELETE"), 3) => Command::FunctionDelete { library: args[2].to_string() },
+                (Some("LIST"), 2) => Command::FunctionList,
+                _ => {
+                    return Err(ProtocolError::new(
+                        "WRONG_ARITY",
+                        "FUNCTION usage: FUNCTION LOAD [REPLACE] <code> | FUNCTION DELETE <library> | FUNCTION LIST",
+                    ))
+                }
+            },
+            "COMPAT" => match args {
+                [_] => Command::Compat { mode: None },
+                [_, mode] => match mode.parse() {
+                    Ok(mode) => Command::Compat { mode: Some(mode) },
+                    Err(e) => return Err(ProtocolError::new("INVALID_ARGUMENT", e.to_string()).at(1)),
+                },
+                _ => return Err(ProtocolError::new("WRONG_ARITY", "COMPAT takes at most one argument: COMPAT [native|strict]")),
+            },
+            "FCALL" => {
+                if args.len() < 3 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "FCALL requires a function and a key count: FCALL <function> <numkeys> [key ...] [arg ...]"));
+                }
+                let (keys, rest) = keys_and_args(args, "FCALL")?;
+                Command::FCall { function: args[1].to_string(), keys, args: rest }
+            }
+            "EVAL" | "EVALSHA" => {
+                let name = args[0].to_uppercase();
+                if args.len() < 3 {
+                    let source = if name == "EVAL" { "script" } else { "sha1" };
+                    return Err(ProtocolError::new(
+                        "WRONG_ARITY",
+                        format!("{} requires a {} and a key count: {} <{}> <numkeys> [key ...] [arg ...]", name, source, name, source),
+                    ));
+                }
+                let (keys, rest) = keys_and_args(args, &name)?;
+                if name == "EVAL" {
+                    Command::Eval { script: args[1].to_string(), keys, args: rest }
+                } else {
+                    Command::EvalSha { sha: args[1].to_string(), keys, args: rest }
+                }
+            }
+            "SCRIPT" => match (args.get(1).map(|sub| sub.to_uppercase()).as_deref(), args.len()) {
+                (Some("LOAD"), 3) => Command::ScriptLoad { script: args[2].to_string() },
+                _ => return Err(ProtocolError::new("WRONG_ARITY", "SCRIPT usage: SCRIPT LOAD <script>")),
+            },
+            "PIN" | "UNPIN" => {
+                if args.len() != 2 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "PIN/UNPIN require exactly one argument: PIN <key>"));
+                }
+                let key = args[1].to_string();
+                if args[0].eq_ignore_ascii_case("PIN") {
+                    Command::Pin { key }
+                } else {
+                    Command::Unpin { key }
+                }
+            }
+            "INFO" => Command::Info,
+            "EXPIRE" | "PEXPIRE" => {
+                let name = if args[0].eq_ignore_ascii_case("EXPIRE") { "EXPIRE" } else { "PEXPIRE" };
+                if args.len() != 3 {
+                    return Err(ProtocolError::new("WRONG_ARITY", format!("{} requires exactly two arguments: {} <key> <ttl>", name, name)));
+                }
+                let Ok(ttl) = args[2].parse() else {
+                    return Err(ProtocolError::new("INVALID_ARGUMENT", format!("{} ttl must be a non-negative integer", name)).at(2));
+                };
+                let key = args[1].to_string();
+                if name == "EXPIRE" {
+                    Command::Expire { key, seconds: ttl }
+                } else {
+                    Command::PExpire { key, millis: ttl }
+                }
+            }
+            "HSET" => {
+                if args.len() < 4 || !args.len().is_multiple_of(2) {
+                    return Err(ProtocolError::new("WRONG_ARITY", "HSET requires field-value pairs: HSET <key> <field> <value> [field value ...]"));
+                }
+                let fields = args[2..].chunks(2).map(|pair| (pair[0].to_string(), pair[1].to_string())).collect();
+                Command::HSet { key: args[1].to_string(), fields }
+            }
+            "HGET" => {
+                if args.len() != 3 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "HGET requires exactly two arguments: HGET <key> <field>"));
+                }
+                Command::HGet { key: args[1].to_string(), field: args[2].to_string() }
+            }
+            "HMGET" | "HDEL" => {
+                if args.len() < 3 {
+                    return Err(ProtocolError::new("WRONG_ARITY", format!("{} requires a key and at least one field", args[0].to_uppercase())));
+                }
+                let key = args[1].to_string();
+                let fields = args[2..].iter().map(|field| field.to_string()).collect();
+                if args[0].eq_ignore_ascii_case("HMGET") {
+                    Command::HMGet { key, fields }
+                } else {
+                    Command::HDel { key, fields }
+                }
+            }
+            "HGETALL" | "HKEYS" | "HVALS" | "HLEN" => {
+                let name = args[0].to_uppercase();
+                if args.len() != 2 {
+                    return Err(ProtocolError::new("WRONG_ARITY", format!("{} requires exactly one argument: {} <key>", name, name)));
+                }
+                let key = args[1].to_string();
+                match name.as_str() {
+                    "HGETALL" => Command::HGetAll { key },
+                    "HKEYS" => Command::HKeys { key },
+                    "HVALS" => Command::HVals { key },
+                    _ => Command::HLen { key },
+                }
+            }
+            "GETSET" => {
+                if args.len() != 3 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "GETSET requires exactly two arguments: GETSET <key> <value>"));
+                }
+                Command::GetSet { key: args[1].to_string(), value: args[2].to_string() }
+            }
+            "GETDEL" => {
+                if args.len() != 2 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "GETDEL requires exactly one argument: GETDEL <key>"));
+                }
+                Command::GetDel { key: args[1].to_string() }
+            }
+            "GETEX" => {
+                if args.len() < 2 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "GETEX requires a key: GETEX <key> [EX s|PX ms|PERSIST]"));
+                }
+                let (mut ex, mut px, mut persist) = (None, None, false);
+                let mut i = 2;
+                while i < args.len() {
+                    let option_at = i;
+                    let mut option = [0u8; MAX_COMMAND_NAME];
+                    match ascii_upper(args[i], &mut option) {
+                        "PERSIST" => persist = true,
+                        unit @ ("EX" | "PX") => {
+                            let Some(ttl) = args.get(i + 1) else {
+                                return Err(ProtocolError::new("WRONG_ARITY", format!("GETEX {} requires a value", unit)).at(i));
+                            };
+                            let Some(ttl) = ttl.parse().ok().filter(|ttl| *ttl > 0) else {
+                                return Err(ProtocolError::new("INVALID_ARGUMENT", format!("GETEX {} must be a positive integer", unit)).at(i + 1));
+                            };
+                            if unit == "EX" { ex = Some(ttl) } else { px = Some(ttl) }
+                            i += 1;
+                        }
+                        _ => return Err(ProtocolError::new("INVALID_ARGUMENT", format!("Unknown GETEX option: {}", args[i])).at(i)),
+                    }
+                    getex_expiry(ex, px, persist).map_err(|e| e.at(option_at))?;
+                    i += 1;
+                }
+                Command::GetEx { key: args[1].to_string(), ex, px, persist }
+            }
+            "RENAME" | "RENAMENX" => {
+                let name = args[0].to_uppercase();
+                if args.len() != 3 {
+                    return Err(ProtocolError::new("WRONG_ARITY", format!("{} requires exactly two arguments: {} <key> <newkey>", name, name)));
+                }
+                let (key, newkey) = (args[1].to_string(), args[2].to_string());
+                match name.as_str() {
+                    "RENAME" => Command::Rename { key, newkey },
+                    _ => Command::RenameNx { key, newkey },
+                }
+            }
+            "COPY" => {
+                if !(3..=4).contains(&args.len()) {
+                    return Err(ProtocolError::new("WRONG_ARITY", "COPY requires two keys: COPY <source> <destination> [REPLACE]"));
+                }
+                let replace = match args.get(3) {
+                    Some(option) if option.eq_ignore_ascii_case("REPLACE") => true,
+                    Some(option) => return Err(ProtocolError::new("INVALID_ARGUMENT", format!("Unknown COPY option: {}", option)).at(3)),
+                    None => false,
+                };
+                Command::Copy { source: args[1].to_string(), destination: args[2].to_string(), replace }
+            }
+            "DUMP" => {
+                if args.len() != 2 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "DUMP requires exactly one argument: DUMP <key>"));
+                }
+                Command::Dump { key: args[1].to_string() }
+            }
+            "RESTORE" => {
+                if !(4..=5).contains(&args.len()) {
+                    return Err(ProtocolError::new("WRONG_ARITY", "RESTORE requires a key, a TTL and a payload: RESTORE <key> <ttl-ms> <payload> [REPLACE]"));
+                }
+                let Ok(ttl) = args[2].parse() else {
+                    return Err(ProtocolError::new("INVALID_ARGUMENT", "RESTORE ttl must be a non-negative integer (0 for none)").at(2));
+                };
+                let replace = match args.get(4) {
+                    Some(option) if option.eq_ignore_ascii_case("REPLACE") => true,
+                    Some(option) => return Err(ProtocolError::new("INVALID_ARGUMENT", format!("Unknown RESTORE option: {}", option)).at(4)),
+                    None => false,
+                };
+                Command::Restore { key: args[1].to_string(), ttl, payload: args[3].to_string(), replace }
+            }
+            "BLOB.CREATE" => {
+                if args.len() != 3 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "BLOB.CREATE requires exactly two arguments: BLOB.CREATE <key> <max-bytes>"));
+                }
+                let Some(max_bytes) = args[2].parse().ok().filter(|max| *max > 0) else {
+                    return Err(ProtocolError::new("INVALID_ARGUMENT", "BLOB.CREATE max-bytes must be a positive integer").at(2));
+                };
+                Command::BlobCreate { key: args[1].to_string(), max_bytes }
+            }
+            "BLOB.APPEND" => {
+                if args.len() != 3 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "BLOB.APPEND requires exactly two arguments: BLOB.APPEND <key> <data>"));
+                }
+                Command::BlobAppend { key: args[1].to_string(), value: args[2].to_string(), encoding: ValueEncoding::Utf8 }
+            }
+            "BLOB.READ" => {
+                if args.len() != 4 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "BLOB.READ requires exactly three arguments: BLOB.READ <key> <offset> <len>"));
+                }
+                let mut range = [0usize; 2];
+                for (i, bound) in range.iter_mut().enumerate() {
+                    let Ok(value) = args[i + 2].parse() else {
+                        return Err(ProtocolError::new("INVALID_ARGUMENT", "BLOB.READ offset and len must be non-negative integers").at(i + 2));
+                    };
+                    *bound = value;
+                }
+                Command::BlobRead { key: args[1].to_string(), offset: range[0], len: range[1], encoding: ValueEncoding::Utf8 }
+            }
+            "BLOB.LEN" => {
+                if args.len() != 2 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "BLOB.LEN requires exactly one argument: BLOB.LEN <key>"));
+                }
+                Command::BlobLen { key: args[1].to_string() }
+            }
+            "HEATMAP" => {
+                if args.len() > 3 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "HEATMAP takes at most two arguments: HEATMAP [prefix|*] [window-seconds]"));
+                }
+                let prefix = match args.get(1) {
+                    Some(&"*") | None => String::new(),
+                    Some(prefix) => prefix.to_string(),
+                };
+                let window = match args.get(2).map(|window| window.parse()) {
+                    Some(Ok(window)) if window > 0 => window,
+                    Some(_) => return Err(ProtocolError::new("INVALID_ARGUMENT", "HEATMAP window must be a positive number of seconds").at(2)),
+                    None => default_heatmap_window(),
+                };
+                Command::Heatmap { prefix, window }
+            }
+            "MGETEX" => {
+                let usage = "MGETEX requires an expiry and at least one key: MGETEX <EX s|PX ms|PERSIST> <key> [key ...]";
+                let mut option = [0u8; MAX_COMMAND_NAME];
+                let (ex, px, persist, first_key) = match args.get(1).map(|arg| ascii_upper(arg, &mut option)) {
+                    Some("PERSIST") => (None, None, true, 2),
+                    Some(unit @ ("EX" | "PX")) => {
+                        let Some(ttl) = args.get(2) else {
+                            return Err(ProtocolError::new("WRONG_ARITY", usage));
+                        };
+                        let Some(ttl) = ttl.parse().ok().filter(|ttl| *ttl > 0) else {
+                            return Err(ProtocolError::new("INVALID_ARGUMENT", format!("MGETEX {} must be a positive integer", unit)).at(2));
+                        };
+                        if unit == "EX" { (Some(ttl), None, false, 3) } else { (None, Some(ttl), false, 3) }
+                    }
+                    Some(_) => return Err(ProtocolError::new("INVALID_ARGUMENT", format!("Unknown MGETEX option: {}", args[1])).at(1)),
+                    None => return Err(ProtocolError::new("WRONG_ARITY", usage)),
+                };
+                if args.len() <= first_key {
+                    return Err(ProtocolError::new("WRONG_ARITY", usage));
+                }
+                Command::MGetEx { keys: args[first_key..].iter().map(|key| key.to_string()).collect(), ex, px, persist }
+            }
+            "MGET" => {
+                if args.len() < 2 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "MGET requires at least one key: MGET <key> [key ...]"));
+                }
+                Command::MGet { keys: args[1..].iter().map(|key| key.to_string()).collect() }
+            }
+            "MSET" => {
+                if args.len() < 3 || args.len().is_multiple_of(2) {
+                    return Err(ProtocolError::new("WRONG_ARITY", "MSET requires key-value pairs: MSET <key> <value> [key value ...]"));
+                }
+                let pairs = args[1..].chunks(2).map(|pair| (pair[0].to_string(), pair[1].to_string())).collect();
+                Command::MSet { pairs }
+            }
+            "INCR" | "DECR" => {
+                if args.len() != 2 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "INCR/DECR require exactly one argument: INCR <key>"));
+                }
+                let key = args[1].to_string();
+                if args[0].eq_ignore_ascii_case("INCR") {
+                    Command::Incr { key }
+                } else {
+                    Command::Decr { key }
+                }
+            }
+            "INCRBY" | "DECRBY" | "INCRBYFLOAT" => {
+                let name = args[0].to_uppercase();
+                if args.len() != 3 {
+                    return Err(ProtocolError::new("WRONG_ARITY", format!("{} requires exactly two arguments: {} <key> <delta>", name, name)));
+                }
+                let key = args[1].to_string();
+                if name == "INCRBYFLOAT" {
+                    let Some(delta) = args[2].parse::<f64>().ok().filter(|delta| delta.is_finite()) else {
+                        return Err(ProtocolError::new("INVALID_ARGUMENT", "INCRBYFLOAT delta must be a finite number").at(2));
+                    };
+                    Command::IncrByFloat { key, delta }
+                } else {
+                    let Ok(delta) = args[2].parse() else {
+                        return Err(ProtocolError::new("INVALID_ARGUMENT", format!("{} delta must be a 64-bit integer", name)).at(2));
+                    };
+                    if name == "INCRBY" {
+                        Command::IncrBy { key, delta }
+                    } else {
+                        Command::DecrBy { key, delta }
+                    }
+                }
+            }
+            "ZADD" => {
+                if args.len() < 4 || !args.len().is_multiple_of(2) {
+                    return Err(ProtocolError::new("WRONG_ARITY", "ZADD requires score-member pairs: ZADD <key> <score> <member> [score member ...]"));
+                }
+                let mut members = IndexMap::new();
+                for (i, pair) in args[2..].chunks(2).enumerate() {
+                    let score = pair[0].parse::<f64>().ok().filter(|score| !score.is_nan());
+                    let Some(score) = score else {
+                        return Err(ProtocolError::new("INVALID_ARGUMENT", "ZADD score must be a number").at(2 + i * 2));
+                    };
+                    members.insert(pair[1].to_string(), score);
+                }
+                Command::ZAdd { key: args[1].to_string(), members }
+            }
+            "ZRANGE" | "ZREVRANGE" => {
+                let name = args[0].to_uppercase();
+                let withscores = args.len() == 5 && args[4].eq_ignore_ascii_case("WITHSCORES");
+                if args.len() != 4 && !withscores {
+                    return Err(ProtocolError::new("WRONG_ARITY", format!("{} requires a key and two ranks: {} <key> <start> <stop> [WITHSCORES]", name, name)));
+                }
+                let rank = |i: usize| {
+                    args[i]
+                        .parse::<i64>()
+                        .map_err(|_| ProtocolError::new("INVALID_ARGUMENT", format!("{} ranks must be integers", name)).at(i))
+                };
+                let (key, start, stop) = (args[1].to_string(), rank(2)?, rank(3)?);
+                if name == "ZRANGE" {
+                    Command::ZRange { key, start, stop, withscores }
+                } else {
+                    Command::ZRevRange { key, start, stop, withscores }
+                }
+            }
+            "ZSCORE" | "ZRANK" => {
+                if args.len() != 3 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "ZSCORE/ZRANK require exactly two arguments: ZSCORE <key> <member>"));
+                }
+                let (key, member) = (args[1].to_string(), args[2].to_string());
+                if args[0].eq_ignore_ascii_case("ZSCORE") {
+                    Command::ZScore { key, member }
+                } else {
+                    Command::ZRank { key, member }
+                }
+            }
+            "ZREM" => {
+                if args.len() < 3 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "ZREM requires a key and at least one member"));
+                }
+                Command::ZRem { key: args[1].to_string(), members: args[2..].iter().map(|member| member.to_string()).collect() }
+            }
+            "ZCARD" => {
+                if args.len() != 2 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "ZCARD requires exactly one argument: ZCARD <key>"));
+                }
+                Command::ZCard { key: args[1].to_string() }
+            }
+            "TTL" | "PTTL" => {
+                if args.len() != 2 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "TTL/PTTL require exactly one argument: TTL <key>"));
+                }
+                let key = args[1].to_string();
+                if args[0].eq_ignore_ascii_case("TTL") {
+                    Command::Ttl { key }
+                } else {
+                    Command::PTtl { key }
+                }
+            }
+            "PROFILE" => {
+                if args.len() < 2 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "PROFILE requires a command: PROFILE <command> [args...]"));
+                }
+                let command = Self::parse_args(&args[1..]).map_err(|mut e| {
+                    // Blame positions are relative to the full PROFILE line
+                    e.arg = e.arg.map(|arg| arg + 1);
+                    e
+                })?;
+                Command::Profile { command: Box::new(command) }
+            }
+            "DEBUG" => match (args.get(1).map(|sub| sub.to_uppercase()).as_deref(), args.len()) {
+                (Some("STATE"), 2) => Command::DebugState,
+                (Some("SET-TIME"), 3) => {
+                    let Ok(millis) = args[2].parse() else {
+                        return Err(ProtocolError::new("INVALID_ARGUMENT", "DEBUG SET-TIME takes milliseconds since the clock origin").at(2));
+                    };
+                    Command::DebugSetTime { millis }
+                }
+                _ => return Err(ProtocolError::new("WRONG_ARITY", "DEBUG usage: DEBUG STATE | DEBUG SET-TIME <millis>")),
+            },
+            "CONFIG" => match (args.get(1).map(|sub| sub.to_uppercase()).as_deref(), args.len()) {
+                (Some("GET"), 3) => Command::ConfigGet { parameter: args[2].to_string() },
+                (Some("SET"), 4) => Command::ConfigSet { parameter: args[2].to_string(), value: args[3].to_string() },
+                _ => return Err(ProtocolError::new("WRONG_ARITY", "CONFIG usage: CONFIG GET <parameter> | CONFIG SET <parameter> <value>")),
+            },
+            "EVICTION" => {
+                if !args.get(1).is_some_and(|sub| sub.eq_ignore_ascii_case("DEBUG")) || args.len() > 3 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "EVICTION usage: EVICTION DEBUG [trials]"));
+                }
+                let trials = match args.get(2).map(|trials| trials.parse()) {
+                    Some(Ok(trials)) => trials,
+                    Some(Err(_)) => return Err(ProtocolError::new("INVALID_ARGUMENT", "EVICTION DEBUG trials must be a non-negative integer").at(2)),
+                    None => default_eviction_trials(),
+                };
+                Command::EvictionDebug { trials }
+            }
+            "GENID" => {
+                if args.len() > 2 {
+                    return Err(ProtocolError::new("WRONG_ARITY", "GENID takes at most one argument: GENID [uuid|ulid|snowflake]"));
+                }
+                let kind = match args.get(1).map(|kind| kind.parse::<IdKind>()) {
+                    Some(Ok(kind)) => kind,
+                    Some(Err(e)) => return Err(ProtocolError::new("INVALID_ARGUMENT", e.to_string()).at(1)),
+                    None => IdKind::default(),
+                };
+                Command::GenId { kind }
+            }
+            _ => {
+                return Err(ProtocolError::new("UNKNOWN_COMMAND", format!("Unknown command: {}", args[0])).at(0));
+            }
+        };
+
+        Ok(command)
+    }
+
+    /// Execute a command given as borrowed text arguments
+    ///
+    /// GET, EXISTS, DEL and SET are the hot path for small-value workloads, so
+    /// they run straight against the cache without building a `Command`: keys
+    /// are only hashed and SET values are copied once into the store. While
+    /// keyspace notifications are on they take the regular path, which
+    /// publishes them.
+    pub fn execute_args(&self, args: &[&str]) -> Response {
+        let args: SmallVec<[&[u8]; 8]> = args.iter().map(|arg| arg.as_bytes()).collect();
+        self.execute_raw_args(&args)
+    }
+
+    /// `execute_args` for arguments as RESP carries them, which may be any
+    /// bytes
+    ///
+    /// Keys and options must still be UTF-8, but a SET value may be binary,
+    /// and GET replies with a binary value as `Response::Bytes` instead of
+    /// failing with `NOT_UTF8`.
+    pub fn execute_raw_args(&self, args: &[&[u8]]) -> Response {
+        #[cfg(feature = "chaos")]
+        if let Some(fault) = self.inject_fault() {
+            return fault;
+        }
+        if args.first().is_some_and(|command| [&b"FCALL"[..], b"EVAL", b"EVALSHA"].iter().any(|name| command.eq_ignore_ascii_case(name))) {
+            return match Self::parse_raw_args(args) {
+                Ok(command) => self.cache.exclusive(|| self.run(command)),
+                Err(e) => e.into(),
+            };
+        }
+
+        self.cache.shared(|| {
+            let mut name = [0u8; MAX_COMMAND_NAME];
+            let fast_path = !self.cache.keyspace_events().flags().is_enabled();
+            let command = args.first().filter(|_| fast_path).and_then(|command| std::str::from_utf8(command).ok());
+            let key = args.get(1).and_then(|key| std::str::from_utf8(key).ok());
+            let response = match (command.map(|command| ascii_upper(command, &mut name)), key, args.len()) {
+                (Some("GET"), Some(key), 2) => self.cache.get_bytes(key).map_or_else(cache_error, |value| value.map_or(Response::StringOption(None), raw_value)),
+                (Some("EXISTS"), Some(key), 2) => self.exists(key),
+                (Some("DEL" | "DELETE"), Some(key), 2) => self.del(key),
+                (Some("SET"), Some(key), 3) => self.set_raw(key.to_string(), args[2].to_vec()),
+                _ => {
+                    return match Self::parse_raw_args(args) {
+                        Ok(command) => {
+                            // The previous value comes back base64 encoded,
+                            // like the binary value that replaced it
+                            let binary_get = matches!(command, Command::Set { encoding: ValueEncoding::Base64, get: true, .. });
+                            match self.run(command) {
+                                Response::StringOption(Some(previous)) if binary_get => match BASE64.decode(&previous) {
+                                    Ok(previous) => raw_value(previous),
+                                    Err(_) => Response::StringOption(Some(previous)),
+                                },
+                                response => response,
+                            }
+                        }
+                        Err(e) => e.into(),
+                    }
+                }
+            };
+            let _ = self.cache.record_heat(key);
+            response
+        })
+    }
+
+    /// `execute_raw_args` taking the arguments by value, so a plain SET
+    /// stores the buffer its value was read into instead of a copy
+    pub fn execute_owned_args(&self, args: Vec<Vec<u8>>) -> Response {
+        match <[Vec<u8>; 3]>::try_from(args) {
+            Ok([name, key, value])
+                if name.eq_ignore_ascii_case(b"SET") && std::str::from_utf8(&key).is_ok() && !self.cache.keyspace_events().flags().is_enabled() =>
+            {
+                #[cfg(feature = "chaos")]
+                if let Some(fault) = self.inject_fault() {
+                    return fault;
+                }
+                let key = String::from_utf8(key).expect("key was checked to be UTF-8");
+                self.cache.shared(|| {
+                    let _ = self.cache.record_heat([key.as_str()]);
+                    self.set_raw(key, value)
+                })
+            }
+            Ok(args) => self.execute_raw_args(&args.each_ref().map(Vec::as_slice)),
+            Err(args) => self.execute_raw_args(&args.iter().map(Vec::as_slice).collect::<SmallVec<[&[u8]; 8]>>()),
+        }
+    }
+
+    /// Plain SET of a value sent as bytes, which always writes
+    fn set_raw(&self, key: String, value: Vec<u8>) -> Response {
+        match self.cache.set_bytes_with(key, value, SetOptions::default()) {
+            Ok(_) => Response::Ok,
+            Err(e) => cache_error(e),
+        }
+    }
+
+    fn get(&self, key: &str, encoding: ValueEncoding) -> Response {
+        let value = match encoding {
+            ValueEncoding::Utf8 => self.cache.get(key),
+            ValueEncoding::Base64 => self.cache.get_bytes(key).map(|value| value.map(|value| BASE64.encode(value))),
+        };
+        match value {
+            Ok(value) => Response::StringOption(value),
+            Err(e) => cache_error(e),
+        }
+    }
+
+    /// SET reply: OK, nil when NX/XX prevented the write, or the previous
+    /// value with GET
+    fn set(&self, key: String, value: String, encoding: ValueEncoding, options: SetOptions) -> Response {
+        let outcome = match encoding {
+            ValueEncoding::Utf8 => self.cache.set_with(key, value, options),
+            ValueEncoding::Base64 => {
+                let value = match decode_value(value, encoding) {
+                    Ok(value) => value,
+                    Err(e) => return e.into(),
+                };
+                self.cache.set_bytes_with(key, value, options).map(|outcome| SetOutcome {
+                    written: outcome.written,
+                    previous: outcome.previous.map(|previous| BASE64.encode(previous)),
+                })
+            }
         };
+        match outcome {
+            Ok(outcome) if options.get => Response::StringOption(outcome.previous),
+            Ok(SetOutcome { written: true, .. }) => Response::Ok,
+            Ok(_) => Response::StringOption(None),
+            Err(e) => cache_error(e),
+        }
+    }
+
+    fn del(&self, key: &str) -> Response {
+        match self.cache.del(key) {
+            Ok(deleted) => Response::Boolean(deleted),
+            Err(e) => cache_error(e),
+        }
+    }
+
+    fn exists(&self, key: &str) -> Response {
+        match self.cache.exists(key) {
+            Ok(exists) => Response::Boolean(exists),
+            Err(e) => Response::error(e.to_string()),
+        }
+    }
+
+    fn expire(&self, key: &str, ttl: Duration) -> Response {
+        match self.cache.expire(key, ttl) {
+            Ok(set) => Response::Boolean(set),
+            Err(e) => Response::error(e.to_string()),
+        }
+    }
+
+    /// TTL/PTTL reply: -2 for a missing key, -1 for no expiry, else `unit(left)`
+    fn ttl(&self, key: &str, unit: fn(Duration) -> i64) -> Response {
+        match self.cache.ttl(key) {
+            Ok(Ttl::Missing) => Response::Integer(-2),
+            Ok(Ttl::Persistent) => Response::Integer(-1),
+            Ok(Ttl::Expires(left)) => Response::Integer(unit(left)),
+            Err(e) => Response::error(e.to_string()),
+        }
+    }
+
+    fn incr_by(&self, key: &str, delta: i64) -> Response {
+        match self.cache.incr_by(key, delta) {
+            Ok(value) => Response::Integer(value),
+            Err(e) => cache_error(e),
+        }
+    }
+
+    /// ZRANGE/ZREVRANGE reply: members, or `[member, score, ...]` with scores
+    fn zrange(&self, key: &str, start: i64, stop: i64, withscores: bool, rev: bool) -> Response {
+        match self.cache.zrange(key, start, stop, rev) {
+            Ok(members) if withscores => Response::Array(
+                members
+                    .into_iter()
+                    .flat_map(|(member, score)| [Response::StringOption(Some(member)), Response::Float(score)])
+                    .collect(),
+            ),
+            Ok(members) => Response::StringArray(members.into_iter().map(|(member, _)| member).collect()),
+            Err(e) => cache_error(e),
+        }
+    }
+
+    /// Executes a command on this thread, measuring it with the per-thread
+    /// profile counters and, under jemalloc, the thread's allocation total
+    fn profile(&self, command: Command) -> Response {
+        if matches!(command, Command::Profile { .. }) {
+            return Response::coded_error("INVALID_ARGUMENT", "PROFILE cannot profile itself");
+        }
+
+        let counters = profile::snapshot();
+        let allocated = allocator::thread_allocated();
+        let started = Instant::now();
+        let response = self.run(command);
+        let elapsed = started.elapsed();
+        let allocated = allocator::thread_allocated().zip(allocated).map(|(after, before)| after - before);
+        let counters = profile::snapshot().since(counters);
+
+        let mut map = BTreeMap::new();
+        map.insert("response".to_string(), response);
+        map.insert("duration_us".to_string(), Response::Number(elapsed.as_micros() as usize));
+        map.insert("lock_acquisitions".to_string(), Response::Number(counters.lock_acquisitions as usize));
+        map.insert("lock_wait_us".to_string(), Response::Number((counters.lock_wait_ns / 1_000) as usize));
+        map.insert("keys_touched".to_string(), Response::Number(counters.keys_touched as usize));
+        map.insert("bytes_read".to_string(), Response::Number(counters.bytes_read as usize));
+        map.insert("bytes_written".to_string(), Response::Number(counters.bytes_written as usize));
+        if let Some(allocated) = allocated {
+            map.insert("allocated_bytes".to_string(), Response::Number(allocated as usize));
+        }
+        Response::Map(map)
+    }
+
+    /// Publishes the keyspace notifications for a command that changed keys,
+    /// named after the command as in Redis (`set`, `del`, `hset`, ...)
+    ///
+    /// Whether anything changed is read from `response`, so commands that
+    /// failed or left the keyspace alone publish nothing.
+    fn notify_keyspace(&self, command: Command, response: &Response) {
+        let events = self.cache.keyspace_events();
+        let changed = match response {
+            Response::Error { .. } => return,
+            Response::Boolean(changed) => *changed,
+            Response::Number(count) => *count > 0,
+            Response::StringOption(value) => value.is_some(),
+            _ => true,
+        };
+        // A hash or sorted set whose last member was removed is deleted
+        let emptied = |key: &str| {
+            if matches!(self.cache.ttl(key), Ok(Ttl::Missing)) {
+                events.notify(EventFlags::GENERIC, "del", key);
+            }
+        };
+        match command {
+            Command::Set { key, nx, xx, get, .. } => {
+                let written = match response {
+                    Response::StringOption(previous) if get => !(nx && previous.is_some() || xx && previous.is_none()),
+                    response => matches!(response, Response::Ok),
+                };
+                if written {
+                    events.notify(EventFlags::STRING, "set", &key);
+                }
+            }
+            Command::GetSet { key, .. } => events.notify(EventFlags::STRING, "set", &key),
+            Command::MSet { pairs } => pairs.keys().for_each(|key| events.notify(EventFlags::STRING, "set", key)),
+            Command::Del { key } | Command::GetDel { key } if changed => events.notify(EventFlags::GENERIC, "del", &key),
+            Command::Expire { key, .. } | Command::PExpire { key, .. } if changed => events.notify(EventFlags::GENERIC, "expire", &key),
+            Command::GetEx { key, ex, px, persist } if changed => Self::notify_getex(events, &key, ex.or(px).is_some(), persist),
+            Command::MGetEx { keys, ex, px, persist } => {
+                if let Response::Array(values) = response {
+                    for (key, value) in keys.iter().zip(values) {
+                        if matches!(value, Response::StringOption(Some(_))) {
+                            Self::notify_getex(events, key, ex.or(px).is_some(), persist);
+                        }
+                    }
+                }
+            }
+            Command::Incr { key } | Command::Decr { key } | Command::IncrBy { key, .. } | Command::DecrBy { key, .. } => {
+                events.notify(EventFlags::STRING, "incrby", &key)
+            }
+            Command::IncrByFloat { key, .. } => events.notify(EventFlags::STRING, "incrbyfloat", &key),
+            Command::Rename { key, newkey } | Command::RenameNx { key, newkey } if changed => {
+                events.notify(EventFlags::GENERIC, "rename_from", &key);
+                events.notify(EventFlags::GENERIC, "rename_to", &newkey);
+            }
+            Command::Copy { destination, .. } if changed => events.notify(EventFlags::GENERIC, "copy_to", &destination),
+            Command::Restore { key, .. } => events.notify(EventFlags::GENERIC, "restore", &key),
+            // Fires even when only existing fields were overwritten
+            Command::HSet { key, .. } => events.notify(EventFlags::HASH, "hset", &key),
+            Command::HDel { key, .. } if changed => {
+                events.notify(EventFlags::HASH, "hdel", &key);
+                emptied(&key);
+            }
+            Command::ZAdd { key, .. } => events.notify(EventFlags::ZSET, "zadd", &key),
+            Command::ZRem { key, .. } if changed => {
+                events.notify(EventFlags::ZSET, "zrem", &key);
+                emptied(&key);
+            }
+            Command::BlobCreate { key, .. } if changed => events.notify(EventFlags::STRING, "blob.create", &key),
+            Command::BlobAppend { key, .. } => events.notify(EventFlags::STRING, "blob.append", &key),
+            _ => {}
+        }
+    }
+
+    fn notify_getex(events: &KeyspaceEvents, key: &str, expire: bool, persist: bool) {
+        if expire {
+            events.notify(EventFlags::GENERIC, "expire", key);
+        } else if persist {
+            events.notify(EventFlags::GENERIC, "persist", key);
+        }
+    }
+
+    /// CAPABILITIES - supported protocol versions, commands and features
+    fn capabilities() -> Response {
+        let names = |items: &[&str]| Response::StringArray(items.iter().map(|s| s.to_string()).collect());
+        let mut map = BTreeMap::new();
+        map.insert("version".to_string(), Response::Number(PROTOCOL_VERSION as usize));
+        map.insert("min_version".to_string(), Response::Number(MIN_PROTOCOL_VERSION as usize));
+        map.insert("commands".to_string(), names(SUPPORTED_COMMANDS));
+        map.insert("features".to_string(), names(PROTOCOL_FEATURES));
+        Response::Map(map)
+    }
+
+    /// Parse a JSON command envelope into a command
+    ///
+    /// The envelope is `{"version": 1, "command": "GET", "args": {...}}`
+    /// where `version` is optional (see `PROTOCOL_VERSION`).
+    pub fn parse_command(input: &str) -> Result<Command, ProtocolError> {
+        let envelope: serde_json::Value = serde_json::from_str(input)
+            .map_err(|e| ProtocolError::new("INVALID_JSON", e.to_string()))?;
+        Self::parse_envelope(envelope)
+    }
+
+    /// Parse a CBOR-encoded command envelope into a command
+    ///
+    /// The envelope has the same shape and versioning rules as the JSON one,
+    /// encoded as a CBOR map.
+    pub fn parse_command_cbor(input: &[u8]) -> Result<Command, ProtocolError> {
+        let envelope: serde_json::Value = ciborium::from_reader(input)
+            .map_err(|e| ProtocolError::new("INVALID_CBOR", e.to_string()))?;
+        Self::parse_envelope(envelope)
+    }
+
+    /// Validate a decoded envelope and turn it into a command
+    pub fn parse_envelope(mut envelope: serde_json::Value) -> Result<Command, ProtocolError> {
+        let Some(fields) = envelope.as_object_mut() else {
+            return Err(ProtocolError::new("INVALID_COMMAND", "Command envelope must be a JSON object"));
+        };
+
+        if let Some(version) = fields.remove("version") {
+            let Some(version) = version.as_u64() else {
+                return Err(ProtocolError::new("INVALID_COMMAND", "version must be a positive integer"));
+            };
+            if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+                return Err(ProtocolError::new(
+                    "UNSUPPORTED_VERSION",
+                    format!("Protocol version {} is not supported (supported: {}-{})", version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION),
+                ));
+            }
+        }
+
+        match fields.get("command").and_then(|name| name.as_str()) {
+            Some(name) if !SUPPORTED_COMMANDS.contains(&name) => {
+                return Err(ProtocolError::new("UNKNOWN_COMMAND", format!("Unknown command: {}", name)));
+            }
+            Some(_) => {}
+            None => return Err(ProtocolError::new("MISSING_FIELD", "missing field `command`")),
+        }
+
+        // Commands whose arguments are all optional may omit `args` entirely
+        if !fields.contains_key("args") {
+            if let Ok(command) = serde_json::from_value(envelope.clone()) {
+                return Ok(command);
+            }
+            if let Some(fields) = envelope.as_object_mut() {
+                fields.insert("args".to_string(), serde_json::json!({}));
+            }
+        }
+
+        serde_json::from_value(envelope).map_err(|e| {
+            let message = e.to_string();
+            let code = if message.starts_with("unknown field") {
+                "UNKNOWN_FIELD"
+            } else if message.starts_with("missing field") {
+                "MISSING_FIELD"
+            } else {
+                "INVALID_COMMAND"
+            };
+            ProtocolError::new(code, message)
+        })
+    }
+
+    /// Convert a response to JSON string
+    pub fn response_to_json(response: &Response) -> Result<String> {
+        let json = serde_json::to_string(response)?;
+        Ok(json)
+    }
+
+    /// Convert a response to CBOR bytes
+    pub fn response_to_cbor(response: &Response) -> Result<Vec<u8>> {
+        let mut cbor = Vec::new();
+        ciborium::into_writer(response, &mut cbor)?;
+        Ok(cbor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protocol_operations() {
+        let cache = RustdisCache::new();
+        let protocol = RustdisProtocol::new(cache);
+
+        // Test SET command
+        let set_cmd = Command::set("test_key".to_string(), "test_value".to_string());
         let response = protocol.execute(set_cmd);
         assert!(matches!(response, Response::Ok));
 
         // Test GET command
-        let get_cmd = Command::Get {
-            key: "test_key".to_string(),
-        };
+        let get_cmd = Command::get("test_key".to_string());
         let response = protocol.execute(get_cmd);
         assert!(matches!(response, Response::StringOption(Some(_))));
 
@@ -142,4 +2736,333 @@ mod tests {
         let json = RustdisProtocol::response_to_json(&response).unwrap();
         assert_eq!(json, r#""PONG""#);
     }
+
+    #[test]
+    fn test_versioned_envelope_errors() {
+        let command = RustdisProtocol::parse_command(r#"{"version": 1, "command": "PING"}"#).unwrap();
+        assert!(matches!(command, Command::Ping));
+        let command = RustdisProtocol::parse_command(r#"{"command": "GENID"}"#).unwrap();
+        assert!(matches!(command, Command::GenId { kind: IdKind::Uuid }));
+        let command = RustdisProtocol::parse_command(r#"{"command": "MSET", "args": {"pairs": {"b": "2", "a": "1"}}}"#).unwrap();
+        assert!(matches!(command, Command::MSet { ref pairs } if pairs.len() == 2 && pairs["a"] == "1"));
+        let command = RustdisProtocol::parse_command(r#"{"command": "MGET", "args": {"keys": ["a", "b"]}}"#).unwrap();
+        assert!(matches!(command, Command::MGet { ref keys } if keys == &["a", "b"]));
+        let command = RustdisProtocol::parse_command(r#"{"command": "SET", "args": {"key": "k", "value": "v", "xx": true, "px": 500}}"#).unwrap();
+        assert!(matches!(command, Command::Set { xx: true, px: Some(500), nx: false, .. }));
+        let protocol = RustdisProtocol::new(RustdisCache::new());
+        let set = RustdisProtocol::parse_command(r#"{"command": "SET", "args": {"key": "k", "value": "v", "nx": true, "xx": true}}"#).unwrap();
+        assert!(matches!(protocol.execute(set), Response::Error { code: Some(ref code), .. } if code == "INVALID_ARGUMENT"));
+
+        let code = |input: &str| RustdisProtocol::parse_command(input).unwrap_err().code;
+        assert_eq!(code(r#"{"version": 99, "command": "PING"}"#), "UNSUPPORTED_VERSION");
+        assert_eq!(code(r#"{"command": "TELEPORT"}"#), "UNKNOWN_COMMAND");
+        assert_eq!(code(r#"{"command": "GET", "args": {"key": "a", "ttl": 5}}"#), "UNKNOWN_FIELD");
+        assert_eq!(code(r#"{"command": "GET", "args": {}}"#), "MISSING_FIELD");
+        assert_eq!(code("not json"), "INVALID_JSON");
+
+        let response: Response = RustdisProtocol::parse_command(r#"{"command": "X"}"#).unwrap_err().into();
+        let json = RustdisProtocol::response_to_json(&response).unwrap();
+        assert_eq!(json, r#"{"error":"Unknown command: X","code":"UNKNOWN_COMMAND"}"#);
+    }
+
+    #[test]
+    fn test_text_args() {
+        let protocol = RustdisProtocol::new(RustdisCache::new());
+
+        assert!(matches!(protocol.execute_args(&["set", "k", "v"]), Response::Ok));
+        assert!(matches!(protocol.execute_args(&["Get", "k"]), Response::StringOption(Some(ref v)) if v == "v"));
+        assert!(matches!(protocol.execute_args(&["EXISTS", "k"]), Response::Boolean(true)));
+        assert!(matches!(protocol.execute_args(&["ns.limit", "k*", "5"]), Response::Number(0)));
+        assert!(matches!(protocol.execute_args(&["TTL", "k"]), Response::Integer(-1)));
+        assert!(matches!(protocol.execute_args(&["expire", "k", "100"]), Response::Boolean(true)));
+        assert!(matches!(protocol.execute_args(&["ttl", "k"]), Response::Integer(100)));
+        assert!(matches!(protocol.execute_args(&["PTTL", "missing"]), Response::Integer(-2)));
+        assert!(matches!(protocol.execute_args(&["HSET", "h", "a", "1", "b", "2"]), Response::Number(2)));
+        assert!(matches!(
+            protocol.execute_args(&["hmget", "h", "b", "x"]),
+            Response::Array(items) if matches!(items.as_slice(), [Response::StringOption(Some(b)), Response::StringOption(None)] if b == "2")
+        ));
+        assert!(matches!(protocol.execute_args(&["HKEYS", "h"]), Response::StringArray(ref fields) if fields == &["a", "b"]));
+        assert!(matches!(protocol.execute_args(&["GET", "h"]), Response::Error { code: Some(ref code), .. } if code == "WRONGTYPE"));
+        assert!(matches!(protocol.execute_args(&["TYPE", "h"]), Response::String(ref name) if name == "hash"));
+        assert!(matches!(protocol.execute_args(&["type", "k"]), Response::String(ref name) if name == "string"));
+        assert!(matches!(protocol.execute_args(&["TYPE", "missing"]), Response::String(ref name) if name == "none"));
+        assert!(matches!(protocol.execute_args(&["MEMORY", "USAGE", "h"]), Response::Number(5)));
+        assert!(matches!(protocol.execute_args(&["memory", "usage", "h", "SAMPLES", "0"]), Response::Number(5)));
+        assert!(matches!(protocol.execute_args(&["MEMORY", "USAGE", "missing"]), Response::StringOption(None)));
+        assert!(matches!(protocol.execute_args(&["MEMORY", "USAGE"]), Response::Error { .. }));
+        assert!(matches!(protocol.execute_args(&["memory", "stats"]), Response::Map(ref stats) if matches!(stats["volatile_keys"], Response::Number(1))));
+        assert!(matches!(
+            protocol.execute_args(&["SAMPLEKEYS", "5", "h*"]),
+            Response::Map(ref sample) if matches!(&sample["keys"], Response::Array(keys) if keys.len() == 1)
+        ));
+        assert!(matches!(protocol.execute_args(&["SAMPLEKEYS", "0"]), Response::Error { .. }));
+        assert!(matches!(protocol.execute_args(&["SET", "lock", "a", "nx", "EX", "30"]), Response::Ok));
+        assert!(matches!(protocol.execute_args(&["SET", "lock", "b", "NX"]), Response::StringOption(None)));
+        assert!(matches!(protocol.execute_args(&["SET", "lock", "c", "KEEPTTL", "GET"]), Response::StringOption(Some(ref old)) if old == "a"));
+        assert!(matches!(protocol.execute_args(&["TTL", "lock"]), Response::Integer(30)));
+        assert!(matches!(protocol.execute_args(&["GETSET", "lock", "d"]), Response::StringOption(Some(ref old)) if old == "c"));
+        assert!(matches!(protocol.execute_args(&["GETEX", "lock", "px", "1500"]), Response::StringOption(Some(ref value)) if value == "d"));
+        assert!(matches!(protocol.execute_args(&["PTTL", "lock"]), Response::Integer(left) if left > 1000 && left <= 1500));
+        assert!(matches!(protocol.execute_args(&["GETEX", "lock", "PERSIST"]), Response::StringOption(Some(_))));
+        assert!(matches!(protocol.execute_args(&["TTL", "lock"]), Response::Integer(-1)));
+        assert!(matches!(protocol.execute_args(&["GETDEL", "lock"]), Response::StringOption(Some(ref value)) if value == "d"));
+        assert!(matches!(protocol.execute_args(&["GETDEL", "lock"]), Response::StringOption(None)));
+        assert!(matches!(protocol.execute_args(&["RENAME", "lock", "x"]), Response::Error { code: Some(ref code), .. } if code == "NO_SUCH_KEY"));
+        assert!(matches!(protocol.execute_args(&["COPY", "h", "h2", "replace"]), Response::Boolean(true)));
+        assert!(matches!(protocol.execute_args(&["COPY", "h", "h2"]), Response::Boolean(false)));
+        assert!(matches!(protocol.execute_args(&["RENAMENX", "h2", "h"]), Response::Boolean(false)));
+        assert!(matches!(protocol.execute_args(&["rename", "h2", "h3"]), Response::Ok));
+        assert!(matches!(protocol.execute_args(&["HGET", "h3", "a"]), Response::StringOption(Some(ref value)) if value == "1"));
+        assert!(matches!(protocol.execute_args(&["COPY", "h", "h3", "NOW"]), Response::Error { code: Some(ref code), .. } if code == "INVALID_ARGUMENT"));
+        assert!(matches!(protocol.execute_args(&["MSET", "a", "1", "b", "2"]), Response::Ok));
+        assert!(matches!(
+            protocol.execute_args(&["mget", "b", "nope", "h", "a"]),
+            Response::Array(items) if matches!(
+                items.as_slice(),
+                [Response::StringOption(Some(b)), Response::StringOption(None), Response::StringOption(None), Response::StringOption(Some(a))] if b == "2" && a == "1"
+            )
+        ));
+        assert!(matches!(protocol.execute_args(&["INCR", "n"]), Response::Integer(1)));
+        assert!(matches!(protocol.execute_args(&["decrby", "n", "5"]), Response::Integer(-4)));
+        assert!(matches!(protocol.execute_args(&["INCRBYFLOAT", "n", "0.5"]), Response::Float(f) if f == -3.5));
+        assert!(matches!(protocol.execute_args(&["INCR", "n"]), Response::Error { code: Some(ref code), .. } if code == "NOT_A_NUMBER"));
+        assert!(matches!(protocol.execute_args(&["DECRBY", "n", &i64::MIN.to_string()]), Response::Error { code: Some(ref code), .. } if code == "OVERFLOW"));
+        assert!(matches!(protocol.execute_args(&["ZADD", "z", "2", "b", "1.5", "a", "3", "c"]), Response::Number(3)));
+        assert!(matches!(protocol.execute_args(&["zrevrange", "z", "0", "1"]), Response::StringArray(ref members) if members == &["c", "b"]));
+        assert!(matches!(
+            protocol.execute_args(&["ZRANGE", "z", "0", "0", "withscores"]),
+            Response::Array(items) if matches!(items.as_slice(), [Response::StringOption(Some(a)), Response::Float(score)] if a == "a" && *score == 1.5)
+        ));
+        assert!(matches!(protocol.execute_args(&["ZRANK", "z", "c"]), Response::Number(2)));
+        assert!(matches!(protocol.execute_args(&["ZSCORE", "z", "x"]), Response::StringOption(None)));
+
+        let code = |args: &[&str]| RustdisProtocol::parse_args(args).unwrap_err().code;
+        assert_eq!(code(&["GET"]), "WRONG_ARITY");
+        assert_eq!(code(&["NS.LIMIT", "k*", "many"]), "INVALID_ARGUMENT");
+        assert_eq!(code(&["PEXPIRE", "k", "-1"]), "INVALID_ARGUMENT");
+        assert_eq!(code(&["HSET", "h", "a", "1", "b"]), "WRONG_ARITY");
+        assert_eq!(code(&["ZADD", "z", "nan", "a"]), "INVALID_ARGUMENT");
+        assert_eq!(code(&["MSET", "a", "1", "b"]), "WRONG_ARITY");
+        assert_eq!(code(&["SET", "k", "v", "EX"]), "WRONG_ARITY");
+        assert_eq!(code(&["SET", "k", "v", "FOREVER"]), "INVALID_ARGUMENT");
+        assert_eq!(code(&["MGET"]), "WRONG_ARITY");
+        assert_eq!(code(&["ZRANGE", "z", "0", "1", "SCORES"]), "WRONG_ARITY");
+        assert_eq!(code(&["FCALL", "f"]), "WRONG_ARITY");
+        assert_eq!(code(&["FCALL", "f", "2", "k"]), "INVALID_ARGUMENT");
+        assert_eq!(code(&["FCALL", "f", &usize::MAX.to_string()]), "INVALID_ARGUMENT");
+        assert_eq!(code(&["AVERYLONGCOMMANDNAMEINDEED"]), "UNKNOWN_COMMAND");
+        assert_eq!(code(&[]), "INVALID_COMMAND");
+
+        let arg = |args: &[&str]| RustdisProtocol::parse_args(args).unwrap_err().arg;
+        assert_eq!(arg(&["NS.LIMIT", "k*", "many"]), Some(2));
+        assert_eq!(arg(&["PROFILE", "SEEN.ADD", "k", "i", "soon"]), Some(4));
+        assert_eq!(arg(&["GET"]), None);
+        assert_eq!(arg(&["ZRANGE", "z", "0", "top"]), Some(3));
+        assert_eq!(arg(&["SET", "k", "v", "NX", "EX", "5", "XX"]), Some(6));
+        assert_eq!(arg(&["SET", "k", "v", "PX", "0"]), Some(4));
+        assert_eq!(arg(&["GETEX", "k", "PERSIST", "EX", "5"]), Some(3));
+    }
+
+    #[test]
+    fn test_command_specs_parse() {
+        // Every registry entry must name a command parse_args knows about
+        for spec in COMMAND_SPECS {
+            let args: Vec<&str> = spec.name.split(' ').collect();
+            if let Err(e) = RustdisProtocol::parse_args(&args) {
+                assert_ne!(e.code, "UNKNOWN_COMMAND", "{} is not parsed", spec.name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cbor_round_trip() {
+        let protocol = RustdisProtocol::new(RustdisCache::new());
+
+        let mut request = Vec::new();
+        let envelope = serde_json::json!({"version": 1, "command": "SET", "args": {"key": "k", "value": "v"}});
+        ciborium::into_writer(&envelope, &mut request).unwrap();
+        let command = RustdisProtocol::parse_command_cbor(&request).unwrap();
+        assert!(matches!(protocol.execute(command), Response::Ok));
+
+        let reply = RustdisProtocol::response_to_cbor(&Response::StringOption(Some("v".to_string()))).unwrap();
+        let decoded: Response = ciborium::from_reader(reply.as_slice()).unwrap();
+        assert!(matches!(decoded, Response::String(ref s) if s == "v"));
+
+        assert_eq!(RustdisProtocol::parse_command_cbor(&[0xff]).unwrap_err().code, "INVALID_CBOR");
+    }
+
+    #[test]
+    fn test_profile() {
+        let protocol = RustdisProtocol::new(RustdisCache::new());
+        protocol.execute_args(&["SET", "k", "value"]);
+
+        let Response::Map(profile) = protocol.execute_args(&["PROFILE", "GET", "k"]) else {
+            panic!("PROFILE must return a map");
+        };
+        assert!(matches!(profile.get("response"), Some(Response::StringOption(Some(ref v))) if v == "value"));
+        assert!(matches!(profile.get("keys_touched"), Some(Response::Number(1))));
+        assert!(matches!(profile.get("bytes_read"), Some(Response::Number(5))));
+        assert!(matches!(profile.get("lock_acquisitions"), Some(Response::Number(1))));
+
+        assert!(matches!(protocol.execute_args(&["PROFILE", "PROFILE", "PING"]), Response::Error { .. }));
+    }
+
+    #[test]
+    fn test_blob_commands() {
+        let protocol = RustdisProtocol::new(RustdisCache::new());
+        let code = |response: Response| match response {
+            Response::Error { code: Some(code), .. } => code,
+            other => panic!("expected an error, got {:?}", other),
+        };
+
+        assert_eq!(code(protocol.execute_args(&["BLOB.APPEND", "log", "x"])), "NO_SUCH_KEY");
+        assert!(matches!(protocol.execute_args(&["blob.create", "log", "10"]), Response::Boolean(true)));
+        assert!(matches!(protocol.execute_args(&["BLOB.CREATE", "log", "99"]), Response::Boolean(false)));
+        assert!(matches!(protocol.execute_args(&["BLOB.APPEND", "log", "line1"]), Response::Number(5)));
+        assert!(matches!(protocol.execute_args(&["BLOB.APPEND", "log", "line2"]), Response::Number(10)));
+        assert_eq!(code(protocol.execute_args(&["BLOB.APPEND", "log", "!"])), "BLOB_FULL");
+        assert!(matches!(protocol.execute_args(&["BLOB.READ", "log", "3", "4"]), Response::StringOption(Some(ref v)) if v == "e1li"));
+        assert!(matches!(protocol.execute_args(&["BLOB.READ", "log", "20", "4"]), Response::StringOption(Some(ref v)) if v.is_empty()));
+        assert!(matches!(protocol.execute_args(&["BLOB.READ", "nope", "0", "4"]), Response::StringOption(None)));
+        assert!(matches!(protocol.execute_args(&["BLOB.LEN", "log"]), Response::Number(10)));
+        assert!(matches!(protocol.execute_args(&["TYPE", "log"]), Response::String(ref name) if name == "blob"));
+        assert_eq!(code(protocol.execute_args(&["GET", "log"])), "WRONGTYPE");
+
+        // Binary chunks go through the JSON protocol as base64
+        protocol.execute_args(&["BLOB.CREATE", "bin", "4"]);
+        let append = r#"{"command": "BLOB.APPEND", "args": {"key": "bin", "value": "/wA=", "encoding": "base64"}}"#;
+        assert!(matches!(protocol.execute(RustdisProtocol::parse_command(append).unwrap()), Response::Number(2)));
+        assert_eq!(code(protocol.execute_args(&["BLOB.READ", "bin", "0", "2"])), "NOT_UTF8");
+        let read = r#"{"command": "BLOB.READ", "args": {"key": "bin", "offset": 1, "len": 9, "encoding": "base64"}}"#;
+        assert!(matches!(protocol.execute(RustdisProtocol::parse_command(read).unwrap()), Response::StringOption(Some(ref v)) if v == "AA=="));
+
+        let arg = |args: &[&str]| RustdisProtocol::parse_args(args).unwrap_err().arg;
+        assert_eq!(arg(&["BLOB.CREATE", "k", "0"]), Some(2));
+        assert_eq!(arg(&["BLOB.READ", "k", "0", "-1"]), Some(3));
+    }
+
+    #[test]
+    fn test_dump_restore() {
+        let source = RustdisProtocol::new(RustdisCache::new());
+        let target = RustdisProtocol::new(RustdisCache::new());
+        let code = |response: Response| match response {
+            Response::Error { code: Some(code), .. } => code,
+            other => panic!("expected an error, got {:?}", other),
+        };
+
+        source.execute_args(&["HSET", "cart:1", "sku", "42", "qty", "2"]);
+        source.execute_args(&["META.SET", "cart:1", "owner", "ana"]);
+        source.execute_args(&["EXPIRE", "cart:1", "60"]);
+        assert!(matches!(source.execute_args(&["DUMP", "missing"]), Response::StringOption(None)));
+        let Response::StringOption(Some(payload)) = source.execute_args(&["DUMP", "cart:1"]) else {
+            panic!("DUMP must return the payload");
+        };
+
+        assert!(matches!(target.execute_args(&["RESTORE", "copy", "0", &payload]), Response::Ok));
+        assert!(matches!(target.execute_args(&["HGET", "copy", "qty"]), Response::StringOption(Some(ref v)) if v == "2"));
+        assert!(matches!(target.execute_args(&["META.GET", "copy", "owner"]), Response::StringOption(Some(ref v)) if v == "ana"));
+        assert!(matches!(target.execute_args(&["TTL", "copy"]), Response::Integer(-1)));
+        assert_eq!(code(target.execute_args(&["RESTORE", "copy", "0", &payload])), "BUSYKEY");
+        assert!(matches!(target.execute_args(&["RESTORE", "copy", "5000", &payload, "replace"]), Response::Ok));
+        assert!(matches!(target.execute_args(&["PTTL", "copy"]), Response::Integer(ttl) if ttl > 4000));
+
+        // A damaged payload is refused rather than restored wrong
+        let mut damaged = BASE64.decode(&payload).unwrap();
+        damaged[12] ^= 1;
+        assert!(matches!(target.execute_args(&["RESTORE", "bad", "0", &BASE64.encode(damaged)]), Response::Error { .. }));
+        assert_eq!(code(target.execute_args(&["RESTORE", "bad", "0", "not base64!"])), "INVALID_ARGUMENT");
+        assert!(matches!(target.execute_args(&["EXISTS", "bad"]), Response::Boolean(false)));
+    }
+
+    #[test]
+    fn test_heatmap_command() {
+        let protocol = RustdisProtocol::new(RustdisCache::new());
+        protocol.execute_args(&["SET", "user:1", "a"]);
+        protocol.execute_args(&["GET", "user:1"]);
+        protocol.execute_args(&["MGET", "user:2", "cart:1"]);
+        protocol.execute_args(&["PING"]);
+
+        let Response::Map(heatmap) = protocol.execute_args(&["HEATMAP", "*", "60"]) else {
+            panic!("HEATMAP must return a map");
+        };
+        assert!(matches!(heatmap.get("window"), Some(Response::Number(60))));
+        assert!(matches!(heatmap.get("prefixes"), Some(Response::StringArray(prefixes)) if prefixes == &["cart", "user"]));
+        let Some(Response::Array(rows)) = heatmap.get("rows") else {
+            panic!("HEATMAP rows must be an array");
+        };
+        assert!(matches!(rows.as_slice(), [Response::Array(row)] if matches!(row.as_slice(), [Response::Integer(_), Response::Number(1), Response::Number(3)])));
+
+        let Response::Map(users) = protocol.execute_args(&["HEATMAP", "us"]) else {
+            panic!("HEATMAP must return a map");
+        };
+        assert!(matches!(users.get("prefixes"), Some(Response::StringArray(prefixes)) if prefixes == &["user"]));
+        assert_eq!(RustdisProtocol::parse_args(&["HEATMAP", "*", "0"]).unwrap_err().arg, Some(2));
+    }
+
+    #[test]
+    fn test_keyspace_notifications() {
+        let clock = std::sync::Arc::new(crate::clock::ManualClock::new());
+        let protocol = RustdisProtocol::new(RustdisCache::with_clock(clock.clone()));
+        let mut subscriber = protocol.cache().pubsub().subscriber();
+        subscriber.psubscribe("__key*__:*").unwrap();
+        let mut received = || std::iter::from_fn(|| subscriber.try_recv()).map(|message| format!("{} {}", message.channel, message.payload)).collect::<Vec<_>>();
+
+        // Off by default
+        protocol.execute_args(&["SET", "user:1", "ana"]);
+        assert!(received().is_empty());
+
+        assert!(matches!(protocol.execute_args(&["CONFIG", "SET", "notify-keyspace-events", "KEA"]), Response::Ok));
+        assert!(matches!(protocol.execute_args(&["CONFIG", "GET", "notify-keyspace-events"]), Response::Map(config) if matches!(&config["notify-keyspace-events"], Response::String(flags) if flags == "AKE")));
+        protocol.execute_args(&["SET", "user:1", "bia", "NX"]);
+        protocol.execute_args(&["DEL", "user:1"]);
+        assert_eq!(received(), ["__keyspace@0__:user:1 del", "__keyevent@0__:del user:1"]);
+
+        // Only the keyevent channel, for hashes and expired keys
+        protocol.execute_args(&["CONFIG", "SET", "notify-keyspace-events", "Ehx"]);
+        protocol.execute_args(&["HSET", "cart:1", "sku", "42"]);
+        protocol.execute_args(&["SET", "session", "abc", "PX", "10"]);
+        protocol.execute_args(&["HDEL", "cart:1", "sku"]);
+        clock.advance(Duration::from_millis(20));
+        protocol.execute_args(&["GET", "session"]);
+        assert_eq!(received(), ["__keyevent@0__:hset cart:1", "__keyevent@0__:hdel cart:1", "__keyevent@0__:expired session"]);
+
+        assert!(matches!(protocol.execute_args(&["CONFIG", "SET", "notify-keyspace-events", "Kq"]), Response::Error { .. }));
+    }
+
+    #[test]
+    fn test_supported_commands_match_serde_names() {
+        // Every advertised name must be a real variant, never an unknown one
+        for name in SUPPORTED_COMMANDS {
+            let envelope = serde_json::json!({ "command": name });
+            if let Err(e) = serde_json::from_value::<Command>(envelope) {
+                assert!(!e.to_string().contains("unknown variant"), "{} is not a Command variant", name);
+            }
+        }
+        assert!(ADMIN_COMMANDS.iter().all(|name| SUPPORTED_COMMANDS.contains(name)));
+        let samples: [&[&str]; 12] = [
+            &["SET", "k", "v"],
+            &["SAMPLEKEYS", "10", "user:*"],
+            &["PUBLISH", "news", "hello"],
+            &["SCAN", "0", "MATCH", "img:*", "META", "owner", "a*"],
+            &["META.SET", "k", "owner", "ana"],
+            &["NS.LIMIT", "k*", "1"],
+            &["CONFIG", "GET", "maxmemory"],
+            &["DEBUG", "SET-TIME", "0"],
+            &["PTTL", "k"],
+            &["HGETALL", "k"],
+            &["ZREVRANGE", "k", "0", "1"],
+            &["INCRBYFLOAT", "k", "1.5"],
+        ];
+        for args in samples {
+            let command = RustdisProtocol::parse_args(args).unwrap();
+            assert_eq!(serde_json::to_value(&command).unwrap()["command"], command.name());
+        }
+
+        let protocol = RustdisProtocol::new(RustdisCache::new());
+        let Response::Map(capabilities) = protocol.execute(Command::Capabilities) else {
+            panic!("CAPABILITIES must return a map");
+        };
+        assert!(matches!(capabilities.get("version"), Some(Response::Number(1))));
+    }
 }
@@ -0,0 +1,455 @@
+use crate::glob::glob_match;
+use crate::protocol::{Command, Response, RustdisProtocol};
+use anyhow::Result;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+/// Unread messages a subscriber may hold; messages published while it's
+/// this far behind are dropped for it rather than buffered without bound
+pub const MAX_PENDING_MESSAGES: usize = 10_000;
+
+/// A message published to a channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    /// The subscribed pattern the channel matched; `None` when delivered
+    /// to a subscriber of the channel itself
+    pub pattern: Option<String>,
+    pub channel: String,
+    pub payload: String,
+}
+
+/// Sent to subscribers as Redis does: `["message", channel, payload]`, or
+/// `["pmessage", pattern, channel, payload]` for pattern subscriptions
+impl From<Message> for Response {
+    fn from(message: Message) -> Self {
+        let bulk = |text: String| Response::StringOption(Some(text));
+        match message.pattern {
+            Some(pattern) => Response::Array(vec![bulk("pmessage".to_string()), bulk(pattern), bulk(message.channel), bulk(message.payload)]),
+            None => Response::Array(vec![bulk("message".to_string()), bulk(message.channel), bulk(message.payload)]),
+        }
+    }
+}
+
+/// Subscriber ids and senders of each channel or pattern
+type Subscriptions = HashMap<String, Vec<(u64, mpsc::Sender<Message>)>>;
+
+#[derive(Debug, Default)]
+struct Registry {
+    channels: Subscriptions,
+    /// Glob patterns, matched against channels as KEYS matches keys
+    patterns: Subscriptions,
+}
+
+impl Registry {
+    fn subscriptions(&mut self, pattern: bool) -> &mut Subscriptions {
+        if pattern {
+            &mut self.patterns
+        } else {
+            &mut self.channels
+        }
+    }
+}
+
+/// Subscribers of every channel and pattern, shared by every handle to a
+/// cache
+#[derive(Debug, Default)]
+pub struct PubSub {
+    registry: Mutex<Registry>,
+    next_id: AtomicU64,
+}
+
+impl PubSub {
+    /// PUBLISH - delivers `payload` to every subscriber of `channel` and of
+    /// each pattern matching it, returning how many received it
+    ///
+    /// A subscriber of both the channel and a matching pattern receives it
+    /// once for each, and is counted for each. Subscribers already holding
+    /// `MAX_PENDING_MESSAGES` unread messages miss it and aren't counted.
+    pub fn publish(&self, channel: &str, payload: &str) -> Result<usize> {
+        let mut registry = self.lock()?;
+        let message = |pattern: Option<&str>| Message { pattern: pattern.map(str::to_string), channel: channel.to_string(), payload: payload.to_string() };
+        let mut received = deliver(&mut registry.channels, channel, || message(None));
+        let patterns: Vec<String> = registry.patterns.keys().filter(|pattern| glob_match(pattern, channel)).cloned().collect();
+        for pattern in patterns {
+            received += deliver(&mut registry.patterns, &pattern, || message(Some(&pattern)));
+        }
+        Ok(received)
+    }
+
+    /// A subscriber for one connection or CLI session, with no channels yet
+    pub fn subscriber(self: &Arc<Self>) -> Subscriber {
+        let (sender, receiver) = mpsc::channel(MAX_PENDING_MESSAGES);
+        Subscriber {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            registry: self.clone(),
+            sender,
+            receiver,
+            channels: BTreeSet::new(),
+            patterns: BTreeSet::new(),
+        }
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Registry>> {
+        self.registry.lock().map_err(|_| anyhow::anyhow!("Failed to acquire pub/sub lock"))
+    }
+}
+
+/// Sends a message to every subscriber of `name`, returning how many took
+/// it; subscribers that have gone away are dropped on the way
+fn deliver(subscriptions: &mut Subscriptions, name: &str, message: impl Fn() -> Message) -> usize {
+    let Some(subscribers) = subscriptions.get_mut(name) else {
+        return 0;
+    };
+    let mut received = 0;
+    subscribers.retain(|(_, sender)| match sender.try_send(message()) {
+        Ok(()) => {
+            received += 1;
+            true
+        }
+        Err(TrySendError::Full(_)) => true,
+        Err(TrySendError::Closed(_)) => false,
+    });
+    if subscribers.is_empty() {
+        subscriptions.remove(name);
+    }
+    received
+}
+
+/// The channels and patterns one connection or CLI session listens to, and
+/// the messages waiting for it
+///
+/// Dropping it unsubscribes from everything.
+#[derive(Debug)]
+pub struct Subscriber {
+    id: u64,
+    registry: Arc<PubSub>,
+    sender: mpsc::Sender<Message>,
+    receiver: mpsc::Receiver<Message>,
+    channels: BTreeSet<String>,
+    patterns: BTreeSet<String>,
+}
+
+impl Subscriber {
+    /// Whether any channel or pattern is subscribed to
+    pub fn is_subscribed(&self) -> bool {
+        self.subscriptions() > 0
+    }
+
+    /// Channels and patterns subscribed to
+    pub fn subscriptions(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    /// Runs (P)SUBSCRIBE or (P)UNSUBSCRIBE, given as text arguments; `None`
+    /// for any other command
+    pub fn execute_args(&mut self, args: &[&str]) -> Option<Vec<Response>> {
+        let name = args.first()?;
+        if !["SUBSCRIBE", "UNSUBSCRIBE", "PSUBSCRIBE", "PUNSUBSCRIBE"].iter().any(|command| name.eq_ignore_ascii_case(command)) {
+            return None;
+        }
+        Some(match RustdisProtocol::parse_args(args) {
+            Ok(command) => self.execute(&command)?,
+            Err(e) => vec![e.into()],
+        })
+    }
+
+    /// Runs (P)SUBSCRIBE or (P)UNSUBSCRIBE, replying like Redis with one
+    /// `[kind, channel or pattern, subscriptions left]` array for each;
+    /// `None` for any other command
+    ///
+    /// (P)UNSUBSCRIBE without arguments leaves every channel (or pattern),
+    /// and when there are none replies once with a nil channel.
+    pub fn execute(&mut self, command: &Command) -> Option<Vec<Response>> {
+        let (kind, names, pattern, join) = match command {
+            Command::Subscribe { channels } => ("subscribe", channels, false, true),
+            Command::Unsubscribe { channels } => ("unsubscribe", channels, false, false),
+            Command::PSubscribe { patterns } => ("psubscribe", patterns, true, true),
+            Command::PUnsubscribe { patterns } => ("punsubscribe", patterns, true, false),
+            _ => return None,
+        };
+        let reply = |name: Option<String>, count: usize| {
+            Response::Array(vec![Response::StringOption(Some(kind.to_string())), Response::StringOption(name), Response::Number(count)])
+        };
+        let names = match names.is_empty() {
+            true => self.names(pattern).iter().cloned().collect(),
+            false => names.clone(),
+        };
+        if names.is_empty() {
+            return Some(vec![reply(None, self.subscriptions())]);
+        }
+        let replies = names
+            .into_iter()
+            .map(|name| {
+                let count = if join { self.join(pattern, &name)? } else { self.leave(pattern, &name)? };
+                Ok(reply(Some(name), count))
+            })
+            .collect::<Result<Vec<_>>>();
+        Some(replies.unwrap_or_else(|e| vec![Response::error(e.to_string())]))
+    }
+
+    /// Subscribes to `channel`, returning the number of subscriptions
+    pub fn subscribe(&mut self, channel: &str) -> Result<usize> {
+        self.join(false, channel)
+    }
+
+    /// Unsubscribes from `channel`, returning the number of subscriptions left
+    pub fn unsubscribe(&mut self, channel: &str) -> Result<usize> {
+        self.leave(false, channel)
+    }
+
+    /// Subscribes to every channel matching the glob `pattern`, returning
+    /// the number of subscriptions
+    pub fn psubscribe(&mut self, pattern: &str) -> Result<usize> {
+        self.join(true, pattern)
+    }
+
+    /// Unsubscribes from `pattern`, returning the number of subscriptions left
+    pub fn punsubscribe(&mut self, pattern: &str) -> Result<usize> {
+        self.leave(true, pattern)
+    }
+
+    /// Waits for the next message
+    pub async fn recv(&mut self) -> Option<Message> {
+        self.receiver.recv().await
+    }
+
+    /// The next message, if one is waiting
+    pub fn try_recv(&mut self) -> Option<Message> {
+        self.receiver.try_recv().ok()
+    }
+
+    fn names(&mut self, pattern: bool) -> &mut BTreeSet<String> {
+        if pattern {
+            &mut self.patterns
+        } else {
+            &mut self.channels
+        }
+    }
+
+    fn join(&mut self, pattern: bool, name: &str) -> Result<usize> {
+        if !self.names(pattern).contains(name) {
+            let subscriber = (self.id, self.sender.clone());
+            self.registry.lock()?.subscriptions(pattern).entry(name.to_string()).or_default().push(subscriber);
+            self.names(pattern).insert(name.to_string());
+        }
+        Ok(self.subscriptions())
+    }
+
+    fn leave(&mut self, pattern: bool, name: &str) -> Result<usize> {
+        if self.names(pattern).contains(name) {
+            remove(self.registry.lock()?.subscriptions(pattern), name, self.id);
+            self.names(pattern).remove(name);
+        }
+        Ok(self.subscriptions())
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        // With the lock poisoned, `publish` drops the registrations instead
+        // once it finds them closed
+        let Ok(mut registry) = self.registry.lock() else {
+            return;
+        };
+        for channel in &self.channels {
+            remove(&mut registry.channels, channel, self.id);
+        }
+        for pattern in &self.patterns {
+            remove(&mut registry.patterns, pattern, self.id);
+        }
+    }
+}
+
+/// Removes subscriber `id` from `name`, and `name` once nobody's left
+fn remove(subscriptions: &mut Subscriptions, name: &str, id: u64) {
+    if let Some(subscribers) = subscriptions.get_mut(name) {
+        subscribers.retain(|(subscriber, _)| *subscriber != id);
+        if subscribers.is_empty() {
+            subscriptions.remove(name);
+        }
+    }
+}
+
+/// Which keyspace notifications are published, written as Redis'
+/// `notify-keyspace-events`: `K` (keyspace channel) and/or `E` (keyevent
+/// channel), plus the event classes `g` (generic: del, expire, rename...),
+/// `$` (strings and blobs), `h` (hashes), `z` (sorted sets), `x` (expired),
+/// `e` (evicted), or `A` for all of them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EventFlags(u16);
+
+impl EventFlags {
+    pub const NONE: Self = Self(0);
+    /// Publish on `__keyspace@0__:<key>`, with the event as the message
+    pub const KEYSPACE: Self = Self(1);
+    /// Publish on `__keyevent@0__:<event>`, with the key as the message
+    pub const KEYEVENT: Self = Self(1 << 1);
+    pub const GENERIC: Self = Self(1 << 2);
+    pub const STRING: Self = Self(1 << 3);
+    pub const HASH: Self = Self(1 << 4);
+    pub const ZSET: Self = Self(1 << 5);
+    pub const EXPIRED: Self = Self(1 << 6);
+    pub const EVICTED: Self = Self(1 << 7);
+    /// Every event class (`A`)
+    pub const ALL: Self = Self(Self::GENERIC.0 | Self::STRING.0 | Self::HASH.0 | Self::ZSET.0 | Self::EXPIRED.0 | Self::EVICTED.0);
+
+    const LETTERS: [(char, Self); 8] = [
+        ('K', Self::KEYSPACE),
+        ('E', Self::KEYEVENT),
+        ('g', Self::GENERIC),
+        ('$', Self::STRING),
+        ('h', Self::HASH),
+        ('z', Self::ZSET),
+        ('x', Self::EXPIRED),
+        ('e', Self::EVICTED),
+    ];
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Whether any event gets published: a channel and a class are both on
+    pub fn is_enabled(self) -> bool {
+        self.0 & (Self::KEYSPACE.0 | Self::KEYEVENT.0) != 0 && self.0 & Self::ALL.0 != 0
+    }
+}
+
+impl std::ops::BitOr for EventFlags {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl FromStr for EventFlags {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.chars().try_fold(Self::NONE, |flags, letter| match letter {
+            'A' => Ok(flags | Self::ALL),
+            letter => match Self::LETTERS.iter().find(|(known, _)| *known == letter) {
+                Some((_, flag)) => Ok(flags | *flag),
+                None => Err(anyhow::anyhow!("Invalid notify-keyspace-events class '{}' (expected K, E, g, $, h, z, x, e or A)", letter)),
+            },
+        })
+    }
+}
+
+impl fmt::Display for EventFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let all = self.contains(Self::ALL);
+        if all {
+            write!(f, "A")?;
+        }
+        for (letter, flag) in Self::LETTERS {
+            if self.contains(flag) && !(all && Self::ALL.contains(flag)) {
+                write!(f, "{}", letter)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Publishes keyspace notifications on a cache's channels, for the event
+/// classes turned on with `CONFIG SET notify-keyspace-events`
+#[derive(Debug, Default)]
+pub struct KeyspaceEvents {
+    pubsub: Arc<PubSub>,
+    flags: AtomicU16,
+}
+
+impl KeyspaceEvents {
+    pub fn new(pubsub: Arc<PubSub>) -> Self {
+        Self { pubsub, flags: AtomicU16::new(0) }
+    }
+
+    pub fn flags(&self) -> EventFlags {
+        EventFlags(self.flags.load(Ordering::Relaxed))
+    }
+
+    pub fn set_flags(&self, flags: EventFlags) {
+        self.flags.store(flags.0, Ordering::Relaxed);
+    }
+
+    /// Publishes `event` for `key` if its class is on
+    pub fn notify(&self, class: EventFlags, event: &str, key: &str) {
+        let flags = self.flags();
+        if !flags.contains(class) {
+            return;
+        }
+        // Only a poisoned lock fails, and the change has happened either way
+        if flags.contains(EventFlags::KEYSPACE) {
+            let _ = self.pubsub.publish(&format!("__keyspace@0__:{}", key), event);
+        }
+        if flags.contains(EventFlags::KEYEVENT) {
+            let _ = self.pubsub.publish(&format!("__keyevent@0__:{}", event), key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_publish_subscribe() {
+        let registry = Arc::new(PubSub::default());
+        let mut first = registry.subscriber();
+        let mut second = registry.subscriber();
+        assert_eq!(first.subscribe("news").unwrap(), 1);
+        assert_eq!(first.subscribe("news").unwrap(), 1);
+        assert_eq!(first.subscribe("sports").unwrap(), 2);
+        second.subscribe("news").unwrap();
+
+        assert_eq!(registry.publish("news", "hello").unwrap(), 2);
+        assert_eq!(registry.publish("weather", "rain").unwrap(), 0);
+        assert_eq!(first.try_recv(), Some(Message { pattern: None, channel: "news".to_string(), payload: "hello".to_string() }));
+        assert_eq!(first.try_recv(), None);
+
+        // UNSUBSCRIBE without channels leaves all of them, in order
+        let replies = first.execute_args(&["UNSUBSCRIBE"]).unwrap();
+        assert_eq!(replies.len(), 2);
+        assert!(matches!(&replies[1], Response::Array(reply) if matches!(&reply[1..], [Response::StringOption(Some(channel)), Response::Number(0)] if channel == "sports")));
+        assert!(first.execute_args(&["GET", "k"]).is_none());
+        assert_eq!(registry.publish("news", "again").unwrap(), 1);
+
+        // Dropped subscribers stop counting, and a full one misses messages
+        drop(second);
+        assert_eq!(registry.publish("news", "gone").unwrap(), 0);
+        first.subscribe("news").unwrap();
+        for _ in 0..MAX_PENDING_MESSAGES {
+            registry.publish("news", "x").unwrap();
+        }
+        assert_eq!(registry.publish("news", "overflow").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_pattern_subscriptions() {
+        let registry = Arc::new(PubSub::default());
+        let mut subscriber = registry.subscriber();
+        assert_eq!(subscriber.psubscribe("events.*").unwrap(), 1);
+        assert_eq!(subscriber.subscribe("events.user").unwrap(), 2);
+
+        // A channel matched both ways is delivered, and counted, twice
+        assert_eq!(registry.publish("events.user", "signup").unwrap(), 2);
+        assert_eq!(registry.publish("events.order", "paid").unwrap(), 1);
+        assert_eq!(registry.publish("metrics.cpu", "90").unwrap(), 0);
+        let direct = subscriber.try_recv().unwrap();
+        assert_eq!(direct.pattern, None);
+        let matched = subscriber.try_recv().unwrap();
+        assert_eq!((matched.pattern.as_deref(), matched.channel.as_str()), (Some("events.*"), "events.user"));
+        let order = subscriber.try_recv().unwrap();
+        assert!(matches!(Response::from(order), Response::Array(reply) if reply.len() == 4));
+
+        // PUNSUBSCRIBE without patterns keeps the channel subscriptions
+        let replies = subscriber.execute_args(&["PUNSUBSCRIBE"]).unwrap();
+        assert!(matches!(&replies[..], [Response::Array(reply)] if matches!(reply[2], Response::Number(1))));
+        assert_eq!(registry.publish("events.order", "refunded").unwrap(), 0);
+        assert_eq!(registry.publish("events.user", "login").unwrap(), 1);
+    }
+}
@@ -0,0 +1,678 @@
+use crate::auth::{AclUser, Authenticator};
+use crate::cache::RustdisCache;
+use crate::compat::{redis_reply, CompatMode, REDIS_COMPAT_COMMANDS};
+use crate::framing;
+use crate::protocol::{Command, Response, RustdisProtocol};
+use crate::transaction::Transaction;
+use anyhow::Result;
+use smallvec::{smallvec, SmallVec};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::TcpListener;
+
+/// Largest bulk string accepted in a request
+pub const MAX_BULK_LEN: usize = crate::framing::MAX_FRAME_LEN;
+
+/// Most arguments accepted in one request
+pub const MAX_ARGS: usize = 1024 * 1024;
+
+/// Longest request header or inline command line accepted, line ending included
+pub const MAX_LINE_LEN: usize = 64 * 1024;
+
+/// Bulk replies at least this long are streamed to the client in chunks of
+/// this size instead of being copied into the reply buffer
+pub const STREAM_CHUNK_LEN: usize = 64 * 1024;
+
+/// Accepts RESP2 connections until the process is stopped
+///
+/// Standard Redis clients and `redis-cli` can connect; every command goes
+/// through the same text parser as the interactive CLI. When `auth` has
+/// providers, clients must `AUTH` before running commands. Connections that
+/// open with the framing handshake speak the framed CBOR protocol instead
+/// (see `serve_negotiated`).
+pub async fn serve(cache: RustdisCache, addr: SocketAddr, auth: Authenticator) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    println!("🚀 Rustdis RESP server listening on {}", listener.local_addr()?);
+    let protocol = Arc::new(RustdisProtocol::new(cache));
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        stream.set_nodelay(true)?;
+        let protocol = protocol.clone();
+        let auth = auth.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_negotiated(&protocol, &auth, stream).await {
+                eprintln!("Connection {} closed: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// Serves one connection until the client disconnects or sends QUIT
+///
+/// Replies are flushed once every already-buffered request has been
+/// answered, so pipelined commands share a write. Large values are streamed
+/// (see `write_reply`). After (P)SUBSCRIBE, published messages are written
+/// as they arrive and, as in Redis, only (P)SUBSCRIBE, (P)UNSUBSCRIBE, PING
+/// and QUIT are accepted until every subscription is left. After MULTI,
+/// commands are queued until EXEC or DISCARD (see `Transaction`). In
+/// strict compatibility mode (`redis-compat-strict`, or COMPAT on the
+/// connection), replies are worded as Redis would (see `compat::redis_reply`).
+pub async fn serve_connection<S: AsyncRead + AsyncWrite>(protocol: &RustdisProtocol, stream: S) -> Result<()> {
+    serve_connection_with_auth(protocol, &Authenticator::default(), stream).await
+}
+
+/// Serves one connection in whichever protocol its first bytes ask for
+///
+/// A connection opening with `framing::MAGIC` is handed to `framing::serve`;
+/// anything else is RESP, with the bytes read to decide replayed first. The
+/// framed protocol has no AUTH, so it is refused while `auth` requires one.
+pub async fn serve_negotiated<S: AsyncRead + AsyncWrite>(protocol: &RustdisProtocol, auth: &Authenticator, stream: S) -> Result<()> {
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let mut head = [0u8; framing::MAGIC.len()];
+    let mut read = 0;
+    while read < head.len() && head[..read] == framing::MAGIC[..read] {
+        match reader.read(&mut head[read..]).await? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    let reader = (&head[..read]).chain(reader);
+
+    if head[..read] != framing::MAGIC[..] {
+        return serve_streams(protocol, auth, reader, writer).await;
+    }
+    if auth.is_required() {
+        writer.write_all(b"-NOAUTH The framed protocol is unavailable while authentication is required\r\n").await?;
+        writer.flush().await?;
+        return Ok(());
+    }
+    framing::serve(protocol, &mut BufReader::new(reader), &mut BufWriter::new(writer)).await
+}
+
+/// Like `serve_connection`, with the connection's commands checked against
+/// the user it authenticated as
+pub async fn serve_connection_with_auth<S: AsyncRead + AsyncWrite>(
+    protocol: &RustdisProtocol,
+    auth: &Authenticator,
+    stream: S,
+) -> Result<()> {
+    let (reader, writer) = tokio::io::split(stream);
+    serve_streams(protocol, auth, reader, writer).await
+}
+
+async fn serve_streams<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+    protocol: &RustdisProtocol,
+    auth: &Authenticator,
+    reader: R,
+    writer: W,
+) -> Result<()> {
+    let mut reader = BufReader::new(reader);
+    let mut writer = BufWriter::new(writer);
+    let mut out = Vec::new();
+    let mut user = None;
+    let mut subscriber = protocol.cache().pubsub().subscriber();
+    let mut transaction = Transaction::default();
+    let mut compat = protocol.cache().compat_mode();
+
+    loop {
+        // Waiting for the next request must not hold back messages; filling
+        // the buffer consumes nothing, so losing the race is harmless
+        if subscriber.is_subscribed() && reader.buffer().is_empty() {
+            tokio::select! {
+                Some(message) = subscriber.recv() => {
+                    write_reply(&mut writer, &message.into(), &mut out).await?;
+                    while let Some(message) = subscriber.try_recv() {
+                        write_reply(&mut writer, &message.into(), &mut out).await?;
+                    }
+                    writer.flush().await?;
+                    continue;
+                }
+                filled = reader.fill_buf() => {
+                    filled?;
+                }
+            }
+        }
+        let mut args = match read_request(&mut reader).await {
+            Ok(Some(args)) => args,
+            Ok(None) => break,
+            Err(e) => {
+                // Like Redis, a malformed request ends the connection
+                writer.write_all(format!("-ERR Protocol error: {}\r\n", e).as_bytes()).await?;
+                writer.flush().await?;
+                return Ok(());
+            }
+        };
+        if args.is_empty() {
+            continue;
+        }
+
+        #[cfg(feature = "chaos")]
+        protocol.faults().wait().await;
+        let quit = args[0].eq_ignore_ascii_case(b"QUIT");
+        // Only SUBSCRIBE and UNSUBSCRIBE reply more than once; `None` when
+        // the command goes to the protocol, which then takes `args`
+        let replies: Option<SmallVec<[Response; 1]>> = if quit {
+            Some(smallvec![Response::Ok])
+        } else {
+            let raw: SmallVec<[&[u8]; 8]> = args.iter().map(Vec::as_slice).collect();
+            let text = text_args(&args);
+            let argv: SmallVec<[&str; 8]> = text.iter().map(|arg| arg.as_ref()).collect();
+            match authorize(auth, &mut user, &argv) {
+                Some(reply) => {
+                    if matches!(reply, Response::Error { .. }) {
+                        transaction.abort();
+                    }
+                    Some(smallvec![reply])
+                }
+                None if argv[0].eq_ignore_ascii_case("COMPAT") => Some(smallvec![switch_compat(&mut compat, &argv)]),
+                None if !subscriber.is_subscribed() && transaction.handles(argv[0]) => Some(smallvec![transaction.execute_raw_args(protocol, &raw)]),
+                None => match subscriber.execute_args(&argv) {
+                    Some(replies) => Some(SmallVec::from_vec(replies)),
+                    None if subscriber.is_subscribed() && !argv[0].eq_ignore_ascii_case("PING") => {
+                        let message = format!("Can't execute '{}': only (P)SUBSCRIBE, (P)UNSUBSCRIBE, PING and QUIT are allowed while subscribed", argv[0]);
+                        Some(smallvec![Response::error(message)])
+                    }
+                    None => None,
+                },
+            }
+        };
+        let replies = replies.unwrap_or_else(|| match compat {
+            // Strict replies are worded from the arguments, so they stay
+            CompatMode::Strict => {
+                let raw: SmallVec<[&[u8]; 8]> = args.iter().map(Vec::as_slice).collect();
+                smallvec![protocol.execute_raw_args(&raw)]
+            }
+            CompatMode::Native => smallvec![protocol.execute_owned_args(std::mem::take(&mut args))],
+        });
+        #[cfg(feature = "chaos")]
+        let replies = if protocol.faults().drop_reply() { SmallVec::new() } else { replies };
+        let text = text_args(&args);
+        let argv: SmallVec<[&str; 8]> = text.iter().map(|arg| arg.as_ref()).collect();
+        for reply in replies {
+            let reply = match compat {
+                CompatMode::Strict => redis_reply(&argv, reply),
+                CompatMode::Native => reply,
+            };
+            write_reply(&mut writer, &reply, &mut out).await?;
+        }
+
+        if quit {
+            break;
+        }
+        if reader.buffer().is_empty() {
+            writer.flush().await?;
+        }
+    }
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Text view of request arguments, for everything but running the command:
+/// keys and options must be UTF-8 anyway, and only a SET value may be binary
+fn text_args(args: &[Vec<u8>]) -> SmallVec<[Cow<'_, str>; 8]> {
+    args.iter().map(|arg| String::from_utf8_lossy(arg)).collect()
+}
+
+/// Writes one reply, streaming bulk strings of `STREAM_CHUNK_LEN` bytes or
+/// more straight from `response` in chunks instead of copying them into `out`
+///
+/// Each chunk waits until the connection accepts it, so a slow reader only
+/// holds back its own connection and a large value is never buffered twice.
+async fn write_reply<W: AsyncWrite + Unpin>(writer: &mut BufWriter<W>, response: &Response, out: &mut Vec<u8>) -> Result<()> {
+    out.clear();
+    let mut encoder = Encoder { out, streamed: Vec::new(), stream_from: STREAM_CHUNK_LEN };
+    encoder.encode(response);
+    let Encoder { out, streamed, .. } = encoder;
+
+    let mut written = 0;
+    for (at, value) in streamed {
+        writer.write_all(&out[written..at]).await?;
+        for chunk in value.chunks(STREAM_CHUNK_LEN) {
+            writer.write_all(chunk).await?;
+        }
+        written = at;
+    }
+    writer.write_all(&out[written..]).await?;
+    Ok(())
+}
+
+/// COMPAT: reports the connection's reply mode and the commands strict mode
+/// covers, or switches the mode
+fn switch_compat(compat: &mut CompatMode, args: &[&str]) -> Response {
+    match RustdisProtocol::parse_args(args) {
+        Ok(Command::Compat { mode: Some(mode) }) => {
+            *compat = mode;
+            Response::Ok
+        }
+        Ok(_) => Response::Map(BTreeMap::from([
+            ("mode".to_string(), Response::String(compat.to_string())),
+            ("commands".to_string(), Response::StringArray(REDIS_COMPAT_COMMANDS.iter().map(|name| name.to_string()).collect())),
+        ])),
+        Err(e) => e.into(),
+    }
+}
+
+/// Handles AUTH and refuses commands the connection may not run yet
+///
+/// Returns the reply when the command is answered here, `None` when it
+/// should run. As in Redis, a failed AUTH keeps the current user.
+fn authorize(auth: &Authenticator, user: &mut Option<AclUser>, args: &[&str]) -> Option<Response> {
+    if args[0].eq_ignore_ascii_case("AUTH") {
+        let (username, secret) = match args {
+            [_, secret] => (None, *secret),
+            [_, username, secret] => (Some(*username), *secret),
+            _ => return Some(Response::coded_error("WRONG_ARITY", "AUTH requires a password: AUTH [username] <password>")),
+        };
+        if !auth.is_required() {
+            return Some(Response::error("AUTH called without any password configured"));
+        }
+        return Some(match auth.authenticate(username, secret) {
+            Some(authenticated) => {
+                *user = Some(authenticated);
+                Response::Ok
+            }
+            None => Response::coded_error("WRONGPASS", "invalid username-password pair or user is disabled"),
+        });
+    }
+    if !auth.is_required() {
+        return None;
+    }
+    match user {
+        None => Some(Response::coded_error("NOAUTH", "Authentication required")),
+        Some(user) if user.role.is_unrestricted() => None,
+        Some(user) => {
+            // Parse errors are left for execution to report
+            let denied = user.role.denied(&RustdisProtocol::parse_args(args).ok()?)?;
+            Some(Response::coded_error("NOPERM", user.no_permission(denied)))
+        }
+    }
+}
+
+/// Reads one request: a RESP array of bulk strings, or an inline command line
+///
+/// Arguments are the bytes as sent (see `RustdisProtocol::execute_raw_args`
+/// for which may be binary). Returns `None` on a clean end of stream.
+async fn read_request<R: AsyncRead + Unpin>(reader: &mut BufReader<R>) -> Result<Option<Vec<Vec<u8>>>> {
+    let mut line = String::new();
+    if read_line(reader, &mut line).await? == 0 {
+        return Ok(None);
+    }
+    let header = line.trim_end_matches(['\r', '\n']);
+
+    let Some(count) = header.strip_prefix('*') else {
+        // Inline command, e.g. typed over telnet
+        return Ok(Some(header.split_whitespace().map(|arg| arg.as_bytes().to_vec()).collect()));
+    };
+    let count = parse_len(count, MAX_ARGS, "multibulk length")?;
+
+    // Grown as arguments arrive, so a large declared count costs nothing
+    // until the client actually sends that many
+    let mut args = Vec::new();
+    for _ in 0..count {
+        read_line(reader, &mut line).await?;
+        let len = line
+            .trim_end_matches(['\r', '\n'])
+            .strip_prefix('$')
+            .ok_or_else(|| anyhow::anyhow!("expected '$', got '{}'", line.trim_end()))?;
+        let len = parse_len(len, MAX_BULK_LEN, "bulk length")?;
+
+        // Also grown as it arrives, and handed on as is: a plain SET stores
+        // this buffer as the value (see `RustdisProtocol::execute_owned_args`)
+        let mut bulk = Vec::with_capacity(len.min(STREAM_CHUNK_LEN));
+        if (&mut *reader).take(len as u64).read_to_end(&mut bulk).await? < len {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        let mut end = [0u8; 2];
+        reader.read_exact(&mut end).await?;
+        if end != *b"\r\n" {
+            return Err(anyhow::anyhow!("bulk string not terminated by CRLF"));
+        }
+        args.push(bulk);
+    }
+    Ok(Some(args))
+}
+
+/// Reads one line into `line`, refusing lines over `MAX_LINE_LEN` so a
+/// client can't grow the buffer without bound by never sending a newline
+async fn read_line<R: AsyncRead + Unpin>(reader: &mut BufReader<R>, line: &mut String) -> Result<usize> {
+    line.clear();
+    let read = (&mut *reader).take(MAX_LINE_LEN as u64).read_line(line).await?;
+    if read == MAX_LINE_LEN && !line.ends_with('\n') {
+        return Err(anyhow::anyhow!("line longer than {} bytes", MAX_LINE_LEN));
+    }
+    Ok(read)
+}
+
+fn parse_len(value: &str, max: usize, what: &str) -> Result<usize> {
+    value
+        .parse()
+        .ok()
+        .filter(|len| *len <= max)
+        .ok_or_else(|| anyhow::anyhow!("invalid {}", what))
+}
+
+/// Encodes a response as a RESP2 reply
+///
+/// Booleans become integers (as Redis does for EXISTS and DEL) and maps
+/// become flat `[key, value, ...]` arrays, like CONFIG GET in Redis.
+pub fn encode(response: &Response, out: &mut Vec<u8>) {
+    Encoder { out, streamed: Vec::new(), stream_from: usize::MAX }.encode(response);
+}
+
+/// Reply encoder that can leave long bulk strings out of `out`
+///
+/// Bulk strings of at least `stream_from` bytes are recorded in `streamed`
+/// with the offset in `out` where their bytes belong; everything else,
+/// their length headers included, is copied into `out`.
+struct Encoder<'a, 'o> {
+    out: &'o mut Vec<u8>,
+    streamed: Vec<(usize, &'a [u8])>,
+    stream_from: usize,
+}
+
+impl<'a> Encoder<'a, '_> {
+    fn encode(&mut self, response: &'a Response) {
+        match response {
+            Response::Ok => self.out.extend_from_slice(b"+OK\r\n"),
+            Response::String(s) if !s.contains(['\r', '\n']) => {
+                self.out.push(b'+');
+                self.out.extend_from_slice(s.as_bytes());
+                self.out.extend_from_slice(b"\r\n");
+            }
+            Response::String(s) | Response::StringOption(Some(s)) => self.bulk(s.as_bytes()),
+            Response::Bytes(bytes) => self.bulk(bytes),
+            Response::StringOption(None) => self.out.extend_from_slice(b"$-1\r\n"),
+            Response::Boolean(b) => self.out.extend_from_slice(if *b { b":1\r\n" } else { b":0\r\n" }),
+            Response::Number(n) => self.out.extend_from_slice(format!(":{}\r\n", n).as_bytes()),
+            Response::Integer(n) => self.out.extend_from_slice(format!(":{}\r\n", n).as_bytes()),
+            Response::Float(f) => {
+                let f = f.to_string();
+                self.out.extend_from_slice(format!("${}\r\n{}\r\n", f.len(), f).as_bytes());
+            }
+            Response::StringArray(items) => {
+                self.out.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+                for item in items {
+                    self.bulk(item.as_bytes());
+                }
+            }
+            Response::Array(items) => {
+                self.out.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+                for item in items {
+                    self.encode(item);
+                }
+            }
+            Response::Error { error, code } => {
+                let error = error.replace(['\r', '\n'], " ");
+                self.out.extend_from_slice(format!("-{} {}\r\n", code.as_deref().unwrap_or("ERR"), error).as_bytes());
+            }
+            Response::Map(map) => {
+                self.out.extend_from_slice(format!("*{}\r\n", map.len() * 2).as_bytes());
+                for (key, value) in map {
+                    self.bulk(key.as_bytes());
+                    self.encode(value);
+                }
+            }
+        }
+    }
+
+    fn bulk(&mut self, bytes: &'a [u8]) {
+        self.out.extend_from_slice(format!("${}\r\n", bytes.len()).as_bytes());
+        if bytes.len() >= self.stream_from {
+            self.streamed.push((self.out.len(), bytes));
+        } else {
+            self.out.extend_from_slice(bytes);
+        }
+        self.out.extend_from_slice(b"\r\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resp_pipeline() {
+        let protocol = RustdisProtocol::new(RustdisCache::new());
+        let (mut client, server) = tokio::io::duplex(4096);
+        let task = tokio::spawn(async move { serve_connection(&protocol, server).await });
+
+        client
+            .write_all(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$5\r\nhello\r\n*2\r\n$3\r\nGET\r\n$1\r\nk\r\nEXISTS missing\r\nGET missing\r\nNOPE\r\nQUIT\r\n")
+            .await
+            .unwrap();
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).await.unwrap();
+        task.await.unwrap().unwrap();
+
+        assert_eq!(
+            String::from_utf8(reply).unwrap(),
+            "+OK\r\n$5\r\nhello\r\n:0\r\n$-1\r\n-UNKNOWN_COMMAND Unknown command: NOPE\r\n+OK\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_binary_values() {
+        let protocol = RustdisProtocol::new(RustdisCache::new());
+        let (mut client, server) = tokio::io::duplex(4096);
+        let task = tokio::spawn(async move { serve_connection(&protocol, server).await });
+
+        client
+            .write_all(b"*3\r\n$3\r\nSET\r\n$3\r\nbin\r\n$4\r\n\xff\0\r\n\r\n*2\r\n$3\r\nGET\r\n$3\r\nbin\r\n")
+            .await
+            .unwrap();
+        // With options SET goes through the parser, and GET replies with
+        // the binary value it replaced as is
+        client
+            .write_all(b"*6\r\n$3\r\nSET\r\n$3\r\nbin\r\n$1\r\n\xfe\r\n$2\r\nPX\r\n$5\r\n10000\r\n$3\r\nGET\r\n")
+            .await
+            .unwrap();
+        client.write_all(b"*2\r\n$3\r\nGET\r\n$1\r\n\xff\r\nQUIT\r\n").await.unwrap();
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).await.unwrap();
+        task.await.unwrap().unwrap();
+
+        let expected = b"+OK\r\n$4\r\n\xff\0\r\n\r\n$4\r\n\xff\0\r\n\r\n-INVALID_ARGUMENT Only a SET value may be binary; other arguments must be UTF-8\r\n+OK\r\n";
+        assert_eq!(reply, expected);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_oversized_lines() {
+        let protocol = RustdisProtocol::new(RustdisCache::new());
+        let (mut client, server) = tokio::io::duplex(4096);
+        let task = tokio::spawn(async move { serve_connection(&protocol, server).await });
+
+        let line = format!("GET {}\r\n", "k".repeat(MAX_LINE_LEN));
+        let send = tokio::spawn(async move {
+            // The server hangs up part-way through
+            let _ = client.write_all(line.as_bytes()).await;
+            client
+        });
+        task.await.unwrap().unwrap();
+        let mut client = send.await.unwrap();
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).await.unwrap();
+        assert_eq!(String::from_utf8(reply).unwrap(), format!("-ERR Protocol error: line longer than {} bytes\r\n", MAX_LINE_LEN));
+    }
+
+    #[tokio::test]
+    async fn test_negotiates_framing() {
+        let protocol = Arc::new(RustdisProtocol::new(RustdisCache::new()));
+
+        // Plain RESP still works, including requests shorter than the magic
+        let (mut client, server) = tokio::io::duplex(4096);
+        let serving = protocol.clone();
+        let task = tokio::spawn(async move { serve_negotiated(&serving, &Authenticator::default(), server).await });
+        client.write_all(b"\r\nSET k v\r\nQUIT\r\n").await.unwrap();
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).await.unwrap();
+        task.await.unwrap().unwrap();
+        assert_eq!(reply, b"+OK\r\n+OK\r\n");
+
+        let (mut client, server) = tokio::io::duplex(4096);
+        let serving = protocol.clone();
+        let task = tokio::spawn(async move { serve_negotiated(&serving, &Authenticator::default(), server).await });
+        let mut payload = Vec::new();
+        ciborium::into_writer(&Command::get("k".to_string()), &mut payload).unwrap();
+        let mut request = framing::MAGIC.to_vec();
+        framing::write_frame(&mut request, &payload).unwrap();
+        client.write_all(&request).await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).await.unwrap();
+        task.await.unwrap().unwrap();
+
+        let mut replies = reply.as_slice();
+        framing::negotiate_client(&mut replies, &mut Vec::new()).unwrap();
+        let get: Response = ciborium::from_reader(framing::read_frame(&mut replies).unwrap().unwrap().as_slice()).unwrap();
+        assert!(matches!(get, Response::String(ref s) if s == "v"));
+    }
+
+    #[cfg(feature = "chaos")]
+    #[tokio::test]
+    async fn test_drops_replies() {
+        let cache = RustdisCache::new();
+        cache.config_set("chaos-drop-rate", "1").unwrap();
+        let protocol = RustdisProtocol::new(cache.clone());
+        let (mut client, server) = tokio::io::duplex(4096);
+        let task = tokio::spawn(async move { serve_connection(&protocol, server).await });
+
+        client.write_all(b"SET k v\r\nQUIT\r\n").await.unwrap();
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).await.unwrap();
+        task.await.unwrap().unwrap();
+
+        // The command ran, but its reply never arrived
+        assert!(reply.is_empty());
+        assert_eq!(cache.get("k").unwrap().as_deref(), Some("v"));
+    }
+
+    #[tokio::test]
+    async fn test_streams_large_values() {
+        let protocol = RustdisProtocol::new(RustdisCache::new());
+        // The pipe holds a fraction of one value, so replies only get through
+        // as the client reads them
+        let (client, server) = tokio::io::duplex(STREAM_CHUNK_LEN / 4);
+        let task = tokio::spawn(async move { serve_connection(&protocol, server).await });
+
+        let value = "v".repeat(3 * STREAM_CHUNK_LEN + 7);
+        let (mut reader, mut writer) = tokio::io::split(client);
+        let request = format!(
+            "*3\r\n$3\r\nSET\r\n$1\r\nk\r\n${}\r\n{}\r\n*3\r\n$4\r\nMGET\r\n$1\r\nk\r\n$1\r\nk\r\nQUIT\r\n",
+            value.len(),
+            value
+        );
+        let send = tokio::spawn(async move { writer.write_all(request.as_bytes()).await });
+        let mut reply = Vec::new();
+        reader.read_to_end(&mut reply).await.unwrap();
+        send.await.unwrap().unwrap();
+        task.await.unwrap().unwrap();
+
+        let mut expected = b"+OK\r\n".to_vec();
+        let values = vec![Response::StringOption(Some(value.clone())); 2];
+        encode(&Response::Array(values), &mut expected);
+        expected.extend_from_slice(b"+OK\r\n");
+        assert_eq!(reply, expected);
+    }
+
+    #[tokio::test]
+    async fn test_resp_pubsub() {
+        let protocol = Arc::new(RustdisProtocol::new(RustdisCache::new()));
+        let (mut client, server) = tokio::io::duplex(4096);
+        let serving = protocol.clone();
+        let task = tokio::spawn(async move { serve_connection(&serving, server).await });
+
+        async fn expect(client: &mut tokio::io::DuplexStream, expected: &str) {
+            let mut reply = vec![0u8; expected.len()];
+            client.read_exact(&mut reply).await.unwrap();
+            assert_eq!(String::from_utf8(reply).unwrap(), expected);
+        }
+
+        client.write_all(b"SUBSCRIBE news sports\r\n").await.unwrap();
+        expect(&mut client, "*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n*3\r\n$9\r\nsubscribe\r\n$6\r\nsports\r\n:2\r\n").await;
+        assert!(matches!(protocol.execute_args(&["PUBLISH", "news", "hello"]), Response::Number(1)));
+        assert!(matches!(protocol.execute_args(&["PUBLISH", "weather", "rain"]), Response::Number(0)));
+        expect(&mut client, "*3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$5\r\nhello\r\n").await;
+
+        client.write_all(b"GET k\r\nUNSUBSCRIBE\r\nGET k\r\nQUIT\r\n").await.unwrap();
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).await.unwrap();
+        task.await.unwrap().unwrap();
+        assert_eq!(
+            String::from_utf8(reply).unwrap(),
+            "-ERR Can't execute 'GET': only (P)SUBSCRIBE, (P)UNSUBSCRIBE, PING and QUIT are allowed while subscribed\r\n\
+             *3\r\n$11\r\nunsubscribe\r\n$4\r\nnews\r\n:1\r\n*3\r\n$11\r\nunsubscribe\r\n$6\r\nsports\r\n:0\r\n\
+             $-1\r\n+OK\r\n"
+        );
+        // The connection's subscriptions went with it
+        assert!(matches!(protocol.execute_args(&["PUBLISH", "news", "bye"]), Response::Number(0)));
+    }
+
+    #[tokio::test]
+    async fn test_resp_auth() {
+        use crate::auth::{Roles, StaticPasswords};
+
+        let mut roles = Roles::new();
+        roles.define("reader=GET").unwrap();
+        let mut passwords = StaticPasswords::new();
+        for spec in ["default:pw", "ops:root:admin", "app:ro:reader"] {
+            let (user, password) = StaticPasswords::parse_user(spec, &roles).unwrap();
+            passwords.insert(user, password);
+        }
+        let auth = Authenticator::new().with_provider(passwords);
+        let protocol = RustdisProtocol::new(RustdisCache::new());
+        let (mut client, server) = tokio::io::duplex(4096);
+        let task = tokio::spawn(async move { serve_connection_with_auth(&protocol, &auth, server).await });
+
+        client
+            .write_all(b"GET k\r\nAUTH wrong\r\nAUTH pw\r\nGET k\r\nFLUSH\r\nAUTH app ro\r\nGET k\r\nSET k v\r\nAUTH ops root\r\nFLUSH\r\nQUIT\r\n")
+            .await
+            .unwrap();
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).await.unwrap();
+        task.await.unwrap().unwrap();
+
+        assert_eq!(
+            String::from_utf8(reply).unwrap(),
+            "-NOAUTH Authentication required\r\n\
+             -WRONGPASS invalid username-password pair or user is disabled\r\n\
+             +OK\r\n$-1\r\n\
+             -NOPERM User default has no permissions to run the 'FLUSH' command\r\n\
+             +OK\r\n$-1\r\n\
+             -NOPERM User app has no permissions to run the 'SET' command\r\n\
+             +OK\r\n+OK\r\n+OK\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resp_compat() {
+        let cache = RustdisCache::new();
+        cache.config_set("redis-compat-strict", "yes").unwrap();
+        let protocol = RustdisProtocol::new(cache);
+        let (mut client, server) = tokio::io::duplex(4096);
+        let task = tokio::spawn(async move { serve_connection(&protocol, server).await });
+
+        client
+            .write_all(b"GET\r\nCOMPAT native\r\nGET\r\nCOMPAT strict\r\nMULTI\r\nINCR\r\nEXEC\r\nCOMPAT loose\r\nQUIT\r\n")
+            .await
+            .unwrap();
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).await.unwrap();
+        task.await.unwrap().unwrap();
+
+        assert_eq!(
+            String::from_utf8(reply).unwrap(),
+            "-ERR wrong number of arguments for 'get' command\r\n\
+             +OK\r\n\
+             -WRONG_ARITY GET requires exactly one argument: GET <key>\r\n\
+             +OK\r\n+OK\r\n\
+             -ERR wrong number of arguments for 'incr' command\r\n\
+             -EXECABORT Transaction discarded because of previous errors.\r\n\
+             -ERR syntax error\r\n\
+             +OK\r\n"
+        );
+    }
+}
@@ -0,0 +1,330 @@
+use crate::eviction;
+use crate::protocol::Response;
+use anyhow::Result;
+use mlua::{HookTriggers, Lua, LuaOptions, MultiValue, StdLib, Table, Value, Variadic};
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::rc::Rc;
+use std::sync::{Arc, RwLock};
+
+/// Lua instructions run between checks of `ScriptLimits::max_instructions`
+const INSTRUCTION_CHECK_INTERVAL: u32 = 1000;
+
+/// Budget of a single EVAL or EVALSHA
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScriptLimits {
+    /// Lua VM instructions before the script is stopped, counted in steps of
+    /// `INSTRUCTION_CHECK_INTERVAL`; 0 for no limit
+    pub max_instructions: u64,
+    /// Bytes the script's Lua state may allocate in total, libraries
+    /// included; 0 for no limit
+    pub max_memory: usize,
+}
+
+impl Default for ScriptLimits {
+    fn default() -> Self {
+        Self { max_instructions: 100_000_000, max_memory: 64 * 1024 * 1024 }
+    }
+}
+
+/// Error for EVALSHA with a SHA1 no script was cached under
+#[derive(Debug)]
+pub struct NoScript;
+
+impl fmt::Display for NoScript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "No matching script; run it with EVAL or SCRIPT LOAD first")
+    }
+}
+
+impl std::error::Error for NoScript {}
+
+/// Lua scripts run with EVAL and EVALSHA (SCRIPT LOAD)
+///
+/// Only compiled with the `scripting` feature. As in Redis, a script gets
+/// its keys and arguments in the `KEYS` and `ARGV` tables, and runs commands
+/// with `redis.call(...)`, which raises the command's error, or
+/// `redis.pcall(...)`, which returns it as `{err = ...}`. Every script run or
+/// loaded is cached by the SHA1 of its code, in memory only; snapshots don't
+/// include them. Each run gets a fresh Lua state with just the base, table,
+/// string, math and utf8 libraries, so scripts can't reach files, the
+/// network or the process.
+#[derive(Debug, Default)]
+pub struct ScriptRegistry {
+    /// Code by lowercase hex SHA1
+    scripts: RwLock<HashMap<String, Arc<str>>>,
+    limits: RwLock<ScriptLimits>,
+}
+
+impl ScriptRegistry {
+    /// SCRIPT LOAD: caches a script once it compiles, returning its SHA1
+    pub fn load(&self, code: &str) -> Result<String> {
+        let lua = sandbox(ScriptLimits::default()).map_err(|e| anyhow::anyhow!("Failed to start Lua: {}", message(&e)))?;
+        lua.load(code)
+            .set_name("=script")
+            .into_function()
+            .map_err(|e| anyhow::anyhow!("Script doesn't compile: {}", message(&e)))?;
+        self.cache(code)
+    }
+
+    /// EVAL: runs `code` with its keys and arguments, within the current
+    /// limits, caching it as SCRIPT LOAD does
+    ///
+    /// `run` executes the commands the script sends with `redis.call` and
+    /// `redis.pcall`; making the whole script atomic is up to the caller.
+    pub fn eval(&self, code: &str, keys: Vec<String>, args: Vec<String>, run: impl Fn(Vec<Vec<u8>>) -> Response + 'static) -> Result<Response> {
+        self.cache(code)?;
+        self.run(code, keys, args, run)
+    }
+
+    /// EVALSHA: `eval` for the script cached under `sha`; fails with
+    /// `NoScript` if there is none
+    pub fn eval_sha(&self, sha: &str, keys: Vec<String>, args: Vec<String>, run: impl Fn(Vec<Vec<u8>>) -> Response + 'static) -> Result<Response> {
+        let code = {
+            let scripts = self.scripts.read().map_err(|_| anyhow::anyhow!("Failed to acquire scripts lock"))?;
+            scripts.get(&sha.to_ascii_lowercase()).cloned().ok_or(NoScript)?
+        };
+        self.run(&code, keys, args, run)
+    }
+
+    fn cache(&self, code: &str) -> Result<String> {
+        let sha = sha1_smol::Sha1::from(code).digest().to_string();
+        let mut scripts = self.scripts.write().map_err(|_| anyhow::anyhow!("Failed to acquire scripts lock"))?;
+        scripts.entry(sha.clone()).or_insert_with(|| code.into());
+        Ok(sha)
+    }
+
+    fn run(&self, code: &str, keys: Vec<String>, args: Vec<String>, run: impl Fn(Vec<Vec<u8>>) -> Response + 'static) -> Result<Response> {
+        let lua = sandbox(self.limits()?).map_err(|e| anyhow::anyhow!("Failed to start Lua: {}", message(&e)))?;
+        let eval = || -> mlua::Result<Response> {
+            let globals = lua.globals();
+            globals.set("KEYS", keys)?;
+            globals.set("ARGV", args)?;
+
+            let run = Rc::new(run);
+            let redis = lua.create_table()?;
+            let call = run.clone();
+            redis.set(
+                "call",
+                lua.create_function(move |lua, args: Variadic<Value>| match call(command_args(args)?) {
+                    Response::Error { error, code } => Err(mlua::Error::RuntimeError(format!("{} {}", code.as_deref().unwrap_or("ERR"), error))),
+                    reply => to_lua(lua, reply),
+                })?,
+            )?;
+            redis.set("pcall", lua.create_function(move |lua, args: Variadic<Value>| to_lua(lua, run(command_args(args)?)))?)?;
+            redis.set("error_reply", lua.create_function(|lua, error: mlua::String| reply_table(lua, "err", error))?)?;
+            redis.set("status_reply", lua.create_function(|lua, status: mlua::String| reply_table(lua, "ok", status))?)?;
+            globals.set("redis", redis)?;
+
+            let value = lua.load(code).set_name("=script").eval::<Value>()?;
+            Ok(to_response(value))
+        };
+        eval().map_err(|e| anyhow::anyhow!("Script failed: {}", message(&e)))
+    }
+
+    pub fn limits(&self) -> Result<ScriptLimits> {
+        Ok(*self.limits.read().map_err(|_| anyhow::anyhow!("Failed to acquire scripts lock"))?)
+    }
+
+    /// Current limits as CONFIG GET parameters
+    pub(crate) fn parameters(&self) -> Result<BTreeMap<String, String>> {
+        let limits = self.limits()?;
+        let mut parameters = BTreeMap::new();
+        parameters.insert("script-max-instructions".to_string(), limits.max_instructions.to_string());
+        parameters.insert("script-max-memory".to_string(), limits.max_memory.to_string());
+        Ok(parameters)
+    }
+
+    /// Applies a `script-*` CONFIG SET parameter, returning false for other names
+    pub(crate) fn set_parameter(&self, parameter: &str, value: &str) -> Result<bool> {
+        let mut limits = self.limits.write().map_err(|_| anyhow::anyhow!("Failed to acquire scripts lock"))?;
+        match parameter {
+            "script-max-instructions" => {
+                limits.max_instructions = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("script-max-instructions must be a non-negative integer"))?;
+            }
+            "script-max-memory" => {
+                limits.max_memory = eviction::parse_memory(value)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid script-max-memory value: {}", value))?;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+/// A Lua state enforcing `limits`, without the libraries and base functions
+/// that reach files or load bytecode; `print` output is discarded
+fn sandbox(limits: ScriptLimits) -> mlua::Result<Lua> {
+    let lua = Lua::new_with(StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::UTF8, LuaOptions::default())?;
+    let globals = lua.globals();
+    for name in ["dofile", "loadfile", "load"] {
+        globals.raw_set(name, Value::Nil)?;
+    }
+    globals.raw_set("print", lua.create_function(|_, _: MultiValue| Ok(()))?)?;
+    drop(globals);
+
+    if limits.max_memory > 0 {
+        lua.set_memory_limit(limits.max_memory)?;
+    }
+    if limits.max_instructions > 0 {
+        let executed = Cell::new(0u64);
+        let max = limits.max_instructions;
+        lua.set_hook(HookTriggers::new().every_nth_instruction(INSTRUCTION_CHECK_INTERVAL), move |_, _| {
+            executed.set(executed.get() + INSTRUCTION_CHECK_INTERVAL as u64);
+            if executed.get() > max {
+                return Err(mlua::Error::RuntimeError(format!("Script ran more than script-max-instructions ({})", max)));
+            }
+            Ok(())
+        });
+    }
+    Ok(lua)
+}
+
+/// A Lua error's message, without its stack traceback
+fn message(error: &mlua::Error) -> String {
+    match error {
+        mlua::Error::CallbackError { cause, .. } => message(cause),
+        mlua::Error::RuntimeError(message) | mlua::Error::SyntaxError { message, .. } => {
+            message.split("\nstack traceback:").next().unwrap_or(message).to_string()
+        }
+        error => error.to_string(),
+    }
+}
+
+/// The arguments of `redis.call` as a command line: strings as they are,
+/// numbers in decimal
+fn command_args(args: Variadic<Value>) -> mlua::Result<Vec<Vec<u8>>> {
+    args.iter()
+        .map(|arg| match arg {
+            Value::String(arg) => Ok(arg.as_bytes().to_vec()),
+            Value::Integer(arg) => Ok(arg.to_string().into_bytes()),
+            Value::Number(arg) => Ok(arg.to_string().into_bytes()),
+            _ => Err(mlua::Error::RuntimeError("Command arguments must be strings or numbers".to_string())),
+        })
+        .collect()
+}
+
+/// `{<field> = <message>}`, how scripts return error and status replies
+fn reply_table<'lua>(lua: &'lua Lua, field: &str, message: mlua::String<'lua>) -> mlua::Result<Table<'lua>> {
+    let table = lua.create_table()?;
+    table.set(field, message)?;
+    Ok(table)
+}
+
+/// A command reply as a Lua value, converted as Redis does: nil is false,
+/// booleans are 1 or 0, OK is `{ok = "OK"}` and errors are `{err = ...}`
+fn to_lua(lua: &Lua, response: Response) -> mlua::Result<Value<'_>> {
+    Ok(match response {
+        Response::Ok => Value::Table(reply_table(lua, "ok", lua.create_string("OK")?)?),
+        Response::String(value) | Response::StringOption(Some(value)) => Value::String(lua.create_string(value)?),
+        Response::StringOption(None) => Value::Boolean(false),
+        Response::Bytes(value) => Value::String(lua.create_string(value)?),
+        Response::Boolean(value) => Value::Integer(value.into()),
+        Response::Number(value) => Value::Integer(value as i64),
+        Response::Integer(value) => Value::Integer(value),
+        Response::Float(value) => Value::Number(value),
+        Response::StringArray(items) => Value::Table(lua.create_sequence_from(items)?),
+        Response::Array(items) => Value::Table(lua.create_sequence_from(items.into_iter().map(|item| to_lua(lua, item)).collect::<mlua::Result<Vec<_>>>()?)?),
+        Response::Map(entries) => Value::Table(lua.create_table_from(entries.into_iter().map(|(name, value)| Ok((name, to_lua(lua, value)?))).collect::<mlua::Result<Vec<_>>>()?)?),
+        Response::Error { error, code } => {
+            let error = format!("{} {}", code.as_deref().unwrap_or("ERR"), error);
+            Value::Table(reply_table(lua, "err", lua.create_string(error)?)?)
+        }
+    })
+}
+
+/// A script's result as a reply, converted as Redis does: false and nil are
+/// nil, true is 1, numbers are truncated to integers, `{ok = ...}` and
+/// `{err = ...}` are status and error replies, and other tables are arrays
+/// up to their first nil
+fn to_response(value: Value) -> Response {
+    match value {
+        Value::Boolean(true) => Response::Integer(1),
+        Value::Integer(value) => Response::Integer(value),
+        Value::Number(value) => Response::Integer(value as i64),
+        Value::String(value) => match value.to_str() {
+            Ok(value) => Response::StringOption(Some(value.to_string())),
+            Err(_) => Response::Bytes(value.as_bytes().to_vec()),
+        },
+        Value::Table(table) => {
+            if let Ok(Value::String(error)) = table.raw_get("err") {
+                return Response::error(error.to_string_lossy());
+            }
+            match table.raw_get("ok") {
+                Ok(Value::String(status)) if status == "OK" => Response::Ok,
+                Ok(Value::String(status)) => Response::String(status.to_string_lossy().into_owned()),
+                _ => Response::Array(table.sequence_values::<Value>().map(|item| item.map_or(Response::StringOption(None), to_response)).collect()),
+            }
+        }
+        _ => Response::StringOption(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::RustdisCache;
+    use crate::protocol::{Response, RustdisProtocol};
+
+    const RESERVE: &str = r#"
+local left = redis.call("GET", KEYS[1])
+if not left or tonumber(left) < tonumber(ARGV[1]) then
+    return false
+end
+return redis.call("DECRBY", KEYS[1], ARGV[1])
+"#;
+
+    #[test]
+    fn test_scripts() {
+        let protocol = RustdisProtocol::new(RustdisCache::new());
+        protocol.execute_args(&["SET", "item:1", "5"]);
+        assert!(matches!(protocol.execute_args(&["EVAL", RESERVE, "1", "item:1", "3"]), Response::Integer(2)));
+        assert!(matches!(protocol.execute_args(&["EVAL", RESERVE, "1", "item:1", "3"]), Response::StringOption(None)));
+        assert!(matches!(protocol.execute_args(&["EVAL", RESERVE, "2", "item:1"]), Response::Error { .. }));
+        let huge = usize::MAX.to_string();
+        assert!(matches!(protocol.execute_args(&["EVAL", "return 1", &huge]), Response::Error { code: Some(ref code), .. } if code == "INVALID_ARGUMENT"));
+        assert!(matches!(protocol.execute_args(&["EVALSHA", &"0".repeat(40), &huge]), Response::Error { code: Some(ref code), .. } if code == "INVALID_ARGUMENT"));
+
+        // EVAL caches the script too, under the same SHA1 as SCRIPT LOAD
+        let Response::StringOption(Some(sha)) = protocol.execute_args(&["SCRIPT", "LOAD", RESERVE]) else {
+            panic!("SCRIPT LOAD must reply with the SHA1");
+        };
+        assert_eq!(sha.len(), 40);
+        assert!(matches!(protocol.execute_args(&["EVALSHA", &sha.to_uppercase(), "1", "item:1", "2"]), Response::Integer(0)));
+        assert!(matches!(protocol.execute_args(&["EVALSHA", &"0".repeat(40), "0"]), Response::Error { code: Some(ref code), .. } if code == "NOSCRIPT"));
+        assert!(matches!(protocol.execute_args(&["SCRIPT", "LOAD", "return +"]), Response::Error { .. }));
+
+        // Replies convert both ways as in Redis
+        let script = r#"return {redis.call("SET", KEYS[1], "v"), redis.call("EXISTS", KEYS[1]), redis.call("GET", "missing"), 1.9}"#;
+        let Response::Array(items) = protocol.execute_args(&["EVAL", script, "1", "k"]) else {
+            panic!("a Lua table must reply with an array");
+        };
+        assert!(matches!(items[..], [Response::Ok, Response::Integer(1), Response::StringOption(None), Response::Integer(1)]));
+        assert!(matches!(protocol.execute_args(&["EVAL", "return redis.status_reply('OK')", "0"]), Response::Ok));
+        assert!(matches!(protocol.execute_args(&["EVAL", "return redis.error_reply('nope')", "0"]), Response::Error { ref error, .. } if error == "nope"));
+
+        // Command errors fail the script unless caught with pcall
+        protocol.execute_args(&["HSET", "cart", "field", "value"]);
+        assert!(matches!(protocol.execute_args(&["EVAL", "return redis.call('GET', KEYS[1])", "1", "cart"]), Response::Error { ref error, .. } if error.contains("WRONGTYPE")));
+        let caught = "local reply = redis.pcall('GET', KEYS[1]); return reply.err ~= nil";
+        assert!(matches!(protocol.execute_args(&["EVAL", caught, "1", "cart"]), Response::Integer(1)));
+
+        // Admin commands, nested scripts, the sandbox and the limits all fail the script
+        let fails = |script: &str, reason: &str| {
+            let reply = protocol.execute_args(&["EVAL", script, "0"]);
+            assert!(matches!(reply, Response::Error { ref error, .. } if error.contains(reason)), "{:?}", reply);
+        };
+        fails("return redis.call('FLUSH')", "FLUSH can't be called from a script");
+        fails("return redis.call('EVAL', 'return 1', '0')", "EVAL can't be called from a script");
+        fails("return io.open('/etc/passwd')", "global 'io'");
+        fails("return loadfile('/etc/passwd')", "global 'loadfile'");
+        fails("error('boom')", "script:1: boom");
+        protocol.execute_args(&["CONFIG", "SET", "script-max-instructions", "100000"]);
+        protocol.execute_args(&["CONFIG", "SET", "script-max-memory", "1mb"]);
+        fails("while true do end", "script-max-instructions");
+        fails("local s = 'x' while true do s = s .. s end", "not enough memory");
+        assert!(matches!(protocol.execute_args(&["EXISTS", "item:1"]), Response::Boolean(true)));
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+
+/// Number of time slices a TTL window is split into
+const SLICES: u32 = 4;
+/// Bits per slice filter (~1% false positives at 10k items per slice)
+const FILTER_BITS: usize = 96 * 1024;
+/// Hash functions per item
+const HASHES: u64 = 7;
+
+/// Bloom filter covering one time slice
+#[derive(Debug, Clone)]
+struct Slice {
+    started: Instant,
+    bits: Vec<u64>,
+}
+
+impl Slice {
+    fn new(started: Instant) -> Self {
+        Self {
+            started,
+            bits: vec![0; FILTER_BITS / 64],
+        }
+    }
+
+    fn insert(&mut self, positions: &[usize]) {
+        for &bit in positions {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    fn contains(&self, positions: &[usize]) -> bool {
+        positions.iter().all(|&bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+}
+
+/// Approximate "seen in the last TTL" set backed by rotating bloom filters
+///
+/// The TTL window is split into `SLICES` slices, each with its own filter.
+/// Items are recorded in the newest slice and whole slices are dropped once
+/// they age out, so membership answers are approximate in two ways: false
+/// positives are possible, and an item may be remembered for up to one
+/// slice longer than the TTL. An item seen within the TTL is never missed.
+#[derive(Debug, Clone)]
+pub struct SeenFilter {
+    ttl: Duration,
+    slices: VecDeque<Slice>,
+}
+
+impl SeenFilter {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            slices: VecDeque::new(),
+        }
+    }
+
+    /// Records an item, returning true if it was not seen within the TTL
+    pub fn add(&mut self, item: &str, ttl: Duration, now: Instant) -> bool {
+        self.ttl = ttl;
+        self.rotate(now);
+
+        let positions = Self::positions(item);
+        let seen = self.slices.iter().any(|slice| slice.contains(&positions));
+        // Record every sighting so the window slides from the latest one
+        if let Some(newest) = self.slices.back_mut() {
+            newest.insert(&positions);
+        }
+        !seen
+    }
+
+    /// Whether every slice has aged out, so the filter remembers nothing
+    pub fn is_idle(&self, now: Instant) -> bool {
+        let window = (self.ttl / SLICES).max(Duration::from_millis(1)) * (SLICES + 1);
+        self.slices.back().is_none_or(|newest| now.saturating_duration_since(newest.started) >= window)
+    }
+
+    /// Drops slices that fell out of the window and opens a new one when due
+    fn rotate(&mut self, now: Instant) {
+        let width = (self.ttl / SLICES).max(Duration::from_millis(1));
+        let window = width * (SLICES + 1);
+
+        while let Some(oldest) = self.slices.front() {
+            if now.saturating_duration_since(oldest.started) >= window {
+                self.slices.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        let due = match self.slices.back() {
+            Some(newest) => now.saturating_duration_since(newest.started) >= width,
+            None => true,
+        };
+        if due {
+            self.slices.push_back(Slice::new(now));
+        }
+    }
+
+    /// Bit positions for an item using double hashing
+    fn positions(item: &str) -> Vec<usize> {
+        let mut hasher = DefaultHasher::new();
+        item.hash(&mut hasher);
+        let h1 = hasher.finish();
+        0x9e37_79b9_7f4a_7c15u64.hash(&mut hasher);
+        let h2 = hasher.finish() | 1;
+
+        (0..HASHES)
+            .map(|i| (h1.wrapping_add(i.wrapping_mul(h2)) % FILTER_BITS as u64) as usize)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seen_filter_ages_out() {
+        let ttl = Duration::from_secs(60);
+        let start = Instant::now();
+        let mut filter = SeenFilter::new(ttl);
+
+        assert!(filter.add("event-1", ttl, start));
+        assert!(!filter.add("event-1", ttl, start + Duration::from_secs(30)));
+        assert!(filter.add("event-2", ttl, start + Duration::from_secs(30)));
+
+        // Still within the TTL of the first sighting
+        assert!(!filter.add("event-1", ttl, start + Duration::from_secs(59)));
+
+        // The window slides from the latest sighting
+        assert!(!filter.add("event-1", ttl, start + Duration::from_secs(100)));
+
+        // Well past TTL plus one slice, the item is forgotten
+        assert!(filter.add("event-1", ttl, start + Duration::from_secs(300)));
+        assert!(!filter.is_idle(start + Duration::from_secs(300)));
+        assert!(filter.is_idle(start + Duration::from_secs(400)));
+    }
+}
@@ -0,0 +1,504 @@
+use crate::blob::Blob;
+use crate::cache::Value;
+use crate::idgen::SnowflakeState;
+use crate::zset::SortedSet;
+use anyhow::Result;
+use indexmap::IndexMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Snapshot file written by SAVE and BGSAVE unless configured otherwise
+pub const DEFAULT_SNAPSHOT_PATH: &str = "dump.rsnap";
+
+/// First bytes of every snapshot file: a magic string and the format version
+///
+/// Version 2 added key metadata and version 3 the generator state after the
+/// entries; older files are still read.
+const MAGIC: &[u8; 8] = b"RSNAP\x00\x00\x03";
+
+const TYPE_STRING: u8 = 0;
+const TYPE_HASH: u8 = 1;
+const TYPE_ZSET: u8 = 2;
+const TYPE_BLOB: u8 = 3;
+
+/// Wait after a failed automatic save before the next attempt, so a full
+/// disk isn't retried every check
+pub const SAVE_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+const FLAG_PINNED: u8 = 1;
+const FLAG_EXPIRES: u8 = 2;
+const FLAG_META: u8 = 4;
+
+const AUX_SNOWFLAKE: u8 = 1;
+
+/// One key as stored in a snapshot
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotEntry {
+    pub key: String,
+    pub value: Value,
+    /// Unix time in milliseconds at which the key expires
+    pub expires_at: Option<u64>,
+    pub pinned: bool,
+    /// Metadata fields (META.SET), in order
+    pub meta: Vec<(String, String)>,
+}
+
+/// Automatic save trigger, as Redis' `save <seconds> <changes>`: a BGSAVE
+/// starts once `changes` writes have happened and `after` has passed since
+/// the last save
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SavePoint {
+    pub after: Duration,
+    pub changes: u64,
+}
+
+/// Parses save points written as Redis does, `<seconds> <changes>` pairs
+/// (e.g. `900 1 300 10`); an empty string disables automatic saves
+pub fn parse_save_points(spec: &str) -> Result<Vec<SavePoint>> {
+    let numbers = spec
+        .split_whitespace()
+        .map(|number| number.parse::<u64>().map_err(|_| anyhow::anyhow!("Invalid save point value: {}", number)))
+        .collect::<Result<Vec<_>>>()?;
+    if numbers.len() % 2 != 0 {
+        return Err(anyhow::anyhow!("Save points must be <seconds> <changes> pairs"));
+    }
+    Ok(numbers.chunks(2).map(|pair| SavePoint { after: Duration::from_secs(pair[0]), changes: pair[1] }).collect())
+}
+
+/// Save points in the form `parse_save_points` reads
+pub fn format_save_points(points: &[SavePoint]) -> String {
+    points.iter().map(|point| format!("{} {}", point.after.as_secs(), point.changes)).collect::<Vec<_>>().join(" ")
+}
+
+/// Where snapshots go, when to take them and how the last one went, shared
+/// by every handle to a cache
+#[derive(Debug)]
+pub struct SnapshotState {
+    path: Mutex<PathBuf>,
+    save_points: Mutex<Vec<SavePoint>>,
+    times: Mutex<SaveTimes>,
+    /// Cache writes counted when the last successful snapshot was taken
+    changes_at_save: AtomicU64,
+    /// Set while a SAVE or BGSAVE is writing
+    saving: AtomicBool,
+    /// Unix time in seconds of the last successful save; 0 if none
+    last_save: AtomicU64,
+    last_bgsave_ok: AtomicBool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SaveTimes {
+    /// Last successful save, or startup
+    saved: Instant,
+    attempted: Instant,
+}
+
+impl Default for SnapshotState {
+    fn default() -> Self {
+        let now = Instant::now();
+        Self {
+            path: Mutex::new(PathBuf::from(DEFAULT_SNAPSHOT_PATH)),
+            save_points: Mutex::new(Vec::new()),
+            times: Mutex::new(SaveTimes { saved: now, attempted: now }),
+            changes_at_save: AtomicU64::new(0),
+            saving: AtomicBool::new(false),
+            last_save: AtomicU64::new(0),
+            last_bgsave_ok: AtomicBool::new(true),
+        }
+    }
+}
+
+impl SnapshotState {
+    pub fn path(&self) -> Result<PathBuf> {
+        Ok(self.path.lock().map_err(|_| anyhow::anyhow!("Failed to acquire snapshot lock"))?.clone())
+    }
+
+    pub fn set_path(&self, path: PathBuf) -> Result<()> {
+        *self.path.lock().map_err(|_| anyhow::anyhow!("Failed to acquire snapshot lock"))? = path;
+        Ok(())
+    }
+
+    pub fn save_points(&self) -> Result<Vec<SavePoint>> {
+        Ok(self.save_points.lock().map_err(|_| anyhow::anyhow!("Failed to acquire snapshot lock"))?.clone())
+    }
+
+    pub fn set_save_points(&self, points: Vec<SavePoint>) -> Result<()> {
+        *self.save_points.lock().map_err(|_| anyhow::anyhow!("Failed to acquire snapshot lock"))? = points;
+        Ok(())
+    }
+
+    /// Cache writes since the last successful snapshot, given the current
+    /// write count
+    pub fn changes_since_save(&self, writes: u64) -> u64 {
+        writes.saturating_sub(self.changes_at_save.load(Ordering::Relaxed))
+    }
+
+    /// Treats the keyspace as saved at `writes`, e.g. right after loading it
+    pub fn mark_saved(&self, writes: u64) {
+        self.changes_at_save.store(writes, Ordering::Relaxed);
+    }
+
+    /// Whether a save point has been reached with `writes` counted so far;
+    /// after a failed save, not until `SAVE_RETRY_DELAY` has passed
+    pub fn save_due(&self, writes: u64) -> Result<bool> {
+        if self.is_saving() {
+            return Ok(false);
+        }
+        let times = *self.times.lock().map_err(|_| anyhow::anyhow!("Failed to acquire snapshot lock"))?;
+        if !self.last_bgsave_ok() && times.attempted.elapsed() < SAVE_RETRY_DELAY {
+            return Ok(false);
+        }
+        let changes = self.changes_since_save(writes);
+        let elapsed = times.saved.elapsed();
+        Ok(self.save_points()?.iter().any(|point| changes >= point.changes && elapsed >= point.after))
+    }
+
+    /// Claims the right to write a snapshot; false if a save is already
+    /// running
+    pub fn begin(&self) -> bool {
+        let claimed = !self.saving.swap(true, Ordering::AcqRel);
+        if claimed {
+            if let Ok(mut times) = self.times.lock() {
+                times.attempted = Instant::now();
+            }
+        }
+        claimed
+    }
+
+    /// Releases the claim taken by `begin`; `saved` holds the write count
+    /// the snapshot was taken at if it reached disk
+    pub fn finish(&self, saved: Option<u64>) {
+        if let Some(writes) = saved {
+            self.changes_at_save.store(writes, Ordering::Relaxed);
+            self.last_save.store(crate::cache::unix_millis() / 1000, Ordering::Relaxed);
+            if let Ok(mut times) = self.times.lock() {
+                times.saved = times.attempted;
+            }
+        }
+        self.saving.store(false, Ordering::Release);
+    }
+
+    /// Records the outcome of a BGSAVE
+    pub fn record_background(&self, saved: bool) {
+        self.last_bgsave_ok.store(saved, Ordering::Relaxed);
+    }
+
+    pub fn is_saving(&self) -> bool {
+        self.saving.load(Ordering::Acquire)
+    }
+
+    pub fn last_save(&self) -> u64 {
+        self.last_save.load(Ordering::Relaxed)
+    }
+
+    pub fn last_bgsave_ok(&self) -> bool {
+        self.last_bgsave_ok.load(Ordering::Relaxed)
+    }
+}
+
+/// Writes `entries` and the snowflake state to `path` atomically: to a
+/// temporary file first, renamed over `path` once complete, so a crash
+/// mid-save leaves the old snapshot
+pub fn write_file(entries: &[SnapshotEntry], snowflake: Option<SnowflakeState>, path: &Path) -> Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    let mut out = BufWriter::new(File::create(&temp)?);
+    write_with(entries, snowflake, &mut out)?;
+    out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    std::fs::rename(&temp, path)?;
+    Ok(())
+}
+
+/// Reads every entry of the snapshot at `path`, and the snowflake state if
+/// it was saved
+pub fn read_file(path: &Path) -> Result<(Vec<SnapshotEntry>, Option<SnowflakeState>)> {
+    read_with(BufReader::new(File::open(path)?))
+}
+
+/// Encodes `entries` with no generator state
+pub fn write(entries: &[SnapshotEntry], out: impl Write) -> Result<()> {
+    write_with(entries, None, out)
+}
+
+/// Encodes `entries`: the magic, an entry count, then per entry a type,
+/// flags, optional expiry, key, value and optional metadata, then a flags
+/// byte and the snowflake state if present. Lengths are LEB128 varints.
+pub fn write_with(entries: &[SnapshotEntry], snowflake: Option<SnowflakeState>, mut out: impl Write) -> Result<()> {
+    out.write_all(MAGIC)?;
+    write_len(&mut out, entries.len())?;
+    for entry in entries {
+        let kind = match entry.value {
+            Value::String(_) => TYPE_STRING,
+            Value::Hash(_) => TYPE_HASH,
+            Value::SortedSet(_) => TYPE_ZSET,
+            Value::Blob(_) => TYPE_BLOB,
+        };
+        let mut flags = 0;
+        if entry.pinned {
+            flags |= FLAG_PINNED;
+        }
+        if entry.expires_at.is_some() {
+            flags |= FLAG_EXPIRES;
+        }
+        if !entry.meta.is_empty() {
+            flags |= FLAG_META;
+        }
+        out.write_all(&[kind, flags])?;
+        if let Some(expires_at) = entry.expires_at {
+            out.write_all(&expires_at.to_le_bytes())?;
+        }
+        write_bytes(&mut out, entry.key.as_bytes())?;
+        match &entry.value {
+            Value::String(value) => write_bytes(&mut out, value)?,
+            Value::Hash(fields) => {
+                write_len(&mut out, fields.len())?;
+                for (field, value) in fields {
+                    write_bytes(&mut out, field.as_bytes())?;
+                    write_bytes(&mut out, value.as_bytes())?;
+                }
+            }
+            Value::SortedSet(set) => {
+                write_len(&mut out, set.len())?;
+                for (member, score) in set.iter() {
+                    write_bytes(&mut out, member.as_bytes())?;
+                    out.write_all(&score.to_le_bytes())?;
+                }
+            }
+            Value::Blob(blob) => {
+                write_len(&mut out, blob.max_len())?;
+                write_bytes(&mut out, blob.read(0, blob.len()))?;
+            }
+        }
+        if !entry.meta.is_empty() {
+            write_len(&mut out, entry.meta.len())?;
+            for (field, value) in &entry.meta {
+                write_bytes(&mut out, field.as_bytes())?;
+                write_bytes(&mut out, value.as_bytes())?;
+            }
+        }
+    }
+    match snowflake {
+        Some(state) => {
+            out.write_all(&[AUX_SNOWFLAKE])?;
+            out.write_all(&state.last_ms.to_le_bytes())?;
+            out.write_all(&state.sequence.to_le_bytes())?;
+        }
+        None => out.write_all(&[0])?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Encodes one entry as a DUMP payload: the snapshot encoding of that entry
+/// alone, then a checksum of it
+pub fn dump_entry(entry: &SnapshotEntry) -> Result<Vec<u8>> {
+    let mut payload = Vec::new();
+    write(std::slice::from_ref(entry), &mut payload)?;
+    let checksum = checksum(&payload);
+    payload.extend_from_slice(&checksum.to_le_bytes());
+    Ok(payload)
+}
+
+/// Decodes a payload written by `dump_entry`, refusing it if the checksum
+/// doesn't match
+pub fn restore_entry(payload: &[u8]) -> Result<SnapshotEntry> {
+    let invalid = || anyhow::anyhow!("DUMP payload version or checksum are wrong");
+    let (body, checksum_bytes) = payload.split_at(payload.len().checked_sub(8).ok_or_else(invalid)?);
+    if u64::from_le_bytes(checksum_bytes.try_into().map_err(|_| invalid())?) != checksum(body) {
+        return Err(invalid());
+    }
+    let mut entries = read(body).map_err(|_| invalid())?;
+    match (entries.pop(), entries.is_empty()) {
+        (Some(entry), true) => Ok(entry),
+        _ => Err(invalid()),
+    }
+}
+
+/// FNV-1a of `bytes`, to catch payloads damaged in transit
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3))
+}
+
+/// Decodes the entries of a snapshot written by `write`
+pub fn read(input: impl Read) -> Result<Vec<SnapshotEntry>> {
+    Ok(read_with(input)?.0)
+}
+
+/// Decodes a snapshot written by `write_with`
+pub fn read_with(mut input: impl Read) -> Result<(Vec<SnapshotEntry>, Option<SnowflakeState>)> {
+    let mut magic = [0u8; 8];
+    input.read_exact(&mut magic).map_err(|_| anyhow::anyhow!("Not a Rustdis snapshot"))?;
+    if magic[..5] != MAGIC[..5] {
+        return Err(anyhow::anyhow!("Not a Rustdis snapshot"));
+    }
+    if magic[..7] != MAGIC[..7] || magic[7] == 0 || magic[7] > MAGIC[7] {
+        return Err(anyhow::anyhow!("Unsupported snapshot version {}", magic[7]));
+    }
+
+    let count = read_len(&mut input)?;
+    let mut entries = Vec::with_capacity(count.min(1 << 16));
+    for _ in 0..count {
+        let mut header = [0u8; 2];
+        input.read_exact(&mut header)?;
+        let [kind, flags] = header;
+        let expires_at = if flags & FLAG_EXPIRES != 0 {
+            let mut expires_at = [0u8; 8];
+            input.read_exact(&mut expires_at)?;
+            Some(u64::from_le_bytes(expires_at))
+        } else {
+            None
+        };
+        let key = read_string(&mut input)?;
+        let value = match kind {
+            TYPE_STRING => Value::String(read_bytes(&mut input)?),
+            TYPE_HASH => {
+                let len = read_len(&mut input)?;
+                let mut fields = IndexMap::with_capacity(len.min(1 << 16));
+                for _ in 0..len {
+                    fields.insert(read_string(&mut input)?, read_string(&mut input)?);
+                }
+                Value::Hash(fields)
+            }
+            TYPE_ZSET => {
+                let len = read_len(&mut input)?;
+                let mut set = SortedSet::new();
+                for _ in 0..len {
+                    let member = read_string(&mut input)?;
+                    let mut score = [0u8; 8];
+                    input.read_exact(&mut score)?;
+                    set.insert(member, f64::from_le_bytes(score));
+                }
+                Value::SortedSet(set)
+            }
+            TYPE_BLOB => {
+                let mut blob = Blob::new(read_len(&mut input)?);
+                blob.append(&read_bytes(&mut input)?)?;
+                Value::Blob(blob)
+            }
+            _ => return Err(anyhow::anyhow!("Unknown value type {} in snapshot", kind)),
+        };
+        let mut meta = Vec::new();
+        if flags & FLAG_META != 0 {
+            for _ in 0..read_len(&mut input)? {
+                meta.push((read_string(&mut input)?, read_string(&mut input)?));
+            }
+        }
+        entries.push(SnapshotEntry { key, value, expires_at, pinned: flags & FLAG_PINNED != 0, meta });
+    }
+
+    let mut snowflake = None;
+    if magic[7] >= 3 {
+        let mut aux = [0u8];
+        input.read_exact(&mut aux)?;
+        if aux[0] & AUX_SNOWFLAKE != 0 {
+            let mut state = [0u8; 10];
+            input.read_exact(&mut state)?;
+            let (last_ms, sequence) = state.split_at(8);
+            snowflake = Some(SnowflakeState {
+                last_ms: u64::from_le_bytes(last_ms.try_into()?),
+                sequence: u16::from_le_bytes(sequence.try_into()?),
+            });
+        }
+    }
+    Ok((entries, snowflake))
+}
+
+fn write_len(out: &mut impl Write, len: usize) -> Result<()> {
+    let mut len = len as u64;
+    loop {
+        let byte = (len & 0x7f) as u8;
+        len >>= 7;
+        if len == 0 {
+            out.write_all(&[byte])?;
+            return Ok(());
+        }
+        out.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_len(input: &mut impl Read) -> Result<usize> {
+    let mut len = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8];
+        input.read_exact(&mut byte)?;
+        len |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return usize::try_from(len).map_err(|_| anyhow::anyhow!("Snapshot length {} is too large", len));
+        }
+    }
+    Err(anyhow::anyhow!("Invalid length in snapshot"))
+}
+
+fn write_bytes(out: &mut impl Write, bytes: &[u8]) -> Result<()> {
+    write_len(out, bytes.len())?;
+    out.write_all(bytes)?;
+    Ok(())
+}
+
+fn read_bytes(input: &mut impl Read) -> Result<Vec<u8>> {
+    let len = read_len(input)?;
+    let mut bytes = Vec::new();
+    // `take` keeps a corrupt length from allocating more than the file holds
+    input.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(anyhow::anyhow!("Snapshot is truncated"));
+    }
+    Ok(bytes)
+}
+
+fn read_string(input: &mut impl Read) -> Result<String> {
+    String::from_utf8(read_bytes(input)?).map_err(|_| anyhow::anyhow!("Snapshot key or field is not UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut set = SortedSet::new();
+        set.insert("bia".to_string(), 2.5);
+        let mut blob = Blob::new(1 << 20);
+        blob.append(&[0xff; 300]).unwrap();
+        let entries = vec![
+            SnapshotEntry {
+                key: "bin".to_string(),
+                value: Value::String(vec![0, 0xff]),
+                expires_at: Some(1_700_000_000_000),
+                pinned: false,
+                meta: vec![("owner".to_string(), "billing".to_string()), ("content-type".to_string(), "image/png".to_string())],
+            },
+            SnapshotEntry {
+                key: "h".to_string(),
+                value: Value::Hash(IndexMap::from([("f".to_string(), "v".to_string())])),
+                expires_at: None,
+                pinned: true,
+                meta: Vec::new(),
+            },
+            SnapshotEntry { key: "z".to_string(), value: Value::SortedSet(set), expires_at: None, pinned: false, meta: Vec::new() },
+            SnapshotEntry { key: "log".to_string(), value: Value::Blob(blob), expires_at: None, pinned: false, meta: Vec::new() },
+        ];
+
+        let snowflake = SnowflakeState { last_ms: 1_800_000_000_000, sequence: 9 };
+        let mut out = Vec::new();
+        write_with(&entries, Some(snowflake), &mut out).unwrap();
+        assert_eq!(read_with(out.as_slice()).unwrap(), (entries.clone(), Some(snowflake)));
+
+        assert!(read(&out[..out.len() - 1]).is_err());
+
+        // Version 1 files, written before metadata and generator state, still load
+        let mut v1 = Vec::new();
+        write(&entries[2..], &mut v1).unwrap();
+        v1.pop();
+        v1[7] = 1;
+        assert_eq!(read(v1.as_slice()).unwrap(), entries[2..]);
+        v1[7] = 4;
+        assert!(read(v1.as_slice()).is_err());
+        assert!(read(&b"RDB0011"[..]).is_err());
+    }
+}
@@ -0,0 +1,185 @@
+use crate::api::RustdisApi;
+use crate::cache::RustdisCache;
+use crate::clock::ManualClock;
+use crate::framing;
+use crate::protocol::{Command, Response, RustdisProtocol};
+use anyhow::Result;
+use std::io::{BufReader, BufWriter, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Isolated Rustdis instance for integration tests
+///
+/// Listens on an ephemeral localhost port speaking the framed CBOR protocol
+/// (see `framing`), backed by its own empty cache. The same cache is also
+/// reachable in-process through `cache()` and `api()`, which skips the
+/// socket entirely. The listener stops when the server is dropped.
+///
+/// The cache runs on a frozen `ManualClock`; time only moves through
+/// `advance`, `set_time` or `DEBUG SET-TIME`.
+pub struct TestServer {
+    cache: RustdisCache,
+    clock: Arc<ManualClock>,
+    addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    acceptor: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    /// Starts a server on `127.0.0.1` with an OS-assigned port
+    pub fn start() -> Result<Self> {
+        let clock = Arc::new(ManualClock::new());
+        let cache = RustdisCache::with_clock(clock.clone());
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        listener.set_nonblocking(true)?;
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+
+        let acceptor = {
+            let cache = cache.clone();
+            let shutdown = shutdown.clone();
+            thread::spawn(move || {
+                runtime.block_on(async move {
+                    let Ok(listener) = tokio::net::TcpListener::from_std(listener) else { return };
+                    loop {
+                        let accepted = listener.accept().await;
+                        if shutdown.load(Ordering::Acquire) {
+                            break;
+                        }
+                        let Ok((stream, _)) = accepted else { continue };
+                        let protocol = RustdisProtocol::new(cache.clone());
+                        // Connection errors only affect that test client
+                        tokio::spawn(async move {
+                            let _ = serve_connection(&protocol, stream).await;
+                        });
+                    }
+                });
+            })
+        };
+
+        Ok(Self {
+            cache,
+            clock,
+            addr,
+            shutdown,
+            acceptor: Some(acceptor),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The server's cache, for seeding data or asserting on state directly
+    pub fn cache(&self) -> &RustdisCache {
+        &self.cache
+    }
+
+    /// In-process API over the server's cache
+    pub fn api(&self) -> RustdisApi {
+        RustdisApi::new(self.cache.clone())
+    }
+
+    /// Moves the server's clock forward
+    pub fn advance(&self, by: Duration) {
+        self.clock.advance(by);
+    }
+
+    /// Sets the server's clock to `since_start` after the server started
+    pub fn set_time(&self, since_start: Duration) {
+        self.clock.set(since_start);
+    }
+
+    /// Opens a framed connection to the server
+    pub fn connect(&self) -> Result<TestClient> {
+        TestClient::connect(self.addr)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Release);
+        // Wake the acceptor so it notices the shutdown flag
+        let _ = TcpStream::connect(self.addr);
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+    }
+}
+
+async fn serve_connection(protocol: &RustdisProtocol, stream: tokio::net::TcpStream) -> Result<()> {
+    stream.set_nodelay(true)?;
+    let (reader, writer) = stream.into_split();
+    let mut reader = tokio::io::BufReader::new(reader);
+    let mut writer = tokio::io::BufWriter::new(writer);
+    framing::serve(protocol, &mut reader, &mut writer).await
+}
+
+/// Blocking framed CBOR client for a `TestServer`
+pub struct TestClient {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+}
+
+impl TestClient {
+    pub fn connect(addr: SocketAddr) -> Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream);
+        framing::negotiate_client(&mut reader, &mut writer)?;
+        Ok(Self { reader, writer })
+    }
+
+    /// Sends one command and waits for its response
+    pub fn execute(&mut self, command: &Command) -> Result<Response> {
+        let mut payload = Vec::new();
+        ciborium::into_writer(command, &mut payload).map_err(|e| anyhow::anyhow!("Failed to encode command: {}", e))?;
+        framing::write_frame(&mut self.writer, &payload)?;
+        self.writer.flush()?;
+
+        let reply = framing::read_frame(&mut self.reader)?.ok_or_else(|| anyhow::anyhow!("Server closed the connection"))?;
+        ciborium::from_reader(reply.as_slice()).map_err(|e| anyhow::anyhow!("Failed to decode response: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_round_trip() {
+        let server = TestServer::start().unwrap();
+        assert_ne!(server.addr().port(), 0);
+
+        let mut client = server.connect().unwrap();
+        let set = client.execute(&Command::set("k".to_string(), "v".to_string())).unwrap();
+        assert!(matches!(set, Response::String(ref s) if s == "OK"));
+
+        // Writes over the socket are visible in-process and vice versa
+        assert_eq!(server.cache().get("k").unwrap(), Some("v".to_string()));
+        server.cache().set("other".to_string(), "x".to_string()).unwrap();
+        let get = client.execute(&Command::get("other".to_string())).unwrap();
+        assert!(matches!(get, Response::String(ref s) if s == "x"));
+
+        // Each server is isolated
+        let other = TestServer::start().unwrap();
+        assert_eq!(other.cache().size().unwrap(), 0);
+
+        // Seen windows follow the server's clock, not the wall clock
+        let ttl = Duration::from_secs(60);
+        assert!(server.cache().seen_add("events", "e1", ttl).unwrap());
+        assert!(!server.cache().seen_add("events", "e1", ttl).unwrap());
+        server.advance(Duration::from_secs(600));
+        assert!(server.cache().seen_add("events", "e1", ttl).unwrap());
+
+        let set_time = client.execute(&Command::DebugSetTime { millis: 0 }).unwrap();
+        assert!(matches!(set_time, Response::String(ref s) if s == "OK"));
+        assert!(RustdisCache::new().set_time(Duration::ZERO).is_err());
+    }
+}
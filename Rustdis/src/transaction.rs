@@ -0,0 +1,179 @@
+use crate::protocol::{Command, Response, RustdisProtocol};
+
+/// MULTI/EXEC/DISCARD state of one connection or CLI session
+///
+/// After MULTI, commands are checked and queued instead of run, each
+/// replying `QUEUED`; EXEC runs them all with no other command in between
+/// (see `RustdisProtocol::exec`) and DISCARD drops them. As in Redis, a
+/// command that can't be queued (unknown, wrong arity, not permitted) makes
+/// EXEC fail with `EXECABORT` without running anything.
+///
+/// WATCH, before MULTI, records the version of keys; if any of them is
+/// written (or deleted, or expires) before EXEC, by this connection or any
+/// other, EXEC runs nothing and replies nil, for optimistic locking. EXEC
+/// and DISCARD forget the watched keys, as does UNWATCH.
+#[derive(Debug, Default)]
+pub struct Transaction {
+    /// Commands queued since MULTI; `None` outside a transaction
+    queued: Option<Vec<Command>>,
+    aborted: bool,
+    /// Watched keys and their version at WATCH
+    watched: Vec<(String, Option<u64>)>,
+}
+
+impl Transaction {
+    /// Whether MULTI was sent and not yet followed by EXEC or DISCARD
+    pub fn is_open(&self) -> bool {
+        self.queued.is_some()
+    }
+
+    /// Whether `execute` must take the command named `name`: MULTI, EXEC,
+    /// DISCARD, WATCH and UNWATCH, and anything else while a transaction is
+    /// open
+    pub fn handles(&self, name: &str) -> bool {
+        self.is_open() || ["MULTI", "EXEC", "DISCARD", "WATCH", "UNWATCH"].iter().any(|command| name.eq_ignore_ascii_case(command))
+    }
+
+    /// Makes EXEC of the open transaction fail, for a command refused before
+    /// it reached `execute`
+    pub fn abort(&mut self) {
+        self.aborted = self.is_open();
+    }
+
+    /// `execute` for a command given as text arguments
+    pub fn execute_args(&mut self, protocol: &RustdisProtocol, args: &[&str]) -> Response {
+        let args: Vec<&[u8]> = args.iter().map(|arg| arg.as_bytes()).collect();
+        self.execute_raw_args(protocol, &args)
+    }
+
+    /// `execute` for a command given as RESP arguments (see
+    /// `RustdisProtocol::parse_raw_args`)
+    pub fn execute_raw_args(&mut self, protocol: &RustdisProtocol, args: &[&[u8]]) -> Response {
+        match RustdisProtocol::parse_raw_args(args) {
+            Ok(command) => self.execute(protocol, command),
+            Err(e) => {
+                self.abort();
+                e.into()
+            }
+        }
+    }
+
+    /// Runs MULTI, EXEC, DISCARD, WATCH or UNWATCH, or queues `command` in
+    /// the open transaction
+    pub fn execute(&mut self, protocol: &RustdisProtocol, command: Command) -> Response {
+        match (command, &mut self.queued) {
+            (Command::Watch { .. }, Some(_)) => Response::error("WATCH inside MULTI is not allowed"),
+            (Command::Watch { keys }, None) => {
+                for key in keys {
+                    match protocol.cache().key_version(&key) {
+                        Ok(version) => self.watched.push((key, version)),
+                        Err(e) => return Response::error(e.to_string()),
+                    }
+                }
+                Response::Ok
+            }
+            (Command::Unwatch, _) => {
+                self.watched.clear();
+                Response::Ok
+            }
+            (Command::Multi, Some(_)) => Response::error("MULTI calls can not be nested"),
+            (Command::Multi, queued @ None) => {
+                *queued = Some(Vec::new());
+                Response::Ok
+            }
+            (command @ (Command::Exec | Command::Discard), None) => Response::error(format!("{} without MULTI", command.name())),
+            (Command::Discard, queued @ Some(_)) => {
+                *queued = None;
+                self.aborted = false;
+                self.watched.clear();
+                Response::Ok
+            }
+            (Command::Exec, queued @ Some(_)) => {
+                let commands = queued.take().unwrap_or_default();
+                let watched = std::mem::take(&mut self.watched);
+                if std::mem::take(&mut self.aborted) {
+                    return Response::coded_error("EXECABORT", "Transaction discarded because of previous errors");
+                }
+                protocol.exec(commands, &watched)
+            }
+            (command, Some(queued)) => {
+                queued.push(command);
+                Response::String("QUEUED".to_string())
+            }
+            (command, None) => protocol.execute(command),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::RustdisCache;
+
+    #[test]
+    fn test_transaction() {
+        let protocol = RustdisProtocol::new(RustdisCache::new());
+        let mut transaction = Transaction::default();
+        assert!(!transaction.handles("GET"));
+        assert!(matches!(transaction.execute_args(&protocol, &["EXEC"]), Response::Error { .. }));
+
+        assert!(matches!(transaction.execute_args(&protocol, &["multi"]), Response::Ok));
+        assert!(transaction.handles("GET"));
+        assert!(matches!(transaction.execute_args(&protocol, &["MULTI"]), Response::Error { .. }));
+        assert!(matches!(transaction.execute_args(&protocol, &["SET", "counter", "1"]), Response::String(ref s) if s == "QUEUED"));
+        transaction.execute_args(&protocol, &["INCR", "counter"]);
+        transaction.execute_args(&protocol, &["HSET", "counter", "f", "v"]);
+        // Nothing runs before EXEC
+        assert!(matches!(protocol.execute_args(&["GET", "counter"]), Response::StringOption(None)));
+        let Response::Array(replies) = transaction.execute_args(&protocol, &["EXEC"]) else {
+            panic!("EXEC must reply with an array");
+        };
+        assert!(matches!(replies[..], [Response::Ok, Response::Integer(2), Response::Error { .. }]));
+        assert!(!transaction.is_open());
+
+        // DISCARD drops the queue; a command that can't be queued aborts EXEC
+        transaction.execute_args(&protocol, &["MULTI"]);
+        transaction.execute_args(&protocol, &["DEL", "counter"]);
+        assert!(matches!(transaction.execute_args(&protocol, &["DISCARD"]), Response::Ok));
+        transaction.execute_args(&protocol, &["MULTI"]);
+        transaction.execute_args(&protocol, &["DEL", "counter"]);
+        assert!(matches!(transaction.execute_args(&protocol, &["INCR"]), Response::Error { .. }));
+        assert!(matches!(transaction.execute_args(&protocol, &["EXEC"]), Response::Error { code: Some(ref code), .. } if code == "EXECABORT"));
+        assert!(matches!(protocol.execute_args(&["GET", "counter"]), Response::StringOption(Some(ref value)) if value == "2"));
+    }
+
+    #[test]
+    fn test_watch() {
+        let protocol = RustdisProtocol::new(RustdisCache::new());
+        let (mut first, mut second) = (Transaction::default(), Transaction::default());
+        protocol.execute_args(&["HSET", "cart", "sku", "1"]);
+
+        // Untouched keys, including a missing one, let EXEC run
+        first.execute_args(&protocol, &["WATCH", "cart", "missing"]);
+        first.execute_args(&protocol, &["MULTI"]);
+        assert!(matches!(first.execute_args(&protocol, &["WATCH", "cart"]), Response::Error { .. }));
+        first.execute_args(&protocol, &["HSET", "cart", "qty", "2"]);
+        assert!(matches!(first.execute_args(&protocol, &["EXEC"]), Response::Array(replies) if replies.len() == 1));
+
+        // A write from elsewhere between WATCH and EXEC makes EXEC a no-op
+        for write in [&["HSET", "cart", "qty", "3"][..], &["EXPIRE", "cart", "60"], &["SET", "missing", "now"]] {
+            first.execute_args(&protocol, &["WATCH", "cart", "missing"]);
+            second.execute_args(&protocol, &["MULTI"]);
+            second.execute_args(&protocol, write);
+            second.execute_args(&protocol, &["EXEC"]);
+            first.execute_args(&protocol, &["MULTI"]);
+            first.execute_args(&protocol, &["DEL", "cart"]);
+            assert!(matches!(first.execute_args(&protocol, &["EXEC"]), Response::StringOption(None)));
+            protocol.execute_args(&["DEL", "missing"]);
+        }
+        assert!(matches!(protocol.execute_args(&["EXISTS", "cart"]), Response::Boolean(true)));
+
+        // EXEC forgot the keys; UNWATCH does too
+        first.execute_args(&protocol, &["WATCH", "cart"]);
+        protocol.execute_args(&["HDEL", "cart", "qty"]);
+        first.execute_args(&protocol, &["UNWATCH"]);
+        first.execute_args(&protocol, &["MULTI"]);
+        first.execute_args(&protocol, &["DEL", "cart"]);
+        assert!(matches!(first.execute_args(&protocol, &["EXEC"]), Response::Array(_)));
+    }
+}
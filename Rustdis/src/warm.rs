@@ -0,0 +1,204 @@
+use crate::cache::{RustdisCache, SetExpiry, SetOptions};
+use crate::client::RespClient;
+use crate::copy::{matching_keys, read_values, DEFAULT_COPY_BATCH};
+use anyhow::Result;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// Preload rule: copy string keys matching `pattern` from a RESP server into
+/// the cache at startup, and again every `every` if set
+///
+/// Written `PATTERN=SOURCE[,every=SECS]`, e.g.
+/// `user:*=redis://db-cache:6379,every=300`. Keys are overwritten with the
+/// source's value and keep its remaining TTL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WarmRule {
+    pub pattern: String,
+    /// Server URL, as accepted by `RespClient::connect`
+    pub source: String,
+    pub every: Option<Duration>,
+}
+
+impl FromStr for WarmRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow::anyhow!("Invalid warm rule: expected PATTERN=SOURCE[,every=SECS]");
+        let (pattern, rest) = s.split_once('=').ok_or_else(invalid)?;
+        let (source, every) = match rest.split_once(',') {
+            Some((source, option)) => {
+                let secs = option.strip_prefix("every=").ok_or_else(invalid)?;
+                match secs.parse() {
+                    Ok(secs) if secs > 0 => (source, Some(Duration::from_secs(secs))),
+                    _ => return Err(anyhow::anyhow!("Warm rule interval must be a positive number of seconds: {}", secs)),
+                }
+            }
+            None => (rest, None),
+        };
+        if pattern.is_empty() || source.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self { pattern: pattern.to_string(), source: source.to_string(), every })
+    }
+}
+
+impl fmt::Display for WarmRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.pattern, self.source)?;
+        if let Some(every) = self.every {
+            write!(f, ",every={}", every.as_secs())?;
+        }
+        Ok(())
+    }
+}
+
+/// How a rule has fared so far, as reported by WARM.STATUS
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WarmStatus {
+    pub rule: String,
+    pub running: bool,
+    /// Completed runs, failed ones included
+    pub runs: u64,
+    /// Keys written by the last successful run
+    pub loaded: usize,
+    /// Keys the last successful run found gone or holding a non-string value
+    pub skipped: usize,
+    pub last_duration: Duration,
+    /// Error of the last run, cleared by the next successful one
+    pub last_error: Option<String>,
+}
+
+/// Status of every warm rule, shared by every handle to a cache
+#[derive(Debug, Default)]
+pub struct WarmState {
+    rules: Mutex<Vec<WarmStatus>>,
+}
+
+impl WarmState {
+    /// Adds a rule to report on, returning its index
+    fn register(&self, rule: &WarmRule) -> Result<usize> {
+        let mut rules = self.rules.lock().map_err(|_| anyhow::anyhow!("Failed to acquire warm status lock"))?;
+        rules.push(WarmStatus { rule: rule.to_string(), ..WarmStatus::default() });
+        Ok(rules.len() - 1)
+    }
+
+    fn update(&self, index: usize, update: impl FnOnce(&mut WarmStatus)) -> Result<()> {
+        let mut rules = self.rules.lock().map_err(|_| anyhow::anyhow!("Failed to acquire warm status lock"))?;
+        if let Some(status) = rules.get_mut(index) {
+            update(status);
+        }
+        Ok(())
+    }
+
+    /// Status of every rule, in the order they were configured
+    pub fn statuses(&self) -> Result<Vec<WarmStatus>> {
+        Ok(self.rules.lock().map_err(|_| anyhow::anyhow!("Failed to acquire warm status lock"))?.clone())
+    }
+}
+
+/// Runs `rule` once, returning the keys loaded and skipped
+pub async fn warm(cache: &RustdisCache, rule: &WarmRule) -> Result<(usize, usize)> {
+    let mut source = RespClient::connect(&rule.source).await?;
+    let keys = matching_keys(&mut source, &rule.pattern).await?;
+    let (mut loaded, mut skipped) = (0, 0);
+    for batch in keys.chunks(DEFAULT_COPY_BATCH) {
+        for (key, value) in batch.iter().zip(read_values(&mut source, batch).await?) {
+            let Some((value, ttl)) = value else {
+                skipped += 1;
+                continue;
+            };
+            let expiry = ttl.map_or(SetExpiry::Clear, SetExpiry::After);
+            cache.set_with(key.clone(), value, SetOptions { expiry, ..SetOptions::default() })?;
+            loaded += 1;
+        }
+    }
+    Ok((loaded, skipped))
+}
+
+/// Starts a task per rule on the current Tokio runtime: each runs at once,
+/// then every `rule.every` if set, recording its status in
+/// `RustdisCache::warmers`
+pub fn spawn(cache: &RustdisCache, rules: Vec<WarmRule>) -> Result<Vec<JoinHandle<()>>> {
+    rules
+        .into_iter()
+        .map(|rule| {
+            let cache = cache.clone();
+            let index = cache.warmers().register(&rule)?;
+            Ok(tokio::spawn(async move {
+                loop {
+                    if cache.warmers().update(index, |status| status.running = true).is_err() {
+                        return;
+                    }
+                    let started = Instant::now();
+                    let result = warm(&cache, &rule).await;
+                    let recorded = cache.warmers().update(index, |status| {
+                        status.running = false;
+                        status.runs += 1;
+                        status.last_duration = started.elapsed();
+                        match result {
+                            Ok((loaded, skipped)) => {
+                                (status.loaded, status.skipped) = (loaded, skipped);
+                                status.last_error = None;
+                            }
+                            Err(e) => status.last_error = Some(e.to_string()),
+                        }
+                    });
+                    let (Ok(()), Some(every)) = (recorded, rule.every) else {
+                        return;
+                    };
+                    tokio::time::sleep(every).await;
+                }
+            }))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::RustdisProtocol;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_warm_rules() {
+        let backend = RustdisCache::new();
+        backend.set("user:1".to_string(), "ana".to_string()).unwrap();
+        backend.set("user:2".to_string(), "bia".to_string()).unwrap();
+        backend.expire("user:2", Duration::from_secs(60)).unwrap();
+        backend.set("order:1".to_string(), "x".to_string()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let protocol = Arc::new(RustdisProtocol::new(backend));
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let protocol = protocol.clone();
+                tokio::spawn(async move { crate::resp::serve_connection(&protocol, stream).await });
+            }
+        });
+
+        let rule: WarmRule = format!("user:*={}", addr).parse().unwrap();
+        assert_eq!(rule.every, None);
+        assert!("user:*".parse::<WarmRule>().is_err());
+        assert!("user:*=localhost,every=0".parse::<WarmRule>().is_err());
+        let rule_every: WarmRule = "p:*=redis://db:6379,every=300".parse().unwrap();
+        assert_eq!((rule_every.every, rule_every.to_string().as_str()), (Some(Duration::from_secs(300)), "p:*=redis://db:6379,every=300"));
+
+        let cache = RustdisCache::new();
+        cache.set("user:1".to_string(), "stale".to_string()).unwrap();
+        let unreachable = "user:*=127.0.0.1:1".parse().unwrap();
+        for handle in spawn(&cache, vec![rule, unreachable]).unwrap() {
+            handle.await.unwrap();
+        }
+
+        assert_eq!(cache.get("user:1").unwrap().as_deref(), Some("ana"));
+        assert!(matches!(cache.ttl("user:2").unwrap(), crate::cache::Ttl::Expires(ttl) if ttl > Duration::from_secs(50)));
+        assert_eq!(cache.get("order:1").unwrap(), None);
+        let statuses = cache.warmers().statuses().unwrap();
+        assert_eq!((statuses[0].runs, statuses[0].loaded, statuses[0].last_error.is_none()), (1, 2, true));
+        assert_eq!((statuses[1].runs, statuses[1].loaded, statuses[1].last_error.is_some()), (1, 0, true));
+    }
+}
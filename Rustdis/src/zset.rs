@@ -0,0 +1,137 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+
+/// Score wrapper ordered with `f64::total_cmp`, so it can key a `BTreeSet`
+#[derive(Debug, Clone, Copy)]
+struct Score(f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Members ordered by score, ties broken by member name, as in Redis
+///
+/// Lookups by member are O(1); ranks are O(n) since the ordered index
+/// isn't augmented with subtree sizes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SortedSet {
+    scores: HashMap<String, Score>,
+    ordered: BTreeSet<(Score, String)>,
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets `member`'s score, returning its previous score if it was present
+    pub fn insert(&mut self, member: String, score: f64) -> Option<f64> {
+        let previous = self.scores.insert(member.clone(), Score(score));
+        if let Some(previous) = previous {
+            self.ordered.remove(&(previous, member.clone()));
+        }
+        self.ordered.insert((Score(score), member));
+        previous.map(|score| score.0)
+    }
+
+    /// Removes `member`, returning its score if it was present
+    pub fn remove(&mut self, member: &str) -> Option<f64> {
+        let (member, score) = self.scores.remove_entry(member)?;
+        self.ordered.remove(&(score, member));
+        Some(score.0)
+    }
+
+    pub fn score(&self, member: &str) -> Option<f64> {
+        self.scores.get(member).map(|score| score.0)
+    }
+
+    /// Zero-based position of `member` in ascending order
+    pub fn rank(&self, member: &str) -> Option<usize> {
+        let score = *self.scores.get(member)?;
+        Some(self.ordered.range(..(score, member.to_string())).count())
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// Members between ranks `start` and `stop` inclusive, with their scores
+    ///
+    /// Negative ranks count from the end (-1 is the last member). With `rev`
+    /// ranks are taken in descending order.
+    pub fn range(&self, start: i64, stop: i64, rev: bool) -> Vec<(String, f64)> {
+        let len = self.len() as i64;
+        let resolve = |index: i64| if index < 0 { len + index } else { index };
+        let (start, stop) = (resolve(start).max(0), resolve(stop).min(len - 1));
+        if start > stop {
+            return Vec::new();
+        }
+        let take = (stop - start + 1) as usize;
+        let members: Box<dyn Iterator<Item = &(Score, String)>> =
+            if rev { Box::new(self.ordered.iter().rev()) } else { Box::new(self.ordered.iter()) };
+        members
+            .skip(start as usize)
+            .take(take)
+            .map(|(score, member)| (member.clone(), score.0))
+            .collect()
+    }
+
+    /// Members in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, f64)> {
+        self.ordered.iter().map(|(score, member)| (member.as_str(), score.0))
+    }
+
+    /// Approximate bytes charged against maxmemory: member names plus scores
+    pub fn size(&self) -> usize {
+        self.scores.keys().map(|member| member.len() + std::mem::size_of::<f64>()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sorted_set_order() {
+        let mut set = SortedSet::new();
+        assert_eq!(set.insert("carol".to_string(), 30.0), None);
+        set.insert("alice".to_string(), 10.0);
+        set.insert("bob".to_string(), 10.0);
+        assert_eq!(set.insert("carol".to_string(), 5.0), Some(30.0));
+
+        // Ties are ordered by member
+        let members: Vec<_> = set.iter().map(|(member, _)| member).collect();
+        assert_eq!(members, ["carol", "alice", "bob"]);
+        assert_eq!(set.rank("bob"), Some(2));
+        assert_eq!(set.rank("nobody"), None);
+
+        assert_eq!(set.range(0, -1, true)[0], ("bob".to_string(), 10.0));
+        assert_eq!(set.range(-2, 10, false).len(), 2);
+        assert!(set.range(2, 1, false).is_empty());
+        assert!(set.range(5, 10, false).is_empty());
+
+        assert_eq!(set.remove("carol"), Some(5.0));
+        assert_eq!(set.rank("alice"), Some(0));
+        assert_eq!(set.size(), "alice".len() + "bob".len() + 16);
+    }
+}